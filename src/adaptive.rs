@@ -0,0 +1,218 @@
+// An OrderMaintenance that starts out with 32-bit tags and moves itself to
+// 64-bit, then 128-bit, tags once they're too crowded to be cheap, so small
+// orders stay small without anyone having to guess how big they'll get.
+// "Too crowded" is measured rather than guessed: over every WINDOW inserts it
+// counts how many elements were relabeled, and if that's more than the
+// relabel budget per insert (bits / 2 by default: 16 for u32 tags, 32 for
+// u64) it promotes, which is one O(n) pass that spreads everyone evenly over
+// the wider tag space. Promotion is one way; nothing ever narrows the tags.
+//
+// Tags change width under you, so this only offers what doesn't depend on it:
+// no tags, keys or handles, just the order.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use super::{IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState, Stats};
+
+// inserts per measurement
+const WINDOW: u64 = 1024;
+
+#[derive(Debug)]
+enum Width<T, S>
+    where T: Hash + Eq {
+    Narrow(OrderMaintenance<T, S, u32>),
+    Default(OrderMaintenance<T, S, u64>),
+    Wide(OrderMaintenance<T, S, u128>),
+    // only while promote() is moving the order over
+    Promoting,
+}
+
+// the same code for whichever width it is
+macro_rules! with_om {
+    ($width:expr, $om:ident => $body:expr) => {
+        match $width {
+            Width::Narrow($om) => $body,
+            Width::Default($om) => $body,
+            Width::Wide($om) => $body,
+            Width::Promoting => unreachable!("order used mid-promotion"),
+        }
+    };
+}
+
+#[derive(Debug)]
+pub struct AdaptiveOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: Width<T, S>,
+    // per insert; None for bits / 2
+    relabel_budget: Option<f64>,
+    // inserts so far in this window, and the relabel count it started at
+    window_inserts: u64,
+    window_relabeled: u64,
+}
+
+#[derive(Debug)]
+pub enum AdaptiveIter<'a, T, S = RandomState>
+    where T: Hash + Eq + 'a, S: 'a {
+    Narrow(IterWithTag<'a, T, S, u32>),
+    Default(IterWithTag<'a, T, S, u64>),
+    Wide(IterWithTag<'a, T, S, u128>),
+}
+impl<'a, T, S> Iterator for AdaptiveIter<'a, T, S>
+    where T: Hash + Eq {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        match *self {
+            AdaptiveIter::Narrow(ref mut iter) => iter.next().map(|(value, _)| value),
+            AdaptiveIter::Default(ref mut iter) => iter.next().map(|(value, _)| value),
+            AdaptiveIter::Wide(ref mut iter) => iter.next().map(|(value, _)| value),
+        }
+    }
+}
+
+impl<T> AdaptiveOrderMaintenance<T>
+    where T: Hash + Eq {
+    pub fn new() -> AdaptiveOrderMaintenance<T> {
+        AdaptiveOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for AdaptiveOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> AdaptiveOrderMaintenance<T, S> {
+        AdaptiveOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+impl<T, S> AdaptiveOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> AdaptiveOrderMaintenance<T, S> {
+        let mut om = OrderMaintenance::with_hasher(hasher);
+        om.enable_stats();
+        AdaptiveOrderMaintenance { om: Width::Narrow(om), relabel_budget: None, window_inserts: 0, window_relabeled: 0 }
+    }
+    // how wide the tags are right now: 32, 64 or 128
+    pub fn tag_bits(&self) -> u32 {
+        match self.om {
+            Width::Narrow(_) => 32,
+            Width::Default(_) => 64,
+            Width::Wide(_) => 128,
+            Width::Promoting => unreachable!("order used mid-promotion"),
+        }
+    }
+    pub fn set_relabel_budget(&mut self, per_insert: f64) {
+        self.relabel_budget = Some(per_insert);
+    }
+    pub fn clear_relabel_budget(&mut self) {
+        self.relabel_budget = None;
+    }
+    // how many relabels per insert, over a window, it takes to promote
+    pub fn relabel_budget(&self) -> f64 {
+        self.relabel_budget.unwrap_or_else(|| (self.tag_bits() / 2) as f64)
+    }
+    // counted from the start, across promotions
+    pub fn stats(&self) -> &Stats {
+        with_om!(&self.om, om => om.stats().expect("stats are always on"))
+    }
+    pub fn len(&self) -> usize {
+        with_om!(&self.om, om => om.len())
+    }
+    pub fn is_empty(&self) -> bool {
+        with_om!(&self.om, om => om.is_empty())
+    }
+    pub fn iter(&self) -> AdaptiveIter<'_, T, S> {
+        match self.om {
+            Width::Narrow(ref om) => AdaptiveIter::Narrow(om.iter_values_with_tags()),
+            Width::Default(ref om) => AdaptiveIter::Default(om.iter_values_with_tags()),
+            Width::Wide(ref om) => AdaptiveIter::Wide(om.iter_values_with_tags()),
+            Width::Promoting => unreachable!("order used mid-promotion"),
+        }
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        with_om!(&self.om, om => om.contains(value))
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        with_om!(&self.om, om => om.compare(a, b))
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        with_om!(&mut self.om, om => om.insert_only(value))
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        with_om!(&mut self.om, om => om.insert_after(after, value))?;
+        self.inserted();
+        Ok(())
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        with_om!(&mut self.om, om => om.remove(value).map(|removed| removed.value))
+    }
+
+    fn inserted(&mut self) {
+        self.window_inserts += 1;
+        if self.window_inserts < WINDOW {
+            return;
+        }
+        let relabeled = self.stats().relabeled;
+        let rate = (relabeled - self.window_relabeled) as f64 / self.window_inserts as f64;
+        if rate > self.relabel_budget() {
+            self.promote();
+        }
+        self.window_inserts = 0;
+        self.window_relabeled = relabeled;
+    }
+    fn promote(&mut self) {
+        self.om = match ::core::mem::replace(&mut self.om, Width::Promoting) {
+            Width::Narrow(om) => Width::Default(om.into_width()),
+            Width::Default(om) => Width::Wide(om.into_width()),
+            other => other,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+    use test_random;
+
+    #[test]
+    fn promotes_when_crowded() {
+        let mut om: AdaptiveOrderMaintenance<u32> = AdaptiveOrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        // random inserts are cheap at any width, so they never promote
+        let mut model = vec![0u32];
+        let mut random = test_random(77);
+        for value in 1..2 * WINDOW as u32 {
+            let at = random(model.len());
+            om.insert_after(&model[at], value).unwrap();
+            model.insert(at + 1, value);
+        }
+        assert_eq!(om.tag_bits(), 32);
+        // always after the same element costs a few relabels per insert, at
+        // any width; over a budget of 2 it keeps promoting until it can't
+        om.set_relabel_budget(2.0);
+        for value in 2 * WINDOW as u32..5 * WINDOW as u32 {
+            om.insert_after(&0, value).unwrap();
+            model.insert(1, value);
+            if value == 3 * WINDOW as u32 {
+                assert_eq!(om.tag_bits(), 64);
+            }
+        }
+        assert_eq!(om.tag_bits(), 128);
+        assert_eq!(om.relabel_budget(), 2.0);
+        om.clear_relabel_budget();
+        assert_eq!(om.relabel_budget(), 64.0);
+        assert_eq!(om.iter().copied().collect::<Vec<_>>(), model);
+        for pair in model.windows(2) {
+            assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+        }
+        assert!(om.stats().relabeled > 0);
+        assert_eq!(om.remove(&0), Some(0));
+        assert_eq!(om.len(), model.len() - 1);
+    }
+}
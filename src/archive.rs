@@ -0,0 +1,113 @@
+// With the rkyv feature, an order archives as its elements in order, in an
+// rkyv ArchivedIndexSet: readable straight out of the bytes (mapped from a
+// file, say) without deserializing anything. Its iter() goes front to back,
+// and get_index_of() is an element's position, so comparing two positions
+// compares like compare() did. Tags aren't archived; deserializing spreads
+// out fresh ones, as the serde feature does.
+
+use core::hash::{BuildHasher, Hash};
+
+use rkyv::collections::swiss_table::{ArchivedIndexSet, IndexSetResolver};
+use rkyv::rancor::{Fallible, Source};
+use rkyv::ser::{Allocator, Writer};
+use rkyv::{Archive, Deserialize, Place, Serialize};
+
+use super::{Index, OrderMaintenance, TagWidth};
+
+// what rkyv's own IndexSet impl uses
+const LOAD_FACTOR: (usize, usize) = (7, 8);
+
+pub type ArchivedOrderMaintenance<T> = ArchivedIndexSet<<T as Archive>::Archived>;
+
+// serialize_from_iter wants to know exactly how many, and to go over them twice
+struct Elements<'a, T, S, W>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a OrderMaintenance<T, S, W>,
+    current: Option<Index>,
+    left: usize,
+}
+impl<'a, T, S, W> Clone for Elements<'a, T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    fn clone(&self) -> Self {
+        Elements { om: self.om, current: self.current, left: self.left }
+    }
+}
+impl<'a, T, S, W> Iterator for Elements<'a, T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let position = &self.om.slots[self.current?];
+        self.current = if Some(position.next) == self.om.front { None } else { Some(position.next) };
+        self.left -= 1;
+        Some(&position.key)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+impl<'a, T, S, W> ExactSizeIterator for Elements<'a, T, S, W>
+    where T: Hash + Eq, W: TagWidth {}
+
+impl<T, S, W> Archive for OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Archive, W: TagWidth {
+    type Archived = ArchivedOrderMaintenance<T>;
+    type Resolver = IndexSetResolver;
+    fn resolve(&self, resolver: IndexSetResolver, out: Place<ArchivedOrderMaintenance<T>>) {
+        ArchivedIndexSet::resolve_from_len(self.index.len(), LOAD_FACTOR, resolver, out);
+    }
+}
+
+impl<T, S, W, Z> Serialize<Z> for OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Serialize<Z>, W: TagWidth, Z: Fallible + Allocator + Writer + ?Sized, Z::Error: Source {
+    fn serialize(&self, serializer: &mut Z) -> Result<IndexSetResolver, Z::Error> {
+        let elements = Elements { om: self, current: self.front, left: self.index.len() };
+        ArchivedIndexSet::<T::Archived>::serialize_from_iter::<_, T, _>(elements, LOAD_FACTOR, serializer)
+    }
+}
+
+impl<T, S, W, D> Deserialize<OrderMaintenance<T, S, W>, D> for ArchivedOrderMaintenance<T>
+    where T: Hash + Eq + Archive, T::Archived: Deserialize<T, D>, S: BuildHasher + Default, W: TagWidth, D: Fallible + ?Sized, D::Error: Source {
+    fn deserialize(&self, deserializer: &mut D) -> Result<OrderMaintenance<T, S, W>, D::Error> {
+        let mut om = OrderMaintenance::default();
+        for element in self.iter() {
+            let value = element.deserialize(deserializer)?;
+            // only a set that was never validated could repeat one
+            match om.last() {
+                None => om.insert_only(value),
+                Some(last) => om.insert_after_index(last, value).map(|_| ()),
+            }.map_err(D::Error::new)?;
+        }
+        om.rebalance_all();
+        Ok(om)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rkyv::rancor::Error;
+    use rkyv::string::ArchivedString;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    #[test]
+    fn read_in_place() {
+        let mut om: OrderMaintenance<String> = OrderMaintenance::new();
+        om.insert_only("b".to_string()).unwrap();
+        om.insert_after("b", "d".to_string()).unwrap();
+        om.insert_after("b", "c".to_string()).unwrap();
+        om.insert_after("b", "a".to_string()).unwrap();
+        om.move_to_front("a").unwrap();
+        let bytes = rkyv::to_bytes::<Error>(&om).unwrap();
+        let archived = rkyv::access::<ArchivedOrderMaintenance<String>, Error>(&bytes).unwrap();
+        assert_eq!(archived.iter().map(ArchivedString::as_str).collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+        assert!(archived.get_index_of("a") < archived.get_index_of("c"));
+        assert!(!archived.contains("e"));
+        let back: OrderMaintenance<String> = rkyv::deserialize::<_, Error>(archived).unwrap();
+        assert_eq!(back, om);
+        back.check_invariants().unwrap();
+        let empty: OrderMaintenance<String> = OrderMaintenance::new();
+        let bytes = rkyv::to_bytes::<Error>(&empty).unwrap();
+        assert!(rkyv::access::<ArchivedOrderMaintenance<String>, Error>(&bytes).unwrap().is_empty());
+    }
+}
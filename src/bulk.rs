@@ -0,0 +1,158 @@
+// Building a whole order at once from its elements in order, on every core:
+// for loading a checkpoint of millions of elements, where going through
+// insert_after one at a time spends most of its time hashing. The elements
+// are split into one run per thread, and since the final tags are known up
+// front (evenly spread, as rebalance_all would leave them), each run can lay
+// out its slots, links, tags and hashes by itself; stitching them together
+// is then just concatenating the runs and putting the precomputed hashes
+// into the index, which is the only part left on one thread.
+//
+// With the rayon feature the runs are hashed on rayon's pool (so with as
+// many threads as it has, and none spawned per order); without it, on
+// std::thread::scope threads spawned afresh for each order built, so that
+// it's there whenever std is.
+
+use core::hash::{BuildHasher, Hash};
+#[cfg(not(feature = "rayon"))]
+use core::num::NonZeroUsize;
+
+use alloc::vec::Vec;
+use hashbrown::HashTable;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(not(feature = "rayon"))]
+use std::thread;
+
+use super::{Index, OrderMaintenance, OrderMaintenanceError, Position, Slot, TagWidth};
+
+// not worth a thread for fewer
+const MIN_CHUNK: usize = 1 << 14;
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Send, S: BuildHasher + Sync, W: TagWidth {
+    // The same order (and tags) as inserting the values one after another
+    // and then calling rebalance_all. An element that's there twice fails
+    // the whole thing with AlreadyPresent.
+    pub fn from_vec_parallel(values: Vec<T>, hasher: S) -> Result<OrderMaintenance<T, S, W>, OrderMaintenanceError> {
+        #[cfg(feature = "rayon")]
+        let threads = rayon::current_num_threads();
+        #[cfg(not(feature = "rayon"))]
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let chunks = threads.min(values.len() / MIN_CHUNK).max(1);
+        OrderMaintenance::build_in_chunks(values, hasher, chunks)
+    }
+    fn build_in_chunks(values: Vec<T>, hasher: S, chunks: usize) -> Result<OrderMaintenance<T, S, W>, OrderMaintenanceError> {
+        let len = values.len();
+        let mut om = OrderMaintenance::with_hasher(hasher);
+        if len == 0 {
+            return Ok(om);
+        }
+        assert!(len <= u32::MAX as usize, "too many elements");
+        let increment = W::MAX / W::from_usize(len + 1);
+        let built = OrderMaintenance::build_runs(values, &om.hasher, len.div_ceil(chunks), increment);
+        let mut hashes = Vec::with_capacity(len);
+        om.slots.slots.reserve_exact(len);
+        for (hash, slot) in built {
+            hashes.push(hash);
+            om.slots.slots.push(slot);
+        }
+        let slots = &om.slots;
+        let hasher = &om.hasher;
+        let mut index = HashTable::with_capacity(len);
+        for (i, hash) in hashes.into_iter().enumerate() {
+            let key = &slots[Index(i as u32)].key;
+            if index.find(hash, |&other: &Index| slots[other].key == *key).is_some() {
+                return Err(OrderMaintenanceError::AlreadyPresent);
+            }
+            index.insert_unique(hash, Index(i as u32), |&other| hasher.hash_one(&slots[other].key));
+        }
+        om.index = index;
+        om.front = Some(Index(0));
+        om.verify_valid_structure();
+        Ok(om)
+    }
+    // each value's hash and slot, in order, chunk_len values per run
+    #[cfg(not(feature = "rayon"))]
+    fn build_runs(mut values: Vec<T>, hasher: &S, chunk_len: usize, increment: W) -> Vec<(u64, Slot<T, W>)> {
+        let len = values.len();
+        // split from the back, so each split_off only moves its own run
+        let mut runs = Vec::with_capacity(len.div_ceil(chunk_len));
+        while !values.is_empty() {
+            let start = (values.len() - 1) / chunk_len * chunk_len;
+            runs.push((start, values.split_off(start)));
+        }
+        let built: Vec<Vec<(u64, Slot<T, W>)>> = thread::scope(|scope| {
+            let running: Vec<_> = runs.into_iter().rev().map(|(start, run)| scope.spawn(move || {
+                run.into_iter().enumerate().map(|(offset, key)| (hasher.hash_one(&key), slot(start + offset, len, increment, key))).collect()
+            })).collect();
+            running.into_iter().map(|run| run.join().expect("building a run panicked")).collect()
+        });
+        built.into_iter().flatten().collect()
+    }
+    // Hashing is the part worth spreading out; laying out the slots is a
+    // move per element, done in one pass after. (par_chunks_mut only for
+    // needing T: Send rather than Sync; nothing is changed through it.)
+    #[cfg(feature = "rayon")]
+    fn build_runs(mut values: Vec<T>, hasher: &S, chunk_len: usize, increment: W) -> Vec<(u64, Slot<T, W>)> {
+        let len = values.len();
+        let hashes: Vec<u64> = values.par_chunks_mut(chunk_len)
+            .flat_map_iter(|run| run.iter().map(|key| hasher.hash_one(key)))
+            .collect();
+        hashes.into_iter().zip(values).enumerate()
+            .map(|(i, (hash, key))| (hash, slot(i, len, increment, key)))
+            .collect()
+    }
+}
+
+// the ith of len, with its links and its tag of the evenly spread ones
+fn slot<T, W>(i: usize, len: usize, increment: W, key: T) -> Slot<T, W>
+    where W: TagWidth {
+    // the list is circular
+    let prev = Index(if i == 0 { len - 1 } else { i - 1 } as u32);
+    let next = Index(if i + 1 == len { 0 } else { i + 1 } as u32);
+    let tag = W::from_u128(increment.to_u128() * (i as u128 + 1));
+    Slot::Occupied(Position { key, prev, next, tag, handle: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use RandomState;
+
+    #[test]
+    fn same_as_one_at_a_time() {
+        // scattered, so the runs aren't in any order of their own
+        let values: Vec<u32> = (0..5000).map(|i| i * 7919 % 5003).collect();
+        let mut expected: OrderMaintenance<u32> = OrderMaintenance::new();
+        expected.insert_only(values[0]).unwrap();
+        expected.insert_all_after(&values[0], values[1..].iter().copied()).unwrap();
+        expected.rebalance_all();
+        let tags = |om: &OrderMaintenance<u32>| om.iter_values_with_tags().map(|(&value, tag)| (value, tag)).collect::<Vec<_>>();
+        for chunks in [1, 3, 4, 7] {
+            let built = OrderMaintenance::build_in_chunks(values.clone(), RandomState::default(), chunks).unwrap();
+            assert_eq!(tags(&built), tags(&expected));
+            assert_eq!(built.predecessor(&values[0]), None);
+            assert_eq!(built.successor(&values[4999]), None);
+        }
+        let built: OrderMaintenance<u32> = OrderMaintenance::from_vec_parallel(values.clone(), RandomState::default()).unwrap();
+        assert_eq!(built, expected);
+        let mut repeated = values;
+        repeated.push(repeated[2500]);
+        assert_eq!(OrderMaintenance::<u32>::build_in_chunks(repeated, RandomState::default(), 4).unwrap_err(), OrderMaintenanceError::AlreadyPresent);
+        assert!(OrderMaintenance::<u32>::from_vec_parallel(Vec::new(), RandomState::default()).unwrap().is_empty());
+    }
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn on_a_rayon_pool() {
+        let values: Vec<u64> = (0..3 * MIN_CHUNK as u64).map(|i| i * 7919 % 49157).collect();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        let built: OrderMaintenance<u64> = pool.install(|| OrderMaintenance::from_vec_parallel(values.clone(), RandomState::default())).unwrap();
+        assert_eq!(built.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>(), values);
+        let mut expected: OrderMaintenance<u64> = OrderMaintenance::new();
+        expected.insert_only(values[0]).unwrap();
+        expected.insert_all_after(&values[0], values[1..].iter().copied()).unwrap();
+        expected.rebalance_all();
+        assert!(built.iter_values_with_tags().eq(expected.iter_values_with_tags()));
+    }
+}
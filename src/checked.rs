@@ -0,0 +1,251 @@
+// An order that checks itself against the simplest possible model of one, a
+// Vec of its elements front to back, for differential testing: every change
+// goes to both, and afterwards they have to agree on what it returned, on
+// the whole order front to back, and on each element comparing before the
+// next (which takes the tags and the index along with it). Any disagreement
+// is a panic naming the op, so a fuzzer or a property test just has to drive
+// it with ops, such as OrderGenerator's.
+//
+// The model is O(n) for nearly everything and so is each check, so this is
+// for finding bugs in small orders, not for using.
+
+use core::cmp::Ordering;
+use core::fmt::Debug;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+
+use super::{Op, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+#[derive(Debug, Clone)]
+pub struct CheckedOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    model: Vec<T>,
+}
+
+impl<T> CheckedOrderMaintenance<T>
+    where T: Hash + Eq + Clone + Debug {
+    pub fn new() -> CheckedOrderMaintenance<T> {
+        CheckedOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for CheckedOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone + Debug, S: BuildHasher + Default {
+    fn default() -> CheckedOrderMaintenance<T, S> {
+        CheckedOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+impl<T, S> CheckedOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone + Debug, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> CheckedOrderMaintenance<T, S> {
+        CheckedOrderMaintenance { om: OrderMaintenance::with_hasher(hasher), model: Vec::new() }
+    }
+    // Checked against straight away, so an order that's already wrong
+    // panics here rather than at the first change.
+    pub fn from_order(om: OrderMaintenance<T, S>) -> CheckedOrderMaintenance<T, S> {
+        let model = om.iter_values_with_tags().map(|(value, _)| value.clone()).collect();
+        let checked = CheckedOrderMaintenance { om, model };
+        checked.check("from_order");
+        checked
+    }
+    pub fn order(&self) -> &OrderMaintenance<T, S> {
+        &self.om
+    }
+    pub fn model(&self) -> &[T] {
+        &self.model
+    }
+    pub fn into_inner(self) -> OrderMaintenance<T, S> {
+        self.om
+    }
+    // Just as the order compares them, once the model says the same.
+    pub fn compare(&self, a: &T, b: &T) -> Option<Ordering> {
+        let got = self.om.compare(a, b);
+        let expected = match (self.position(a), self.position(b)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => None,
+        };
+        assert_eq!(got, expected, "compare({:?}, {:?})", a, b);
+        got
+    }
+    pub fn apply<I>(&mut self, ops: I) -> Result<(), OrderMaintenanceError>
+        where I: IntoIterator<Item = Op<T>> {
+        for op in ops {
+            self.apply_op(op)?;
+        }
+        Ok(())
+    }
+    // A failed op has to fail the same way in both, and change neither.
+    pub fn apply_op(&mut self, op: Op<T>) -> Result<(), OrderMaintenanceError> {
+        let expected = self.model_apply(&op);
+        let got = self.om.apply_op(op.clone());
+        assert_eq!(got, expected, "{:?}", op);
+        self.check(&op);
+        got
+    }
+
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::InsertOnly(value))
+    }
+    pub fn insert_after(&mut self, after: &T, value: T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::InsertAfter { after: after.clone(), value })
+    }
+    pub fn remove(&mut self, value: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::Remove(value.clone()))
+    }
+    pub fn replace_key(&mut self, old: &T, new: T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::ReplaceKey { old: old.clone(), new })
+    }
+    pub fn move_after(&mut self, value: &T, after: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::MoveAfter { value: value.clone(), after: after.clone() })
+    }
+    pub fn move_before(&mut self, value: &T, before: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::MoveBefore { value: value.clone(), before: before.clone() })
+    }
+    pub fn move_to_front(&mut self, value: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::MoveToFront(value.clone()))
+    }
+    pub fn move_to_back(&mut self, value: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::MoveToBack(value.clone()))
+    }
+    pub fn swap_positions(&mut self, a: &T, b: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::SwapPositions(a.clone(), b.clone()))
+    }
+    pub fn reverse(&mut self) {
+        self.apply_op(Op::Reverse).expect("reverse can't fail")
+    }
+    pub fn rotate_to_front(&mut self, value: &T) -> Result<(), OrderMaintenanceError> {
+        self.apply_op(Op::RotateToFront(value.clone()))
+    }
+    pub fn rebalance_all(&mut self) {
+        self.apply_op(Op::RebalanceAll).expect("rebalance_all can't fail")
+    }
+
+    fn position(&self, value: &T) -> Option<usize> {
+        self.model.iter().position(|other| other == value)
+    }
+    // what the op should do, done to the model; the errors are checked for
+    // in the same order the order's methods check for them
+    fn model_apply(&mut self, op: &Op<T>) -> Result<(), OrderMaintenanceError> {
+        use OrderMaintenanceError::{AlreadyPresent, AnchorMissing, NotEmpty, NotPresent, SelfInsert};
+        let find = |model: &Vec<T>, value: &T, missing| model.iter().position(|other| other == value).ok_or(missing);
+        let model = &mut self.model;
+        match op {
+            Op::InsertOnly(value) => {
+                if !model.is_empty() {
+                    return Err(NotEmpty);
+                }
+                model.push(value.clone());
+            }
+            Op::InsertAfter { after, value } => {
+                if after == value {
+                    return Err(SelfInsert);
+                }
+                let after = find(model, after, AnchorMissing)?;
+                if find(model, value, NotPresent).is_ok() {
+                    return Err(AlreadyPresent);
+                }
+                model.insert(after + 1, value.clone());
+            }
+            Op::Remove(value) => {
+                let value = find(model, value, NotPresent)?;
+                model.remove(value);
+            }
+            Op::ReplaceKey { old, new } => {
+                let old = find(model, old, NotPresent)?;
+                if model[old] != *new && find(model, new, NotPresent).is_ok() {
+                    return Err(AlreadyPresent);
+                }
+                model[old] = new.clone();
+            }
+            Op::MoveAfter { value: moved, after: anchor } | Op::MoveBefore { value: moved, before: anchor } => {
+                let value = find(model, moved, NotPresent)?;
+                let anchor_at = find(model, anchor, AnchorMissing)?;
+                if value == anchor_at {
+                    return Err(SelfInsert);
+                }
+                let moved = model.remove(value);
+                let anchor_at = find(model, anchor, AnchorMissing)?;
+                let at = if let Op::MoveAfter { .. } = op { anchor_at + 1 } else { anchor_at };
+                model.insert(at, moved);
+            }
+            Op::MoveToFront(value) => {
+                let value = find(model, value, NotPresent)?;
+                let moved = model.remove(value);
+                model.insert(0, moved);
+            }
+            Op::MoveToBack(value) => {
+                let value = find(model, value, NotPresent)?;
+                let moved = model.remove(value);
+                model.push(moved);
+            }
+            Op::SwapPositions(a, b) => {
+                let a = find(model, a, NotPresent)?;
+                let b = find(model, b, NotPresent)?;
+                model.swap(a, b);
+            }
+            Op::Reverse => model.reverse(),
+            Op::RotateToFront(value) => {
+                let value = find(model, value, NotPresent)?;
+                model.rotate_left(value);
+            }
+            Op::RebalanceAll => {}
+        }
+        Ok(())
+    }
+    fn check<D>(&self, after: D)
+        where D: Debug {
+        let got: Vec<&T> = self.om.iter_values_with_tags().map(|(value, _)| value).collect();
+        let expected: Vec<&T> = self.model.iter().collect();
+        assert_eq!(got, expected, "order differs from the model after {:?}", after);
+        assert_eq!(self.om.len(), self.model.len(), "len after {:?}", after);
+        for pair in self.model.windows(2) {
+            assert_eq!(self.om.compare(&pair[0], &pair[1]), Some(Ordering::Less), "compare({:?}, {:?}) after {:?}", pair[0], pair[1], after);
+        }
+        if let (Some(front), Some(back)) = (self.model.first(), self.model.last()) {
+            let expected = if self.model.len() == 1 { Ordering::Equal } else { Ordering::Greater };
+            assert_eq!(self.om.compare(back, front), Some(expected), "compare(back, front) after {:?}", after);
+        }
+        if let Err(error) = self.om.check_invariants() {
+            panic!("{:?} after {:?}", error, after);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use OrderGenerator;
+
+    #[test]
+    fn agrees_with_the_model() {
+        for seed in 0..10 {
+            let mut generator = OrderGenerator::new(seed);
+            let mut checked = CheckedOrderMaintenance::from_order(generator.order(seed as usize * 5));
+            let ops = generator.ops(checked.order(), 200);
+            checked.apply(ops).unwrap();
+            assert_eq!(checked.order().len(), checked.model().len());
+        }
+        // failures have to match too
+        let mut checked: CheckedOrderMaintenance<u32> = CheckedOrderMaintenance::new();
+        assert_eq!(checked.remove(&1), Err(OrderMaintenanceError::NotPresent));
+        checked.insert_only(1).unwrap();
+        assert_eq!(checked.insert_only(2), Err(OrderMaintenanceError::NotEmpty));
+        assert_eq!(checked.insert_after(&1, 1), Err(OrderMaintenanceError::SelfInsert));
+        assert_eq!(checked.insert_after(&3, 2), Err(OrderMaintenanceError::AnchorMissing));
+        checked.insert_after(&1, 2).unwrap();
+        checked.insert_after(&2, 3).unwrap();
+        assert_eq!(checked.insert_after(&1, 3), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(checked.replace_key(&1, 3), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(checked.move_after(&2, &2), Err(OrderMaintenanceError::SelfInsert));
+        assert_eq!(checked.move_before(&2, &9), Err(OrderMaintenanceError::AnchorMissing));
+        checked.move_before(&3, &1).unwrap();
+        checked.rotate_to_front(&2).unwrap();
+        checked.reverse();
+        assert_eq!(checked.model(), [1, 3, 2]);
+        assert_eq!(checked.compare(&2, &3), Some(Ordering::Greater));
+        assert_eq!(checked.compare(&2, &4), None);
+    }
+}
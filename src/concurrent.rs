@@ -0,0 +1,216 @@
+// An order that can live in an Arc and be used from any number of threads at
+// once: every method takes &self. Changes take a lock on the order itself,
+// so they still happen one at a time, but compare() doesn't touch it.
+// Instead each element's OrderedHandle is kept in one of SHARDS maps, picked
+// by the element's hash and each behind a lock of its own; a comparison
+// read-locks the (at most two) shards its elements are in, and compares the
+// handles, which is lock-free (see OrderedHandle). So comparisons run side
+// by side with each other, and with a change as well, unless that change
+// is adding or removing one of their elements in one of their shards.
+//
+// An insert shows up in compare() once it has returned, and a remove has
+// been forgotten by then; in between, compare() may or may not know about
+// the element. Anything else (iterating, tags, successor and the rest) is
+// there through with_order(), which holds the order's lock throughout.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+
+use hashbrown::HashMap;
+
+use super::{OrderMaintenance, OrderMaintenanceError, OrderedHandle, RandomState};
+
+const SHARDS: usize = 16;
+
+// Nothing is left half-changed by a panic partway through (a panicking
+// OrderMaintenance method leaves the order as it was or finished), so a
+// poisoned lock is just used anyway.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug)]
+pub struct ConcurrentOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: Mutex<OrderMaintenance<T, S>>,
+    shards: Vec<RwLock<HashMap<T, OrderedHandle, S>>>,
+    hasher: S,
+}
+
+impl<T> ConcurrentOrderMaintenance<T>
+    where T: Hash + Eq + Clone {
+    pub fn new() -> ConcurrentOrderMaintenance<T> {
+        ConcurrentOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for ConcurrentOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone + Default {
+    fn default() -> ConcurrentOrderMaintenance<T, S> {
+        ConcurrentOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+// Each key is kept twice, in the order and in its shard, hence T: Clone.
+impl<T, S> ConcurrentOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone {
+    pub fn with_hasher(hasher: S) -> ConcurrentOrderMaintenance<T, S> {
+        let shards = (0..SHARDS).map(|_| RwLock::new(HashMap::with_hasher(hasher.clone()))).collect();
+        ConcurrentOrderMaintenance { om: Mutex::new(OrderMaintenance::with_hasher(hasher.clone())), shards, hasher }
+    }
+    pub fn into_inner(self) -> OrderMaintenance<T, S> {
+        self.om.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+    // Runs f with the order locked: neither changes nor other with_order
+    // calls can happen meanwhile, though comparisons still can.
+    pub fn with_order<R, F>(&self, f: F) -> R
+        where F: FnOnce(&OrderMaintenance<T, S>) -> R {
+        f(&lock(&self.om))
+    }
+    pub fn len(&self) -> usize {
+        lock(&self.om).len()
+    }
+    pub fn is_empty(&self) -> bool {
+        lock(&self.om).is_empty()
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.handle(value).is_some()
+    }
+    // A handle for value, to keep comparing without going through the
+    // shards at all.
+    pub fn handle<Q>(&self, value: &Q) -> Option<OrderedHandle>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let shard = self.shard(value).read().unwrap_or_else(PoisonError::into_inner);
+        shard.get(value).filter(|handle| !handle.is_removed()).cloned()
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.handle(a)?;
+        let b = self.handle(b)?;
+        Some(a.cmp(&b))
+    }
+    pub fn is_before<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.compare(a, b) == Some(Ordering::Less)
+    }
+    pub fn is_after<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.compare(a, b) == Some(Ordering::Greater)
+    }
+    pub fn insert_only(&self, value: T) -> Result<(), OrderMaintenanceError> {
+        let mut om = lock(&self.om);
+        om.insert_only(value.clone())?;
+        self.track(&mut om, value);
+        Ok(())
+    }
+    pub fn insert_after<Q>(&self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let mut om = lock(&self.om);
+        om.insert_after(after, value.clone())?;
+        self.track(&mut om, value);
+        Ok(())
+    }
+    pub fn remove<Q>(&self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let mut om = lock(&self.om);
+        let removed = om.remove(value)?.value;
+        self.shard(value).write().unwrap_or_else(PoisonError::into_inner).remove(value);
+        Some(removed)
+    }
+    // Moves, swaps and the rest only change tags, which the handles follow,
+    // so the shards aren't touched.
+    pub fn move_after<Q>(&self, value: &Q, after: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        lock(&self.om).move_after(value, after)
+    }
+    pub fn move_before<Q>(&self, value: &Q, before: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        lock(&self.om).move_before(value, before)
+    }
+    pub fn move_to_front<Q>(&self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        lock(&self.om).move_to_front(value)
+    }
+    pub fn move_to_back<Q>(&self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        lock(&self.om).move_to_back(value)
+    }
+    pub fn swap_positions<Q>(&self, a: &Q, b: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        lock(&self.om).swap_positions(a, b)
+    }
+    pub fn reverse(&self) {
+        lock(&self.om).reverse()
+    }
+    pub fn rebalance_all(&self) {
+        lock(&self.om).rebalance_all()
+    }
+
+    fn shard<Q>(&self, value: &Q) -> &RwLock<HashMap<T, OrderedHandle, S>>
+        where Q: Hash + ?Sized {
+        // middle bits: the shard's own table goes by the bottom ones (and
+        // the top seven)
+        let hash = self.hasher.hash_one(value);
+        &self.shards[(hash >> 32) as usize % SHARDS]
+    }
+    // while om is still locked, so a remove can't get in first
+    fn track(&self, om: &mut OrderMaintenance<T, S>, value: T) {
+        let handle = om.handle(&value).expect("just inserted");
+        self.shard(&value).write().unwrap_or_else(PoisonError::into_inner).insert(value, handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn compares_from_many_threads() {
+        let om: ConcurrentOrderMaintenance<u32> = ConcurrentOrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..10 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        thread::scope(|scope| {
+            // each writer crowds in after an element of its own, and takes
+            // out what it put in, relabeling 0..10 all the while
+            for writer in 0..2 {
+                let om = &om;
+                scope.spawn(move || {
+                    let base = 1000 * (writer + 1);
+                    for value in base..base + 300 {
+                        om.insert_after(&(writer * 5), value).unwrap();
+                        if value >= base + 20 {
+                            assert_eq!(om.remove(&(value - 20)), Some(value - 20));
+                        }
+                    }
+                });
+            }
+            for _ in 0..2 {
+                let om = &om;
+                scope.spawn(move || {
+                    for round in 0..300 {
+                        let (a, b) = (round % 9, round % 9 + 1);
+                        assert_eq!(om.compare(&a, &b), Some(Ordering::Less));
+                        assert!(om.is_after(&9, &0));
+                    }
+                });
+            }
+        });
+        assert_eq!(om.len(), 50);
+        assert_eq!(om.compare(&1000, &1), None);
+        om.move_to_front(&9).unwrap();
+        assert!(om.is_before(&9, &0));
+        assert_eq!(om.remove(&9), Some(9));
+        assert!(!om.contains(&9));
+        assert_eq!(om.with_order(|om| om.iter_values_with_tags().take(2).map(|(&value, _)| value).collect::<Vec<_>>()), [0, 1000 + 299]);
+        assert_eq!(om.into_inner().len(), 49);
+    }
+}
@@ -0,0 +1,108 @@
+// An order that's cheap to snapshot: clone() is O(1), sharing the order
+// behind an Arc, and it's only when one of the copies is written to (through
+// make_mut) that that one gets an ordinary O(n) Clone of its own, if anyone
+// else still shares it. So taking a snapshot before some speculative
+// reordering and dropping it unused costs nothing more than the refcount.
+//
+// Reads go straight through (Deref). Since a write that has to copy goes
+// through OrderMaintenance's Clone, the copy doing the writing is the one
+// that starts over without the on_relabel callback, relabel listener and
+// handles; the copies that didn't write keep them.
+
+use core::hash::{BuildHasher, Hash};
+use core::ops::Deref;
+
+use alloc::sync::Arc;
+
+use super::{OrderMaintenance, RandomState, TagWidth};
+
+#[derive(Debug)]
+pub struct CowOrderMaintenance<T, S = RandomState, W = u64>(Arc<OrderMaintenance<T, S, W>>)
+    where T: Hash + Eq, W: TagWidth;
+
+impl<T> CowOrderMaintenance<T>
+    where T: Hash + Eq {
+    pub fn new() -> CowOrderMaintenance<T> {
+        CowOrderMaintenance::from(OrderMaintenance::new())
+    }
+}
+
+impl<T, S, W> Default for CowOrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher + Default, W: TagWidth {
+    fn default() -> CowOrderMaintenance<T, S, W> {
+        CowOrderMaintenance::from(OrderMaintenance::default())
+    }
+}
+
+impl<T, S, W> From<OrderMaintenance<T, S, W>> for CowOrderMaintenance<T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    fn from(om: OrderMaintenance<T, S, W>) -> CowOrderMaintenance<T, S, W> {
+        CowOrderMaintenance(Arc::new(om))
+    }
+}
+
+impl<T, S, W> Clone for CowOrderMaintenance<T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    fn clone(&self) -> CowOrderMaintenance<T, S, W> {
+        CowOrderMaintenance(Arc::clone(&self.0))
+    }
+}
+
+impl<T, S, W> Deref for CowOrderMaintenance<T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    type Target = OrderMaintenance<T, S, W>;
+    fn deref(&self) -> &OrderMaintenance<T, S, W> {
+        &self.0
+    }
+}
+
+impl<T, S, W> CowOrderMaintenance<T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    // whether some other copy would see a write (and so make_mut would copy)
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+    pub fn make_mut(&mut self) -> &mut OrderMaintenance<T, S, W>
+        where T: Clone, S: Clone {
+        Arc::make_mut(&mut self.0)
+    }
+    // without copying, if nobody else shares it
+    pub fn into_inner(self) -> OrderMaintenance<T, S, W>
+        where T: Clone, S: Clone {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn copies_on_write() {
+        let mut order: CowOrderMaintenance<u32> = CowOrderMaintenance::new();
+        order.make_mut().insert_only(0).unwrap();
+        for value in 1..100 {
+            order.make_mut().insert_after(&(value - 1), value).unwrap();
+        }
+        assert!(!order.is_shared());
+        let snapshot = order.clone();
+        assert!(order.is_shared() && snapshot.is_shared());
+        // reads don't copy
+        assert!(order.is_before(&3, &4));
+        assert!(Arc::ptr_eq(&order.0, &snapshot.0));
+        order.make_mut().move_to_front(&50).unwrap();
+        assert!(!order.is_shared() && !snapshot.is_shared());
+        assert_eq!(order.iter_values_with_tags().next().map(|(&value, _)| value), Some(50));
+        assert_eq!(snapshot.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+        // now it has its own, so writing doesn't copy again
+        let before = Arc::as_ptr(&order.0);
+        order.make_mut().remove(&0);
+        assert_eq!(Arc::as_ptr(&order.0), before);
+        let dropped = order.clone();
+        drop(dropped);
+        assert!(!order.is_shared());
+        assert_eq!(order.into_inner().len(), 99);
+        snapshot.into_inner().check_invariants().unwrap();
+    }
+}
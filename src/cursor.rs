@@ -0,0 +1,212 @@
+// Cursors, for walking the order (and, with CursorMut, changing it) a step
+// at a time, as with LinkedList's: the cursor holds on to a slot, so a step
+// follows a link instead of hashing a key to find where it is. Past either
+// end there's a "ghost" position, where current() is None; stepping forward
+// from it goes to the front, and back from it to the back, so a walk can go
+// round past the end and start over.
+//
+// A new element still has to be hashed once, to go in the index, and a
+// removed one to come out of it; nothing else a cursor does looks anything
+// up.
+
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+
+use super::{Index, OrderMaintenance, OrderMaintenanceError, RandomState, Tag, TagWidth};
+
+#[derive(Debug)]
+pub struct Cursor<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a OrderMaintenance<T, S, W>,
+    // None for the ghost
+    current: Option<Index>,
+}
+// Like derive(Clone) would be, but without needing T: Clone
+impl<'a, T, S, W> Clone for Cursor<'a, T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    fn clone(&self) -> Cursor<'a, T, S, W> {
+        Cursor { om: self.om, current: self.current }
+    }
+}
+
+#[derive(Debug)]
+pub struct CursorMut<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a mut OrderMaintenance<T, S, W>,
+    current: Option<Index>,
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // at the ghost if the order is empty
+    pub fn cursor_front(&self) -> Cursor<'_, T, S, W> {
+        Cursor { om: self, current: self.front }
+    }
+    pub fn cursor_back(&self) -> Cursor<'_, T, S, W> {
+        Cursor { om: self, current: self.last() }
+    }
+    // the one lookup
+    pub fn cursor_at<Q>(&self, value: &Q) -> Option<Cursor<'_, T, S, W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let current = self.find(value)?;
+        Some(Cursor { om: self, current: Some(current) })
+    }
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T, S, W> {
+        let current = self.front;
+        CursorMut { om: self, current }
+    }
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T, S, W> {
+        let current = self.last();
+        CursorMut { om: self, current }
+    }
+    pub fn cursor_at_mut<Q>(&mut self, value: &Q) -> Option<CursorMut<'_, T, S, W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let current = self.find(value)?;
+        Some(CursorMut { om: self, current: Some(current) })
+    }
+    // a step from index, where None is the ghost either way
+    fn step_next(&self, index: Option<Index>) -> Option<Index> {
+        match index {
+            None => self.front,
+            Some(index) => Some(self.slots[index].next).filter(|&next| Some(next) != self.front),
+        }
+    }
+    fn step_prev(&self, index: Option<Index>) -> Option<Index> {
+        match index {
+            None => self.last(),
+            Some(index) if Some(index) == self.front => None,
+            Some(index) => Some(self.slots[index].prev),
+        }
+    }
+}
+
+impl<'a, T, S, W> Cursor<'a, T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn current(&self) -> Option<&'a T> {
+        let om = self.om;
+        self.current.map(|index| &om.slots[index].key)
+    }
+    pub fn tag(&self) -> Option<Tag<W>> {
+        self.current.map(|index| Tag(self.om.tag(index)))
+    }
+    pub fn move_next(&mut self) {
+        self.current = self.om.step_next(self.current);
+    }
+    pub fn move_prev(&mut self) {
+        self.current = self.om.step_prev(self.current);
+    }
+    pub fn peek_next(&self) -> Option<&'a T> {
+        let om = self.om;
+        om.step_next(self.current).map(|index| &om.slots[index].key)
+    }
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        let om = self.om;
+        om.step_prev(self.current).map(|index| &om.slots[index].key)
+    }
+}
+
+impl<'a, T, S, W> CursorMut<'a, T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn current(&self) -> Option<&T> {
+        self.current.map(|index| &self.om.slots[index].key)
+    }
+    pub fn tag(&self) -> Option<Tag<W>> {
+        self.current.map(|index| Tag(self.om.tag(index)))
+    }
+    pub fn move_next(&mut self) {
+        self.current = self.om.step_next(self.current);
+    }
+    pub fn move_prev(&mut self) {
+        self.current = self.om.step_prev(self.current);
+    }
+    pub fn peek_next(&self) -> Option<&T> {
+        self.om.step_next(self.current).map(|index| &self.om.slots[index].key)
+    }
+    pub fn peek_prev(&self) -> Option<&T> {
+        self.om.step_prev(self.current).map(|index| &self.om.slots[index].key)
+    }
+    // A read-only cursor at the same place, for as long as this one's lent.
+    pub fn as_cursor(&self) -> Cursor<'_, T, S, W> {
+        Cursor { om: self.om, current: self.current }
+    }
+    // Leaves the cursor where it was; at the ghost, value goes at the front.
+    pub fn insert_after_cursor(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        match (self.current, self.om.front) {
+            (Some(current), _) => self.om.insert_after_index(current, value).map(|_| ()),
+            (None, None) => self.om.insert_only(value),
+            (None, Some(front)) => self.insert_before_index(front, value),
+        }
+    }
+    // Leaves the cursor where it was; at the ghost, value goes at the back.
+    pub fn insert_before_cursor(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        match (self.current, self.om.last()) {
+            (Some(current), _) => self.insert_before_index(current, value),
+            (None, None) => self.om.insert_only(value),
+            (None, Some(last)) => self.om.insert_after_index(last, value).map(|_| ()),
+        }
+    }
+    // Moves the cursor on to the next element (or the ghost, if it was the
+    // back); None at the ghost.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let current = self.current?;
+        self.current = self.om.step_next(Some(current));
+        Some(self.om.remove_index(current).value)
+    }
+    fn insert_before_index(&mut self, before: Index, value: T) -> Result<(), OrderMaintenanceError> {
+        self.om.check_absent(&value)?;
+        let index = self.om.allocate(value, W::ZERO);
+        self.om.link_before(index, before);
+        self.om.verify_valid_structure();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn walks_and_edits_in_place() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        {
+            let mut cursor = om.cursor_front_mut();
+            assert_eq!(cursor.current(), None);
+            cursor.insert_after_cursor(2).unwrap();
+            cursor.insert_before_cursor(5).unwrap();
+            cursor.move_next();
+            assert_eq!(cursor.current(), Some(&2));
+            cursor.insert_before_cursor(1).unwrap();
+            cursor.insert_after_cursor(3).unwrap();
+            cursor.move_next();
+            cursor.insert_after_cursor(4).unwrap();
+            assert_eq!(cursor.insert_after_cursor(4), Err(OrderMaintenanceError::AlreadyPresent));
+            assert_eq!((cursor.peek_prev(), cursor.current(), cursor.peek_next()), (Some(&2), Some(&3), Some(&4)));
+        }
+        let values = |om: &OrderMaintenance<u32>| om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>();
+        assert_eq!(values(&om), [1, 2, 3, 4, 5]);
+        let mut cursor = om.cursor_back();
+        let mut backwards = Vec::new();
+        while let Some(&value) = cursor.current() {
+            assert_eq!(cursor.tag(), om.tag_of(&value));
+            backwards.push(value);
+            cursor.move_prev();
+        }
+        assert_eq!(backwards, [5, 4, 3, 2, 1]);
+        // round past the end
+        assert_eq!(cursor.peek_next(), Some(&1));
+        assert_eq!(cursor.peek_prev(), Some(&5));
+        assert!(om.cursor_at(&9).is_none());
+        {
+            let mut cursor = om.cursor_at_mut(&2).unwrap();
+            assert_eq!(cursor.remove_current(), Some(2));
+            assert_eq!(cursor.as_cursor().current(), Some(&3));
+            cursor.move_next();
+            cursor.move_next();
+            assert_eq!(cursor.remove_current(), Some(5));
+            assert_eq!(cursor.current(), None);
+            assert_eq!(cursor.remove_current(), None);
+        }
+        assert_eq!(values(&om), [1, 3, 4]);
+    }
+}
@@ -0,0 +1,223 @@
+// A top level for TwoLevelOrderMaintenance that never relabels in bulk.
+// Instead a sweep runs continuously over the buckets, front to back, giving
+// each one an evenly spaced label from a fresh generation; every update
+// advances it by a few buckets, and when it reaches the end a new one starts.
+// Buckets the sweep has passed always form a prefix of the order, so two
+// buckets compare by label if they're on the same side of it, and otherwise
+// the swept one comes first.
+//
+// A new bucket takes the label halfway between its neighbours'. That can
+// only halve a gap so many times before the sweep comes round and spreads
+// its labels out again, so the sweep has to come round in time: it finishes
+// within about SWEEP_PERIOD updates however many buckets there are, by taking
+// more of them per update, and in that time at most one update in
+// BUCKET_CAPACITY / 4 or so splits a bucket, far fewer halvings than a
+// 128-bit gap between a few billion buckets allows. If a gap ever did run out
+// anyway, the whole top level is relabeled on the spot, in O(buckets).
+//
+// So this is not worst-case O(1). Each update does O(1 + buckets /
+// SWEEP_PERIOD) top-level work, which is still linear in n: with at most
+// about 4n / BUCKET_CAPACITY buckets, that's a couple of steps plus one per
+// 8192 elements. Then relabeling, splitting or merging its own bucket is
+// O(BUCKET_CAPACITY). What it does buy is that, short of that fallback, no
+// one update pays for a cascade built up by the ones before it, as an
+// OrderMaintenance insert can: every update's share is small and fixed by
+// the length. And those bounds are on the relabeling only. The hash index and
+// the slot and bucket Vecs underneath still grow by doubling, so an insert
+// that makes one of them grow is O(n) (amortized O(1), as with any HashMap or
+// Vec).
+
+use core::cmp::Ordering;
+
+use alloc::vec::Vec;
+
+use super::two_level::{sealed, TopLevel, TwoLevelOrderMaintenance};
+use super::RandomState;
+
+// each sweep takes at most about this many updates
+const SWEEP_PERIOD: usize = 512;
+
+#[derive(Debug)]
+struct TopEntry {
+    label: u128,
+    // the sweep that gave it its label
+    generation: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct IncrementalTop {
+    // by bucket id
+    entries: Vec<Option<TopEntry>>,
+    front: Option<usize>,
+    len: usize,
+    generation: u64,
+    // the next bucket for the sweep; None once it has reached the end
+    cursor: Option<usize>,
+    next_label: u128,
+    step: u128,
+}
+
+// An order kept in buckets over an IncrementalTop: see above.
+pub type DeamortizedOrderMaintenance<T, S = RandomState> = TwoLevelOrderMaintenance<T, S, IncrementalTop>;
+
+// halfway between, where None means there's nothing above
+fn midpoint(lo: u128, hi: Option<u128>) -> Option<u128> {
+    let hi = hi.unwrap_or(u128::MAX);
+    if hi - lo >= 2 { Some(lo + (hi - lo) / 2) } else { None }
+}
+
+impl IncrementalTop {
+    fn entry(&self, bucket: usize) -> &TopEntry {
+        self.entries[bucket].as_ref().expect("bucket missing from the top level")
+    }
+    fn entry_mut(&mut self, bucket: usize) -> &mut TopEntry {
+        self.entries[bucket].as_mut().expect("bucket missing from the top level")
+    }
+    fn swept(&self, bucket: usize) -> bool {
+        self.entry(bucket).generation == self.generation
+    }
+    fn put(&mut self, bucket: usize, entry: TopEntry) {
+        if self.entries.len() <= bucket {
+            self.entries.resize_with(bucket + 1, || None);
+        }
+        self.entries[bucket] = Some(entry);
+        self.len += 1;
+    }
+    fn start_sweep(&mut self) {
+        self.generation += 1;
+        self.cursor = self.front;
+        // room for the sweep to label twice as many buckets as there are now,
+        // more than can be added while it runs
+        self.step = u128::MAX / (2 * self.len as u128 + 2);
+        self.next_label = self.step;
+    }
+    fn sweep_one(&mut self) {
+        let bucket = match self.cursor {
+            None => return self.start_sweep(),
+            Some(bucket) => bucket,
+        };
+        let (label, generation) = (self.next_label, self.generation);
+        let entry = self.entry_mut(bucket);
+        entry.label = label;
+        entry.generation = generation;
+        self.cursor = entry.next;
+        self.next_label = self.next_label.saturating_add(self.step);
+    }
+    // the fallback: one sweep, start to finish
+    fn relabel_all(&mut self) {
+        self.start_sweep();
+        while self.cursor.is_some() {
+            self.sweep_one();
+        }
+    }
+    // where a bucket going after `after` fits, if there's room
+    fn place_after(&self, after: usize) -> Option<(u128, u64)> {
+        let entry = self.entry(after);
+        if !self.swept(after) {
+            let hi = entry.next.map(|next| self.entry(next).label);
+            return midpoint(entry.label, hi).map(|label| (label, entry.generation));
+        }
+        let hi = match entry.next {
+            Some(next) if self.swept(next) => Some(self.entry(next).label),
+            // next in line for the sweep, which will label it next_label
+            Some(_) => Some(self.next_label),
+            None => None,
+        };
+        midpoint(entry.label, hi).map(|label| (label, self.generation))
+    }
+}
+
+impl sealed::Sealed for IncrementalTop {}
+impl TopLevel for IncrementalTop {
+    fn insert_only(&mut self, bucket: usize) {
+        self.put(bucket, TopEntry { label: 0, generation: self.generation, prev: None, next: None });
+        self.front = Some(bucket);
+        self.cursor = None;
+    }
+    fn insert_after(&mut self, after: usize, bucket: usize) {
+        let (label, generation) = match self.place_after(after) {
+            Some(place) => place,
+            None => {
+                self.relabel_all();
+                self.place_after(after).expect("no room after relabeling everything")
+            }
+        };
+        let next = self.entry(after).next;
+        self.put(bucket, TopEntry { label, generation, prev: Some(after), next });
+        self.entry_mut(after).next = Some(bucket);
+        if let Some(next) = next {
+            self.entry_mut(next).prev = Some(bucket);
+        }
+    }
+    fn remove(&mut self, bucket: usize) {
+        let entry = self.entries[bucket].take().expect("bucket missing from the top level");
+        self.len -= 1;
+        match entry.prev {
+            Some(prev) => self.entry_mut(prev).next = entry.next,
+            None => self.front = entry.next,
+        }
+        if let Some(next) = entry.next {
+            self.entry_mut(next).prev = entry.prev;
+        }
+        if self.cursor == Some(bucket) {
+            self.cursor = entry.next;
+        }
+    }
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        match (self.swept(a), self.swept(b)) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => self.entry(a).label.cmp(&self.entry(b).label),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    fn tick(&mut self) {
+        for _ in 0..2 + self.len / SWEEP_PERIOD {
+            self.sweep_one();
+        }
+    }
+    // re-bucketing everything at once is just the kind of pause to avoid
+    const RESCALES: bool = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn orders_while_sweeping() {
+        let mut om: DeamortizedOrderMaintenance<u32> = DeamortizedOrderMaintenance::new();
+        let mut model: Vec<u32> = Vec::new();
+        om.insert_only(0).unwrap();
+        model.push(0);
+        // always inserting after the same element splits the same spot in
+        // the top level over and over
+        for value in 1..20000 {
+            om.insert_after(&0, value).unwrap();
+            model.insert(1, value);
+            if value % 1000 == 0 {
+                // a sweep is probably halfway through by now
+                for pair in model.windows(2) {
+                    assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+                }
+            }
+        }
+        for value in 20000..21000 {
+            om.insert_before(&0, value).unwrap();
+            model.insert((value - 20000) as usize, value);
+        }
+        assert_eq!(om.iter().copied().collect::<Vec<_>>(), model);
+        for pair in model.windows(2) {
+            assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+        }
+        for value in model.drain(..) {
+            assert_eq!(om.remove(&value), Some(value));
+        }
+        assert!(om.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+// diff(): the ops that turn one order into another, as few moves as it
+// reasonably can. Whatever other doesn't have is removed; of what both have,
+// the longest run that's already in the right order (a longest increasing
+// subsequence of their positions in other) stays where it is; and then,
+// walking other front to back, everything else is moved (or inserted) just
+// after whatever other has before it. Each of those lands next to something
+// already in its final place, and nothing later lands between them, so the
+// result is other's order.
+
+use core::hash::{BuildHasher, Hash};
+use core::iter::repeat_n;
+
+use alloc::vec::Vec;
+
+use super::{OrderMaintenance, Op, TagWidth};
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // O((n + m) log(n + m)): self.apply(self.diff(other)) makes self hold
+    // what other does, in other's order (not with its tags).
+    pub fn diff<S2, W2>(&self, other: &OrderMaintenance<T, S2, W2>) -> Vec<Op<T>>
+        where T: Clone, S2: BuildHasher, W2: TagWidth {
+        let mut ops = Vec::new();
+        // other's positions, by slot
+        let mut positions: Vec<usize> = repeat_n(usize::MAX, other.slots.slots.len()).collect();
+        let mut at = other.front;
+        let mut position = 0;
+        while let Some(index) = at {
+            positions[index.0 as usize] = position;
+            position += 1;
+            let next = other.slots[index].next;
+            at = if Some(next) == other.front { None } else { Some(next) };
+        }
+        // what both have, in self's order, with positions in other
+        let mut common = Vec::new();
+        for (value, _) in self.iter_values_with_tags() {
+            match other.find::<T>(value).map(|index| positions[index.0 as usize]) {
+                Some(position) => common.push((value, position)),
+                None => ops.push(Op::Remove(value.clone())),
+            }
+        }
+        let stays = longest_increasing(&common);
+        let mut present = common.len();
+        let mut previous: Option<&T> = None;
+        let mut stays = stays.into_iter().peekable();
+        for (position, (value, _)) in other.iter_values_with_tags().enumerate() {
+            let kept = self.contains(value);
+            if kept && stays.peek() == Some(&position) {
+                stays.next();
+            } else {
+                let placed = match (kept, previous) {
+                    (true, Some(after)) => Op::MoveAfter { value: value.clone(), after: after.clone() },
+                    (true, None) => Op::MoveToFront(value.clone()),
+                    (false, Some(after)) => Op::InsertAfter { after: after.clone(), value: value.clone() },
+                    (false, None) if present == 0 => Op::InsertOnly(value.clone()),
+                    (false, None) => {
+                        // there's no insert_before, so: anywhere, then to the front
+                        let anchor = common[0].0.clone();
+                        ops.push(Op::InsertAfter { after: anchor, value: value.clone() });
+                        Op::MoveToFront(value.clone())
+                    }
+                };
+                ops.push(placed);
+                if !kept {
+                    present += 1;
+                }
+            }
+            previous = Some(value);
+        }
+        ops
+    }
+}
+
+// Positions (in sorted order) of a longest strictly increasing run of the
+// second halves, by patience sorting: O(n log n).
+fn longest_increasing<V>(items: &[(V, usize)]) -> Vec<usize> {
+    // tails[k]: index into items of the smallest last element of any run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut before: Vec<Option<usize>> = Vec::with_capacity(items.len());
+    for (i, &(_, position)) in items.iter().enumerate() {
+        let k = tails.partition_point(|&t| items[t].1 < position);
+        before.push(if k == 0 { None } else { Some(tails[k - 1]) });
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut run = Vec::with_capacity(tails.len());
+    let mut at = tails.last().copied();
+    while let Some(i) = at {
+        run.push(items[i].1);
+        at = before[i];
+    }
+    run.reverse();
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use test_random;
+
+    fn order(values: &[u32]) -> OrderMaintenance<u32> {
+        let mut om = OrderMaintenance::new();
+        for (i, &value) in values.iter().enumerate() {
+            match i {
+                0 => om.insert_only(value).unwrap(),
+                _ => om.insert_after(&values[i - 1], value).unwrap(),
+            }
+        }
+        om
+    }
+
+    #[test]
+    fn turns_one_into_the_other() {
+        let a = order(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        let b = order(&[9, 1, 2, 8, 4, 3, 5, 7]);
+        let ops = a.diff(&b);
+        // 0 and 6 go, 9 and 8 come, and only 3 of the rest has to move
+        assert_eq!(ops, [
+            Op::Remove(0),
+            Op::Remove(6),
+            Op::InsertAfter { after: 1, value: 9 },
+            Op::MoveToFront(9),
+            Op::InsertAfter { after: 2, value: 8 },
+            Op::MoveAfter { value: 3, after: 4 },
+        ]);
+        let mut patched = a.clone();
+        patched.apply(ops).unwrap();
+        assert_eq!(patched, b);
+        assert!(a.diff(&a).is_empty());
+        assert_eq!(order(&[]).diff(&order(&[1, 2])), [Op::InsertOnly(1), Op::InsertAfter { after: 1, value: 2 }]);
+
+        // and at random
+        let mut next = test_random(7);
+        for _ in 0..20 {
+            let mut x: Vec<u32> = (0..40).filter(|_| next(4) != 0).collect();
+            let mut y: Vec<u32> = (20..60).filter(|_| next(4) != 0).collect();
+            for v in [&mut x, &mut y] {
+                for i in (1..v.len()).rev() {
+                    v.swap(i, next(i + 1));
+                }
+            }
+            let (x, y) = (order(&x), order(&y));
+            let mut patched = x.clone();
+            patched.apply(x.diff(&y)).unwrap();
+            assert_eq!(patched, y);
+            patched.check_invariants().unwrap();
+        }
+    }
+}
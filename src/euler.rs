@@ -0,0 +1,294 @@
+// A rooted forest kept as its Euler tour: each node is a pair of brackets in
+// one order, opening before everything in its subtree and closing after it,
+// with a node's children in between, one after another. That makes "is a an
+// ancestor of b" two compares (a opens no later than b, and closes no
+// earlier), and a subtree the stretch of the tour between its node's
+// brackets, walked in O(k) for k nodes with no lookups. Trees follow each
+// other in the tour, so roots are in the order they were made.
+//
+// Nodes are given small ids, as MultiOrder's keys are, and the brackets in
+// the tour are id * 2 and id * 2 + 1.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+use core::ops::Bound;
+
+use alloc::vec::Vec;
+use hashbrown::HashTable;
+
+use super::{Index, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+fn open(id: u32) -> u64 {
+    (id as u64) << 1
+}
+fn close(id: u32) -> u64 {
+    open(id) | 1
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    key: T,
+    parent: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct EulerTourForest<T, S = RandomState>
+    where T: Hash + Eq {
+    nodes: Vec<Option<Node<T>>>,
+    free: Vec<u32>,
+    // ids, hashed by their keys
+    index: HashTable<u32>,
+    hasher: S,
+    tour: OrderMaintenance<u64>,
+}
+
+// A subtree in preorder, its root first.
+#[derive(Debug)]
+pub struct SubtreeIter<'a, T, S = RandomState>
+    where T: Hash + Eq + 'a, S: 'a {
+    forest: &'a EulerTourForest<T, S>,
+    next: Option<Index>,
+    // the root's closing bracket
+    end: Index,
+}
+impl<'a, T, S> Iterator for SubtreeIter<'a, T, S>
+    where T: Hash + Eq {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let tour = &self.forest.tour;
+        loop {
+            let index = self.next?;
+            self.next = if index == self.end { None } else { Some(tour.slots[index].next) };
+            let bracket = tour.slots[index].key;
+            if bracket & 1 == 0 {
+                return Some(&self.forest.node((bracket >> 1) as u32).key);
+            }
+        }
+    }
+}
+
+impl<T> EulerTourForest<T>
+    where T: Hash + Eq {
+    pub fn new() -> EulerTourForest<T> {
+        EulerTourForest::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for EulerTourForest<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> EulerTourForest<T, S> {
+        EulerTourForest::with_hasher(S::default())
+    }
+}
+
+// key access, which the iterator needs too, whatever the hasher
+impl<T, S> EulerTourForest<T, S>
+    where T: Hash + Eq {
+    fn node(&self, id: u32) -> &Node<T> {
+        self.nodes[id as usize].as_ref().expect("dangling node id")
+    }
+}
+
+impl<T, S> EulerTourForest<T, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> EulerTourForest<T, S> {
+        EulerTourForest { nodes: Vec::new(), free: Vec::new(), index: HashTable::new(), hasher, tour: OrderMaintenance::new() }
+    }
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    pub fn contains<Q>(&self, node: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(node).is_some()
+    }
+    pub fn parent<Q>(&self, node: &Q) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let parent = self.node(self.find(node)?).parent?;
+        Some(&self.node(parent).key)
+    }
+    // Every node counts as its own ancestor; false if either isn't there.
+    pub fn is_ancestor<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        match (self.find(a), self.find(b)) {
+            (Some(a), Some(b)) => self.encloses(a, b),
+            _ => false,
+        }
+    }
+    // preorder: parents before children, and siblings in order
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.tour.compare(&open(self.find(a)?), &open(self.find(b)?))
+    }
+    pub fn subtree<Q>(&self, node: &Q) -> Option<SubtreeIter<'_, T, S>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let id = self.find(node)?;
+        let start = self.tour.find(&open(id)).expect("nodes are in the tour");
+        let end = self.tour.find(&close(id)).expect("nodes are in the tour");
+        Some(SubtreeIter { forest: self, next: Some(start), end })
+    }
+    // a new tree, after all the others
+    pub fn add_root(&mut self, node: T) -> Result<(), OrderMaintenanceError> {
+        if self.find::<T>(&node).is_some() {
+            return Err(OrderMaintenanceError::AlreadyPresent);
+        }
+        let id = self.intern(node, None);
+        match self.tour.last() {
+            None => self.tour.insert_only(open(id))?,
+            Some(last) => {
+                self.tour.insert_after_index(last, open(id))?;
+            }
+        }
+        self.tour.insert_after(&open(id), close(id))
+    }
+    // as parent's last child
+    pub fn add_child<Q>(&mut self, parent: &Q, node: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if node.borrow() == parent {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        let parent = self.find(parent).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        if self.find::<T>(&node).is_some() {
+            return Err(OrderMaintenanceError::AlreadyPresent);
+        }
+        let id = self.intern(node, Some(parent));
+        let before = self.tour.find(&close(parent)).expect("nodes are in the tour");
+        let after = self.tour.slots[before].prev;
+        self.tour.insert_after_index(after, open(id))?;
+        self.tour.insert_after(&open(id), close(id))
+    }
+    // Moves node's whole subtree to be new_parent's last child, in O(k).
+    // Moving a node under itself (or anything below it) is SelfInsert, as
+    // inserting an element after itself is.
+    pub fn move_subtree<Q>(&mut self, node: &Q, new_parent: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let id = self.find(node).ok_or(OrderMaintenanceError::NotPresent)?;
+        let parent = self.find(new_parent).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        if self.encloses(id, parent) {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        for bracket in self.brackets(id) {
+            self.tour.move_before(&bracket, &close(parent)).expect("nodes are in the tour");
+        }
+        self.nodes[id as usize].as_mut().expect("dangling node id").parent = Some(parent);
+        Ok(())
+    }
+    // Cuts node's subtree off from its parent, to be a tree of its own after
+    // all the others. A root just moves to the back.
+    pub fn make_root<Q>(&mut self, node: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let id = self.find(node).ok_or(OrderMaintenanceError::NotPresent)?;
+        for bracket in self.brackets(id) {
+            self.tour.move_to_back(&bracket).expect("nodes are in the tour");
+        }
+        self.nodes[id as usize].as_mut().expect("dangling node id").parent = None;
+        Ok(())
+    }
+    // node and everything under it, handed back in preorder
+    pub fn remove_subtree<Q>(&mut self, node: &Q) -> Option<Vec<T>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let id = self.find(node)?;
+        let ids: Vec<u32> = self.brackets(id).into_iter().filter(|&bracket| bracket & 1 == 0).map(|bracket| (bracket >> 1) as u32).collect();
+        self.tour.remove_range(Bound::Included(&open(id)), Bound::Included(&close(id))).expect("nodes are in the tour");
+        Some(ids.into_iter().map(|id| self.release(id)).collect())
+    }
+
+    // a encloses b: a is b or one of its ancestors
+    fn encloses(&self, a: u32, b: u32) -> bool {
+        self.tour.compare(&open(a), &open(b)) != Some(Ordering::Greater) && self.tour.compare(&close(b), &close(a)) != Some(Ordering::Greater)
+    }
+    // the brackets of id's subtree, in tour order
+    fn brackets(&self, id: u32) -> Vec<u64> {
+        let mut index = self.tour.find(&open(id)).expect("nodes are in the tour");
+        let mut brackets = Vec::from([open(id)]);
+        while brackets.last() != Some(&close(id)) {
+            index = self.tour.slots[index].next;
+            brackets.push(self.tour.slots[index].key);
+        }
+        brackets
+    }
+    fn find<Q>(&self, key: &Q) -> Option<u32>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let hash = self.hasher.hash_one(key);
+        let nodes = &self.nodes;
+        self.index.find(hash, |&id| nodes[id as usize].as_ref().is_some_and(|node| node.key.borrow() == key)).copied()
+    }
+    // a new id for key, which mustn't be there already
+    fn intern(&mut self, key: T, parent: Option<u32>) -> u32 {
+        let node = Some(Node { key, parent });
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                assert!(self.nodes.len() < u32::MAX as usize, "too many nodes");
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            }
+        };
+        let nodes = &self.nodes;
+        let hasher = &self.hasher;
+        let hash = hasher.hash_one(&nodes[id as usize].as_ref().unwrap().key);
+        self.index.insert_unique(hash, id, |&i| hasher.hash_one(&nodes[i as usize].as_ref().unwrap().key));
+        id
+    }
+    // forgets id, whose brackets must be out of the tour by now
+    fn release(&mut self, id: u32) -> T {
+        let hash = self.hasher.hash_one(&self.node(id).key);
+        if let Ok(entry) = self.index.find_entry(hash, |&i| i == id) {
+            entry.remove();
+        }
+        self.free.push(id);
+        self.nodes[id as usize].take().expect("dangling node id").key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn ancestors_and_subtrees() {
+        //     1        7
+        //   2   5      8
+        //  3 4  6
+        let mut forest: EulerTourForest<u32> = EulerTourForest::new();
+        forest.add_root(1).unwrap();
+        for &(parent, child) in &[(1, 2), (2, 3), (2, 4), (1, 5), (5, 6)] {
+            forest.add_child(&parent, child).unwrap();
+        }
+        forest.add_root(7).unwrap();
+        forest.add_child(&7, 8).unwrap();
+        let subtree = |forest: &EulerTourForest<u32>, node| forest.subtree(&node).unwrap().copied().collect::<Vec<_>>();
+        assert_eq!(subtree(&forest, 1), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(subtree(&forest, 2), [2, 3, 4]);
+        assert_eq!(subtree(&forest, 8), [8]);
+        assert!(forest.is_ancestor(&1, &4) && forest.is_ancestor(&5, &5));
+        assert!(!forest.is_ancestor(&2, &6) && !forest.is_ancestor(&4, &2) && !forest.is_ancestor(&1, &8));
+        assert_eq!(forest.compare(&4, &5), Some(Ordering::Less));
+        assert_eq!(forest.parent(&4), Some(&2));
+        assert_eq!(forest.parent(&7), None);
+        assert_eq!(forest.add_child(&9, 10), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(forest.add_root(3), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(forest.move_subtree(&2, &3), Err(OrderMaintenanceError::SelfInsert));
+        forest.move_subtree(&2, &8).unwrap();
+        assert_eq!(subtree(&forest, 7), [7, 8, 2, 3, 4]);
+        assert_eq!(subtree(&forest, 1), [1, 5, 6]);
+        assert!(forest.is_ancestor(&7, &3) && !forest.is_ancestor(&1, &3));
+        assert_eq!(forest.parent(&2), Some(&8));
+        forest.make_root(&5).unwrap();
+        assert_eq!(forest.parent(&5), None);
+        assert!(forest.compare(&7, &5) == Some(Ordering::Less) && !forest.is_ancestor(&1, &6));
+        assert_eq!(forest.remove_subtree(&8), Some(Vec::from([8, 2, 3, 4])));
+        assert_eq!(forest.remove_subtree(&8), None);
+        assert_eq!(forest.len(), 4);
+        forest.add_child(&7, 2).unwrap();
+        assert_eq!(subtree(&forest, 7), [7, 2]);
+        forest.tour.check_invariants().unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+// A C ABI for the order: extern "C" functions over an opaque OmOrder, laid
+// out so that cbindgen can write the header. Build a staticlib or cdylib
+// crate that depends on this one with the feature on to get a library to
+// link against.
+//
+// Keys are OmKeys: a byte string if bytes isn't null (len bytes of it,
+// copied in, so the caller's buffer can go once the call returns), and
+// otherwise the integer. The two kinds never equal each other.
+//
+// Everything that can fail returns an OmStatus, Ok or what went wrong,
+// rather than panicking across the boundary: a null pointer is NullPointer,
+// the order's own errors have a status each, and a panic inside (which
+// would be a bug here) is caught and becomes Panicked. Handles must come from
+// om_new, and not be used after om_free or from two threads at once; key
+// bytes and out-pointers must be valid for the lengths given. That's the
+// safety contract for every function here, hence no per-function sections.
+#![allow(clippy::missing_safety_doc)]
+
+use core::cmp::Ordering;
+use core::ffi::{c_char, c_int, c_void};
+use core::ptr;
+use core::slice;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use std::panic::{self, AssertUnwindSafe};
+
+use super::{OrderMaintenance, OrderMaintenanceError};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Bytes(Vec<u8>),
+}
+
+// A key, as passed in and out.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct OmKey {
+    // null for an integer key
+    pub bytes: *const u8,
+    pub len: usize,
+    pub integer: i64,
+}
+impl OmKey {
+    unsafe fn to_key(self) -> Key {
+        if self.bytes.is_null() {
+            Key::Int(self.integer)
+        } else {
+            Key::Bytes(slice::from_raw_parts(self.bytes, self.len).to_vec())
+        }
+    }
+    // borrows key's bytes, for as long as key is left alone
+    fn from_key(key: &Key) -> OmKey {
+        match *key {
+            Key::Int(integer) => OmKey { bytes: ptr::null(), len: 0, integer },
+            Key::Bytes(ref bytes) => OmKey { bytes: bytes.as_ptr(), len: bytes.len(), integer: 0 },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OmStatus {
+    Ok = 0,
+    NotPresent,
+    AnchorMissing,
+    AlreadyPresent,
+    SelfInsert,
+    InvalidThreshold,
+    NotEmpty,
+    NotAdjacent,
+    Full,
+    NullPointer,
+    Panicked,
+}
+impl From<OrderMaintenanceError> for OmStatus {
+    fn from(error: OrderMaintenanceError) -> OmStatus {
+        match error {
+            OrderMaintenanceError::NotPresent => OmStatus::NotPresent,
+            OrderMaintenanceError::AnchorMissing => OmStatus::AnchorMissing,
+            OrderMaintenanceError::AlreadyPresent => OmStatus::AlreadyPresent,
+            OrderMaintenanceError::SelfInsert => OmStatus::SelfInsert,
+            OrderMaintenanceError::InvalidThreshold => OmStatus::InvalidThreshold,
+            OrderMaintenanceError::NotEmpty => OmStatus::NotEmpty,
+            OrderMaintenanceError::NotAdjacent => OmStatus::NotAdjacent,
+            OrderMaintenanceError::Full => OmStatus::Full,
+        }
+    }
+}
+
+// The order behind the handle; opaque to C.
+#[derive(Debug)]
+pub struct OmOrder {
+    om: OrderMaintenance<Key>,
+}
+
+// f on om, unless it's null, with any panic caught
+unsafe fn with_order<F>(om: *mut OmOrder, f: F) -> OmStatus
+    where F: FnOnce(&mut OrderMaintenance<Key>) -> Result<(), OrderMaintenanceError> {
+    let om = match om.as_mut() {
+        None => return OmStatus::NullPointer,
+        Some(om) => &mut om.om,
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(om))) {
+        Ok(Ok(())) => OmStatus::Ok,
+        Ok(Err(error)) => OmStatus::from(error),
+        Err(_) => OmStatus::Panicked,
+    }
+}
+
+// Null only if allocating failed, which aborts anyway.
+#[no_mangle]
+pub extern "C" fn om_new() -> *mut OmOrder {
+    Box::into_raw(Box::new(OmOrder { om: OrderMaintenance::new() }))
+}
+// Null is fine, and does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn om_free(om: *mut OmOrder) {
+    if !om.is_null() {
+        drop(Box::from_raw(om));
+    }
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_len(om: *mut OmOrder, len: *mut usize) -> OmStatus {
+    if len.is_null() {
+        return OmStatus::NullPointer;
+    }
+    with_order(om, |om| {
+        *len = om.len();
+        Ok(())
+    })
+}
+// Ok if it's there, NotPresent if not.
+#[no_mangle]
+pub unsafe extern "C" fn om_contains(om: *mut OmOrder, key: OmKey) -> OmStatus {
+    with_order(om, |om| if om.contains(&key.to_key()) { Ok(()) } else { Err(OrderMaintenanceError::NotPresent) })
+}
+// -1, 0 or 1 into ordering.
+#[no_mangle]
+pub unsafe extern "C" fn om_compare(om: *mut OmOrder, a: OmKey, b: OmKey, ordering: *mut c_int) -> OmStatus {
+    if ordering.is_null() {
+        return OmStatus::NullPointer;
+    }
+    with_order(om, |om| {
+        let compared = om.compare(&a.to_key(), &b.to_key()).ok_or(OrderMaintenanceError::NotPresent)?;
+        *ordering = match compared {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        };
+        Ok(())
+    })
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_insert_only(om: *mut OmOrder, key: OmKey) -> OmStatus {
+    with_order(om, |om| om.insert_only(key.to_key()))
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_insert_after(om: *mut OmOrder, after: OmKey, key: OmKey) -> OmStatus {
+    with_order(om, |om| om.insert_after(&after.to_key(), key.to_key()))
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_insert_before(om: *mut OmOrder, before: OmKey, key: OmKey) -> OmStatus {
+    with_order(om, |om| om.insert_all_before(&before.to_key(), Some(key.to_key())))
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_remove(om: *mut OmOrder, key: OmKey) -> OmStatus {
+    with_order(om, |om| om.remove(&key.to_key()).map(|_| ()).ok_or(OrderMaintenanceError::NotPresent))
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_move_after(om: *mut OmOrder, key: OmKey, after: OmKey) -> OmStatus {
+    with_order(om, |om| om.move_after(&key.to_key(), &after.to_key()))
+}
+#[no_mangle]
+pub unsafe extern "C" fn om_move_before(om: *mut OmOrder, key: OmKey, before: OmKey) -> OmStatus {
+    with_order(om, |om| om.move_before(&key.to_key(), &before.to_key()))
+}
+// Calls visit with each key, front to back, and context, for as long as it
+// returns nonzero. The keys' bytes are only good until visit returns, and
+// visit mustn't change the order.
+#[no_mangle]
+pub unsafe extern "C" fn om_for_each(om: *mut OmOrder, visit: Option<extern "C" fn(OmKey, *mut c_void) -> c_int>, context: *mut c_void) -> OmStatus {
+    let visit = match visit {
+        None => return OmStatus::NullPointer,
+        Some(visit) => visit,
+    };
+    with_order(om, |om| {
+        for (key, _) in om.iter_values_with_tags() {
+            if visit(OmKey::from_key(key), context) == 0 {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+// A static, NUL-terminated description of status.
+#[no_mangle]
+pub extern "C" fn om_status_message(status: OmStatus) -> *const c_char {
+    let message: &'static [u8] = match status {
+        OmStatus::Ok => b"ok\0",
+        OmStatus::NotPresent => b"element is not present\0",
+        OmStatus::AnchorMissing => b"anchor element is not present\0",
+        OmStatus::AlreadyPresent => b"element is already present\0",
+        OmStatus::SelfInsert => b"cannot place an element relative to itself\0",
+        OmStatus::InvalidThreshold => b"overflow threshold must be more than 1 and at most 2\0",
+        OmStatus::NotEmpty => b"order is not empty\0",
+        OmStatus::NotAdjacent => b"elements are not adjacent\0",
+        OmStatus::Full => b"order is full\0",
+        OmStatus::NullPointer => b"null pointer\0",
+        OmStatus::Panicked => b"panicked\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ffi::CStr;
+    use std::vec::Vec;
+
+    fn int(integer: i64) -> OmKey {
+        OmKey { bytes: ptr::null(), len: 0, integer }
+    }
+    fn bytes(bytes: &[u8]) -> OmKey {
+        OmKey { bytes: bytes.as_ptr(), len: bytes.len(), integer: 0 }
+    }
+    extern "C" fn collect(key: OmKey, context: *mut c_void) -> c_int {
+        let keys = unsafe { &mut *(context as *mut Vec<Key>) };
+        keys.push(unsafe { key.to_key() });
+        1
+    }
+
+    #[test]
+    fn round_trips_through_c() {
+        unsafe {
+            let om = om_new();
+            assert_eq!(om_insert_only(om, int(1)), OmStatus::Ok);
+            assert_eq!(om_insert_after(om, int(1), bytes(b"one")), OmStatus::Ok);
+            assert_eq!(om_insert_before(om, int(1), int(0)), OmStatus::Ok);
+            assert_eq!(om_insert_after(om, int(7), int(2)), OmStatus::AnchorMissing);
+            assert_eq!(om_insert_only(om, int(2)), OmStatus::NotEmpty);
+            // the string "1" isn't the integer 1
+            assert_eq!(om_contains(om, bytes(b"1")), OmStatus::NotPresent);
+            assert_eq!(om_move_before(om, bytes(b"one"), int(0)), OmStatus::Ok);
+            let mut ordering = 0;
+            assert_eq!(om_compare(om, int(1), bytes(b"one"), &mut ordering), OmStatus::Ok);
+            assert_eq!(ordering, 1);
+            assert_eq!(om_compare(om, int(1), int(5), &mut ordering), OmStatus::NotPresent);
+            let mut keys: Vec<Key> = Vec::new();
+            assert_eq!(om_for_each(om, Some(collect), &mut keys as *mut Vec<Key> as *mut c_void), OmStatus::Ok);
+            assert_eq!(keys, [Key::Bytes(b"one".to_vec()), Key::Int(0), Key::Int(1)]);
+            assert_eq!(om_remove(om, int(0)), OmStatus::Ok);
+            assert_eq!(om_remove(om, int(0)), OmStatus::NotPresent);
+            let mut len = 0;
+            assert_eq!(om_len(om, &mut len), OmStatus::Ok);
+            assert_eq!(len, 2);
+            assert_eq!(om_len(ptr::null_mut(), &mut len), OmStatus::NullPointer);
+            assert_eq!(CStr::from_ptr(om_status_message(OmStatus::SelfInsert)).to_str(), Ok("cannot place an element relative to itself"));
+            om_free(om);
+            om_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,428 @@
+// A heapless take on OrderMaintenance, for when there's no allocator or it
+// mustn't be touched after startup: room for at most N elements, all stored
+// inline, and inserting into a full order fails with
+// OrderMaintenanceError::Full instead of growing anything.
+//
+// Keys are found through N hash buckets whose chains are threaded through
+// the slots themselves, so lookups stay O(1) expected without a separate
+// table. The relabeling is OrderMaintenance's, minus the extras (rotation,
+// handles, listeners, stats), except that since N bounds the length, its
+// parameters are worked out from N at compile time instead of from the
+// current length on every rebalance: new elements are spaced GAP apart, so
+// filling the order by appending never relabels, and a rebalance widens its
+// window by the fixed MULTIPLIER.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use super::{OrderMaintenanceError, RandomState, RawTag, Tag};
+
+// (2n)^(1/62), which OrderMaintenance gets from powf; bisection is enough
+// for a const
+const fn root62(n: usize) -> f64 {
+    let target = 2.0 * n as f64;
+    let mut lo: f64 = 1.0;
+    let mut hi: f64 = 4.0;
+    let mut step = 0;
+    while step < 64 {
+        let mid = (lo + hi) / 2.0;
+        let mut power = 1.0;
+        let mut i = 0;
+        while i < 62 {
+            power *= mid;
+            i += 1;
+        }
+        if power > target { hi = mid } else { lo = mid }
+        step += 1;
+    }
+    lo
+}
+
+#[derive(Debug)]
+struct FixedPosition<T> {
+    key: T,
+    prev: usize,
+    next: usize,
+    tag: RawTag,
+    // the next slot in the same hash bucket
+    chain: Option<usize>,
+}
+
+// Vacant slots make up the free list: each holds the next vacant one.
+#[derive(Debug)]
+enum FixedSlot<T> {
+    Occupied(FixedPosition<T>),
+    Vacant(Option<usize>),
+}
+
+#[derive(Debug)]
+pub struct FixedOrderMaintenance<T, const N: usize, S = RandomState>
+    where T: Hash + Eq {
+    slots: [FixedSlot<T>; N],
+    // the first slot of each hash chain
+    buckets: [Option<usize>; N],
+    free: Option<usize>,
+    len: usize,
+    front: Option<usize>,
+    hasher: S,
+}
+
+#[derive(Debug)]
+pub struct FixedIter<'a, T, const N: usize, S = RandomState>
+    where T: Hash + Eq + 'a, S: 'a {
+    om: &'a FixedOrderMaintenance<T, N, S>,
+    current: Option<usize>,
+}
+impl<'a, T, const N: usize, S> Iterator for FixedIter<'a, T, N, S>
+    where T: Hash + Eq {
+    type Item = (&'a T, Tag);
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.current?;
+        let position = self.om.position(current);
+        if Some(position.next) != self.om.front {
+            self.current = Some(position.next);
+        } else {
+            self.current = None;
+        }
+        Some((&position.key, Tag(position.tag)))
+    }
+}
+
+// slot access, which the iterator needs too, whatever the hasher
+impl<T, const N: usize, S> FixedOrderMaintenance<T, N, S>
+    where T: Hash + Eq {
+    // room left between an element and the one appended (or prepended) next
+    // to it at the ends: N of them fit in the tag space
+    pub const GAP: RawTag = RawTag::MAX / (N as RawTag + 1);
+    // how much sparser each doubling of a rebalance window has to be
+    const MULTIPLIER: f64 = 2.0 / root62(if N == 0 { 1 } else { N });
+    fn position(&self, index: usize) -> &FixedPosition<T> {
+        match self.slots[index] {
+            FixedSlot::Occupied(ref position) => position,
+            FixedSlot::Vacant(_) => panic!("slot {} is vacant", index),
+        }
+    }
+    fn position_mut(&mut self, index: usize) -> &mut FixedPosition<T> {
+        match self.slots[index] {
+            FixedSlot::Occupied(ref mut position) => position,
+            FixedSlot::Vacant(_) => panic!("slot {} is vacant", index),
+        }
+    }
+}
+
+impl<T, const N: usize> FixedOrderMaintenance<T, N>
+    where T: Hash + Eq {
+    pub fn new() -> FixedOrderMaintenance<T, N> {
+        FixedOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, const N: usize, S> Default for FixedOrderMaintenance<T, N, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> FixedOrderMaintenance<T, N, S> {
+        FixedOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+impl<T, const N: usize, S> FixedOrderMaintenance<T, N, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> FixedOrderMaintenance<T, N, S> {
+        FixedOrderMaintenance {
+            slots: ::core::array::from_fn(|i| FixedSlot::Vacant(if i + 1 < N { Some(i + 1) } else { None })),
+            buckets: [None; N],
+            free: if N > 0 { Some(0) } else { None },
+            len: 0,
+            front: None,
+            hasher,
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        N
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+    pub fn iter_values_with_tags(&self) -> FixedIter<'_, T, N, S> {
+        FixedIter { om: self, current: self.front }
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).is_some()
+    }
+    pub fn tag_of<Q>(&self, value: &Q) -> Option<Tag>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).map(|index| Tag(self.position(index).tag))
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.find(a)?;
+        let b = self.find(b)?;
+        Some(self.position(a).tag.cmp(&self.position(b).tag))
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        if !self.is_empty() {
+            return Err(OrderMaintenanceError::NotEmpty);
+        }
+        let index = self.allocate(value, Self::GAP)?;
+        self.front = Some(index);
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.find_anchor(after, &value)?;
+        self.insert_after_index(after, value)
+    }
+    fn insert_after_index(&mut self, after: usize, value: T) -> Result<(), OrderMaintenanceError> {
+        let prev_tag = self.position(after).tag;
+        let next = self.position(after).next;
+        // halfway to the next element, or GAP past the last one
+        let tag = if Some(next) == self.front {
+            prev_tag.saturating_add(Self::GAP)
+        } else {
+            prev_tag + (self.position(next).tag - prev_tag) / 2
+        };
+        let index = self.allocate(value, tag)?;
+        self.link(index, after, next);
+        if tag == prev_tag {
+            self.rebalance(index);
+        }
+        Ok(())
+    }
+    pub fn insert_before<Q>(&mut self, before: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let before_index = self.find_anchor(before, &value)?;
+        if self.front != Some(before_index) {
+            let prev = self.position(before_index).prev;
+            return self.insert_after_index(prev, value);
+        }
+        // becoming the new front: there's no prev to make room after
+        let next_tag = self.position(before_index).tag;
+        let tag = next_tag.saturating_sub(Self::GAP);
+        let index = self.allocate(value, tag)?;
+        let last = self.position(before_index).prev;
+        self.link(index, last, before_index);
+        self.front = Some(index);
+        if tag == next_tag {
+            self.rebalance(index);
+        }
+        Ok(())
+    }
+    // Any element can be removed; its slot goes straight back on the free list.
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        self.unchain(index);
+        let position = match ::core::mem::replace(&mut self.slots[index], FixedSlot::Vacant(self.free)) {
+            FixedSlot::Occupied(position) => position,
+            FixedSlot::Vacant(_) => unreachable!("found a vacant slot"),
+        };
+        self.free = Some(index);
+        self.len -= 1;
+        if self.is_empty() {
+            self.front = None;
+        } else {
+            self.position_mut(position.prev).next = position.next;
+            self.position_mut(position.next).prev = position.prev;
+            if self.front == Some(index) {
+                self.front = Some(position.next);
+            }
+        }
+        Some(position.key)
+    }
+    fn bucket<Q>(&self, value: &Q) -> usize
+        where Q: Hash + ?Sized {
+        (self.hasher.hash_one(value) % N as u64) as usize
+    }
+    fn find<Q>(&self, value: &Q) -> Option<usize>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if self.is_empty() {
+            return None;
+        }
+        let mut current = self.buckets[self.bucket(value)];
+        while let Some(index) = current {
+            let position = self.position(index);
+            if position.key.borrow() == value {
+                return Some(index);
+            }
+            current = position.chain;
+        }
+        None
+    }
+    fn find_anchor<Q>(&self, anchor: &Q, value: &T) -> Result<usize, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if value.borrow() == anchor {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        self.find(anchor).ok_or(OrderMaintenanceError::AnchorMissing)
+    }
+    // A slot for key, linked only to itself and findable by its key; Full if
+    // there are no slots left.
+    fn allocate(&mut self, key: T, tag: RawTag) -> Result<usize, OrderMaintenanceError> {
+        if self.find(&key).is_some() {
+            return Err(OrderMaintenanceError::AlreadyPresent);
+        }
+        let index = self.free.ok_or(OrderMaintenanceError::Full)?;
+        let bucket = self.bucket(&key);
+        let position = FixedPosition { key, prev: index, next: index, tag, chain: self.buckets[bucket] };
+        self.free = match ::core::mem::replace(&mut self.slots[index], FixedSlot::Occupied(position)) {
+            FixedSlot::Vacant(next_free) => next_free,
+            FixedSlot::Occupied(_) => unreachable!("free list slot is occupied"),
+        };
+        self.buckets[bucket] = Some(index);
+        self.len += 1;
+        Ok(index)
+    }
+    // takes the slot out of its hash chain
+    fn unchain(&mut self, index: usize) {
+        let bucket = self.bucket(&self.position(index).key);
+        let chain = self.position(index).chain;
+        if self.buckets[bucket] == Some(index) {
+            self.buckets[bucket] = chain;
+            return;
+        }
+        let mut current = self.buckets[bucket];
+        while let Some(other) = current {
+            if self.position(other).chain == Some(index) {
+                self.position_mut(other).chain = chain;
+                return;
+            }
+            current = self.position(other).chain;
+        }
+    }
+    fn link(&mut self, index: usize, prev: usize, next: usize) {
+        {
+            let position = self.position_mut(index);
+            position.prev = prev;
+            position.next = next;
+        }
+        self.position_mut(prev).next = index;
+        self.position_mut(next).prev = index;
+    }
+    // see OrderMaintenance::rebalance
+    fn rebalance(&mut self, index: usize) {
+        let front = match self.front { None => return, Some(a) => a };
+        let mut base_tag: RawTag = self.position(index).tag;
+        let mut mask: RawTag = 0;
+        let mut threshold: f64 = 1.0;
+        let mut first = index;
+        let mut last = index;
+        let mut num_items: usize = 1;
+        loop {
+            loop {
+                let prev = self.position(first).prev;
+                if first != front && self.position(prev).tag & !mask == base_tag {
+                    first = prev;
+                    num_items += 1;
+                } else {
+                    break;
+                }
+            }
+            loop {
+                let next = self.position(last).next;
+                if next != front && self.position(next).tag & !mask == base_tag {
+                    last = next;
+                    num_items += 1;
+                } else {
+                    break;
+                }
+            }
+            let increment = (mask + 1) / (num_items as RawTag);
+            if (increment as f64) >= threshold {
+                let mut item = first;
+                let mut new_tag = base_tag;
+                loop {
+                    let position = self.position_mut(item);
+                    position.tag = new_tag;
+                    if item == last {
+                        return;
+                    }
+                    new_tag += increment;
+                    item = position.next;
+                }
+            }
+            mask = (mask << 1) + 1;
+            base_tag &= !mask;
+            threshold *= Self::MULTIPLIER;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn order<const N: usize>(om: &FixedOrderMaintenance<u32, N>) -> Vec<u32> {
+        om.iter_values_with_tags().map(|(value, _)| *value).collect()
+    }
+
+    #[test]
+    fn fills_up() {
+        let mut om: FixedOrderMaintenance<u32, 4> = FixedOrderMaintenance::new();
+        om.insert_only(1).unwrap();
+        om.insert_after(&1, 3).unwrap();
+        om.insert_before(&3, 2).unwrap();
+        om.insert_before(&1, 0).unwrap();
+        assert!(om.is_full());
+        assert_eq!(om.insert_after(&3, 4), Err(OrderMaintenanceError::Full));
+        assert_eq!(om.insert_after(&3, 2), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(order(&om), vec![0, 1, 2, 3]);
+        assert_eq!(om.remove(&1), Some(1));
+        assert_eq!(om.remove(&1), None);
+        om.insert_after(&3, 4).unwrap();
+        assert_eq!(order(&om), vec![0, 2, 3, 4]);
+        assert_eq!(om.compare(&4, &0), Some(Ordering::Greater));
+
+        let mut empty: FixedOrderMaintenance<u32, 0> = FixedOrderMaintenance::new();
+        assert_eq!(empty.insert_only(0), Err(OrderMaintenanceError::Full));
+    }
+
+    #[test]
+    fn relabels_in_place() {
+        let mut om: FixedOrderMaintenance<u32, 300> = FixedOrderMaintenance::new();
+        let mut expected = vec![0];
+        om.insert_only(0).unwrap();
+        // always inserting right after the front forces lots of rebalancing
+        for i in 1..200 {
+            om.insert_after(&0, i).unwrap();
+            expected.insert(1, i);
+        }
+        for i in 200..300 {
+            om.insert_before(&0, i).unwrap();
+            expected.insert(i as usize - 200, i);
+        }
+        assert_eq!(order(&om), expected);
+        let tags: Vec<Tag> = om.iter_values_with_tags().map(|(_, tag)| tag).collect();
+        assert!(tags.windows(2).all(|pair| pair[0] < pair[1]));
+        for i in (0..300).step_by(3) {
+            assert_eq!(om.remove(&i), Some(i));
+        }
+        expected.retain(|i| i % 3 != 0);
+        assert_eq!(order(&om), expected);
+        assert_eq!(om.len(), 200);
+    }
+
+    #[test]
+    fn sized_by_capacity() {
+        let expected = 2.0 / (2.0f64 * 300.0).powf(1.0 / 62.0);
+        assert!((FixedOrderMaintenance::<u32, 300>::MULTIPLIER - expected).abs() < 1e-12);
+        // appending up to capacity lands every element exactly GAP apart
+        let mut om: FixedOrderMaintenance<u32, 300> = FixedOrderMaintenance::new();
+        let gap = FixedOrderMaintenance::<u32, 300>::GAP;
+        om.insert_only(0).unwrap();
+        for i in 1..300 {
+            om.insert_after(&(i - 1), i).unwrap();
+        }
+        for (i, (_, tag)) in om.iter_values_with_tags().enumerate() {
+            assert_eq!(tag, Tag((i as RawTag + 1) * gap));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Labels as doubles, for consumers (JSON, postMessage, a JS sort) that only
+// have f64 to sort by: a tag over the size of the tag space, so in [0, 1].
+// That's exact for the top 53 bits of a tag and no further, so two
+// neighbours whose tags only differ below that get the same double even
+// though their tags differ. keep_f64_labels_distinct is for after an
+// insert or a move: if value can't be told apart from a neighbour, it
+// relabels the smallest run around it (doubling each time) that, spread out
+// evenly, can, and that goes out through on_relabel and the relabel
+// listener like any other rebalance, so whoever's holding onto the old
+// doubles hears about it.
+
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+
+use super::{Index, OrderMaintenance, OrderMaintenanceError, Tag, TagWidth};
+
+fn f64_label<W>(tag: W) -> f64
+    where W: TagWidth {
+    // 2^-BITS, built by hand since powi is std-only
+    tag.to_f64() * f64::from_bits((1023 - W::BITS as u64) << 52)
+}
+
+impl<W> Tag<W>
+    where W: TagWidth {
+    pub fn f64_label(self) -> f64 {
+        f64_label(self.0)
+    }
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn f64_label<Q>(&self, value: &Q) -> Option<f64>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.tag_of(value).map(Tag::f64_label)
+    }
+    // Whether it had to relabel. Doubles run out after about 2^52 elements,
+    // well before u64 tags do; past that, the best it can do is spread
+    // everything out evenly, and some neighbours will still share a label.
+    pub fn keep_f64_labels_distinct<Q>(&mut self, value: &Q) -> Result<bool, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        let front = self.front.expect("value is in it");
+        let last = self.last().expect("value is in it");
+        let bounds = |om: &OrderMaintenance<T, S, W>, first: Index, end: Index| {
+            let lower = if first == front { W::ZERO } else { om.tag(om.slots[first].prev) };
+            let upper = if end == last { W::MAX } else { om.tag(om.slots[end].next) };
+            (lower, upper)
+        };
+        let tag = self.tag(index);
+        let (lower, upper) = bounds(self, index, index);
+        let distinct = |low: W, high: W| f64_label(low) < f64_label(high);
+        let has_lower = index != front;
+        let has_upper = index != last;
+        if (!has_lower || distinct(lower, tag)) && (!has_upper || distinct(tag, upper)) {
+            return Ok(false);
+        }
+        let len = self.len();
+        let (mut first, mut end, mut count) = (index, index, 1);
+        loop {
+            // about doubling it, half on either side
+            let grow = count;
+            for _ in 0..grow {
+                if first != front {
+                    first = self.slots[first].prev;
+                    count += 1;
+                }
+                if end != last {
+                    end = self.slots[end].next;
+                    count += 1;
+                }
+            }
+            let (lower, upper) = bounds(self, first, end);
+            let increment = (upper - lower) / W::from_usize(count + 1);
+            // the run's labels all distinct, and from the neighbours either
+            // side of it, where there are any
+            let mut fits = increment != W::ZERO;
+            let mut previous = if first != front { Some(lower) } else { None };
+            let mut tag = lower;
+            for _ in 0..count {
+                tag = tag + increment;
+                fits = fits && previous.is_none_or(|previous| distinct(previous, tag));
+                previous = Some(tag);
+            }
+            fits = fits && (end == last || distinct(tag, upper));
+            if fits || count == len {
+                self.relabel_run(first, count, lower + increment, increment);
+                self.verify_valid_structure();
+                return Ok(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::vec::Vec;
+
+    #[test]
+    fn doubles_stay_distinct() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        let heard = Arc::new(Mutex::new(0));
+        let counter = heard.clone();
+        om.set_on_relabel(move |_, _| *counter.lock().unwrap() += 1);
+        om.insert_only(0).unwrap();
+        om.insert_after(&0, 1).unwrap();
+        assert_eq!(om.f64_label(&0), om.tag_of(&0).map(Tag::f64_label));
+        assert!(om.f64_label(&0).unwrap() < om.f64_label(&1).unwrap());
+        // each one halves the gap after 0, so tags run out of doubles long
+        // before they run out of bits
+        let mut relabeled = 0;
+        for value in 2..120 {
+            om.insert_after(&0, value).unwrap();
+            if om.keep_f64_labels_distinct(&value).unwrap() {
+                relabeled += 1;
+            }
+            let labels: Vec<f64> = om.iter_values_with_tags().map(|(_, tag)| tag.f64_label()).collect();
+            assert!(labels.windows(2).all(|pair| pair[0] < pair[1]), "{} {:?}", value, labels);
+        }
+        assert!(relabeled > 0);
+        assert!(*heard.lock().unwrap() >= relabeled);
+        assert!(!om.keep_f64_labels_distinct(&5).unwrap());
+        assert_eq!(om.keep_f64_labels_distinct(&500), Err(OrderMaintenanceError::NotPresent));
+        assert!(om.iter_values_with_tags().all(|(_, tag)| (0.0..=1.0).contains(&tag.f64_label())));
+    }
+}
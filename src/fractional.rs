@@ -0,0 +1,322 @@
+// Positions as strings that sort the same way bytewise: fractional indexing,
+// in the base-62 scheme Figma popularized and the JS fractional-indexing
+// package implements, key for key, so labels made here and there can be
+// mixed in the same column. A key is an integer part, a head letter saying
+// how many digits follow ('a' one, 'b' two, .. 'z' 27; 'Z' down to 'A' the
+// same for ones below "a0"), then an optional fraction without trailing
+// zeros. Between two keys there's always another, and appending at either
+// end only counts the integer part up or down, so labels for an order built
+// front to back stay short.
+
+use core::error::Error;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{OrderMaintenance, TagWidth};
+
+const DIGITS: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// "A" and 26 zeros, the one integer that can't be decremented, so that
+// nothing has to go below it
+const SMALLEST_INTEGER: &[u8; 27] = b"A00000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FractionalKeyError {
+    // not a key this scheme could have made
+    InvalidKey,
+    // the lower bound isn't below the upper one
+    OutOfOrder,
+    // past "zzz..z" (or below "A000..01"), which takes some doing
+    OutOfRoom,
+}
+impl fmt::Display for FractionalKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            FractionalKeyError::InvalidKey => "invalid fractional index key",
+            FractionalKeyError::OutOfOrder => "fractional index keys are out of order",
+            FractionalKeyError::OutOfRoom => "fractional index keys have run out of room",
+        })
+    }
+}
+impl Error for FractionalKeyError {}
+
+fn digit(byte: u8) -> Result<usize, FractionalKeyError> {
+    DIGITS.iter().position(|&d| d == byte).ok_or(FractionalKeyError::InvalidKey)
+}
+
+// a fraction strictly between a and b (None: 1), as digits after the point
+fn midpoint(a: &[u8], b: Option<&[u8]>) -> Result<Vec<u8>, FractionalKeyError> {
+    if a.last() == Some(&DIGITS[0]) || b.and_then(<[u8]>::last) == Some(&DIGITS[0]) {
+        return Err(FractionalKeyError::InvalidKey);
+    }
+    if let Some(b) = b {
+        // a shared prefix (a padded with zeros) stays as it is
+        let n = (0..).take_while(|&n| b.get(n).is_some_and(|&d| d == a.get(n).copied().unwrap_or(DIGITS[0]))).count();
+        if n > 0 {
+            let mut mid = b[..n].to_vec();
+            mid.extend(midpoint(a.get(n..).unwrap_or(&[]), Some(&b[n..]))?);
+            return Ok(mid);
+        }
+    }
+    let digit_a = match a.first() { None => 0, Some(&d) => digit(d)? };
+    let digit_b = match b { None => DIGITS.len(), Some(b) => digit(*b.first().ok_or(FractionalKeyError::InvalidKey)?)? };
+    if digit_b - digit_a > 1 {
+        // rounding half up, as Math.round does
+        Ok(Vec::from([DIGITS[(digit_a + digit_b).div_ceil(2)]]))
+    } else if let Some(b) = b.filter(|b| b.len() > 1) {
+        Ok(b[..1].to_vec())
+    } else {
+        let mut mid = Vec::from([DIGITS[digit_a]]);
+        mid.extend(midpoint(a.get(1..).unwrap_or(&[]), None)?);
+        Ok(mid)
+    }
+}
+
+fn integer_len(head: u8) -> Result<usize, FractionalKeyError> {
+    match head {
+        b'a'..=b'z' => Ok((head - b'a') as usize + 2),
+        b'A'..=b'Z' => Ok((b'Z' - head) as usize + 2),
+        _ => Err(FractionalKeyError::InvalidKey),
+    }
+}
+
+// the integer part and the fraction, of a valid key
+fn split(key: &[u8]) -> Result<(&[u8], &[u8]), FractionalKeyError> {
+    let len = integer_len(*key.first().ok_or(FractionalKeyError::InvalidKey)?)?;
+    if len > key.len() || key == SMALLEST_INTEGER || key[1..].iter().any(|&d| digit(d).is_err()) || key.len() > len && key.last() == Some(&DIGITS[0]) {
+        return Err(FractionalKeyError::InvalidKey);
+    }
+    Ok(key.split_at(len))
+}
+
+// None past "zzz..z"
+fn increment_integer(integer: &[u8]) -> Result<Option<Vec<u8>>, FractionalKeyError> {
+    let (head, digits) = (integer[0], &integer[1..]);
+    let mut digits = digits.to_vec();
+    for d in digits.iter_mut().rev() {
+        let next = digit(*d)? + 1;
+        if next < DIGITS.len() {
+            *d = DIGITS[next];
+            let mut incremented = Vec::from([head]);
+            incremented.extend(digits);
+            return Ok(Some(incremented));
+        }
+        *d = DIGITS[0];
+    }
+    // carried out of the last digit, so one more of them (or one fewer, below "a0")
+    let head = match head {
+        b'Z' => return Ok(Some(Vec::from([b'a', DIGITS[0]]))),
+        b'z' => return Ok(None),
+        head => head + 1,
+    };
+    if head > b'a' {
+        digits.push(DIGITS[0]);
+    } else {
+        digits.pop();
+    }
+    let mut incremented = Vec::from([head]);
+    incremented.extend(digits);
+    Ok(Some(incremented))
+}
+
+// None below "A000..0"
+fn decrement_integer(integer: &[u8]) -> Result<Option<Vec<u8>>, FractionalKeyError> {
+    let (head, digits) = (integer[0], &integer[1..]);
+    let mut digits = digits.to_vec();
+    let last = DIGITS[DIGITS.len() - 1];
+    for d in digits.iter_mut().rev() {
+        let d_index = digit(*d)?;
+        if d_index > 0 {
+            *d = DIGITS[d_index - 1];
+            let mut decremented = Vec::from([head]);
+            decremented.extend(digits);
+            return Ok(Some(decremented));
+        }
+        *d = last;
+    }
+    let head = match head {
+        b'a' => return Ok(Some(Vec::from([b'Z', last]))),
+        b'A' => return Ok(None),
+        head => head - 1,
+    };
+    if head < b'Z' {
+        digits.push(last);
+    } else {
+        digits.pop();
+    }
+    let mut decremented = Vec::from([head]);
+    decremented.extend(digits);
+    Ok(Some(decremented))
+}
+
+fn key_between(a: Option<&[u8]>, b: Option<&[u8]>) -> Result<Vec<u8>, FractionalKeyError> {
+    let (whole_a, whole_b) = (a, b);
+    let a = a.map(split).transpose()?;
+    let b = b.map(split).transpose()?;
+    if let (Some(a), Some(b)) = (whole_a, whole_b) {
+        if a >= b {
+            return Err(FractionalKeyError::OutOfOrder);
+        }
+    }
+    let joined = |integer: &[u8], fraction: Vec<u8>| {
+        let mut key = integer.to_vec();
+        key.extend(fraction);
+        key
+    };
+    match (a, b) {
+        (None, None) => Ok(Vec::from([b'a', DIGITS[0]])),
+        (None, Some((ib, fb))) => {
+            if ib == SMALLEST_INTEGER {
+                Ok(joined(ib, midpoint(&[], Some(fb))?))
+            } else if !fb.is_empty() {
+                Ok(ib.to_vec())
+            } else {
+                decrement_integer(ib)?.ok_or(FractionalKeyError::OutOfRoom)
+            }
+        }
+        (Some((ia, fa)), None) => match increment_integer(ia)? {
+            Some(incremented) => Ok(incremented),
+            None => Ok(joined(ia, midpoint(fa, None)?)),
+        },
+        (Some((ia, fa)), Some((ib, fb))) => {
+            if ia == ib {
+                return Ok(joined(ia, midpoint(fa, Some(fb))?));
+            }
+            let incremented = increment_integer(ia)?.ok_or(FractionalKeyError::OutOfRoom)?;
+            if incremented[..] < *whole_b.expect("b is there") {
+                Ok(incremented)
+            } else {
+                Ok(joined(ia, midpoint(fa, None)?))
+            }
+        }
+    }
+}
+
+fn into_string(key: Vec<u8>) -> String {
+    String::from_utf8(key).expect("keys are ASCII")
+}
+
+// A key that sorts after a and before b, either of which can be missing to
+// mean that end. What generateKeyBetween gives for the same arguments.
+pub fn fractional_key_between(a: Option<&str>, b: Option<&str>) -> Result<String, FractionalKeyError> {
+    key_between(a.map(str::as_bytes), b.map(str::as_bytes)).map(into_string)
+}
+
+// n keys in order between a and b, spread out rather than each squeezed in
+// after the last, as generateNKeysBetween does.
+pub fn fractional_keys_between(a: Option<&str>, b: Option<&str>, n: usize) -> Result<Vec<String>, FractionalKeyError> {
+    let mut keys = Vec::with_capacity(n);
+    keys_between(a.map(str::as_bytes), b.map(str::as_bytes), n, &mut keys)?;
+    Ok(keys.into_iter().map(into_string).collect())
+}
+fn keys_between(a: Option<&[u8]>, b: Option<&[u8]>, n: usize, keys: &mut Vec<Vec<u8>>) -> Result<(), FractionalKeyError> {
+    match (n, a, b) {
+        (0, _, _) => {}
+        (1, _, _) => keys.push(key_between(a, b)?),
+        (_, _, None) => {
+            let mut key = key_between(a, None)?;
+            for _ in 1..n {
+                let next = key_between(Some(&key), None)?;
+                keys.push(key);
+                key = next;
+            }
+            keys.push(key);
+        }
+        (_, None, _) => {
+            let start = keys.len();
+            let mut key = key_between(None, b)?;
+            for _ in 1..n {
+                let next = key_between(None, Some(&key))?;
+                keys.push(key);
+                key = next;
+            }
+            keys.push(key);
+            keys[start..].reverse();
+        }
+        (_, Some(_), Some(_)) => {
+            let mid = key_between(a, b)?;
+            keys_between(a, Some(&mid), n / 2, keys)?;
+            keys.push(mid.clone());
+            keys_between(Some(&mid), b, n - n / 2 - 1, keys)?;
+        }
+    }
+    Ok(())
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // Every element with a fractional index key for where it is, front to
+    // back: "a0", "a1", .., "az", "b00", ..; O(n). New elements get keys from
+    // fractional_key_between their neighbours' keys.
+    pub fn fractional_labels(&self) -> Vec<(&T, String)> {
+        let keys = fractional_keys_between(None, None, self.len()).expect("from nothing there's room");
+        self.iter_values_with_tags().map(|(value, _)| value).zip(keys).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn matches_generate_key_between() {
+        let between = |a: Option<&str>, b: Option<&str>| fractional_key_between(a, b);
+        // from the fractional-indexing package's own tests
+        let expected = [
+            (None, None, "a0"),
+            (None, Some("a0"), "Zz"),
+            (None, Some("Zz"), "Zy"),
+            (Some("a0"), None, "a1"),
+            (Some("a1"), None, "a2"),
+            (Some("a0"), Some("a1"), "a0V"),
+            (Some("a1"), Some("a2"), "a1V"),
+            (Some("a0V"), Some("a1"), "a0l"),
+            (Some("Zz"), Some("a0"), "ZzV"),
+            (Some("Zz"), Some("a1"), "a0"),
+            (None, Some("Y00"), "Xzzz"),
+            (Some("bzz"), None, "c000"),
+            (Some("a0"), Some("a0V"), "a0G"),
+            (Some("a0"), Some("a0G"), "a08"),
+            (Some("b125"), Some("b129"), "b127"),
+            (Some("a0"), Some("a1V"), "a1"),
+            (Some("Zz"), Some("a01"), "a0"),
+            (None, Some("a0V"), "a0"),
+            (None, Some("b999"), "b99"),
+            (None, Some("A000000000000000000000000001"), "A000000000000000000000000000V"),
+            (Some("zzzzzzzzzzzzzzzzzzzzzzzzzzy"), None, "zzzzzzzzzzzzzzzzzzzzzzzzzzz"),
+            (Some("zzzzzzzzzzzzzzzzzzzzzzzzzzz"), None, "zzzzzzzzzzzzzzzzzzzzzzzzzzzV"),
+        ];
+        for (a, b, key) in expected {
+            assert_eq!(between(a, b).as_deref(), Ok(key), "between {:?} and {:?}", a, b);
+        }
+        assert_eq!(between(None, Some("A00000000000000000000000000")), Err(FractionalKeyError::InvalidKey));
+        assert_eq!(between(Some("a00"), None), Err(FractionalKeyError::InvalidKey));
+        assert_eq!(between(Some("a00"), Some("a1")), Err(FractionalKeyError::InvalidKey));
+        assert_eq!(between(Some("0"), Some("1")), Err(FractionalKeyError::InvalidKey));
+        assert_eq!(between(Some("a1"), Some("a0")), Err(FractionalKeyError::OutOfOrder));
+        let keys = |a, b, n| fractional_keys_between(a, b, n).unwrap().join(" ");
+        assert_eq!(keys(None, None, 5), "a0 a1 a2 a3 a4");
+        assert_eq!(keys(Some("a4"), None, 10), "a5 a6 a7 a8 a9 aA aB aC aD aE");
+        assert_eq!(keys(None, Some("a0"), 5), "Zv Zw Zx Zy Zz");
+        assert_eq!(keys(Some("a0"), Some("a2"), 20), "a04 a08 a0G a0K a0O a0V a0Z a0d a0l a0t a1 a14 a18 a1G a1O a1V a1Z a1d a1l a1t");
+    }
+
+    #[test]
+    fn labels_sort_like_the_order() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..200 {
+            om.insert_after(&(value / 2), value).unwrap();
+        }
+        let labels = om.fractional_labels();
+        assert_eq!(labels.len(), 200);
+        assert_eq!(labels[0].1, "a0");
+        assert!(labels.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        let between = fractional_key_between(Some(&labels[10].1), Some(&labels[11].1)).unwrap();
+        assert!(labels[10].1 < between && between < labels[11].1);
+        assert_eq!(FractionalKeyError::OutOfRoom.to_string(), "fractional index keys have run out of room");
+    }
+}
@@ -0,0 +1,108 @@
+// What the tags look like right now, from gap_report(): for telling whether
+// slow inserts come from one crowded spot (a few tiny gaps, and a densest
+// region much denser than the rest) or from the whole tag space filling up
+// (high utilization, every gap small). O(n), so not for every insert.
+
+use alloc::vec::Vec;
+
+use super::{Tag, TagWidth};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GapReport<W = u64> {
+    // histogram[i] is how many gaps between neighbours are at least 2^i and
+    // less than 2^(i + 1); one bucket per bit of the tags
+    pub histogram: Vec<usize>,
+    pub smallest_gap: Option<W>,
+    pub densest: Option<DenseRegion<W>>,
+    // from the first tag to the last
+    pub span: W,
+    // elements per tag: what fraction of the tag space is taken
+    pub utilization: f64,
+}
+
+// The run of consecutive elements squeezed into the fewest tags. It's
+// window_len() elements long (or everyone, if there aren't that many): about
+// as many as the smallest rebalances take, so it's where the next one is
+// likeliest to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DenseRegion<W = u64> {
+    // how many elements come before it
+    pub offset: usize,
+    pub len: usize,
+    pub first: Tag<W>,
+    pub last: Tag<W>,
+}
+impl<W> DenseRegion<W>
+    where W: TagWidth {
+    pub fn span(&self) -> W {
+        self.last.0 - self.first.0
+    }
+}
+
+// log2(len), and at least 2 so that there's a gap in it
+fn window_len(len: usize) -> usize {
+    (usize::BITS - len.leading_zeros()).max(2) as usize
+}
+
+impl<W> GapReport<W>
+    where W: TagWidth {
+    // tags in order
+    pub(crate) fn of<I>(tags: I) -> GapReport<W>
+        where I: IntoIterator<Item = Tag<W>> {
+        let tags: Vec<W> = tags.into_iter().map(|tag| tag.0).collect();
+        let mut histogram: Vec<usize> = (0..W::BITS).map(|_| 0).collect();
+        let mut smallest_gap = None;
+        for pair in tags.windows(2) {
+            let gap = pair[1] - pair[0];
+            histogram[(W::BITS - 1 - gap.leading_zeros()) as usize] += 1;
+            smallest_gap = Some(smallest_gap.map_or(gap, |smallest: W| smallest.min(gap)));
+        }
+        let len = window_len(tags.len()).min(tags.len());
+        let densest = tags.windows(len.max(1))
+            .enumerate()
+            .min_by_key(|&(_, window)| window[len - 1] - window[0])
+            .map(|(offset, window)| DenseRegion { offset, len, first: Tag(window[0]), last: Tag(window[len - 1]) });
+        let span = match (tags.first(), tags.last()) {
+            (Some(&first), Some(&last)) => last - first,
+            _ => W::ZERO,
+        };
+        // MAX + 1 tags, to within f64 precision
+        let utilization = tags.len() as f64 / (W::MAX.to_f64() + 1.0);
+        GapReport { histogram, smallest_gap, densest, span, utilization }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::OrderMaintenance;
+
+    #[test]
+    fn hot_spot() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        assert_eq!(om.gap_report().densest, None);
+        assert_eq!(om.gap_report().span, 0);
+        om.insert_only(0).unwrap();
+        for value in 1..1000 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        om.rebalance_all();
+        let even = om.gap_report();
+        assert_eq!(even.histogram.iter().sum::<usize>(), 999);
+        assert_eq!(even.histogram.iter().filter(|&&count| count > 0).count(), 1);
+        // crowding in right after 500
+        for value in 1000..1040 {
+            om.insert_after(&500, value).unwrap();
+        }
+        let report = om.gap_report();
+        assert_eq!(report.histogram.len(), 64);
+        assert_eq!(report.histogram.iter().sum::<usize>(), 1039);
+        assert!(report.smallest_gap < even.smallest_gap);
+        let densest = report.densest.unwrap();
+        assert_eq!(densest.len, 11);
+        // somewhere between 500 and 501
+        assert!(densest.first >= om.tag_of(&500).unwrap() && densest.last <= om.tag_of(&501).unwrap(), "{:?}", densest);
+        assert!(densest.span() < even.densest.unwrap().span());
+        assert_eq!(report.utilization, 1040.0 / 2.0f64.powi(64));
+        assert_eq!(Some(report.span), om.tag_of(&999).zip(om.tag_of(&0)).map(|(last, first)| last.0 - first.0));
+    }
+}
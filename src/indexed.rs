@@ -0,0 +1,496 @@
+// An OrderMaintenance that also knows where everything is by position:
+// rank() (how many elements come before this one) and select() (the element
+// at a position), both O(log n) expected. Comparisons are still the O(1) tag
+// comparisons; the positions come from a treap kept alongside, one node per
+// slot of the order, with subtree sizes and parent links so that a node's
+// rank can be read off on the way up to the root. That costs every insert
+// and remove an extra O(log n), which is why it's a type of its own rather
+// than something every order pays for. The same walks give prefix sums of
+// per-element weights, for offsets rather than positions (see set_weight).
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+
+use super::{Index, IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    parent: Option<u32>,
+    left: Option<u32>,
+    right: Option<u32>,
+    // this node and everything under it
+    size: u32,
+    weight: u64,
+    // weight, likewise
+    total: u64,
+    // a max-heap on these keeps it balanced, in expectation
+    priority: u32,
+}
+
+// positions, with nodes numbered like the order's slots
+#[derive(Debug)]
+struct Treap {
+    nodes: Vec<Node>,
+    root: Option<u32>,
+    seed: u64,
+}
+impl Treap {
+    fn size(&self, node: Option<u32>) -> u32 {
+        node.map_or(0, |node| self.nodes[node as usize].size)
+    }
+    fn total(&self, node: Option<u32>) -> u64 {
+        node.map_or(0, |node| self.nodes[node as usize].total)
+    }
+    fn resize(&mut self, node: u32) {
+        let Node { left, right, weight, .. } = self.nodes[node as usize];
+        self.nodes[node as usize].size = 1 + self.size(left) + self.size(right);
+        self.nodes[node as usize].total = weight + self.total(left) + self.total(right);
+    }
+    // node and everything above it, by delta
+    fn add_weight(&mut self, node: u32, delta: i128) {
+        let mut ancestor = Some(node);
+        while let Some(a) = ancestor {
+            let a = &mut self.nodes[a as usize];
+            a.total = (a.total as i128 + delta) as u64;
+            ancestor = a.parent;
+        }
+    }
+    fn set_weight(&mut self, node: u32, weight: u64) {
+        let delta = weight as i128 - self.nodes[node as usize].weight as i128;
+        self.nodes[node as usize].weight = weight;
+        self.add_weight(node, delta);
+    }
+    // in place of whichever child of parent was old (or the root)
+    fn replace_child(&mut self, parent: Option<u32>, old: u32, new: Option<u32>) {
+        match parent {
+            None => self.root = new,
+            Some(parent) if self.nodes[parent as usize].left == Some(old) => self.nodes[parent as usize].left = new,
+            Some(parent) => self.nodes[parent as usize].right = new,
+        }
+    }
+    // swaps node with its parent, keeping the order
+    fn rotate_up(&mut self, node: u32) {
+        let parent = self.nodes[node as usize].parent.expect("rotating the root");
+        let grandparent = self.nodes[parent as usize].parent;
+        let moved = if self.nodes[parent as usize].left == Some(node) {
+            let moved = self.nodes[node as usize].right;
+            self.nodes[parent as usize].left = moved;
+            self.nodes[node as usize].right = Some(parent);
+            moved
+        } else {
+            let moved = self.nodes[node as usize].left;
+            self.nodes[parent as usize].right = moved;
+            self.nodes[node as usize].left = Some(parent);
+            moved
+        };
+        if let Some(moved) = moved {
+            self.nodes[moved as usize].parent = Some(parent);
+        }
+        self.nodes[parent as usize].parent = Some(node);
+        self.nodes[node as usize].parent = grandparent;
+        self.replace_child(grandparent, parent, Some(node));
+        self.resize(parent);
+        self.resize(node);
+    }
+    // node goes just after after, or first for None
+    fn insert(&mut self, node: u32, after: Option<u32>) {
+        self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        if self.nodes.len() <= node as usize {
+            self.nodes.resize(node as usize + 1, Node::default());
+        }
+        self.nodes[node as usize] = Node { parent: None, left: None, right: None, size: 1, weight: 1, total: 1, priority: (self.seed >> 32) as u32 };
+        // where the leftmost descendant of parent is
+        let leftmost_from = |treap: &Treap, mut parent: u32| {
+            while let Some(left) = treap.nodes[parent as usize].left {
+                parent = left;
+            }
+            parent
+        };
+        let parent = match (after, self.root) {
+            (_, None) => {
+                self.root = Some(node);
+                return;
+            }
+            (None, Some(root)) => {
+                let parent = leftmost_from(self, root);
+                self.nodes[parent as usize].left = Some(node);
+                parent
+            }
+            (Some(after), Some(_)) => match self.nodes[after as usize].right {
+                None => {
+                    self.nodes[after as usize].right = Some(node);
+                    after
+                }
+                Some(right) => {
+                    let parent = leftmost_from(self, right);
+                    self.nodes[parent as usize].left = Some(node);
+                    parent
+                }
+            },
+        };
+        self.nodes[node as usize].parent = Some(parent);
+        let mut ancestor = Some(parent);
+        while let Some(a) = ancestor {
+            self.nodes[a as usize].size += 1;
+            self.nodes[a as usize].total += 1;
+            ancestor = self.nodes[a as usize].parent;
+        }
+        while let Some(parent) = self.nodes[node as usize].parent {
+            if self.nodes[parent as usize].priority >= self.nodes[node as usize].priority {
+                break;
+            }
+            self.rotate_up(node);
+        }
+    }
+    fn remove(&mut self, node: u32) {
+        // down to a leaf, under whichever child should be on top
+        loop {
+            let Node { left, right, .. } = self.nodes[node as usize];
+            let child = match (left, right) {
+                (None, None) => break,
+                (Some(child), None) | (None, Some(child)) => child,
+                (Some(left), Some(right)) => {
+                    if self.nodes[left as usize].priority > self.nodes[right as usize].priority { left } else { right }
+                }
+            };
+            self.rotate_up(child);
+        }
+        let parent = self.nodes[node as usize].parent;
+        self.replace_child(parent, node, None);
+        let mut ancestor = parent;
+        let weight = self.nodes[node as usize].weight;
+        while let Some(a) = ancestor {
+            self.nodes[a as usize].size -= 1;
+            self.nodes[a as usize].total -= weight;
+            ancestor = self.nodes[a as usize].parent;
+        }
+    }
+    fn rank(&self, node: u32) -> usize {
+        let mut rank = self.size(self.nodes[node as usize].left) as usize;
+        let mut child = node;
+        while let Some(parent) = self.nodes[child as usize].parent {
+            if self.nodes[parent as usize].right == Some(child) {
+                rank += self.size(self.nodes[parent as usize].left) as usize + 1;
+            }
+            child = parent;
+        }
+        rank
+    }
+    fn weight_before(&self, node: u32) -> u64 {
+        let mut before = self.total(self.nodes[node as usize].left);
+        let mut child = node;
+        while let Some(parent) = self.nodes[child as usize].parent {
+            if self.nodes[parent as usize].right == Some(child) {
+                before += self.total(self.nodes[parent as usize].left) + self.nodes[parent as usize].weight;
+            }
+            child = parent;
+        }
+        before
+    }
+    fn select_by_weight(&self, mut offset: u64) -> Option<u32> {
+        let mut node = self.root?;
+        loop {
+            let Node { left, right, weight, .. } = self.nodes[node as usize];
+            let before = self.total(left);
+            if offset < before {
+                node = left?;
+            } else if offset - before < weight {
+                return Some(node);
+            } else {
+                offset -= before + weight;
+                node = right?;
+            }
+        }
+    }
+    // the first node in order that pred holds for, given that it holds for
+    // everything after that too
+    fn partition_point<F>(&self, mut pred: F) -> Option<u32>
+        where F: FnMut(u32) -> bool {
+        let mut found = None;
+        let mut node = self.root;
+        while let Some(n) = node {
+            if pred(n) {
+                found = Some(n);
+                node = self.nodes[n as usize].left;
+            } else {
+                node = self.nodes[n as usize].right;
+            }
+        }
+        found
+    }
+    fn select(&self, mut position: usize) -> Option<u32> {
+        let mut node = self.root?;
+        loop {
+            let left = self.size(self.nodes[node as usize].left) as usize;
+            match position.cmp(&left) {
+                Ordering::Less => node = self.nodes[node as usize].left?,
+                Ordering::Equal => return Some(node),
+                Ordering::Greater => {
+                    position -= left + 1;
+                    node = self.nodes[node as usize].right?;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexedOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    positions: Treap,
+}
+
+impl<T> IndexedOrderMaintenance<T>
+    where T: Hash + Eq {
+    pub fn new() -> IndexedOrderMaintenance<T> {
+        IndexedOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for IndexedOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> IndexedOrderMaintenance<T, S> {
+        IndexedOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+impl<T, S> IndexedOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> IndexedOrderMaintenance<T, S> {
+        IndexedOrderMaintenance { om: OrderMaintenance::with_hasher(hasher), positions: Treap { nodes: Vec::new(), root: None, seed: 1 } }
+    }
+    pub fn len(&self) -> usize {
+        self.om.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.om.is_empty()
+    }
+    pub fn iter_values_with_tags(&self) -> IterWithTag<'_, T, S> {
+        self.om.iter_values_with_tags()
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.contains(value)
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.compare(a, b)
+    }
+    // how many elements come before value
+    pub fn rank<Q>(&self, value: &Q) -> Option<usize>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.find(value).map(|index| self.positions.rank(index.0))
+    }
+    // how many positions apart they are, either way round: 1 for neighbours,
+    // 0 for the same element
+    pub fn distance<Q>(&self, a: &Q, b: &Q) -> Option<usize>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let (a, b) = (self.rank(a)?, self.rank(b)?);
+        Some(a.max(b) - a.min(b))
+    }
+    // n steps from value either way, through the positions: O(log n)
+    pub fn nth_after<Q>(&self, value: &Q, n: usize) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.select(self.rank(value)?.checked_add(n)?)
+    }
+    pub fn nth_before<Q>(&self, value: &Q, n: usize) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.select(self.rank(value)?.checked_sub(n)?)
+    }
+    // Every element has a weight, 1 until it's set otherwise (a length, say,
+    // to turn positions into offsets). The weights of everything together
+    // have to fit in a u64.
+    pub fn weight<Q>(&self, value: &Q) -> Option<u64>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.find(value).map(|index| self.positions.nodes[index.get()].weight)
+    }
+    pub fn set_weight<Q>(&mut self, value: &Q, weight: u64) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.om.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        self.positions.set_weight(index.0, weight);
+        Ok(())
+    }
+    pub fn total_weight(&self) -> u64 {
+        self.positions.total(self.positions.root)
+    }
+    // what rank is to positions: the weight of everything before value
+    pub fn weight_before<Q>(&self, value: &Q) -> Option<u64>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.find(value).map(|index| self.positions.weight_before(index.0))
+    }
+    // what select is to positions: the element whose weight covers offset,
+    // counting from the front (so never one that weighs 0)
+    pub fn select_by_weight(&self, offset: u64) -> Option<&T> {
+        self.positions.select_by_weight(offset).map(|node| &self.om.slots[Index(node)].key)
+    }
+    // the element with position elements before it
+    pub fn select(&self, position: usize) -> Option<&T> {
+        self.positions.select(position).map(|node| &self.om.slots[Index(node)].key)
+    }
+    // The first element pred is true for, where it's false for some prefix
+    // of the order and true for the rest; None if it's true for none of it.
+    // O(log n) calls to pred, expected.
+    pub fn partition_point<F>(&self, mut pred: F) -> Option<&T>
+        where F: FnMut(&T) -> bool {
+        let slots = &self.om.slots;
+        self.positions.partition_point(|node| pred(&slots[Index(node)].key)).map(|node| &slots[Index(node)].key)
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        self.om.insert_only(value)?;
+        let index = self.om.front.expect("just inserted");
+        self.positions.insert(index.0, None);
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.om.find_anchor(after, &value)?;
+        let index = self.om.insert_after_index(after, value)?;
+        self.positions.insert(index.0, Some(after.0));
+        Ok(())
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.om.find(value)?;
+        self.positions.remove(index.0);
+        Some(self.om.remove_index(index).value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use test_random;
+
+    #[test]
+    fn rank_and_select() {
+        let mut om: IndexedOrderMaintenance<u32> = IndexedOrderMaintenance::new();
+        assert_eq!(om.select(0), None);
+        om.insert_only(0).unwrap();
+        let mut model = Vec::from([0u32]);
+        let mut random = test_random(99);
+        for value in 1..2000 {
+            let at = random(model.len());
+            om.insert_after(&model[at], value).unwrap();
+            model.insert(at + 1, value);
+            if value % 4 == 0 {
+                let at = random(model.len());
+                assert_eq!(om.remove(&model[at]), Some(model[at]));
+                model.remove(at);
+            }
+        }
+        assert_eq!(om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>(), model);
+        for (position, value) in model.iter().enumerate() {
+            assert_eq!(om.rank(value), Some(position));
+            assert_eq!(om.select(position), Some(value));
+        }
+        assert_eq!(om.select(model.len()), None);
+        assert_eq!(om.rank(&5000), None);
+        assert_eq!(om.rank(&model[0]), Some(0));
+        // fewer than 2 log2(n) levels or so, in expectation
+        let depth = |mut node: u32| {
+            let mut depth = 0;
+            while let Some(parent) = om.positions.nodes[node as usize].parent {
+                node = parent;
+                depth += 1;
+            }
+            depth
+        };
+        let deepest = model.iter().map(|value| depth(om.om.find(value).unwrap().0)).max().unwrap();
+        assert!(deepest < 40, "{}", deepest);
+    }
+
+    #[test]
+    fn distance() {
+        let mut om: IndexedOrderMaintenance<u32> = IndexedOrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..100 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        assert_eq!(om.distance(&10, &35), Some(25));
+        assert_eq!(om.distance(&35, &10), Some(25));
+        assert_eq!(om.distance(&7, &7), Some(0));
+        assert_eq!(om.distance(&7, &100), None);
+        om.remove(&20);
+        assert_eq!(om.distance(&10, &35), Some(24));
+    }
+
+    #[test]
+    fn nth_after() {
+        let mut om: IndexedOrderMaintenance<u32> = IndexedOrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..100 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        assert_eq!(om.nth_after(&10, 0), Some(&10));
+        assert_eq!(om.nth_after(&10, 89), Some(&99));
+        assert_eq!(om.nth_after(&10, 90), None);
+        assert_eq!(om.nth_after(&10, usize::MAX), None);
+        assert_eq!(om.nth_before(&10, 10), Some(&0));
+        assert_eq!(om.nth_before(&10, 11), None);
+    }
+
+    #[test]
+    fn partition_point() {
+        let mut om: IndexedOrderMaintenance<u32> = IndexedOrderMaintenance::new();
+        assert_eq!(om.partition_point(|_| true), None);
+        om.insert_only(0).unwrap();
+        for value in 1..500 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        let mut calls = 0;
+        assert_eq!(om.partition_point(|&value| { calls += 1; value >= 321 }), Some(&321));
+        assert!(calls < 40, "{}", calls);
+        assert_eq!(om.partition_point(|_| true), Some(&0));
+        assert_eq!(om.partition_point(|_| false), None);
+        assert_eq!(om.partition_point(|&value| value >= 499), Some(&499));
+    }
+
+    #[test]
+    fn weights() {
+        let mut om: IndexedOrderMaintenance<&str> = IndexedOrderMaintenance::new();
+        om.insert_only("Hello").unwrap();
+        om.insert_after(&"Hello", ", ").unwrap();
+        om.insert_after(&", ", "world").unwrap();
+        assert_eq!(om.total_weight(), 3);
+        assert_eq!(om.weight_before(&"world"), Some(2));
+        for word in ["Hello", ", ", "world"] {
+            om.set_weight(&word, word.len() as u64).unwrap();
+        }
+        assert_eq!(om.set_weight(&"!", 1), Err(OrderMaintenanceError::NotPresent));
+        om.insert_after(&", ", "").unwrap();
+        om.set_weight(&"", 0).unwrap();
+        assert_eq!(om.weight(&"world"), Some(5));
+        assert_eq!(om.total_weight(), 12);
+        assert_eq!(om.weight_before(&"world"), Some(7));
+        assert_eq!(om.weight_before(&""), Some(7));
+        assert_eq!(om.select_by_weight(0), Some(&"Hello"));
+        assert_eq!(om.select_by_weight(4), Some(&"Hello"));
+        assert_eq!(om.select_by_weight(5), Some(&", "));
+        assert_eq!(om.select_by_weight(7), Some(&"world"));
+        assert_eq!(om.select_by_weight(12), None);
+        om.remove(&", ");
+        assert_eq!(om.total_weight(), 10);
+        assert_eq!(om.weight_before(&"world"), Some(5));
+        // and through plenty of rotations
+        let mut om: IndexedOrderMaintenance<u32> = IndexedOrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        let mut model = Vec::from([0u32]);
+        for value in 1..500 {
+            let at = (value as usize * 7919) % model.len();
+            om.insert_after(&model[at], value).unwrap();
+            om.set_weight(&value, (value % 7) as u64).unwrap();
+            model.insert(at + 1, value);
+        }
+        let mut before = 0;
+        for value in &model {
+            assert_eq!(om.weight_before(value), Some(before));
+            before += om.weight(value).unwrap();
+        }
+        assert_eq!(om.total_weight(), before);
+    }
+}
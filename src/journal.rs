@@ -0,0 +1,425 @@
+// Every change to an order, as a value: an Op. apply() replays a list of
+// them onto any order, and JournaledOrderMaintenance keeps the list for you,
+// recording each change that succeeds. Replaying a journal onto a copy of
+// where it started (an empty order, say, or one loaded from a snapshot)
+// gives the same order back, tags and all, since every step is done the same
+// way in the same order; so it doubles as an event log to persist, a feed to
+// mirror an order elsewhere, or a bug report that reproduces itself.
+//
+// The same wrapper can keep an undo stack too (enable_undo): before each
+// change it works out the ops that would put things back (a remove's undo
+// is an insert next to a former neighbour, a move's is a move back next to
+// one, and so on), so undo() and redo() are ordinary ops like any other,
+// go in the journal like any other, and cost no more than the change did.
+// What they restore is the order, not the tags: the element put back gets
+// whatever tag inserting it there gets, and nothing else is relabeled on
+// undo's account, beyond what that insert might need.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+use core::mem;
+
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState, TagWidth};
+
+// One call of the method of the same name. Anchors are named by value like
+// the methods' arguments are, so a journal means the same thing to any order
+// holding the same elements.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Op<T> {
+    InsertOnly(T),
+    InsertAfter { after: T, value: T },
+    Remove(T),
+    ReplaceKey { old: T, new: T },
+    MoveAfter { value: T, after: T },
+    MoveBefore { value: T, before: T },
+    MoveToFront(T),
+    MoveToBack(T),
+    SwapPositions(T, T),
+    Reverse,
+    RotateToFront(T),
+    RebalanceAll,
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // Stops at the first op that fails and returns its error; the ones
+    // before it stay done.
+    pub fn apply<I>(&mut self, ops: I) -> Result<(), OrderMaintenanceError>
+        where I: IntoIterator<Item = Op<T>> {
+        for op in ops {
+            self.apply_op(op)?;
+        }
+        Ok(())
+    }
+    pub fn apply_op(&mut self, op: Op<T>) -> Result<(), OrderMaintenanceError> {
+        match op {
+            Op::InsertOnly(value) => self.insert_only(value),
+            Op::InsertAfter { after, value } => self.insert_after(&after, value),
+            Op::Remove(value) => self.remove(&value).map(|_| ()).ok_or(OrderMaintenanceError::NotPresent),
+            Op::ReplaceKey { old, new } => self.replace_key(&old, new),
+            Op::MoveAfter { value, after } => self.move_after(&value, &after),
+            Op::MoveBefore { value, before } => self.move_before(&value, &before),
+            Op::MoveToFront(value) => self.move_to_front(&value),
+            Op::MoveToBack(value) => self.move_to_back(&value),
+            Op::SwapPositions(a, b) => self.swap_positions(&a, &b),
+            Op::Reverse => {
+                self.reverse();
+                Ok(())
+            }
+            Op::RotateToFront(value) => self.rotate_to_front(&value),
+            Op::RebalanceAll => {
+                self.rebalance_all();
+                Ok(())
+            }
+        }
+    }
+}
+
+// The ops have to own their elements, hence T: Clone; anchors are cloned
+// out of the order, so they're the stored keys rather than whatever was
+// passed in to find them.
+#[derive(Debug)]
+pub struct JournaledOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    journal: Vec<Op<T>>,
+    // None until enable_undo
+    undo: Option<Vec<Step<T>>>,
+    redo: Vec<Step<T>>,
+}
+
+// what one call did, and what undoes it (in the order to apply them)
+#[derive(Debug)]
+struct Step<T> {
+    done: Vec<Op<T>>,
+    undo: Vec<Op<T>>,
+}
+
+impl<T> JournaledOrderMaintenance<T>
+    where T: Hash + Eq + Clone {
+    pub fn new() -> JournaledOrderMaintenance<T> {
+        JournaledOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for JournaledOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Default {
+    fn default() -> JournaledOrderMaintenance<T, S> {
+        JournaledOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+// The journal starts from om as it is.
+impl<T, S> From<OrderMaintenance<T, S>> for JournaledOrderMaintenance<T, S>
+    where T: Hash + Eq {
+    fn from(om: OrderMaintenance<T, S>) -> JournaledOrderMaintenance<T, S> {
+        JournaledOrderMaintenance { om, journal: Vec::new(), undo: None, redo: Vec::new() }
+    }
+}
+
+impl<T, S> JournaledOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> JournaledOrderMaintenance<T, S> {
+        JournaledOrderMaintenance::from(OrderMaintenance::with_hasher(hasher))
+    }
+    pub fn order(&self) -> &OrderMaintenance<T, S> {
+        &self.om
+    }
+    pub fn into_inner(self) -> OrderMaintenance<T, S> {
+        self.om
+    }
+    pub fn journal(&self) -> &[Op<T>] {
+        &self.journal
+    }
+    // Hands over what's been recorded so far and starts a new journal from here.
+    pub fn take_journal(&mut self) -> Vec<Op<T>> {
+        mem::take(&mut self.journal)
+    }
+    // From here on each change can be undone, back to this point.
+    pub fn enable_undo(&mut self) {
+        if self.undo.is_none() {
+            self.undo = Some(Vec::new());
+        }
+    }
+    // and forgets what could have been undone or redone
+    pub fn disable_undo(&mut self) {
+        self.undo = None;
+        self.redo.clear();
+    }
+    pub fn can_undo(&self) -> bool {
+        self.undo.as_ref().is_some_and(|undo| !undo.is_empty())
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+    // Puts the order back the way it was before the last change (one call:
+    // an apply() undoes as a whole), if there's one to undo. rebalance_all
+    // doesn't count, since it leaves the order as it was.
+    pub fn undo(&mut self) -> bool {
+        let step = match self.undo.as_mut().and_then(Vec::pop) {
+            None => return false,
+            Some(step) => step,
+        };
+        self.om.apply(step.undo.iter().cloned()).expect("undo ops fit the order they undo");
+        self.journal.extend(step.undo.iter().cloned());
+        self.redo.push(step);
+        true
+    }
+    // Does the last undone change again; any other change in between
+    // means there's nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let step = match self.redo.pop() {
+            None => return false,
+            Some(step) => step,
+        };
+        self.om.apply(step.done.iter().cloned()).expect("redo ops fit the order they were undone from");
+        self.journal.extend(step.done.iter().cloned());
+        self.undo.as_mut().expect("only undo makes something to redo").push(step);
+        true
+    }
+    pub fn len(&self) -> usize {
+        self.om.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.om.is_empty()
+    }
+    pub fn iter_values_with_tags(&self) -> IterWithTag<'_, T, S> {
+        self.om.iter_values_with_tags()
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.contains(value)
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.compare(a, b)
+    }
+    // as an op of its own, not one per element, and only as far as it got
+    pub fn apply<I>(&mut self, ops: I) -> Result<(), OrderMaintenanceError>
+        where I: IntoIterator<Item = Op<T>> {
+        let mut step = Step { done: Vec::new(), undo: Vec::new() };
+        let applied = ops.into_iter().try_for_each(|op| self.do_op(&mut step, op));
+        self.finish(step);
+        applied
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        self.record(Op::InsertOnly(value))
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.stored(after, OrderMaintenanceError::AnchorMissing)?;
+        self.record(Op::InsertAfter { after, value })
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let undo = if self.undo.is_some() { self.put_back(value)? } else { Vec::new() };
+        let value = self.om.remove(value)?.value;
+        self.finish(Step { done: Vec::from([Op::Remove(value.clone())]), undo });
+        Some(value)
+    }
+    pub fn replace_key<Q>(&mut self, old: &Q, new: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let old = self.stored(old, OrderMaintenanceError::NotPresent)?;
+        self.record(Op::ReplaceKey { old, new })
+    }
+    pub fn move_after<Q>(&mut self, value: &Q, after: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.stored(value, OrderMaintenanceError::NotPresent)?;
+        let after = self.stored(after, OrderMaintenanceError::AnchorMissing)?;
+        self.record(Op::MoveAfter { value, after })
+    }
+    pub fn move_before<Q>(&mut self, value: &Q, before: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.stored(value, OrderMaintenanceError::NotPresent)?;
+        let before = self.stored(before, OrderMaintenanceError::AnchorMissing)?;
+        self.record(Op::MoveBefore { value, before })
+    }
+    pub fn move_to_front<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.stored(value, OrderMaintenanceError::NotPresent)?;
+        self.record(Op::MoveToFront(value))
+    }
+    pub fn move_to_back<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.stored(value, OrderMaintenanceError::NotPresent)?;
+        self.record(Op::MoveToBack(value))
+    }
+    pub fn swap_positions<Q>(&mut self, a: &Q, b: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.stored(a, OrderMaintenanceError::NotPresent)?;
+        let b = self.stored(b, OrderMaintenanceError::NotPresent)?;
+        self.record(Op::SwapPositions(a, b))
+    }
+    pub fn reverse(&mut self) {
+        self.record(Op::Reverse).expect("reversing can't fail");
+    }
+    pub fn rotate_to_front<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.stored(value, OrderMaintenanceError::NotPresent)?;
+        self.record(Op::RotateToFront(value))
+    }
+    pub fn rebalance_all(&mut self) {
+        self.record(Op::RebalanceAll).expect("rebalancing can't fail");
+    }
+    fn stored<Q>(&self, value: &Q, missing: OrderMaintenanceError) -> Result<T, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.stored(value).cloned().ok_or(missing)
+    }
+    // does op, and journals it if it worked
+    fn record(&mut self, op: Op<T>) -> Result<(), OrderMaintenanceError> {
+        let mut step = Step { done: Vec::new(), undo: Vec::new() };
+        let applied = self.do_op(&mut step, op);
+        self.finish(step);
+        applied
+    }
+    fn do_op(&mut self, step: &mut Step<T>, op: Op<T>) -> Result<(), OrderMaintenanceError> {
+        let mut undo = if self.undo.is_some() { self.inverse(&op) } else { Vec::new() };
+        self.om.apply_op(op.clone())?;
+        step.done.push(op);
+        // later ops are undone first
+        undo.append(&mut step.undo);
+        step.undo = undo;
+        Ok(())
+    }
+    fn finish(&mut self, step: Step<T>) {
+        self.journal.extend(step.done.iter().cloned());
+        if let Some(undo) = self.undo.as_mut() {
+            if !step.undo.is_empty() {
+                undo.push(step);
+                self.redo.clear();
+            }
+        }
+    }
+    // what would undo op, worked out before it's done
+    fn inverse(&self, op: &Op<T>) -> Vec<Op<T>> {
+        match *op {
+            Op::InsertOnly(ref value) | Op::InsertAfter { ref value, .. } => Vec::from([Op::Remove(value.clone())]),
+            Op::Remove(ref value) => self.put_back(value).unwrap_or_default(),
+            Op::ReplaceKey { ref old, ref new } => Vec::from([Op::ReplaceKey { old: new.clone(), new: old.clone() }]),
+            Op::MoveAfter { ref value, .. } | Op::MoveBefore { ref value, .. } | Op::MoveToFront(ref value) | Op::MoveToBack(ref value) => {
+                let back = match self.om.predecessor(value) {
+                    Some(prev) => Op::MoveAfter { value: value.clone(), after: prev.clone() },
+                    None => Op::MoveToFront(value.clone()),
+                };
+                Vec::from([back])
+            }
+            Op::SwapPositions(ref a, ref b) => Vec::from([Op::SwapPositions(a.clone(), b.clone())]),
+            Op::Reverse => Vec::from([Op::Reverse]),
+            Op::RotateToFront(_) => self.om.iter_values_with_tags().next().map(|(front, _)| Op::RotateToFront(front.clone())).into_iter().collect(),
+            Op::RebalanceAll => Vec::new(),
+        }
+    }
+    // what would re-insert value where it is now, once it's been removed
+    fn put_back<Q>(&self, value: &Q) -> Option<Vec<Op<T>>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.om.stored(value)?.clone();
+        Some(match (self.om.predecessor::<T>(&value), self.om.successor::<T>(&value)) {
+            (Some(prev), _) => Vec::from([Op::InsertAfter { after: prev.clone(), value }]),
+            (None, Some(next)) => Vec::from([Op::InsertAfter { after: next.clone(), value: value.clone() }, Op::MoveToFront(value)]),
+            (None, None) => Vec::from([Op::InsertOnly(value)]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn replays_to_the_same_order() {
+        let mut journaled: JournaledOrderMaintenance<u32> = JournaledOrderMaintenance::new();
+        journaled.insert_only(0).unwrap();
+        for value in 1..200 {
+            journaled.insert_after(&(value / 3), value).unwrap();
+        }
+        journaled.move_to_front(&150).unwrap();
+        journaled.move_after(&3, &199).unwrap();
+        journaled.swap_positions(&1, &2).unwrap();
+        assert_eq!(journaled.remove(&7), Some(7));
+        assert_eq!(journaled.remove(&7), None);
+        journaled.replace_key(&8, 1000).unwrap();
+        journaled.rotate_to_front(&40).unwrap();
+        journaled.reverse();
+        // failures aren't journaled
+        assert_eq!(journaled.insert_after(&7, 7), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(journaled.move_after(&3, &3), Err(OrderMaintenanceError::SelfInsert));
+        assert_eq!(journaled.journal().len(), 200 + 7);
+        assert_eq!(journaled.journal()[200], Op::MoveToFront(150));
+
+        let mut replayed: OrderMaintenance<u32> = OrderMaintenance::new();
+        replayed.apply(journaled.journal().iter().cloned()).unwrap();
+        assert_eq!(replayed.iter_values_with_tags().collect::<Vec<_>>(), journaled.iter_values_with_tags().collect::<Vec<_>>());
+
+        // and onward from there, with a fresh journal
+        let first = journaled.take_journal();
+        assert!(journaled.journal().is_empty());
+        journaled.apply([Op::MoveToBack(0), Op::Remove(1000), Op::Remove(1000), Op::Remove(1)]).unwrap_err();
+        assert_eq!(journaled.journal(), [Op::MoveToBack(0), Op::Remove(1000)]);
+        let mut from_scratch: OrderMaintenance<u32> = OrderMaintenance::new();
+        from_scratch.apply(first.into_iter().chain(journaled.take_journal())).unwrap();
+        assert_eq!(from_scratch, *journaled.order());
+        assert!(journaled.contains(&1) && !journaled.contains(&1000));
+    }
+    #[test]
+    fn undo_and_redo() {
+        let mut journaled: JournaledOrderMaintenance<u32> = JournaledOrderMaintenance::new();
+        let values = |journaled: &JournaledOrderMaintenance<u32>| journaled.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>();
+        journaled.insert_only(0).unwrap();
+        assert!(!journaled.undo() && !journaled.can_undo());
+        for value in 1..6 {
+            journaled.insert_after(&(value - 1), value).unwrap();
+        }
+        journaled.enable_undo();
+        let mut history = Vec::from([values(&journaled)]);
+        journaled.remove(&0);
+        history.push(values(&journaled));
+        journaled.move_to_back(&1).unwrap();
+        history.push(values(&journaled));
+        journaled.swap_positions(&2, &5).unwrap();
+        history.push(values(&journaled));
+        journaled.replace_key(&3, 30).unwrap();
+        history.push(values(&journaled));
+        journaled.rotate_to_front(&4).unwrap();
+        history.push(values(&journaled));
+        journaled.reverse();
+        history.push(values(&journaled));
+        journaled.remove(&30);
+        history.push(values(&journaled));
+        // as far as it got, undone as one step
+        journaled.apply([Op::MoveAfter { value: 4, after: 5 }, Op::Remove(1), Op::Remove(1)]).unwrap_err();
+        history.push(values(&journaled));
+        journaled.rebalance_all();
+        assert_eq!(history.last(), Some(&Vec::from([5, 4, 2])));
+
+        for expected in history.iter().rev().skip(1) {
+            assert!(journaled.undo());
+            assert_eq!(&values(&journaled), expected);
+        }
+        assert!(!journaled.undo());
+        // the inserts came before undo was on
+        assert_eq!(values(&journaled), [0, 1, 2, 3, 4, 5]);
+        for expected in history.iter().skip(1).take(3) {
+            assert!(journaled.redo());
+            assert_eq!(&values(&journaled), expected);
+        }
+        journaled.move_to_front(&4).unwrap();
+        assert!(!journaled.can_redo() && !journaled.redo());
+        assert!(journaled.undo());
+        assert_eq!(&values(&journaled), &history[3]);
+
+        // the journal saw it all, undos and redos included
+        let mut replayed: OrderMaintenance<u32> = OrderMaintenance::new();
+        replayed.apply(journaled.take_journal()).unwrap();
+        assert_eq!(replayed, *journaled.order());
+        journaled.order().check_invariants().unwrap();
+        journaled.disable_undo();
+        assert!(!journaled.can_undo());
+    }
+}
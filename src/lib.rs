@@ -6,338 +6,2039 @@
 // https://www.ics.uci.edu/~eppstein/PADS/ABOUT-PADS.txt
 // (MIT license, if it even applied)
 
-use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::cmp::Eq;
-use std::iter::FromIterator;
+// Only core + alloc are needed; std (on by default) just adds the SipHash
+// RandomState as the default hasher and debug().
+#![no_std]
+#[cfg(any(feature = "std", test))]
+extern crate std;
+extern crate alloc;
+extern crate hashbrown;
+#[cfg(not(feature = "std"))]
+extern crate libm;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "ahash")]
+extern crate ahash;
+#[cfg(feature = "fxhash")]
+extern crate rustc_hash;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "rkyv")]
+extern crate rkyv;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "wasm")]
+extern crate js_sys;
+#[cfg(feature = "pyo3")]
+extern crate pyo3;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "proptest")]
+extern crate proptest;
+#[cfg(feature = "quickcheck")]
+extern crate quickcheck;
 
-use std::fmt::Debug;
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+use core::hash::{BuildHasher, Hash};
+use core::borrow::Borrow;
+use core::cmp::Eq;
 
-type Tag = u64;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::fmt::Debug;
+use core::mem::size_of;
+use core::ops::Bound;
+use alloc::sync::Arc;
+use core::sync::atomic::{fence, AtomicBool, AtomicU64};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
+use hashbrown::HashTable;
+
+mod width;
+mod fixed;
+mod two_level;
+mod deamortized;
+mod stable;
+mod adaptive;
+mod gaps;
+mod map;
+mod list;
+mod multi;
+mod partition;
+mod indexed;
+mod journal;
+mod diff;
+mod reconcile;
+mod patch;
+mod replicated;
+mod fractional;
+mod float;
+mod sort_key;
+mod cursor;
+mod topological;
+mod euler;
+mod markers;
+mod watchdog;
+mod cow;
+mod versioned;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(feature = "testing")]
+mod testing;
+#[cfg(feature = "testing")]
+mod checked;
+#[cfg(feature = "rkyv")]
+mod archive;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "pyo3")]
+mod python;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "std")]
+mod concurrent;
+#[cfg(feature = "std")]
+mod published;
+#[cfg(feature = "std")]
+mod bulk;
+#[cfg(feature = "std")]
+mod recorder;
+pub use width::TagWidth;
+pub use fixed::{FixedIter, FixedOrderMaintenance};
+pub use two_level::{AmortizedTop, TopLevel, TwoLevelIter, TwoLevelOrderMaintenance, BUCKET_CAPACITY};
+pub use deamortized::{DeamortizedOrderMaintenance, IncrementalTop};
+pub use stable::{StableIter, StableLabel, StableOrderMaintenance};
+pub use adaptive::{AdaptiveIter, AdaptiveOrderMaintenance};
+pub use gaps::{DenseRegion, GapReport};
+pub use map::{OrderedMap, OrderedMapIter};
+pub use list::{ListHandle, OrderedList, OrderedListIter};
+pub use multi::{MultiOrder, MultiOrderIter};
+pub use partition::{ListId, PartitionIter, PartitionedOrder};
+pub use indexed::IndexedOrderMaintenance;
+pub use journal::{JournaledOrderMaintenance, Op};
+pub use patch::PatchConflict;
+pub use reconcile::Reconciliation;
+pub use fractional::{fractional_key_between, fractional_keys_between, FractionalKeyError};
+pub use replicated::{Delta, ElementId, MalformedDelta, ReplicatedIter, ReplicatedSequence, SequenceOp, SiteId, VersionVector};
+pub use sort_key::{DirtyRows, SortKeyIter, SortKeyOrder};
+pub use cursor::{Cursor, CursorMut};
+pub use topological::{TopologicalError, TopologicalOrder};
+pub use euler::{EulerTourForest, SubtreeIter};
+pub use markers::Between;
+pub use watchdog::{CascadeAlarm, CascadeLimits};
+use watchdog::Watchdog;
+pub use cow::CowOrderMaintenance;
+pub use versioned::{VersionId, VersionedOrderMaintenance};
+#[cfg(feature = "rkyv")]
+pub use archive::ArchivedOrderMaintenance;
+#[cfg(feature = "wasm")]
+pub use wasm::JsOrderMaintenance;
+#[cfg(feature = "ffi")]
+pub use ffi::{OmKey, OmOrder, OmStatus};
+#[cfg(feature = "pyo3")]
+pub use python::{register as register_python, PyOrderMaintenance};
+#[cfg(feature = "testing")]
+pub use testing::{OpSequence, OrderGenerator};
+#[cfg(feature = "proptest")]
+pub use testing::{op_sequence_strategy, order_strategy};
+#[cfg(feature = "testing")]
+pub use checked::CheckedOrderMaintenance;
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentOrderMaintenance;
+#[cfg(feature = "std")]
+pub use published::PublishedOrderMaintenance;
+#[cfg(feature = "std")]
+pub use recorder::{RecordingOrderMaintenance, Repro, ReproFailure};
+// Without std there's no SipHash RandomState, so the default is hashbrown's.
+#[cfg(not(feature = "std"))]
+use hashbrown::DefaultHashBuilder as RandomState;
+
+type RawTag = u64;
+// An element's label in the order, from tag_of() or the iterator. Tags only
+// promise to compare like the elements they belong to (until the next
+// relabel, see epoch()), so they're good as sort keys in an external index
+// but there's nothing else to do with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tag<W = u64>(W);
+
+// One element: its key (the only copy of it we keep) and its neighbours in
+// the circular list, by their index in slots.
+#[derive(Debug)]
+struct Position<T, W>
+    where W: TagWidth {
+    key: T,
+    prev: Index,
+    next: Index,
+    tag: W,
+    // only there once someone has asked for handle(); kept in sync with tag
+    handle: Option<Arc<HandleState<W>>>,
+}
+impl<T, W> Position<T, W>
+    where W: TagWidth {
+    fn set_tag(&mut self, tag: W) {
+        self.tag = tag;
+        if let Some(ref handle) = self.handle {
+            W::store(&handle.tag, tag);
+        }
+    }
+    fn detach(&self) {
+        if let Some(ref handle) = self.handle {
+            handle.removed.store(true, Relaxed);
+        }
+    }
+}
+
+// A slot number. u32 keeps the links (and the hash index) at half the size
+// of usize ones; four billion elements ought to be enough for one order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Index(u32);
+impl Index {
+    fn get(self) -> usize {
+        self.0 as usize
+    }
+}
+// A dense arena of Positions. Vacant slots are chained into a free list
+// through the slots themselves, so removals leave no holes for long.
+#[derive(Debug)]
+struct Slots<T, W>
+    where W: TagWidth {
+    slots: Vec<Slot<T, W>>,
+    free: Option<Index>,
+}
 #[derive(Debug)]
-struct Position<T> {
-    prev: T,
-    next: T,
-    tag: Tag,
+enum Slot<T, W>
+    where W: TagWidth {
+    Occupied(Position<T, W>),
+    // the next free slot after this one
+    Vacant(Option<Index>),
+}
+impl<T, W> Slots<T, W>
+    where W: TagWidth {
+    fn new() -> Slots<T, W> {
+        Slots { slots: Vec::new(), free: None }
+    }
+    fn get(&self, index: Index) -> Option<&Position<T, W>> {
+        match self.slots.get(index.get()) {
+            Some(Slot::Occupied(position)) => Some(position),
+            _ => None,
+        }
+    }
+    fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+    }
+    fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+    fn occupied(&self) -> usize {
+        self.slots.iter().filter(|slot| match **slot { Slot::Occupied(_) => true, Slot::Vacant(_) => false }).count()
+    }
+    fn iter(&self) -> impl Iterator<Item = &Position<T, W>> {
+        self.slots.iter().filter_map(|slot| match *slot {
+            Slot::Occupied(ref position) => Some(position),
+            Slot::Vacant(_) => None,
+        })
+    }
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Position<T, W>> {
+        self.slots.iter_mut().filter_map(|slot| match *slot {
+            Slot::Occupied(ref mut position) => Some(position),
+            Slot::Vacant(_) => None,
+        })
+    }
+    // two different occupied slots at once
+    fn pair_mut(&mut self, a: Index, b: Index) -> (&mut Position<T, W>, &mut Position<T, W>) {
+        assert!(a != b);
+        let (low, high) = self.slots.split_at_mut(a.get().max(b.get()));
+        let (first, second) = match (&mut low[a.get().min(b.get())], &mut high[0]) {
+            (Slot::Occupied(first), Slot::Occupied(second)) => (first, second),
+            _ => panic!("vacant slot"),
+        };
+        if a < b { (first, second) } else { (second, first) }
+    }
+    // make gets the index it's going into, for the links
+    fn insert<F>(&mut self, make: F) -> Index
+        where F: FnOnce(Index) -> Position<T, W> {
+        match self.free {
+            Some(index) => {
+                self.free = match self.slots[index.get()] {
+                    Slot::Vacant(next_free) => next_free,
+                    Slot::Occupied(_) => unreachable!("occupied slot in the free list"),
+                };
+                self.slots[index.get()] = Slot::Occupied(make(index));
+                index
+            }
+            None => {
+                assert!(self.slots.len() < u32::MAX as usize, "too many elements");
+                let index = Index(self.slots.len() as u32);
+                self.slots.push(Slot::Occupied(make(index)));
+                index
+            }
+        }
+    }
+    fn remove(&mut self, index: Index) -> Position<T, W> {
+        match ::core::mem::replace(&mut self.slots[index.get()], Slot::Vacant(self.free)) {
+            Slot::Occupied(position) => {
+                self.free = Some(index);
+                position
+            }
+            Slot::Vacant(next_free) => {
+                self.slots[index.get()] = Slot::Vacant(next_free);
+                panic!("removing a vacant slot")
+            }
+        }
+    }
+}
+// Handles belong to the order they were taken from, so copies start without.
+impl<T, W> Clone for Slots<T, W>
+    where T: Clone, W: TagWidth {
+    fn clone(&self) -> Slots<T, W> {
+        let slots = self.slots.iter().map(|slot| match *slot {
+            Slot::Occupied(ref position) => Slot::Occupied(Position {
+                key: position.key.clone(),
+                prev: position.prev,
+                next: position.next,
+                tag: position.tag,
+                handle: None,
+            }),
+            Slot::Vacant(next_free) => Slot::Vacant(next_free),
+        });
+        Slots { slots: slots.collect(), free: self.free }
+    }
+}
+impl<T, W> ::core::ops::Index<Index> for Slots<T, W>
+    where W: TagWidth {
+    type Output = Position<T, W>;
+    fn index(&self, index: Index) -> &Position<T, W> {
+        self.get(index).expect("vacant slot")
+    }
+}
+impl<T, W> ::core::ops::IndexMut<Index> for Slots<T, W>
+    where W: TagWidth {
+    fn index_mut(&mut self, index: Index) -> &mut Position<T, W> {
+        match self.slots[index.get()] {
+            Slot::Occupied(ref mut position) => position,
+            Slot::Vacant(_) => panic!("vacant slot"),
+        }
+    }
+}
+
+// W is what the tags are made of (see TagWidth); like other hashers, other
+// widths than u64 start from default() or with_hasher().
+pub struct OrderMaintenance<T, S = RandomState, W = u64>
+    where T: Hash + Eq, W: TagWidth {
+    // Every element lives in a slot, and links are slot indices, so keys
+    // never need to be cloned.
+    slots: Slots<T, W>,
+    // slot indices, hashed by the keys in those slots
+    index: HashTable<Index>,
+    hasher: S,
+    front: Option<Index>,
+    // stored tags are offset by this (wrapping), so that rotating the
+    // circular list doesn't require relabeling; see rotate_to_front
+    rotation: W,
+    // bumped whenever an existing element's tag changes, so that OrderKeys
+    // taken before that can be told apart from current ones
+    epoch: u64,
+    // the rotation as seen by OrderedHandles, and the seqlock they read
+    // under (shared by all of them)
+    handle_shared: Arc<HandleShared<W>>,
+    on_relabel: Option<OnRelabel<T, W>>,
+    relabel_listener: Option<Listener<T, W>>,
+    // None until set_cascade_watchdog()
+    watchdog: Option<Watchdog>,
+    // None until enable_stats()
+    stats: Option<Stats>,
+    strategy: LabelingStrategy,
+    // Bender's T; None for the default, which depends on len()
+    overflow_threshold: Option<f64>,
+    // how big the caller said it's going to get; 0 for no idea
+    expected_len: usize,
+}
+// How to make room when a new element's tag would collide with a
+// neighbour's; see set_strategy. New tags always go halfway into the gap
+// (or a share of the way into the room at either end); the strategies only
+// differ in what they relabel once a gap has been used up. They all keep the
+// same order, and the same tags are valid under any of them, so it's fine to
+// switch back and forth.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LabelingStrategy {
+    // Bender et al: the smallest enclosing power-of-two range of tags that's
+    // sparse enough
+    #[default]
+    Bender,
+    // Dietz and Sleator: the fewest following elements j whose span is more
+    // than j^2
+    DietzSleator,
+    // everything, evenly; best when collisions are rare, e.g. random inserts
+    Midpoint,
+}
+// Orders that hash their keys with ahash or FxHash instead of SipHash: quicker,
+// but without HashDoS resistance, so only for keys an attacker can't pick.
+// new() is only for the default hasher; these start from default().
+#[cfg(feature = "ahash")]
+pub type AHashOrderMaintenance<T> = OrderMaintenance<T, ahash::RandomState>;
+#[cfg(feature = "fxhash")]
+pub type FxOrderMaintenance<T> = OrderMaintenance<T, rustc_hash::FxBuildHasher>;
+// 128-bit tags, for orders that see billions of inserts over their lifetime:
+// twice the memory per tag, but gaps between neighbours start out 2^64 times
+// as wide, so the same gap can be split about 126 times between relabels
+// instead of about 62 (Dietz-Sleator gains least: it only ever relabels
+// locally). The default overflow threshold follows the width. Like the other
+// aliases these start from default().
+pub type WideOrderMaintenance<T, S = RandomState> = OrderMaintenance<T, S, u128>;
+// Counters for how hard the relabeling is working, from stats().
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // how many times a rebalance ran
+    pub rebalances: u64,
+    // how many elements all of those relabeled, together
+    pub relabeled: u64,
+    // the most elements relabeled by a single rebalance
+    pub largest_cascade: usize,
+}
+// Roughly how much heap an order is using, from memory_usage(). Each key is
+// stored exactly once, in its slot, so size_of::<T>() is all it costs us;
+// anything a key owns on the heap itself (a String's bytes, say) isn't
+// counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub len: usize,
+    // slots allocated, occupied or not: removals leave slots to be reused,
+    // and set_expected_len reserves them ahead of time
+    pub slot_capacity: usize,
+    // key, links, tag, the handle pointer and padding
+    pub bytes_per_slot: usize,
+    // how many elements the hash index can hold before it grows
+    pub index_capacity: usize,
+    // everything the hash index allocated, control bytes included
+    pub index_bytes: usize,
+    // the state shared with OrderedHandles, for elements handle() was asked about
+    pub handle_bytes: usize,
+}
+impl MemoryUsage {
+    pub fn total_bytes(&self) -> usize {
+        self.slot_capacity * self.bytes_per_slot + self.index_bytes + self.handle_bytes
+    }
+    // including everything allocated ahead of time; 0 when empty
+    pub fn bytes_per_element(&self) -> f64 {
+        if self.len == 0 { 0.0 } else { self.total_bytes() as f64 / self.len as f64 }
+    }
+}
+// set_on_relabel's callback, in a box of its own so that the order can still
+// be Debug. It and the listener are Sync as well as Send, though only ever
+// called through &mut, so that an order stays Sync and can be read from
+// many threads at once (as PublishedOrderMaintenance's versions are).
+struct OnRelabel<T, W>(Box<RelabelFn<T, W>>);
+type RelabelFn<T, W> = dyn FnMut(&T, Tag<W>) + Send + Sync;
+// Like set_on_relabel, but told about a whole rebalance at once: relabeled
+// holds the contiguous run of elements it touched, in order, with their new
+// tags. Worth it when each notification costs a round trip somewhere.
+pub trait RelabelListener<T, W = u64> {
+    fn relabeled(&mut self, relabeled: &[(&T, Tag<W>)]);
+}
+struct Listener<T, W>(Box<dyn RelabelListener<T, W> + Send + Sync>);
+impl<T, W> Debug for Listener<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Listener(..)")
+    }
+}
+impl<T, W> Debug for OnRelabel<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("OnRelabel(..)")
+    }
+}
+// Everything that can go wrong when using the API; nothing in it panics on
+// bad input, it returns one of these instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderMaintenanceError {
+    // an element the operation acts on isn't in the order
+    NotPresent,
+    // the element to place something next to isn't in the order
+    AnchorMissing,
+    // the element being inserted is already in the order
+    AlreadyPresent,
+    // asked to place an element relative to itself
+    SelfInsert,
+    // an overflow threshold outside (1, 2]
+    InvalidThreshold,
+    // insert_only on an order that already has elements
+    NotEmpty,
+    // insert_between with elements that aren't next to each other
+    NotAdjacent,
+    // a FixedOrderMaintenance has no room left, or a SortKeyOrder none within
+    // its relabel budget
+    Full,
+}
+impl fmt::Display for OrderMaintenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            OrderMaintenanceError::NotPresent => "element is not present",
+            OrderMaintenanceError::AnchorMissing => "anchor element is not present",
+            OrderMaintenanceError::AlreadyPresent => "element is already present",
+            OrderMaintenanceError::SelfInsert => "cannot place an element relative to itself",
+            OrderMaintenanceError::InvalidThreshold => "overflow threshold must be more than 1 and at most 2",
+            OrderMaintenanceError::NotEmpty => "order is not empty",
+            OrderMaintenanceError::NotAdjacent => "elements are not adjacent",
+            OrderMaintenanceError::Full => "order is full",
+        })
+    }
+}
+impl Error for OrderMaintenanceError {}
+// What check_invariants() found wrong with the structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityError {
+    // there are elements but no front
+    MissingFront,
+    // the front isn't one of the elements
+    DanglingFront,
+    // some element's next isn't one of the elements
+    DanglingLink,
+    // some element's next doesn't have it as its prev
+    PrevNextMismatch,
+    // tags don't strictly increase from the front
+    TagInversion,
+    // some elements can't be reached from the front
+    Unreachable,
+}
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            IntegrityError::MissingFront => "elements are present but there is no front",
+            IntegrityError::DanglingFront => "front is not present",
+            IntegrityError::DanglingLink => "next element is not present",
+            IntegrityError::PrevNextMismatch => "prev and next links disagree",
+            IntegrityError::TagInversion => "tags are out of order",
+            IntegrityError::Unreachable => "not every element is reachable from the front",
+        })
+    }
+}
+impl Error for IntegrityError {}
+// insert_after's error type from before there was a crate-wide one
+pub type InsertError = OrderMaintenanceError;
+
+// A snapshot of an element's place in the order, from key(). It's Copy and
+// Ord so it can go in BTreeMaps and heaps, but it's only meaningful until
+// the next relabel: comparing keys from different epochs panics (try_cmp
+// returns None instead).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OrderKey<W = u64> {
+    tag: W,
+    epoch: u64,
+}
+impl<W> OrderKey<W>
+    where W: TagWidth {
+    pub fn try_cmp(&self, other: &OrderKey<W>) -> Option<Ordering> {
+        if self.epoch == other.epoch { Some(self.tag.cmp(&other.tag)) } else { None }
+    }
+}
+impl<W> Ord for OrderKey<W>
+    where W: TagWidth {
+    fn cmp(&self, other: &OrderKey<W>) -> Ordering {
+        self.try_cmp(other).expect("compared OrderKeys from different epochs")
+    }
+}
+impl<W> PartialOrd for OrderKey<W>
+    where W: TagWidth {
+    fn partial_cmp(&self, other: &OrderKey<W>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
-// sorry about the Clone, todo maybe index prev/next by tag somehow?
-// also maybe TODO custom Eq that treats tag exact values as irrelevant?
-// possibly by an iter that does something interesting
+// A live reference to an element's place in the order, from handle(). Unlike
+// OrderKey it stays correct across relabels (the order updates it), and it
+// can be compared without borrowing the OrderMaintenance at all, so handles
+// can go to other threads and be compared there while the order goes on
+// changing. A handle whose element was removed keeps its last place; see
+// is_removed().
+//
+// Anything that changes more than one tag at once (a rebalance, reverse,
+// swap_positions) does so inside a seqlock: the order bumps a shared
+// sequence number to odd before it starts and back to even once it's done,
+// and a comparison that saw it odd, or saw it change, reads again. So a
+// comparison never mixes tags from before and after a relabel, and never
+// waits on a lock; the writer pays two stores per change. (on_relabel and
+// the listener hear about it once it's over, so they can compare handles.)
+#[derive(Debug, Clone)]
+pub struct OrderedHandle<W = u64>(Arc<HandleState<W>>)
+    where W: TagWidth;
 #[derive(Debug)]
-pub struct OrderMaintenance<T>
-    where T: Hash + Eq + Clone {
-    positions: HashMap<T, Position<T>>,
-    front: Option<T>,
+struct HandleState<W>
+    where W: TagWidth {
+    tag: W::Atomic,
+    removed: AtomicBool,
+    shared: Arc<HandleShared<W>>,
+}
+#[derive(Debug)]
+struct HandleShared<W>
+    where W: TagWidth {
+    rotation: W::Atomic,
+    // odd while the order is changing handles' tags
+    sequence: AtomicU64,
+}
+impl<W> HandleShared<W>
+    where W: TagWidth {
+    fn new(rotation: W) -> HandleShared<W> {
+        HandleShared { rotation: rotation.new_atomic(), sequence: AtomicU64::new(0) }
+    }
+    // Whether this started a write (false inside one that already has).
+    // Only the order calls these, and it has &mut, so there's one writer.
+    fn begin_write(&self) -> bool {
+        let sequence = self.sequence.load(Relaxed);
+        if sequence % 2 == 1 {
+            return false;
+        }
+        self.sequence.store(sequence + 1, Relaxed);
+        fence(Release);
+        true
+    }
+    fn end_write(&self, began: bool) {
+        if began {
+            self.sequence.store(self.sequence.load(Relaxed) + 1, Release);
+        }
+    }
+    // read() again until it ran without a write in the middle of it
+    fn read<R, F>(&self, read: F) -> R
+        where F: Fn() -> R {
+        loop {
+            let before = self.sequence.load(Acquire);
+            if before % 2 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+            let result = read();
+            fence(Acquire);
+            if self.sequence.load(Relaxed) == before {
+                return result;
+            }
+        }
+    }
+}
+impl<W> OrderedHandle<W>
+    where W: TagWidth {
+    pub fn is_removed(&self) -> bool {
+        self.0.removed.load(Relaxed)
+    }
+    // Sorts handles from the same order into that order, all by tags read
+    // at the same moment. (Sorting with cmp instead would read each pair at
+    // a different moment, and an element moved partway through could make
+    // the comparisons disagree with each other.)
+    pub fn sort(handles: &mut [OrderedHandle<W>]) {
+        let shared = match handles.first() { None => return, Some(handle) => handle.0.shared.clone() };
+        assert!(handles.iter().all(|handle| Arc::ptr_eq(&handle.0.shared, &shared)), "sorted OrderedHandles from different orders");
+        let tags: Vec<W> = shared.read(|| handles.iter().map(OrderedHandle::tag).collect());
+        let mut by_tag: Vec<(W, OrderedHandle<W>)> = tags.into_iter().zip(handles.iter().cloned()).collect();
+        by_tag.sort_by_key(|&(tag, _)| tag);
+        for (handle, (_, sorted)) in handles.iter_mut().zip(by_tag) {
+            *handle = sorted;
+        }
+    }
+    // only meaningful inside HandleShared::read
+    fn tag(&self) -> W {
+        W::load(&self.0.tag).wrapping_sub(W::load(&self.0.shared.rotation))
+    }
+}
+impl<W> Ord for OrderedHandle<W>
+    where W: TagWidth {
+    fn cmp(&self, other: &OrderedHandle<W>) -> Ordering {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return Ordering::Equal;
+        }
+        let shared = &self.0.shared;
+        assert!(Arc::ptr_eq(shared, &other.0.shared), "compared OrderedHandles from different orders");
+        shared.read(|| self.tag().cmp(&other.tag()))
+    }
+}
+impl<W> PartialOrd for OrderedHandle<W>
+    where W: TagWidth {
+    fn partial_cmp(&self, other: &OrderedHandle<W>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<W> PartialEq for OrderedHandle<W>
+    where W: TagWidth {
+    fn eq(&self, other: &OrderedHandle<W>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<W> Eq for OrderedHandle<W>
+    where W: TagWidth {}
+
+// What remove() hands back: the key itself, the tag it had, and its former
+// neighbours (None at either end of the order), which are still in the order
+// and so are borrowed from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedEntry<'a, T, W = u64>
+    where T: 'a {
+    pub value: T,
+    pub prev: Option<&'a T>,
+    pub next: Option<&'a T>,
+    pub tag: Tag<W>,
+}
+fn bound_map<T, U, F>(bound: Bound<T>, f: F) -> Result<Bound<U>, OrderMaintenanceError>
+    where F: Fn(T) -> Result<U, OrderMaintenanceError> {
+    Ok(match bound {
+        Bound::Included(value) => Bound::Included(f(value)?),
+        Bound::Excluded(value) => Bound::Excluded(f(value)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+// f64::powf is std-only
+#[cfg(feature = "std")]
+fn powf(x: f64, y: f64) -> f64 {
+    x.powf(y)
+}
+#[cfg(not(feature = "std"))]
+fn powf(x: f64, y: f64) -> f64 {
+    libm::pow(x, y)
 }
 #[derive(Debug)]
-pub struct IterWithTag<'a, T>
-    where T: Hash + Eq + Clone + 'a {
-    om: &'a OrderMaintenance<T>,
-    first: Option<T>,
-    current: Option<T>,
-}
-impl<'a, T> Iterator for IterWithTag<'a, T>
-    where T: Hash + Eq + Clone {
-    type Item = (T, Tag);
+pub struct IterWithTag<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a OrderMaintenance<T, S, W>,
+    current: Option<Index>,
+}
+impl<'a, T, S, W> Iterator for IterWithTag<'a, T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    type Item = (&'a T, Tag<W>);
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(current) = self.current.clone() {
-            let current_position: &Position<T> = self.om.positions.get(&current).unwrap();
-            let next = Some(current_position.next.clone());
-            if next != self.first {
-                self.current = next;
-            } else {
-                self.current = None;
+        let current = self.current?;
+        let current_position = &self.om.slots[current];
+        if Some(current_position.next) != self.om.front {
+            self.current = Some(current_position.next);
+        } else {
+            self.current = None;
+        }
+        Some((&current_position.key, Tag(current_position.tag.wrapping_sub(self.om.rotation))))
+    }
+}
+impl<'a, T, S, W> IntoIterator for &'a OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    type Item = (&'a T, Tag<W>);
+    type IntoIter = IterWithTag<'a, T, S, W>;
+    fn into_iter(self) -> IterWithTag<'a, T, S, W> {
+        self.iter_values_with_tags()
+    }
+}
+// Every key, in no particular order (slot order, which is roughly the order
+// they went in, with removed ones' slots reused), from keys(). It reads the
+// slots front to back instead of following links, so it's the quick way to
+// go over the set of elements when their order doesn't matter.
+#[derive(Debug)]
+pub struct Keys<'a, T, W = u64>
+    where T: 'a, W: TagWidth {
+    slots: core::slice::Iter<'a, Slot<T, W>>,
+    remaining: usize,
+}
+impl<'a, T, W> Iterator for Keys<'a, T, W>
+    where W: TagWidth {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        for slot in &mut self.slots {
+            if let Slot::Occupied(ref position) = *slot {
+                self.remaining -= 1;
+                return Some(&position.key);
             }
-            Some((current, current_position.tag))
+        }
+        None
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+impl<'a, T, W> ExactSizeIterator for Keys<'a, T, W>
+    where W: TagWidth {}
+// For when the keys themselves are wanted, not borrows of them: taking the
+// order apart, in order, with the tags the keys had.
+#[derive(Debug)]
+pub struct IntoIterWithTag<T, W = u64>(alloc::vec::IntoIter<(T, Tag<W>)>);
+impl<T, W> Iterator for IntoIterWithTag<T, W> {
+    type Item = (T, Tag<W>);
+    fn next(&mut self) -> Option<(T, Tag<W>)> {
+        self.0.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+impl<T, W> DoubleEndedIterator for IntoIterWithTag<T, W> {
+    fn next_back(&mut self) -> Option<(T, Tag<W>)> {
+        self.0.next_back()
+    }
+}
+impl<T, W> ExactSizeIterator for IntoIterWithTag<T, W> {}
+impl<T, S, W> IntoIterator for OrderMaintenance<T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    type Item = (T, Tag<W>);
+    type IntoIter = IntoIterWithTag<T, W>;
+    fn into_iter(self) -> IntoIterWithTag<T, W> {
+        let mut order = Vec::with_capacity(self.index.len());
+        let mut current = self.front;
+        while let Some(index) = current {
+            order.push(index);
+            let next = self.slots[index].next;
+            current = if self.front == Some(next) { None } else { Some(next) };
+        }
+        let rotation = self.rotation;
+        let mut positions: Vec<Option<(T, W)>> = self.slots.slots.into_iter().map(|slot| match slot {
+            Slot::Occupied(position) => Some((position.key, position.tag)),
+            Slot::Vacant(_) => None,
+        }).collect();
+        let owned: Vec<(T, Tag<W>)> = order.into_iter().map(|index| {
+            let (key, tag) = positions[index.get()].take().expect("vacant slot in the order");
+            (key, Tag(tag.wrapping_sub(rotation)))
+        }).collect();
+        IntoIterWithTag(owned.into_iter())
+    }
+}
+
+
+impl<T, S, W> Default for OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher + Default, W: TagWidth {
+    fn default() -> OrderMaintenance<T, S, W> {
+        OrderMaintenance::with_hasher(S::default())
+    }
+}
+
+// A snapshot: O(n), cloning every key once, and the tags come along as they
+// are. What isn't cloned: OrderedHandles still follow the original (the copy
+// hands out handles of its own), and the on_relabel callback and relabel
+// listener (and the cascade watchdog) stay with the original too.
+impl<T, S, W> Clone for OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Clone, S: Clone, W: TagWidth {
+    fn clone(&self) -> OrderMaintenance<T, S, W> {
+        OrderMaintenance {
+            slots: self.slots.clone(),
+            index: self.index.clone(),
+            hasher: self.hasher.clone(),
+            front: self.front,
+            rotation: self.rotation,
+            epoch: self.epoch,
+            handle_shared: Arc::new(HandleShared::new(W::load(&self.handle_shared.rotation))),
+            on_relabel: None,
+            relabel_listener: None,
+            watchdog: None,
+            stats: self.stats,
+            strategy: self.strategy,
+            overflow_threshold: self.overflow_threshold,
+            expected_len: self.expected_len,
+        }
+    }
+}
+
+// Equal when they hold the same elements in the same order, whatever the
+// tags (or their width, or the hashers): O(n).
+impl<T, S, W, S2, W2> PartialEq<OrderMaintenance<T, S2, W2>> for OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth, S2: BuildHasher, W2: TagWidth {
+    fn eq(&self, other: &OrderMaintenance<T, S2, W2>) -> bool {
+        self.same_order_as(other)
+    }
+}
+impl<T, S, W> Eq for OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {}
+
+// The elements in order: {:?} is just them, and {:#?} has each one's tag
+// with it, one per line. The links, slots and hash index are left out;
+// check_invariants is for those.
+impl<T, S, W> Debug for OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Debug, W: TagWidth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let iter = IterWithTag { om: self, current: self.front };
+        f.write_str("OrderMaintenance ")?;
+        if f.alternate() {
+            f.debug_map().entries(iter).finish()
         } else {
-            None
+            f.debug_list().entries(iter.map(|(value, _)| value)).finish()
         }
     }
 }
 
+// {:#?} to stderr
+#[cfg(feature = "std")]
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Debug, W: TagWidth {
+    pub fn debug(&self) {
+        std::eprintln!("{:#?}", self);
+    }
+}
 
 impl<T> OrderMaintenance<T>
-    where T: Hash + Eq + Clone + Debug {
+    where T: Hash + Eq {
     pub fn new() -> OrderMaintenance<T> {
-        OrderMaintenance { positions: HashMap::new(), front: None }
-    }
-    pub fn debug(&self) {
-        eprintln!("om:{:?} full {:?}", Vec::from_iter(self.iter_values_with_tags()), self);
+        OrderMaintenance::with_hasher(RandomState::default())
     }
-    pub fn iter_values_with_tags<'a>(&'a self) -> IterWithTag<'a, T> {
-        let front = self.front().map(|t| t.clone());
-        IterWithTag{om: self, first: front.clone(), current: front.clone()}
+    // see set_expected_len
+    pub fn with_expected_len(expected_len: usize) -> OrderMaintenance<T> {
+        let mut om = OrderMaintenance::new();
+        om.set_expected_len(expected_len);
+        om
     }
-    pub fn compare(&self, a: &T, b: &T) -> Option<Ordering> {
-        let a_tag = self.positions.get(a)?.tag;
-        let b_tag = self.positions.get(b)?.tag;
-        Some(a_tag.cmp(&b_tag))
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // Hashing keys with something other than the std SipHash. The keys are
+    // only ever hashed to find them again, so a faster hasher is fine as long
+    // as nobody hostile gets to pick them (see the ahash/fxhash features).
+    pub fn with_hasher(hasher: S) -> OrderMaintenance<T, S, W> {
+        OrderMaintenance {
+            slots: Slots::new(),
+            index: HashTable::new(),
+            hasher,
+            front: None,
+            rotation: W::ZERO,
+            epoch: 0,
+            handle_shared: Arc::new(HandleShared::new(W::ZERO)),
+            on_relabel: None,
+            relabel_listener: None,
+            watchdog: None,
+            stats: None,
+            strategy: LabelingStrategy::Bender,
+            overflow_threshold: None,
+            expected_len: 0,
+        }
     }
-    pub fn remove(&mut self, value: &T) -> bool {
-        if let Some(position) = self.positions.remove(value) {
-            let prev = position.prev.clone();
-            let next = position.next.clone();
-            self.positions.get_mut(&position.prev).map(|p| { p.next = next; });
-            self.positions.get_mut(&position.next).map(|p| { p.prev = prev; });
-            true
-        } else {
-            false
+    // The same order with tags of another width, spread evenly over the new
+    // tag space: O(n). The strategy, overflow threshold and stats carry over,
+    // but the relabel callback and listener (which take tags of the old
+    // width) don't, and nothing updates handles taken from the old order.
+    pub fn into_width<V>(self) -> OrderMaintenance<T, S, V>
+        where V: TagWidth {
+        let mut order = Vec::with_capacity(self.len());
+        let mut current = self.front;
+        while let Some(index) = current {
+            order.push(index);
+            let next = self.slots[index].next;
+            current = if self.front == Some(next) { None } else { Some(next) };
         }
+        let OrderMaintenance { slots, hasher, stats, strategy, overflow_threshold, expected_len, .. } = self;
+        let mut keys: Vec<Option<T>> = slots.slots.into_iter().map(|slot| match slot {
+            Slot::Occupied(position) => Some(position.key),
+            Slot::Vacant(_) => None,
+        }).collect();
+        let mut om = OrderMaintenance::with_hasher(hasher);
+        om.stats = stats;
+        om.strategy = strategy;
+        om.overflow_threshold = overflow_threshold;
+        om.expected_len = expected_len;
+        let mut order = order.into_iter().map(|index| keys[index.get()].take().expect("vacant slot in the order"));
+        if let Some(first) = order.next() {
+            om.insert_only(first).expect("new order is empty");
+            let front = om.front.unwrap();
+            om.insert_all_after_index(front, order.collect()).expect("keys were unique");
+        }
+        om
     }
-    pub fn insert_only(&mut self, value: T) {
-        assert!(self.len() == 0);
-        self.positions.insert(value.clone(), Position {
-            prev: value.clone(),
-            next: value.clone(),
-            tag: 0
-        });
-        self.front = Some(value.clone());
-        self.debug();
-    }
-    pub fn insert_after(&mut self, after: &T, value: T) {
-        // todo:
-        // error if no after
-        // error if value is already somewhere (else)
-        // error if after == value
-        let (prev_tag, next) = {
-                let prev_position = self.positions.get(after).unwrap();
-                (prev_position.tag, prev_position.next.clone())
-            };
-        let next_tag = self.positions.get(&next).unwrap().tag;
-        // TODO: wrapping, mid way, etc ?
-        let tag = if prev_tag == Tag::max_value() { prev_tag } else { prev_tag + 1 };
-        let position = Position {
-                prev: after.clone(),
-                next: next.clone(),
-                tag: tag,
-            };
-        self.positions.insert(value.clone(), position);
-        self.positions.get_mut(after).map(|p| { p.next = value.clone() });
-        self.positions.get_mut(&next).map(|p| { p.prev = value.clone() });
-        if tag == prev_tag || tag == next_tag {
-            self.rebalance(&value);
+    pub fn iter_values_with_tags(&self) -> IterWithTag<'_, T, S, W> {
+        IterWithTag{om: self, current: self.front}
+    }
+    // see Keys
+    pub fn keys(&self) -> Keys<'_, T, W> {
+        Keys { slots: self.slots.slots.iter(), remaining: self.len() }
+    }
+    pub fn entry(&mut self, value: T) -> Entry<'_, T, S, W> {
+        match self.find(&value) {
+            Some(index) => Entry::Occupied(OccupiedEntry { om: self, index }),
+            None => Entry::Vacant(VacantEntry { om: self, value }),
         }
-        self.debug();
-        self.verify_valid_structure();
     }
-    pub fn len(&self) -> usize {
-        self.positions.len()
-    }
-    fn front(&self) -> Option<T> {
-        self.front.clone()
-        /*if let Some((value1, position1)) = self.positions.iter().next() {
-            let mut value: T = value1.clone();
-            let mut prev: T = position1.prev.clone();
-            let mut lowest_tag: Tag = position1.tag;
-            let lowest_value = loop {
-                if let Some(prev_position) = self.positions.get(&prev) {
-                    if prev_position.tag < lowest_tag {
-                        value = prev;
-                        prev = prev_position.prev.clone();
-                        lowest_tag = prev_position.tag;
-                        continue;
-                    }
-                }
-                break value.clone();
-            };
-            Some(lowest_value)
-        } else {
-            None
-        }*/
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).is_some()
     }
-    /*
-    fn next_cyclic<'a, 'b>(&'a self, value: &'b T) -> &'a T {
-        &self.positions.get(value).unwrap().next
+    // the neighbours in the (linear, not circular) order
+    pub fn successor<Q>(&self, value: &Q) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let next = self.slots[self.find(value)?].next;
+        if self.front == Some(next) { None } else { Some(&self.slots[next].key) }
     }
-    fn next_linear<'a, 'b>(&'a self, value: &'b T) -> Option<&'a T> {
-        let next = self.next_cyclic(value);
-        if next == value || self.positions.get(next).unwrap().tag < self.positions.get(value).unwrap().tag {
-            None
-        } else {
-            Some(next)
-        }
-    }
-    */
-    fn verify_list_integrity(&self) {
-        if let Some(ref front) = self.front {
-            let mut value: &T = &front;
-            let mut next: &T = &self.positions.get(&front).expect("front not in positions").next;
-            let mut num_seen: u64 = 0;
-            loop {
-                num_seen += 1;
-                if let Some(ref next_position) = self.positions.get(&next) {
-                    if &next_position.prev != value {
-                        panic!("integrity of prev/next");
-                    }
-                    if next == front {
-                        break;
-                    }
-                    value = next;
-                    next = &next_position.next;
-                } else {
-                    panic!("there should always be a next");
-                }
+    pub fn predecessor<Q>(&self, value: &Q) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        if self.front == Some(index) { None } else { Some(&self.slots[self.slots[index].prev].key) }
+    }
+    // n steps forwards from value (value itself for 0), or None if the order
+    // ends first. O(n): walks the list; see IndexedOrderMaintenance for better.
+    pub fn nth_after<Q>(&self, value: &Q, n: usize) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let mut index = self.find(value)?;
+        for _ in 0..n {
+            index = self.slots[index].next;
+            if self.front == Some(index) {
+                return None;
+            }
+        }
+        Some(&self.slots[index].key)
+    }
+    pub fn nth_before<Q>(&self, value: &Q, n: usize) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let mut index = self.find(value)?;
+        for _ in 0..n {
+            if self.front == Some(index) {
+                return None;
             }
-            if num_seen != self.positions.len() as u64 {
-                panic!("not all seen");
+            index = self.slots[index].prev;
+        }
+        Some(&self.slots[index].key)
+    }
+    pub fn key<Q>(&self, value: &Q) -> Option<OrderKey<W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let tag = self.tag(self.find(value)?);
+        Some(OrderKey { tag, epoch: self.epoch })
+    }
+    pub fn handle<Q>(&mut self, value: &Q) -> Option<OrderedHandle<W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        let shared = &self.handle_shared;
+        let position = &mut self.slots[index];
+        let tag = position.tag;
+        let handle = position.handle.get_or_insert_with(|| Arc::new(HandleState {
+            tag: tag.new_atomic(),
+            removed: AtomicBool::new(false),
+            shared: shared.clone(),
+        }));
+        Some(OrderedHandle(handle.clone()))
+    }
+    pub fn tag_of<Q>(&self, value: &Q) -> Option<Tag<W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).map(|index| Tag(self.tag(index)))
+    }
+    // Called with every element whose tag a rebalance changes, and its new
+    // tag, for keeping an external copy of the tags up to date. Tag changes
+    // you asked for yourself (moves, swaps, reverse, rotate_to_front) aren't
+    // reported, except for whatever relabeling they set off.
+    pub fn set_on_relabel<F>(&mut self, on_relabel: F)
+        where F: FnMut(&T, Tag<W>) + Send + Sync + 'static {
+        self.on_relabel = Some(OnRelabel(Box::new(on_relabel)));
+    }
+    pub fn clear_on_relabel(&mut self) {
+        self.on_relabel = None;
+    }
+    // Starts counting (from zero, if it already was).
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(Stats::default());
+    }
+    pub fn disable_stats(&mut self) {
+        self.stats = None;
+    }
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+    // how the tags are spread out right now; see GapReport
+    pub fn gap_report(&self) -> GapReport<W> {
+        GapReport::of(self.iter_values_with_tags().map(|(_, tag)| tag))
+    }
+    pub fn set_strategy(&mut self, strategy: LabelingStrategy) {
+        self.strategy = strategy;
+    }
+    pub fn strategy(&self) -> LabelingStrategy {
+        self.strategy
+    }
+    // The T in Bender et al's analysis: a rebalance relabels the smallest
+    // enclosing range of 2^i tags holding at most T^i elements. Nearer 1 it
+    // relabels bigger ranges less often, leaving the tags evenly spread; at
+    // 2 it relabels as little as it can each time, packing tags tightly and
+    // relabeling more often. The default is (2 * len())^(1/(bits - 2)), so
+    // that the whole tag space (i = 62 or so, for u64 tags) always has room
+    // for everyone (or expected_len() in place of len(), if that's bigger).
+    pub fn set_overflow_threshold(&mut self, threshold: f64) -> Result<(), OrderMaintenanceError> {
+        if !(threshold > 1.0 && threshold <= 2.0) {
+            return Err(OrderMaintenanceError::InvalidThreshold);
+        }
+        self.overflow_threshold = Some(threshold);
+        Ok(())
+    }
+    pub fn clear_overflow_threshold(&mut self) {
+        self.overflow_threshold = None;
+    }
+    // the T the next rebalance will use
+    pub fn overflow_threshold(&self) -> f64 {
+        self.overflow_threshold.unwrap_or_else(|| powf(2.0 * (self.planned_len().max(1) as f64), 1.0 / (W::BITS - 2) as f64))
+    }
+    // A hint that the order is going to grow to about expected_len elements.
+    // Everything that's otherwise sized from len() (the default overflow
+    // threshold, and how much of the room at either end an insert there takes)
+    // is sized for at least this many instead, so the tags are laid out for
+    // the order it's going to be rather than the small one it is now; and room
+    // for that many is allocated up front. 0 takes the hint back.
+    pub fn set_expected_len(&mut self, expected_len: usize) {
+        self.expected_len = expected_len;
+        let additional = expected_len.saturating_sub(self.len());
+        self.slots.reserve(additional);
+        let slots = &self.slots;
+        let hasher = &self.hasher;
+        self.index.reserve(additional, |&i| hasher.hash_one(&slots[i].key));
+    }
+    pub fn expected_len(&self) -> usize {
+        self.expected_len
+    }
+    fn planned_len(&self) -> usize {
+        self.len().max(self.expected_len)
+    }
+    pub fn set_relabel_listener<L>(&mut self, listener: L)
+        where L: RelabelListener<T, W> + Send + Sync + 'static {
+        self.relabel_listener = Some(Listener(Box::new(listener)));
+    }
+    pub fn clear_relabel_listener(&mut self) {
+        self.relabel_listener = None;
+    }
+    // false once anything has been relabeled since the key was taken
+    pub fn is_current(&self, key: &OrderKey<W>) -> bool {
+        key.epoch == self.epoch
+    }
+    // For hot loops comparing the same few elements over and over: take
+    // their keys once, and this compares them without hashing anything. None
+    // once either key is out of date (take them again), and a key from
+    // another order is only caught if its epoch happens to differ. A key of
+    // an element that's since been removed still compares as where it was.
+    pub fn compare_cached(&self, a: &OrderKey<W>, b: &OrderKey<W>) -> Option<Ordering> {
+        if a.epoch == self.epoch && b.epoch == self.epoch { Some(a.tag.cmp(&b.tag)) } else { None }
+    }
+    // Goes up (wrapping) every time any element's tag changes: relabels,
+    // moves, swaps, reverse and rotate_to_front. Inserting and removing
+    // leave everyone else's tags alone, so they don't count.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a_tag = self.tag(self.find(a)?);
+        let b_tag = self.tag(self.find(b)?);
+        Some(a_tag.cmp(&b_tag))
+    }
+    // These are false if any of the keys is missing.
+    pub fn is_before<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.compare(a, b) == Some(Ordering::Less)
+    }
+    pub fn is_after<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.compare(a, b) == Some(Ordering::Greater)
+    }
+    // strictly between: lo < x < hi
+    pub fn is_between<Q>(&self, x: &Q, lo: &Q, hi: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        match (self.find(x), self.find(lo), self.find(hi)) {
+            (Some(x), Some(lo), Some(hi)) => {
+                let x_tag = self.tag(x);
+                self.tag(lo) < x_tag && x_tag < self.tag(hi)
             }
+            _ => false,
+        }
+    }
+    // The earliest/latest of the given keys in the order; keys that aren't
+    // present are skipped, and None means none of them were.
+    pub fn min_by_order<'a, Q, I>(&self, keys: I) -> Option<&'a Q>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized + 'a, I: IntoIterator<Item = &'a Q> {
+        keys.into_iter()
+            .filter_map(|key| self.find(key).map(|index| (key, self.tag(index))))
+            .min_by_key(|&(_, tag)| tag)
+            .map(|(key, _)| key)
+    }
+    pub fn max_by_order<'a, Q, I>(&self, keys: I) -> Option<&'a Q>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized + 'a, I: IntoIterator<Item = &'a Q> {
+        keys.into_iter()
+            .filter_map(|key| self.find(key).map(|index| (key, self.tag(index))))
+            .max_by_key(|&(_, tag)| tag)
+            .map(|(key, _)| key)
+    }
+    // A total order over all T, for sort_by and friends: present keys in the
+    // maintained order, then every missing key (all Equal to each other).
+    pub fn comparator(&self) -> impl Fn(&T, &T) -> Ordering + '_ {
+        move |a, b| self.sort_key(a).cmp(&self.sort_key(b))
+    }
+    // Sorts the slice into the maintained order, using the same policy as
+    // comparator() for keys that aren't present (the sort is stable).
+    pub fn sort_slice(&self, slice: &mut [T]) {
+        self.sort_slice_by_key(slice, |value| value)
+    }
+    pub fn sort_slice_by_key<E, Q, F>(&self, slice: &mut [E], mut key: F)
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized, F: FnMut(&E) -> &Q {
+        slice.sort_by_cached_key(|item| self.sort_key(key(item)))
+    }
+    // missing keys sort after everything present
+    fn sort_key<Q>(&self, value: &Q) -> (bool, W)
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        match self.find(value) {
+            Some(index) => (false, self.tag(index)),
+            None => (true, W::ZERO),
+        }
+    }
+    // Any element can be removed, including the front, the last one and the
+    // only one; the front moves on to the next element when it's removed.
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<RemovedEntry<'_, T, W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        Some(self.remove_index(index))
+    }
+    // The deque ends: take off the first or last element, with the tag it
+    // had; None when empty. The one after (or before) it takes its place at
+    // the end, and no one else's tag changes.
+    pub fn pop_front(&mut self) -> Option<(T, Tag<W>)> {
+        let front = self.front?;
+        let removed = self.remove_index(front);
+        Some((removed.value, removed.tag))
+    }
+    pub fn pop_back(&mut self) -> Option<(T, Tag<W>)> {
+        let last = self.last()?;
+        let removed = self.remove_index(last);
+        Some((removed.value, removed.tag))
+    }
+    fn remove_index(&mut self, index: Index) -> RemovedEntry<'_, T, W> {
+        let was_front = self.front == Some(index);
+        let position = self.deallocate(index);
+        let was_last = self.front == Some(position.next);
+        if self.is_empty() {
+            self.front = None;
         } else {
-            if self.positions.len() != 0 {
-                panic!("positions but no front");
+            self.slots[position.prev].next = position.next;
+            self.slots[position.next].prev = position.prev;
+            if was_front {
+                self.front = Some(position.next);
             }
         }
+        self.verify_valid_structure();
+        RemovedEntry {
+            value: position.key,
+            prev: if was_front { None } else { Some(&self.slots[position.prev].key) },
+            next: if was_last { None } else { Some(&self.slots[position.next].key) },
+            tag: Tag(position.tag.wrapping_sub(self.rotation)),
+        }
     }
-    fn verify_valid_structure(&self) {
-        self.verify_list_integrity();
-        let mut previous_tag: Option<Tag> = None;
-        let mut num_seen: u64 = 0;
-        for (_, tag) in self.iter_values_with_tags() {
-            num_seen += 1;
-            if let Some(ptag) = previous_tag {
-                if !(ptag < tag) {
-                    panic!("ordering problem");
+    // Removes everything from start to end (in the maintained order) in one
+    // pass, touching only the links at the two edges of the range.
+    // Returns how many elements were removed; an empty range removes nothing.
+    pub fn remove_range<Q>(&mut self, start: Bound<&Q>, end: Bound<&Q>) -> Result<usize, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let start = bound_map(start, |value| self.find(value).ok_or(OrderMaintenanceError::NotPresent))?;
+        let end = bound_map(end, |value| self.find(value).ok_or(OrderMaintenanceError::NotPresent))?;
+        Ok(self.remove_range_indices(start, end))
+    }
+    fn remove_range_indices(&mut self, start: Bound<Index>, end: Bound<Index>) -> usize {
+        let front = match self.front { None => return 0, Some(front) => front };
+        let last = self.slots[front].prev;
+        let first = match start {
+            Bound::Included(a) => a,
+            Bound::Excluded(a) => {
+                if a == last {
+                    return 0;
                 }
+                self.slots[a].next
             }
-            previous_tag = Some(tag);
-        }
-        if num_seen != self.positions.len() as u64 {
-            panic!("not all seen in iter");
-        }
-        /*
-        // TODO maybe verify list integrity and then tag ordering? idk
-        if let Some((value1, position1)) = self.positions.iter().next() {
-            let mut value: T = value1.clone();
-            let mut prev: T = position1.prev.clone();
-            let mut next: T = position1.next.clone();
-            let mut lowest_tag: Tag = position1.tag;
-            let lowest_value = loop {
-                if let Some(prev_position) = self.positions.get(&prev) {
-                    if prev_position.tag < lowest_tag {
-                        value = prev;
-                        prev = prev_position.prev.clone();
-                        next = prev_position.next.clone();
-                        lowest_tag = prev_position.tag;
-                        continue;
-                    }
-                }
-                break value.clone();
-            };
-            let mut num_seen: u64 = 0;
-            let mut tag: Tag = lowest_tag;
-            loop {
-                num_seen += 1;
-                if let Some(next_position) = self.positions.get(&next) {
-                    if next_position.prev != value {
-                        panic!("integrity of prev/next");
-                    }
-                    if next == lowest_value {
-                        break;
-                    }
-                    if tag >= next_position.tag {
-                        panic!("sequencing bug");
-                    }
-                    tag = next_position.tag;
-                    value = next;
-                    next = next_position.next.clone();
-                } else {
-                    panic!("there should always be a next");
+            Bound::Unbounded => front,
+        };
+        let until = match end {
+            Bound::Included(b) => b,
+            Bound::Excluded(b) => {
+                if b == front {
+                    return 0;
                 }
+                self.slots[b].prev
             }
-            if num_seen != self.positions.len() as u64 {
-                panic!("not all seen");
+            Bound::Unbounded => last,
+        };
+        if self.tag(first) > self.tag(until) {
+            return 0;
+        }
+        let before = self.slots[first].prev;
+        let after = self.slots[until].next;
+        let mut removed = 0;
+        let mut index = first;
+        loop {
+            let position = self.deallocate(index);
+            removed += 1;
+            if index == until {
+                break;
             }
-        }*/
+            index = position.next;
+        }
+        if self.is_empty() {
+            self.front = None;
+            return removed;
+        }
+        self.slots[before].next = after;
+        self.slots[after].prev = before;
+        if first == front {
+            self.front = Some(after);
+        }
+        self.verify_valid_structure();
+        removed
     }
-    fn rebalance(&mut self, value: &T) {
-       let front = match self.front.clone() {None => return, Some(a) => a};
-       let mut base_tag: Tag = self.positions.get(value).unwrap().tag;
-       let mut mask: Tag = 0;
-       let mut threshold: f64 = 1.0;
-       let mut first: T = value.clone();
-       let mut last: T = value.clone();
-       let mut num_items: usize = 1;
-       let multiplier: f64 = 2.0 / (2.0 * (self.len() as f64)).powf(1.0 / 62.0); // ??
-       loop {
-           {
-               let mut prev: T;
-               //let mut first_tag: Tag;
-               {
-                   let first_position = self.positions.get(&first).unwrap();
-                   prev = first_position.prev.clone();
-                   //first_tag = first_position.tag;
-               }
-               loop {
-                   let prev_position = self.positions.get(&prev).unwrap();
-                   let prev_tag = prev_position.tag;
-                   if first != front && prev_tag &! mask == base_tag {
-                       first = prev;
-                       prev = prev_position.prev.clone();
-                       //first_tag = prev_position.tag;
-                       num_items += 1;
-                   } else {
-                       break;
-                   }
-               }
-           }
-           {
-               let mut next: T;
-               //let mut last_tag: Tag;
-               {
-                   let last_position = self.positions.get(&last).unwrap();
-                   next = last_position.next.clone();
-                   //last_tag = last_position.tag;
-               }
-               loop {
-                   let next_position = self.positions.get(&next).unwrap();
-                   let next_tag = next_position.tag;
-                   if next != front && next_tag &! mask == base_tag {
-                       last = next;
-                       next = next_position.next.clone();
-                       //last_tag = next_position.tag;
-                       num_items += 1;
-                   } else {
-                       break;
-                   }
-               }
-           }
-           let increment = (mask + 1) / (num_items as Tag);
-           if (increment as f64) >= threshold {
-               let mut item = first;
-               let mut new_tag = base_tag;
-               while item != last {
-                   let item_position = self.positions.get_mut(&item).unwrap();
-                   item_position.tag = new_tag;
-                   new_tag += increment;
-                   item = item_position.next.clone();
-               }
-               self.positions.get_mut(&item).unwrap().tag = new_tag;
-               return;
-           }
-           mask = (mask << 1) + 1;
-           base_tag = base_tag &! mask;
-           threshold *= multiplier;
-       }
+    // drop everything after (resp. before) the anchor, which itself stays
+    pub fn truncate_after<Q>(&mut self, after: &Q) -> Result<usize, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.find(after).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        Ok(self.remove_range_indices(Bound::Excluded(after), Bound::Unbounded))
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn basically() {
-        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
-        assert_eq!(om.len(), 0);
-        om.insert_only("bob");
-        assert_eq!(om.len(), 1);
-        om.insert_after(&"bob", "carol");
+    pub fn truncate_before<Q>(&mut self, before: &Q) -> Result<usize, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let before = self.find(before).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        Ok(self.remove_range_indices(Bound::Unbounded, Bound::Excluded(before)))
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        if !self.is_empty() {
+            return Err(OrderMaintenanceError::NotEmpty);
+        }
+        // the middle of the tag space, so there's as much room to grow
+        // backwards as forwards
+        let tag = W::MAX.half() + W::ONE;
+        let rotation = self.rotation;
+        let index = self.allocate(value, tag.wrapping_add(rotation));
+        self.front = Some(index);
+        #[cfg(feature = "log")]
+        trace!("insert_only: tag {:?}", tag);
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.find_anchor(after, &value)?;
+        self.insert_after_index(after, value).map(|_| ())
+    }
+    fn insert_after_index(&mut self, after: Index, value: T) -> Result<Index, OrderMaintenanceError> {
+        self.check_absent(&value)?;
+        let index = self.allocate(value, W::ZERO);
+        self.link_after(index, after);
+        #[cfg(feature = "log")]
+        trace!("insert_after: tag {:?}, len {}", self.tag(index), self.len());
+        self.verify_valid_structure();
+        Ok(index)
+    }
+    // Links the whole run in, then spreads its tags evenly over the gap after
+    // the anchor, so there is at most one rebalance however many values there are.
+    // Nothing is inserted unless every value can be.
+    pub fn insert_all_after<Q, I>(&mut self, after: &Q, values: I) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized, I: IntoIterator<Item = T> {
+        let after = self.find(after).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        self.insert_all_after_index(after, values.into_iter().collect())
+    }
+    fn insert_all_after_index(&mut self, after: Index, values: Vec<T>) -> Result<(), OrderMaintenanceError> {
+        self.check_run(after, &values)?;
+        if !values.is_empty() {
+            self.link_run_after(after, values);
+        }
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // Every value can go in next to anchor: none of them is it, or already
+    // there, or there twice.
+    fn check_run(&self, anchor: Index, values: &[T]) -> Result<(), OrderMaintenanceError> {
+        // hashed with our own hasher, so this works whatever S is
+        let mut seen: HashTable<&T> = HashTable::with_capacity(values.len());
+        for value in values {
+            if value == &self.slots[anchor].key {
+                return Err(OrderMaintenanceError::SelfInsert);
+            }
+            self.check_absent(value)?;
+            let hasher = &self.hasher;
+            match seen.entry(hasher.hash_one(value), |&seen| seen == value, |&seen| hasher.hash_one(seen)) {
+                hashbrown::hash_table::Entry::Occupied(_) => return Err(OrderMaintenanceError::AlreadyPresent),
+                hashbrown::hash_table::Entry::Vacant(entry) => { entry.insert(value); }
+            }
+        }
+        Ok(())
+    }
+    // values, checked and not empty, linked in after after with their tags
+    // spread evenly over the gap there
+    fn link_run_after(&mut self, after: Index, values: Vec<T>) {
+        let prev_tag = self.tag(after);
+        let next = self.slots[after].next;
+        let gap = if self.front == Some(next) { W::MAX - prev_tag } else { self.tag(next) - prev_tag };
+        // zero if there isn't room for them all
+        let step = gap / W::from_usize(values.len() + 1);
+        let mut tag = prev_tag;
+        let mut prev = after;
+        for value in values {
+            tag = tag + step;
+            let rotation = self.rotation;
+            let index = self.allocate(value, tag.wrapping_add(rotation));
+            {
+                let position = &mut self.slots[index];
+                position.prev = prev;
+                position.next = next;
+            }
+            self.slots[prev].next = index;
+            self.slots[next].prev = index;
+            prev = index;
+        }
+        if step == W::ZERO {
+            // everything new shares the anchor's tag; one rebalance spreads the whole run
+            let first = self.slots[after].next;
+            self.make_room(first);
+        }
+    }
+    // a must be immediately before b; the new tag goes in the middle of their gap
+    pub fn insert_between<Q>(&mut self, a: &Q, b: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.find_anchor(a, &value)?;
+        let b = self.find_anchor(b, &value)?;
+        self.insert_between_indices(a, b, value)
+    }
+    fn insert_between_indices(&mut self, a: Index, b: Index, value: T) -> Result<(), OrderMaintenanceError> {
+        self.check_absent(&value)?;
+        if self.slots[a].next != b || self.front == Some(b) {
+            return Err(OrderMaintenanceError::NotAdjacent);
+        }
+        let prev_tag = self.tag(a);
+        let next_tag = self.tag(b);
+        let index = self.allocate(value, W::ZERO);
+        self.link_after_with_tag(index, a, prev_tag + (next_tag - prev_tag).half());
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // new takes over old's place (and tag) in the order
+    pub fn replace_key<Q>(&mut self, old: &Q, new: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let old = self.find(old).ok_or(OrderMaintenanceError::NotPresent)?;
+        self.replace_key_index(old, new)
+    }
+    fn replace_key_index(&mut self, index: Index, new: T) -> Result<(), OrderMaintenanceError> {
+        if self.slots[index].key == new {
+            return Ok(());
+        }
+        self.check_absent(&new)?;
+        self.unindex(index);
+        self.slots[index].key = new;
+        self.reindex(index);
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // Both move_* take the element out of its current place and relink it
+    // next to the anchor, keeping its slot (no remove/insert).
+    pub fn move_after<Q>(&mut self, value: &Q, after: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        let after = self.find(after).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        self.move_after_index(value, after)
+    }
+    fn move_after_index(&mut self, value: Index, after: Index) -> Result<(), OrderMaintenanceError> {
+        if value == after {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        if self.slots[after].next == value && self.front != Some(value) {
+            return Ok(());
+        }
+        self.unlink(value);
+        self.link_after(value, after);
+        self.verify_valid_structure();
+        Ok(())
+    }
+    pub fn move_before<Q>(&mut self, value: &Q, before: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        let before = self.find(before).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        self.move_before_index(value, before)
+    }
+    fn move_before_index(&mut self, value: Index, before: Index) -> Result<(), OrderMaintenanceError> {
+        if value == before {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        if self.slots[before].prev == value && self.front != Some(before) {
+            return Ok(());
+        }
+        self.unlink(value);
+        self.link_before(value, before);
+        self.verify_valid_structure();
+        Ok(())
+    }
+    pub fn move_to_front<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        let front = self.front.unwrap();
+        if front == value {
+            return Ok(());
+        }
+        self.unlink(value);
+        self.link_before(value, front);
+        self.verify_valid_structure();
+        Ok(())
+    }
+    pub fn move_to_back<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        if self.last() == Some(value) {
+            return Ok(());
+        }
+        self.unlink(value);
+        let last = self.last().unwrap();
+        self.link_after(value, last);
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // The two elements trade places (and tags). Places are slots, so this
+    // just swaps the keys (and their handles) between the two slots.
+    pub fn swap_positions<Q>(&mut self, a: &Q, b: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.find(a).ok_or(OrderMaintenanceError::NotPresent)?;
+        let b = self.find(b).ok_or(OrderMaintenanceError::NotPresent)?;
+        self.swap_positions_indices(a, b);
+        Ok(())
+    }
+    fn swap_positions_indices(&mut self, a: Index, b: Index) {
+        if a == b {
+            return;
+        }
+        self.unindex(a);
+        self.unindex(b);
+        {
+            let (x, y) = self.slots.pair_mut(a, b);
+            ::core::mem::swap(&mut x.key, &mut y.key);
+            ::core::mem::swap(&mut x.handle, &mut y.handle);
+            let (x_tag, y_tag) = (x.tag, y.tag);
+            let began = self.handle_shared.begin_write();
+            x.set_tag(x_tag);
+            y.set_tag(y_tag);
+            self.handle_shared.end_write(began);
+        }
+        self.reindex(a);
+        self.reindex(b);
+        self.relabeled();
+        self.verify_valid_structure();
+    }
+    // O(n): relabels everything evenly over the whole tag space, with as much
+    // room at either end as between neighbours, the way a rebalance that has
+    // to use the whole space would. Worth doing after lots of removals, or
+    // before a burst of inserts, instead of paying for cascades in the middle
+    // of it. Counts as a rebalance (stats, on_relabel, the listener, epoch).
+    pub fn rebalance_all(&mut self) {
+        self.relabel_everything();
+        self.verify_valid_structure();
+    }
+    // O(n): flips every prev/next pair and mirrors the tags within the tag
+    // universe, so the gaps between neighbours are kept as they were.
+    pub fn reverse(&mut self) {
+        let last = match self.last() { None => return, Some(last) => last };
+        let rotation = self.rotation;
+        let began = self.handle_shared.begin_write();
+        for position in self.slots.iter_mut() {
+            ::core::mem::swap(&mut position.prev, &mut position.next);
+            let tag = W::MAX - position.tag.wrapping_sub(rotation);
+            position.set_tag(tag);
+        }
+        self.front = Some(last);
+        self.rotation = W::ZERO;
+        W::store(&self.handle_shared.rotation, W::ZERO);
+        self.handle_shared.end_write(began);
+        self.relabeled();
+        self.verify_valid_structure();
+    }
+    // The list is circular, so making value the front is just a matter of
+    // moving the rotation offset to its tag: O(1), nothing is relabeled.
+    pub fn rotate_to_front<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        let tag = self.slots[index].tag;
+        if self.rotation != tag {
+            self.rotation = tag;
+            // a u128 rotation is stored in two halves
+            let began = self.handle_shared.begin_write();
+            W::store(&self.handle_shared.rotation, tag);
+            self.handle_shared.end_write(began);
+            self.relabeled();
+        }
+        self.front = Some(index);
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // the key as we store it, which may carry more than Hash and Eq look at
+    fn stored<Q>(&self, value: &Q) -> Option<&T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).map(|index| &self.slots[index].key)
+    }
+    // Only for the parts of a key that its Hash and Eq don't look at, like
+    // OrderedMap's values: changing anything else would lose it in the index.
+    fn stored_mut<Q>(&mut self, value: &Q) -> Option<&mut T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        Some(&mut self.slots[index].key)
+    }
+    // The slot holding value, if it's present. The public methods take
+    // anything T borrows as (like HashMap), and resolve it with this before
+    // doing the real work on slot indices.
+    fn find<Q>(&self, value: &Q) -> Option<Index>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let hash = self.hasher.hash_one(value);
+        self.index.find(hash, |&index| self.slots[index].key.borrow() == value).cloned()
+    }
+    fn find_anchor<Q>(&self, anchor: &Q, value: &T) -> Result<Index, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if value.borrow() == anchor {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        self.find(anchor).ok_or(OrderMaintenanceError::AnchorMissing)
+    }
+    fn check_absent(&self, value: &T) -> Result<(), OrderMaintenanceError> {
+        if self.find(value).is_some() { Err(OrderMaintenanceError::AlreadyPresent) } else { Ok(()) }
+    }
+    // A new slot for key, linked only to itself, and findable by its key.
+    fn allocate(&mut self, key: T, tag: W) -> Index {
+        let index = self.slots.insert(|index| Position { key, prev: index, next: index, tag, handle: None });
+        self.reindex(index);
+        if let Some(ref mut watchdog) = self.watchdog {
+            watchdog.inserted();
+        }
+        index
+    }
+    // Empties the slot, leaving its neighbours' links for the caller to fix.
+    fn deallocate(&mut self, index: Index) -> Position<T, W> {
+        self.unindex(index);
+        let position = self.slots.remove(index);
+        position.detach();
+        position
+    }
+    fn reindex(&mut self, index: Index) {
+        let slots = &self.slots;
+        let hasher = &self.hasher;
+        let hash = hasher.hash_one(&slots[index].key);
+        self.index.insert_unique(hash, index, |&i| hasher.hash_one(&slots[i].key));
+    }
+    fn unindex(&mut self, index: Index) {
+        let hash = self.hasher.hash_one(&self.slots[index].key);
+        if let Ok(entry) = self.index.find_entry(hash, |&i| i == index) {
+            entry.remove();
+        }
+    }
+    // Takes value out of the circular list (fixing up front). It keeps its
+    // slot, but with stale links and tag: the caller must relink it, and it
+    // gets a new tag then.
+    fn unlink(&mut self, index: Index) {
+        self.relabeled();
+        let (prev, next) = {
+            let position = &self.slots[index];
+            (position.prev, position.next)
+        };
+        if next == index {
+            self.front = None;
+            return;
+        }
+        self.slots[prev].next = next;
+        self.slots[next].prev = prev;
+        if self.front == Some(index) {
+            self.front = Some(next);
+        }
+    }
+    fn relabeled(&mut self) {
+        self.epoch = self.epoch.wrapping_add(1);
+    }
+    fn tag(&self, index: Index) -> W {
+        self.slots[index].tag.wrapping_sub(self.rotation)
+    }
+    // index must be allocated but not currently linked into the list
+    fn link_after(&mut self, index: Index, after: Index) {
+        // halfway into the gap, so that inserting at the same spot again
+        // doesn't collide straight away (as prev_tag + 1 would)
+        let prev_tag = self.tag(after);
+        let next = self.slots[after].next;
+        let tag = if self.front == Some(next) {
+            prev_tag + self.edge_step(W::MAX - prev_tag)
+        } else {
+            prev_tag + (self.tag(next) - prev_tag).half()
+        };
+        self.link_after_with_tag(index, after, tag);
+    }
+    // How far past the last element (or before the first) a new one goes,
+    // given the room left there. Halving it, as between elements, would run
+    // into the edge of the tag space after log2(room) appends, and at the
+    // edge there's nothing to make room with but relabeling everyone; taking
+    // 1/(len + 1) of it instead lasts about len * ln(room) appends, which pays
+    // for that relabel. Zero means there's no room at all.
+    fn edge_step(&self, room: W) -> W {
+        if room == W::ZERO {
+            return W::ZERO;
+        }
+        let step = room / W::from_usize(self.planned_len() + 1);
+        if step == W::ZERO { W::ONE } else { step }
+    }
+    // tag may collide with a neighbour's, in which case we make room
+    fn link_after_with_tag(&mut self, index: Index, after: Index, tag: W) {
+        let prev_tag = self.tag(after);
+        let next = self.slots[after].next;
+        let next_tag = self.tag(next);
+        let collides = tag == prev_tag || tag == next_tag;
+        self.place(index, after, next, tag, collides);
+        self.slots[after].next = index;
+        self.slots[next].prev = index;
+        if collides {
+            self.make_room(index);
+        }
+    }
+    // Sets index's links and tag (but not its neighbours' links). A tag
+    // that collides is left out of index's handle: make_room will relabel
+    // index along with its neighbours, handle and all, in one go.
+    fn place(&mut self, index: Index, prev: Index, next: Index, tag: W, collides: bool) {
+        let tag = tag.wrapping_add(self.rotation);
+        let position = &mut self.slots[index];
+        position.prev = prev;
+        position.next = next;
+        if collides {
+            position.tag = tag;
+        } else {
+            // a u128 tag is stored in two halves
+            let began = self.handle_shared.begin_write();
+            position.set_tag(tag);
+            self.handle_shared.end_write(began);
+        }
+    }
+    fn link_before(&mut self, index: Index, before: Index) {
+        if self.front != Some(before) {
+            let prev = self.slots[before].prev;
+            self.link_after(index, prev);
+            return;
+        }
+        // becoming the new front: there's no prev to make room after
+        let next_tag = self.tag(before);
+        let last = self.slots[before].prev;
+        let tag = next_tag - self.edge_step(next_tag);
+        let collides = tag == next_tag;
+        self.place(index, last, before, tag, collides);
+        self.slots[last].next = index;
+        self.slots[before].prev = index;
+        self.front = Some(index);
+        if collides {
+            self.make_room(index);
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    // Whether other holds exactly these elements in exactly this order,
+    // whatever the tags, hashers or widths. == is this too, but an assertion
+    // that two replicas agree reads better saying so. O(n), stopping at the
+    // first difference, and O(1) if the lengths differ.
+    pub fn same_order_as<S2, W2>(&self, other: &OrderMaintenance<T, S2, W2>) -> bool
+        where S2: BuildHasher, W2: TagWidth {
+        self.len() == other.len()
+            && self.iter_values_with_tags().map(|(value, _)| value).eq(other.iter_values_with_tags().map(|(value, _)| value))
+    }
+    // O(n), for the handles
+    pub fn memory_usage(&self) -> MemoryUsage {
+        // an Arc's allocation is the two counts and then what it holds
+        let per_handle = 2 * size_of::<usize>() + size_of::<HandleState<W>>();
+        let handles = self.slots.iter().filter(|position| position.handle.is_some()).count();
+        MemoryUsage {
+            len: self.len(),
+            slot_capacity: self.slots.capacity(),
+            bytes_per_slot: size_of::<Slot<T, W>>(),
+            index_capacity: self.index.capacity(),
+            index_bytes: self.index.allocation_size(),
+            handle_bytes: handles * per_handle,
+        }
+    }
+    fn last(&self) -> Option<Index> {
+        self.front.map(|front| self.slots[front].prev)
+    }
+    // Walks the whole list checking everything the rest of the code relies
+    // on; O(n). Nothing the public API does should ever make this fail.
+    pub fn check_invariants(&self) -> Result<(), IntegrityError> {
+        let front = match self.front {
+            None if self.is_empty() => return Ok(()),
+            None => return Err(IntegrityError::MissingFront),
+            Some(front) => front,
+        };
+        let slot = |index: Index| self.slots.get(index);
+        let mut index = front;
+        let mut position = slot(front).ok_or(IntegrityError::DanglingFront)?;
+        let mut num_seen: usize = 1;
+        loop {
+            // an element that lookups can't find is as good as unreachable
+            if self.find(&position.key) != Some(index) {
+                return Err(IntegrityError::Unreachable);
+            }
+            let next = position.next;
+            let next_position = slot(next).ok_or(IntegrityError::DanglingLink)?;
+            if next_position.prev != index {
+                return Err(IntegrityError::PrevNextMismatch);
+            }
+            if next == front {
+                break;
+            }
+            if next_position.tag.wrapping_sub(self.rotation) <= position.tag.wrapping_sub(self.rotation) {
+                return Err(IntegrityError::TagInversion);
+            }
+            num_seen += 1;
+            if num_seen > self.len() {
+                return Err(IntegrityError::Unreachable);
+            }
+            index = next;
+            position = next_position;
+        }
+        if num_seen != self.len() || num_seen != self.slots.occupied() {
+            return Err(IntegrityError::Unreachable);
+        }
+        Ok(())
+    }
+    // O(n), so only with debug assertions or the strict-checks feature;
+    // release builds get the amortized bounds they were promised.
+    fn verify_valid_structure(&self) {
+        if !cfg!(any(debug_assertions, feature = "strict-checks")) {
+            return;
+        }
+        if let Err(error) = self.check_invariants() {
+            panic!("{}", error);
+        }
+    }
+    fn make_room(&mut self, index: Index) {
+        match self.strategy {
+            LabelingStrategy::Bender => self.rebalance(index),
+            LabelingStrategy::DietzSleator => self.dietz_sleator(index),
+            LabelingStrategy::Midpoint => self.relabel_everything(),
+        }
+    }
+    // Gives the num_items elements starting at first the tags base_tag,
+    // base_tag + increment, ..., telling everyone who asked to be told.
+    fn relabel_run(&mut self, first: Index, num_items: usize, base_tag: W, increment: W) {
+        self.relabeled();
+        let rotation = self.rotation;
+        let mut item = first;
+        let mut new_tag = base_tag;
+        let mut batch = if self.relabel_listener.is_some() { Some(Vec::with_capacity(num_items)) } else { None };
+        let began = self.handle_shared.begin_write();
+        for i in 0..num_items {
+            let item_position = &mut self.slots[item];
+            item_position.set_tag(new_tag.wrapping_add(rotation));
+            if let Some(ref mut batch) = batch {
+                batch.push((item, Tag(new_tag)));
+            }
+            if i + 1 < num_items {
+                new_tag = new_tag + increment;
+                item = item_position.next;
+            }
+        }
+        self.handle_shared.end_write(began);
+        if let Some(OnRelabel(ref mut on_relabel)) = self.on_relabel {
+            let mut item = first;
+            for _ in 0..num_items {
+                let item_position = &self.slots[item];
+                on_relabel(&item_position.key, Tag(item_position.tag.wrapping_sub(rotation)));
+                item = item_position.next;
+            }
+        }
+        if let Some(ref mut stats) = self.stats {
+            stats.rebalances += 1;
+            stats.relabeled += num_items as u64;
+            stats.largest_cascade = stats.largest_cascade.max(num_items);
+        }
+        let len = self.len();
+        if let Some(ref mut watchdog) = self.watchdog {
+            watchdog.relabeled(num_items, len);
+        }
+        if let (Some(batch), Some(&mut Listener(ref mut listener))) = (batch, self.relabel_listener.as_mut()) {
+            let slots = &self.slots;
+            let batch: Vec<(&T, Tag<W>)> = batch.into_iter()
+                .map(|(item, tag)| (&slots[item].key, tag))
+                .collect();
+            listener.relabeled(&batch);
+        }
+    }
+    // Spreads everything evenly over the whole tag space, leaving as much room
+    // before the first and after the last as between any two, for the next
+    // inserts at either end.
+    fn relabel_everything(&mut self) {
+        if let Some(front) = self.front {
+            let len = self.len();
+            let increment = W::MAX / W::from_usize(len + 1);
+            self.relabel_run(front, len, increment, increment);
+        }
+    }
+    // Relabels index and the fewest elements j after it whose tags, together
+    // with the next one's, span more than j^2 past index's predecessor, evenly
+    // over that span; if there's no such run before the end, everything.
+    fn dietz_sleator(&mut self, index: Index) {
+        let front = match self.front { None => return, Some(front) => front };
+        // a front element's "predecessor" is just below tag 0
+        // (and the end's successor just above MAX); None for either
+        let lo = if index == front { None } else { Some(self.tag(self.slots[index].prev)) };
+        let mut last = index;
+        let mut j: usize = 1;
+        loop {
+            let next = self.slots[last].next;
+            let hi = if next == front { None } else { Some(self.tag(next)) };
+            // the tags strictly between lo and hi, one less than their span
+            // (and capped at MAX: there's one more when both are None); hi
+            // can equal lo partway through a run from insert_all_after
+            let room = match (lo, hi) {
+                (Some(lo), Some(hi)) => if hi > lo { hi - lo - W::ONE } else { W::ZERO },
+                (None, Some(hi)) => hi,
+                (Some(lo), None) => W::MAX - lo,
+                (None, None) => W::MAX,
+            };
+            if room >= W::from_usize(j.saturating_mul(j)) {
+                // the span / (j + 1)
+                let count = W::from_usize(j);
+                let increment = (room - count) / (count + W::ONE) + W::ONE;
+                let base_tag = match lo { Some(lo) => lo + increment, None => increment - W::ONE };
+                self.relabel_run(index, j, base_tag, increment);
+                return;
+            }
+            if next == front {
+                return self.relabel_everything();
+            }
+            last = next;
+            j += 1;
+        }
+    }
+    fn rebalance(&mut self, index: Index) {
+       let front = match self.front {None => return, Some(a) => a};
+       #[cfg(feature = "tracing")]
+       let _span = tracing::debug_span!("rebalance", len = self.len()).entered();
+       let rotation = self.rotation;
+       let mut base_tag: W = self.tag(index);
+       let mut mask: W = W::ZERO;
+       let mut threshold: f64 = 1.0;
+       let mut first = index;
+       let mut last = index;
+       let mut num_items: usize = 1;
+       let multiplier: f64 = 2.0 / self.overflow_threshold();
+       loop {
+           {
+               let mut prev = self.slots[first].prev;
+               loop {
+                   let prev_position = &self.slots[prev];
+                   let prev_tag = prev_position.tag.wrapping_sub(rotation);
+                   if first != front && prev_tag & !mask == base_tag {
+                       first = prev;
+                       prev = prev_position.prev;
+                       num_items += 1;
+                   } else {
+                       break;
+                   }
+               }
+           }
+           {
+               let mut next = self.slots[last].next;
+               loop {
+                   let next_position = &self.slots[next];
+                   let next_tag = next_position.tag.wrapping_sub(rotation);
+                   if next != front && next_tag & !mask == base_tag {
+                       last = next;
+                       next = next_position.next;
+                       num_items += 1;
+                   } else {
+                       break;
+                   }
+               }
+           }
+           if mask == W::MAX {
+               // the whole tag space has to do, however small T is (and it
+               // keeps headroom at both ends)
+               self.relabel_everything();
+           } else {
+               let increment = (mask + W::ONE) / W::from_usize(num_items);
+               if increment.to_f64() < threshold {
+                   mask = (mask << 1) + W::ONE;
+                   base_tag = base_tag & !mask;
+                   threshold *= multiplier;
+                   continue;
+               }
+               self.relabel_run(first, num_items, base_tag, increment);
+           }
+           #[cfg(feature = "tracing")]
+           tracing::debug!(mask_level = mask.count_ones(), items = num_items, "relabeled");
+           #[cfg(feature = "log")]
+           trace!("rebalance: relabeled {} items at mask level {}", num_items, mask.count_ones());
+           return;
+       }
+    }
+}
+
+pub enum Entry<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    Occupied(OccupiedEntry<'a, T, S, W>),
+    Vacant(VacantEntry<'a, T, S, W>),
+}
+pub struct OccupiedEntry<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a mut OrderMaintenance<T, S, W>,
+    index: Index,
+}
+pub struct VacantEntry<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a mut OrderMaintenance<T, S, W>,
+    value: T,
+}
+impl<'a, T, S, W> Entry<'a, T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn key(&self) -> &T {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+    pub fn or_insert_after<Q>(self, after: &Q) -> Result<OccupiedEntry<'a, T, S, W>, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        match self {
+            Entry::Occupied(entry) => Ok(entry),
+            Entry::Vacant(entry) => entry.insert_after(after),
+        }
+    }
+}
+impl<'a, T, S, W> OccupiedEntry<'a, T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn key(&self) -> &T {
+        &self.om.slots[self.index].key
+    }
+    pub fn compare<Q>(&self, other: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let other = self.om.find(other)?;
+        Some(self.om.tag(self.index).cmp(&self.om.tag(other)))
+    }
+    pub fn remove(self) -> RemovedEntry<'a, T, W> {
+        self.om.remove_index(self.index)
+    }
+}
+impl<'a, T, S, W> VacantEntry<'a, T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn key(&self) -> &T {
+        &self.value
+    }
+    pub fn into_key(self) -> T {
+        self.value
+    }
+    pub fn insert_after<Q>(self, after: &Q) -> Result<OccupiedEntry<'a, T, S, W>, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.om.find_anchor(after, &self.value)?;
+        let index = self.om.insert_after_index(after, self.value)?;
+        Ok(OccupiedEntry { om: self.om, index })
+    }
+}
+
+// A cheap LCG for the tests, so that inserts land all over the place: each
+// call gives a number below its argument.
+#[cfg(test)]
+fn test_random(mut seed: u64) -> impl FnMut(usize) -> usize {
+    move |below| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((seed >> 33) as usize) % below
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::string::{String, ToString};
+    use std::vec;
+
+    #[test]
+    fn basically() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        assert_eq!(om.len(), 0);
+        om.insert_only("bob").unwrap();
+        assert_eq!(om.len(), 1);
+        om.insert_after(&"bob", "carol").unwrap();
         assert_eq!(om.len(), 2);
-        om.insert_after(&"bob", "james");
+        om.insert_after(&"bob", "james").unwrap();
         assert_eq!(om.len(), 3);
-        om.insert_after(&"carol", "gene");
+        om.insert_after(&"carol", "gene").unwrap();
         assert_eq!(om.len(), 4);
         assert_eq!(om.compare(&"bob", &"carol"), Some(Ordering::Less));
         assert_eq!(om.compare(&"bob", &"james"), Some(Ordering::Less));
@@ -351,5 +2052,1181 @@ mod tests {
         assert_eq!(om.compare(&"bob", &"bob"), Some(Ordering::Equal));
         assert_eq!(om.compare(&"carol", &"james"), Some(Ordering::Greater));
     }
-}
 
+    fn order(om: &OrderMaintenance<&'static str>) -> Vec<&'static str> {
+        om.iter_values_with_tags().map(|(v, _)| *v).collect()
+    }
+
+    #[test]
+    fn moving() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.insert_after(&"c", "d").unwrap();
+        om.move_after(&"a", &"c").unwrap();
+        assert_eq!(order(&om), vec!["b", "c", "a", "d"]);
+        om.move_before(&"d", &"b").unwrap();
+        assert_eq!(order(&om), vec!["d", "b", "c", "a"]);
+        assert_eq!(om.compare(&"d", &"b"), Some(Ordering::Less));
+        om.move_after(&"d", &"a").unwrap();
+        assert_eq!(order(&om), vec!["b", "c", "a", "d"]);
+        om.move_before(&"c", &"a").unwrap();
+        assert_eq!(order(&om), vec!["b", "c", "a", "d"]);
+        om.move_before(&"a", &"b").unwrap();
+        om.move_before(&"d", &"a").unwrap();
+        assert_eq!(order(&om), vec!["d", "a", "b", "c"]);
+        assert_eq!(om.compare(&"d", &"a"), Some(Ordering::Less));
+        assert_eq!(om.compare(&"a", &"c"), Some(Ordering::Less));
+        assert_eq!(om.len(), 4);
+    }
+
+    #[test]
+    fn moving_missing() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        assert_eq!(om.move_after(&"a", &"nobody"), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(om.move_before(&"nobody", &"a"), Err(OrderMaintenanceError::NotPresent));
+        assert_eq!(om.move_before(&"a", &"a"), Err(OrderMaintenanceError::SelfInsert));
+        assert_eq!(om.move_to_back(&"nobody"), Err(OrderMaintenanceError::NotPresent));
+    }
+
+    #[test]
+    fn moving_to_ends() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.move_to_front(&"a").unwrap();
+        om.move_to_back(&"a").unwrap();
+        assert_eq!(order(&om), vec!["a"]);
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.move_to_front(&"c").unwrap();
+        assert_eq!(order(&om), vec!["c", "a", "b"]);
+        om.move_to_back(&"c").unwrap();
+        assert_eq!(order(&om), vec!["a", "b", "c"]);
+        om.move_to_back(&"b").unwrap();
+        assert_eq!(order(&om), vec!["a", "c", "b"]);
+        om.move_to_front(&"b").unwrap();
+        om.move_to_front(&"c").unwrap();
+        assert_eq!(order(&om), vec!["c", "b", "a"]);
+        assert_eq!(om.compare(&"c", &"a"), Some(Ordering::Less));
+        assert_eq!(om.compare(&"b", &"a"), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn swapping() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.swap_positions(&"a", &"b").unwrap();
+        assert_eq!(order(&om), vec!["b", "a"]);
+        om.insert_after(&"a", "c").unwrap();
+        om.insert_after(&"c", "d").unwrap();
+        om.insert_after(&"d", "e").unwrap();
+        // b a c d e
+        om.swap_positions(&"a", &"d").unwrap();
+        assert_eq!(order(&om), vec!["b", "d", "c", "a", "e"]);
+        om.swap_positions(&"c", &"d").unwrap();
+        assert_eq!(order(&om), vec!["b", "c", "d", "a", "e"]);
+        om.swap_positions(&"e", &"b").unwrap();
+        assert_eq!(order(&om), vec!["e", "c", "d", "a", "b"]);
+        om.swap_positions(&"a", &"b").unwrap();
+        assert_eq!(order(&om), vec!["e", "c", "d", "b", "a"]);
+        assert_eq!(om.compare(&"e", &"c"), Some(Ordering::Less));
+        assert_eq!(om.compare(&"b", &"a"), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn reversing() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.reverse();
+        assert_eq!(om.len(), 0);
+        om.insert_only("a").unwrap();
+        om.reverse();
+        assert_eq!(order(&om), vec!["a"]);
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.reverse();
+        assert_eq!(order(&om), vec!["c", "b", "a"]);
+        assert_eq!(om.compare(&"c", &"a"), Some(Ordering::Less));
+        om.insert_after(&"a", "d").unwrap();
+        om.insert_after(&"c", "e").unwrap();
+        assert_eq!(order(&om), vec!["c", "e", "b", "a", "d"]);
+        om.reverse();
+        assert_eq!(order(&om), vec!["d", "a", "b", "e", "c"]);
+        assert_eq!(om.compare(&"b", &"e"), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn rotating() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.insert_after(&"c", "d").unwrap();
+        om.rotate_to_front(&"c").unwrap();
+        assert_eq!(order(&om), vec!["c", "d", "a", "b"]);
+        assert_eq!(om.compare(&"d", &"a"), Some(Ordering::Less));
+        assert_eq!(om.compare(&"b", &"c"), Some(Ordering::Greater));
+        om.insert_after(&"d", "e").unwrap();
+        om.insert_after(&"b", "f").unwrap();
+        om.move_to_front(&"a").unwrap();
+        assert_eq!(order(&om), vec!["a", "c", "d", "e", "b", "f"]);
+        om.rotate_to_front(&"e").unwrap();
+        om.reverse();
+        assert_eq!(order(&om), vec!["d", "c", "a", "f", "b", "e"]);
+        assert_eq!(om.compare(&"a", &"f"), Some(Ordering::Less));
+        om.rotate_to_front(&"f").unwrap();
+        om.swap_positions(&"f", &"e").unwrap();
+        assert_eq!(order(&om), vec!["e", "b", "f", "d", "c", "a"]);
+        assert_eq!(om.compare(&"e", &"a"), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn inserting_between() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "z").unwrap();
+        for &value in &["b", "c", "d", "e", "f", "g", "h"] {
+            let last = *order(&om).iter().rev().nth(1).unwrap();
+            om.insert_between(&last, &"z", value).unwrap();
+        }
+        assert_eq!(order(&om), vec!["a", "b", "c", "d", "e", "f", "g", "h", "z"]);
+        om.insert_between(&"a", &"b", "ab").unwrap();
+        assert_eq!(om.compare(&"a", &"ab"), Some(Ordering::Less));
+        assert_eq!(om.compare(&"ab", &"b"), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn inserting_between_nonadjacent() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        assert_eq!(om.insert_between(&"a", &"c", "x"), Err(OrderMaintenanceError::NotAdjacent));
+        assert_eq!(om.insert_between(&"c", &"a", "x"), Err(OrderMaintenanceError::NotAdjacent));
+        assert_eq!(om.insert_between(&"a", &"b", "c"), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(om.len(), 3);
+    }
+
+    #[test]
+    fn inserting_all_after() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        om.insert_all_after(&0, 1..100).unwrap();
+        om.insert_all_after(&99, vec![]).unwrap();
+        om.insert_after(&0, 1000).unwrap();
+        om.insert_all_after(&1000, 1001..1300).unwrap();
+        om.insert_all_after(&1299, 2000..2010).unwrap();
+        let expected: Vec<u32> = ::std::iter::once(0)
+            .chain(1000..1300).chain(2000..2010).chain(1..100).collect();
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), expected);
+        assert_eq!(om.compare(&1299, &2000), Some(Ordering::Less));
+        assert_eq!(om.compare(&2009, &1), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn removing_ranges() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        om.insert_all_after(&0, 1..10).unwrap();
+        assert_eq!(om.remove_range(Bound::Included(&2), Bound::Included(&4)).unwrap(), 3);
+        assert_eq!(om.remove_range(Bound::Excluded(&5), Bound::Excluded(&7)).unwrap(), 1);
+        assert_eq!(om.remove_range(Bound::Excluded(&7), Bound::Excluded(&8)).unwrap(), 0);
+        assert_eq!(om.remove_range(Bound::Included(&8), Bound::Included(&1)).unwrap(), 0);
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(),
+                   vec![0, 1, 5, 7, 8, 9]);
+        assert_eq!(om.remove_range(Bound::Unbounded, Bound::Excluded(&5)).unwrap(), 2);
+        assert_eq!(om.remove_range(Bound::Excluded(&8), Bound::Unbounded).unwrap(), 1);
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), vec![5, 7, 8]);
+        om.insert_after(&8, 10).unwrap();
+        assert_eq!(om.compare(&5, &10), Some(Ordering::Less));
+        assert_eq!(om.remove_range(Bound::Unbounded, Bound::Unbounded).unwrap(), 4);
+        assert_eq!(om.len(), 0);
+        om.insert_only(11).unwrap();
+        assert_eq!(om.len(), 1);
+    }
+
+    #[test]
+    fn truncating() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        om.insert_all_after(&0, 1..10).unwrap();
+        assert_eq!(om.truncate_after(&9).unwrap(), 0);
+        assert_eq!(om.truncate_before(&0).unwrap(), 0);
+        assert_eq!(om.truncate_after(&6).unwrap(), 3);
+        assert_eq!(om.truncate_before(&2).unwrap(), 2);
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), vec![2, 3, 4, 5, 6]);
+        om.insert_after(&6, 7).unwrap();
+        assert_eq!(om.compare(&2, &7), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn replacing_keys() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.replace_key(&"a", "x").unwrap();
+        assert_eq!(order(&om), vec!["x"]);
+        om.insert_after(&"x", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.replace_key(&"b", "y").unwrap();
+        om.replace_key(&"x", "z").unwrap();
+        om.replace_key(&"c", "c").unwrap();
+        assert_eq!(order(&om), vec!["z", "y", "c"]);
+        assert_eq!(om.compare(&"b", &"c"), None);
+        assert_eq!(om.compare(&"y", &"c"), Some(Ordering::Less));
+        om.insert_after(&"c", "d").unwrap();
+        assert_eq!(order(&om), vec!["z", "y", "c", "d"]);
+    }
+
+    #[test]
+    fn entries() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        assert_eq!(om.entry("c").or_insert_after(&"a").unwrap().compare(&"b"), Some(Ordering::Less));
+        assert_eq!(om.entry("c").or_insert_after(&"b").unwrap().compare(&"b"), Some(Ordering::Less));
+        assert_eq!(om.entry("e").or_insert_after(&"z").err(), Some(InsertError::AnchorMissing));
+        assert_eq!(order(&om), vec!["a", "c", "b"]);
+        match om.entry("d") {
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), "d"),
+            Entry::Occupied(_) => panic!("d should be vacant"),
+        }
+        match om.entry("a") {
+            Entry::Occupied(entry) => assert_eq!(entry.remove().next, Some(&"c")),
+            Entry::Vacant(_) => panic!("a should be occupied"),
+        }
+        assert_eq!(om.len(), 2);
+    }
+
+    #[test]
+    fn removing() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.insert_after(&"c", "d").unwrap();
+        let tag = om.iter_values_with_tags().nth(1).unwrap().1;
+        assert_eq!(om.remove(&"b"), Some(RemovedEntry { value: "b", prev: Some(&"a"), next: Some(&"c"), tag }));
+        assert_eq!(om.remove(&"b"), None);
+        let removed = om.remove(&"d").unwrap();
+        assert_eq!((removed.prev, removed.next), (Some(&"c"), None));
+        assert_eq!(order(&om), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn removing_front_and_last() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        let removed = om.remove(&"a").unwrap();
+        assert_eq!((removed.prev, removed.next), (None, None));
+        assert_eq!(om.len(), 0);
+        assert_eq!(order(&om), Vec::<&str>::new());
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.insert_after(&"c", "d").unwrap();
+        om.remove(&"a");
+        assert_eq!(order(&om), vec!["b", "c", "d"]);
+        om.insert_after(&"d", "e").unwrap();
+        om.remove(&"e");
+        assert_eq!(order(&om), vec!["b", "c", "d"]);
+        om.insert_after(&"d", "f").unwrap();
+        om.insert_after(&"b", "g").unwrap();
+        assert_eq!(order(&om), vec!["b", "g", "c", "d", "f"]);
+        om.remove(&"b");
+        om.remove(&"g");
+        om.move_to_front(&"f").unwrap();
+        assert_eq!(order(&om), vec!["f", "c", "d"]);
+        om.remove(&"f");
+        om.remove(&"d");
+        om.remove(&"c");
+        assert_eq!(om.len(), 0);
+        om.insert_only("h").unwrap();
+        om.insert_after(&"h", "i").unwrap();
+        assert_eq!(order(&om), vec!["h", "i"]);
+    }
+
+    #[test]
+    fn insert_errors() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        assert_eq!(om.insert_after(&"x", "c"), Err(InsertError::AnchorMissing));
+        assert_eq!(om.insert_after(&"a", "b"), Err(InsertError::AlreadyPresent));
+        assert_eq!(om.insert_after(&"b", "a"), Err(InsertError::AlreadyPresent));
+        assert_eq!(om.insert_after(&"a", "a"), Err(InsertError::SelfInsert));
+        assert_eq!(order(&om), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn errors_leave_the_order_alone() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        assert_eq!(om.remove_range(Bound::Included(&"a"), Bound::Unbounded), Err(OrderMaintenanceError::NotPresent));
+        om.insert_only("a").unwrap();
+        assert_eq!(om.insert_only("b"), Err(OrderMaintenanceError::NotEmpty));
+        om.insert_after(&"a", "b").unwrap();
+        assert_eq!(om.insert_all_after(&"a", vec!["c", "d", "c"]), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(om.insert_all_after(&"a", vec!["c", "b"]), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(om.replace_key(&"a", "b"), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(om.replace_key(&"x", "y"), Err(OrderMaintenanceError::NotPresent));
+        assert_eq!(om.swap_positions(&"a", &"x"), Err(OrderMaintenanceError::NotPresent));
+        assert_eq!(om.rotate_to_front(&"x"), Err(OrderMaintenanceError::NotPresent));
+        assert_eq!(om.truncate_after(&"x"), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(om.remove_range(Bound::Excluded(&"b"), Bound::Included(&"x")), Err(OrderMaintenanceError::NotPresent));
+        assert_eq!(order(&om), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let mut om: OrderMaintenance<String> = OrderMaintenance::new();
+        om.insert_only("a".to_string()).unwrap();
+        om.insert_after("a", "b".to_string()).unwrap();
+        om.insert_after("b", "c".to_string()).unwrap();
+        assert!(om.contains("b"));
+        assert!(!om.contains("z"));
+        assert_eq!(om.compare("a", "c"), Some(Ordering::Less));
+        assert_eq!(om.successor("a").map(|s| &s[..]), Some("b"));
+        assert_eq!(om.successor("c"), None);
+        assert_eq!(om.predecessor("c").map(|s| &s[..]), Some("b"));
+        assert_eq!(om.predecessor("a"), None);
+        om.move_to_front("c").unwrap();
+        om.move_after("a", "b").unwrap();
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| &v[..]).collect::<Vec<_>>(), vec!["c", "b", "a"]);
+        assert_eq!(om.remove("c").unwrap().next.map(|s| &s[..]), Some("b"));
+        assert_eq!(om.remove_range(Bound::Included("b"), Bound::Included("a")), Ok(2));
+        assert!(om.is_empty());
+    }
+
+    #[test]
+    fn predicates() {
+        let mut om: OrderMaintenance<&'static str> = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        assert!(om.is_before(&"a", &"b"));
+        assert!(!om.is_before(&"b", &"a"));
+        assert!(!om.is_before(&"b", &"b"));
+        assert!(om.is_after(&"c", &"a"));
+        assert!(!om.is_after(&"a", &"z"));
+        assert!(om.is_between(&"b", &"a", &"c"));
+        assert!(!om.is_between(&"b", &"c", &"a"));
+        assert!(!om.is_between(&"a", &"a", &"c"));
+        assert!(!om.is_between(&"z", &"a", &"c"));
+    }
+
+    #[test]
+    fn min_and_max_by_order() {
+        let mut om: OrderMaintenance<String> = OrderMaintenance::new();
+        om.insert_only("a".to_string()).unwrap();
+        om.insert_after("a", "b".to_string()).unwrap();
+        om.insert_after("b", "c".to_string()).unwrap();
+        om.move_to_front("c").unwrap();
+        let keys = ["b", "zzz", "a", "c"];
+        assert_eq!(om.min_by_order(keys.iter().cloned()), Some("c"));
+        assert_eq!(om.max_by_order(keys.iter().cloned()), Some("b"));
+        assert_eq!(om.min_by_order(vec!["zzz", "yyy"]), None);
+        assert_eq!(om.max_by_order(Vec::<&str>::new()), None);
+    }
+
+    #[test]
+    fn sorting_slices() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(5).unwrap();
+        om.insert_after(&5, 3).unwrap();
+        om.insert_after(&3, 9).unwrap();
+        om.insert_after(&9, 1).unwrap();
+        let mut values = vec![1, 100, 9, 3, 42, 5];
+        om.sort_slice(&mut values);
+        assert_eq!(values, vec![5, 3, 9, 1, 100, 42]);
+        let mut named = vec![(9, "nine"), (7, "seven"), (5, "five"), (1, "one")];
+        om.sort_slice_by_key(&mut named, |item| &item.0);
+        assert_eq!(named, vec![(5, "five"), (9, "nine"), (1, "one"), (7, "seven")]);
+    }
+
+    #[test]
+    fn comparators() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(2).unwrap();
+        om.insert_after(&2, 1).unwrap();
+        let cmp = om.comparator();
+        assert_eq!(cmp(&2, &1), Ordering::Less);
+        assert_eq!(cmp(&1, &1), Ordering::Equal);
+        assert_eq!(cmp(&1, &7), Ordering::Less);
+        assert_eq!(cmp(&7, &2), Ordering::Greater);
+        assert_eq!(cmp(&7, &8), Ordering::Equal);
+        let mut values = vec![8, 1, 2, 7];
+        values.sort_by(om.comparator());
+        assert_eq!(values, vec![2, 1, 8, 7]);
+    }
+
+    #[test]
+    fn order_keys() {
+        use std::collections::BTreeMap;
+        let mut om = OrderMaintenance::new();
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "c").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        let mut by_key = BTreeMap::new();
+        for value in &["c", "a", "b"] {
+            by_key.insert(om.key(value).unwrap(), *value);
+        }
+        assert_eq!(by_key.values().cloned().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert!(om.key(&"z").is_none());
+        let a = om.key(&"a").unwrap();
+        assert!(om.is_current(&a));
+        om.insert_after(&"c", "d").unwrap();
+        assert!(om.is_current(&a));
+        om.move_to_back(&"a").unwrap();
+        assert!(!om.is_current(&a));
+        let b = om.key(&"b").unwrap();
+        assert_eq!(a.try_cmp(&b), None);
+        assert_eq!(b.try_cmp(&om.key(&"a").unwrap()), Some(Ordering::Less));
+    }
+    #[test]
+    #[should_panic(expected = "different epochs")]
+    fn order_keys_from_different_epochs() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only(1).unwrap();
+        om.insert_after(&1, 2).unwrap();
+        let before = om.key(&1).unwrap();
+        om.reverse();
+        let _ = before < om.key(&2).unwrap();
+    }
+    #[test]
+    fn unordered_keys() {
+        use std::collections::BTreeSet;
+        let mut om = OrderMaintenance::new();
+        om.insert_only(5).unwrap();
+        om.insert_all_after(&5, [1, 4, 2, 3]).unwrap();
+        om.remove(&4);
+        om.insert_after(&3, 9).unwrap();
+        om.move_to_front(&2).unwrap();
+        let keys = om.keys();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(keys.copied().collect::<BTreeSet<_>>(), BTreeSet::from([1, 2, 3, 5, 9]));
+        om.remove(&9);
+        om.remove(&1);
+        assert_eq!(om.keys().count(), 3);
+        assert_eq!(OrderMaintenance::<u32>::new().keys().next(), None);
+    }
+    #[test]
+    fn pops() {
+        let mut om = OrderMaintenance::new();
+        assert_eq!(om.pop_front(), None);
+        om.insert_only(1).unwrap();
+        om.insert_all_after(&1, 2..=5).unwrap();
+        let tag = om.tag_of(&1).unwrap();
+        assert_eq!(om.pop_front(), Some((1, tag)));
+        assert_eq!(om.pop_back().map(|(value, _)| value), Some(5));
+        om.move_to_front(&4).unwrap();
+        assert_eq!(om.pop_front().map(|(value, _)| value), Some(4));
+        assert_eq!(om.pop_back().map(|(value, _)| value), Some(3));
+        om.insert_after(&2, 6).unwrap();
+        assert_eq!(om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>(), vec![2, 6]);
+        assert_eq!(om.pop_back().map(|(value, _)| value), Some(6));
+        assert_eq!(om.pop_back().map(|(value, _)| value), Some(2));
+        assert!(om.is_empty());
+        assert_eq!(om.pop_back(), None);
+        om.insert_only(7).unwrap();
+        assert_eq!(om.pop_front().map(|(value, _)| value), Some(7));
+        om.check_invariants().unwrap();
+    }
+    #[test]
+    fn cached_compares() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only(1).unwrap();
+        om.insert_after(&1, 3).unwrap();
+        let (one, three) = (om.key(&1).unwrap(), om.key(&3).unwrap());
+        om.insert_after(&1, 2).unwrap();
+        let two = om.key(&2).unwrap();
+        assert_eq!(om.compare_cached(&one, &three), Some(Ordering::Less));
+        assert_eq!(om.compare_cached(&three, &two), Some(Ordering::Greater));
+        assert_eq!(om.compare_cached(&two, &two), Some(Ordering::Equal));
+        om.move_to_front(&3).unwrap();
+        assert_eq!(om.compare_cached(&one, &two), None);
+        let three = om.key(&3).unwrap();
+        assert_eq!(om.compare_cached(&three, &om.key(&1).unwrap()), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn handles() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        let zero = om.handle(&0).unwrap();
+        let mut handles = vec![zero.clone()];
+        // always inserting right after 0 forces plenty of relabeling
+        for i in 1..200 {
+            om.insert_after(&0, i).unwrap();
+            handles.push(om.handle(&i).unwrap());
+        }
+        for w in handles[1..].windows(2) {
+            assert!(w[0] > w[1]);
+        }
+        assert!(handles.iter().all(|h| *h >= zero));
+        assert_eq!(om.handle(&5).unwrap(), handles[5]);
+        om.reverse();
+        assert!(handles[1] < handles[2] && handles[199] < handles[0]);
+        om.rotate_to_front(&100).unwrap();
+        assert!(handles[100] < handles[199] && handles[0] < handles[1] && handles[1] < handles[99]);
+        om.swap_positions(&1, &199).unwrap();
+        assert!(handles[1] < handles[0] && handles[0] < handles[199]);
+        assert!(!handles[3].is_removed());
+        om.remove(&3);
+        assert!(handles[3].is_removed());
+        assert!(om.handle(&3).is_none());
+    }
+    #[test]
+    #[should_panic(expected = "different orders")]
+    fn handles_from_different_orders() {
+        let mut a = OrderMaintenance::new();
+        let mut b = OrderMaintenance::new();
+        a.insert_only(1).unwrap();
+        b.insert_only(1).unwrap();
+        let _ = a.handle(&1).unwrap() < b.handle(&1).unwrap();
+    }
+
+    #[test]
+    fn epochs() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        om.insert_after(&0, 1).unwrap();
+        om.insert_after(&1, 2).unwrap();
+        om.remove(&2);
+        assert_eq!(om.epoch(), 0);
+        om.move_to_front(&1).unwrap();
+        let mut epoch = om.epoch();
+        assert!(epoch > 0);
+        om.move_to_front(&1).unwrap();
+        assert_eq!(om.epoch(), epoch);
+        om.reverse();
+        assert!(om.epoch() > epoch);
+        epoch = om.epoch();
+        for i in 2..100 {
+            let tags: Vec<_> = om.iter_values_with_tags().map(|(v, tag)| (*v, tag)).collect();
+            om.insert_after(&0, i).unwrap();
+            let unchanged = tags.iter().all(|&(value, tag)| om.tag_of(&value) == Some(tag));
+            assert_eq!(unchanged, om.epoch() == epoch);
+            epoch = om.epoch();
+        }
+    }
+
+    #[test]
+    fn tags() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only("b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.move_to_front(&"c").unwrap();
+        assert!(om.tag_of(&"c").unwrap() < om.tag_of(&"b").unwrap());
+        assert_eq!(om.tag_of(&"a"), None);
+        let tags: Vec<_> = om.iter_values_with_tags().map(|(v, tag)| (tag, *v)).collect();
+        assert_eq!(tags, vec![(om.tag_of(&"c").unwrap(), "c"), (om.tag_of(&"b").unwrap(), "b")]);
+    }
+
+    #[test]
+    fn relabel_callbacks() {
+        use std::sync::Mutex;
+        let mirror: Arc<Mutex<HashMap<u32, Tag>>> = Arc::new(Mutex::new(HashMap::new()));
+        let mut om = OrderMaintenance::new();
+        {
+            let mirror = mirror.clone();
+            om.set_on_relabel(move |value, tag| { mirror.lock().unwrap().insert(*value, tag); });
+        }
+        om.insert_only(0).unwrap();
+        mirror.lock().unwrap().insert(0, om.tag_of(&0).unwrap());
+        for i in 1..300 {
+            om.insert_after(&0, i).unwrap();
+            let mut mirror = mirror.lock().unwrap();
+            mirror.entry(i).or_insert_with(|| om.tag_of(&i).unwrap());
+            for (value, tag) in om.iter_values_with_tags() {
+                assert_eq!(mirror[value], tag);
+            }
+        }
+        om.clear_on_relabel();
+        let epoch = om.epoch();
+        let before = mirror.lock().unwrap().clone();
+        for i in 300..400 {
+            om.insert_after(&0, i).unwrap();
+        }
+        assert!(om.epoch() > epoch);
+        assert_eq!(*mirror.lock().unwrap(), before);
+    }
+
+    #[test]
+    fn relabel_listeners() {
+        use std::sync::Mutex;
+        type Batch = Vec<(u32, Tag)>;
+        struct Batches(Arc<Mutex<Vec<Batch>>>);
+        impl RelabelListener<u32> for Batches {
+            fn relabeled(&mut self, relabeled: &[(&u32, Tag)]) {
+                self.0.lock().unwrap().push(relabeled.iter().map(|&(value, tag)| (*value, tag)).collect());
+            }
+        }
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let mut om = OrderMaintenance::new();
+        om.set_relabel_listener(Batches(batches.clone()));
+        om.insert_only(0).unwrap();
+        let mut epoch = om.epoch();
+        let mut seen = 0;
+        for i in 1..300 {
+            om.insert_after(&0, i).unwrap();
+            let batches = batches.lock().unwrap();
+            assert_eq!(om.epoch() != epoch, batches.len() > seen);
+            epoch = om.epoch();
+            if batches.len() > seen {
+                seen = batches.len();
+                let batch = batches.last().unwrap();
+                assert!(batch.windows(2).all(|w| om.successor(&w[0].0) == Some(&w[1].0)));
+                assert!(batch.iter().all(|&(value, tag)| om.tag_of(&value) == Some(tag)));
+            }
+        }
+        assert!(!batches.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        assert_eq!(om.stats(), None);
+        om.enable_stats();
+        let mut rebalances = 0;
+        for i in 1..300 {
+            let epoch = om.epoch();
+            om.insert_after(&0, i).unwrap();
+            if om.epoch() != epoch {
+                rebalances += 1;
+            }
+        }
+        let stats = *om.stats().unwrap();
+        assert_eq!(stats.rebalances, rebalances);
+        assert!(stats.largest_cascade > 1 && stats.largest_cascade <= om.len());
+        assert!(stats.relabeled >= stats.largest_cascade as u64);
+        om.enable_stats();
+        assert_eq!(om.stats(), Some(&Stats::default()));
+        om.disable_stats();
+        om.insert_after(&0, 300).unwrap();
+        assert_eq!(om.stats(), None);
+    }
+
+    #[test]
+    fn checking_invariants() {
+        let mut om = OrderMaintenance::new();
+        assert_eq!(om.check_invariants(), Ok(()));
+        om.insert_only(1).unwrap();
+        om.insert_after(&1, 2).unwrap();
+        om.insert_after(&2, 3).unwrap();
+        assert_eq!(om.check_invariants(), Ok(()));
+        // 1, 2 and 3 are in slots 0, 1 and 2
+        om.slots[Index(1)].tag = om.slots[Index(2)].tag;
+        assert_eq!(om.check_invariants(), Err(IntegrityError::TagInversion));
+        om.slots[Index(2)].prev = Index(0);
+        assert_eq!(om.check_invariants(), Err(IntegrityError::PrevNextMismatch));
+        om.slots[Index(0)].next = Index(4);
+        assert_eq!(om.check_invariants(), Err(IntegrityError::DanglingLink));
+        om.front = Some(Index(4));
+        assert_eq!(om.check_invariants(), Err(IntegrityError::DanglingFront));
+        om.front = None;
+        assert_eq!(om.check_invariants(), Err(IntegrityError::MissingFront));
+        let mut om = OrderMaintenance::new();
+        om.insert_only(1).unwrap();
+        om.insert_after(&1, 2).unwrap();
+        om.slots.insert(|_| Position { key: 3, prev: Index(1), next: Index(1), tag: 7, handle: None });
+        assert_eq!(om.check_invariants(), Err(IntegrityError::Unreachable));
+    }
+
+    #[test]
+    fn keys_without_debug() {
+        #[derive(PartialEq, Eq, Hash, Clone)]
+        struct Opaque(u8);
+        let mut om = OrderMaintenance::new();
+        om.insert_only(Opaque(1)).unwrap();
+        om.entry(Opaque(2)).or_insert_after(&Opaque(1)).unwrap();
+        assert!(om.is_before(&Opaque(1), &Opaque(2)));
+        assert!(om.remove(&Opaque(1)).is_some());
+    }
+
+    #[test]
+    fn keys_without_clone() {
+        #[derive(PartialEq, Eq, Hash, Debug)]
+        struct Unique(String);
+        let mut om = OrderMaintenance::new();
+        om.insert_only(Unique("b".to_string())).unwrap();
+        om.insert_after(&Unique("b".to_string()), Unique("c".to_string())).unwrap();
+        om.move_to_front(&Unique("c".to_string())).unwrap();
+        om.swap_positions(&Unique("b".to_string()), &Unique("c".to_string())).unwrap();
+        om.replace_key(&Unique("c".to_string()), Unique("d".to_string())).unwrap();
+        let keys: Vec<&str> = om.iter_values_with_tags().map(|(key, _)| &key.0[..]).collect();
+        assert_eq!(keys, vec!["b", "d"]);
+        let removed = om.remove(&Unique("b".to_string())).unwrap();
+        assert_eq!((removed.value, removed.next), (Unique("b".to_string()), Some(&Unique("d".to_string()))));
+    }
+
+    #[test]
+    fn reusing_slots() {
+        let mut om = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        om.insert_all_after(&0, 1..10).unwrap();
+        assert_eq!(om.remove_range(Bound::Included(&2), Bound::Included(&5)), Ok(4));
+        om.remove(&8);
+        for i in 10..15 {
+            om.insert_after(&0, i).unwrap();
+        }
+        assert_eq!(om.slots.slots.len(), 10);
+        om.insert_after(&0, 15).unwrap();
+        assert_eq!(om.slots.slots.len(), 11);
+        assert_eq!(om.check_invariants(), Ok(()));
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(),
+                   vec![0, 15, 14, 13, 12, 11, 10, 1, 6, 7, 9]);
+    }
+
+    #[test]
+    fn custom_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+        let mut om: OrderMaintenance<u32, BuildHasherDefault<DefaultHasher>> = OrderMaintenance::default();
+        om.insert_only(1).unwrap();
+        om.insert_all_after(&1, 2..100).unwrap();
+        assert_eq!(om.compare(&3, &50), Some(Ordering::Less));
+        om.entry(100).or_insert_after(&99).unwrap();
+        assert_eq!(om.check_invariants(), Ok(()));
+        #[cfg(feature = "fxhash")]
+        {
+            let mut fx = FxOrderMaintenance::default();
+            fx.insert_only("a").unwrap();
+            fx.insert_after(&"a", "b").unwrap();
+            assert_eq!(fx.compare(&"b", &"a"), Some(Ordering::Greater));
+        }
+    }
+
+    #[test]
+    fn every_strategy_keeps_the_order() {
+        for &strategy in &[LabelingStrategy::Bender, LabelingStrategy::DietzSleator, LabelingStrategy::Midpoint] {
+            let mut om = OrderMaintenance::new();
+            om.set_strategy(strategy);
+            om.enable_stats();
+            let mut model = vec![0u32];
+            om.insert_only(0).unwrap();
+            let mut random = test_random(99);
+            for value in 1..1000 {
+                let at = random(model.len());
+                match value % 4 {
+                    // the same spot over and over, to force collisions
+                    0 => { om.insert_after(&0, value).unwrap(); let at = model.iter().position(|&v| v == 0).unwrap(); model.insert(at + 1, value); }
+                    1 => { om.insert_after(&model[0], value).unwrap(); om.move_to_front(&value).unwrap(); model.insert(0, value); }
+                    _ => { om.insert_after(&model[at], value).unwrap(); model.insert(at + 1, value); }
+                }
+            }
+            om.insert_all_after(&0, 1000..1100).unwrap();
+            let at = model.iter().position(|&v| v == 0).unwrap();
+            model.splice(at + 1..at + 1, 1000..1100);
+            assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), model, "{:?}", strategy);
+            assert_eq!(om.check_invariants(), Ok(()), "{:?}", strategy);
+            assert!(om.stats().unwrap().rebalances > 0, "{:?}", strategy);
+        }
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        assert_eq!(om.strategy(), LabelingStrategy::Bender);
+        om.set_strategy(LabelingStrategy::Midpoint);
+        om.insert_only(1).unwrap();
+        // at the end it only takes a share of the room, in between it halves
+        om.insert_after(&1, 3).unwrap();
+        let (middle, step) = (1 << 63, (u64::MAX - (1 << 63)) / 3);
+        assert_eq!(om.tag_of(&3).unwrap().0, middle + step);
+        om.insert_after(&1, 2).unwrap();
+        assert_eq!(om.tag_of(&2).unwrap().0, middle + step / 2);
+    }
+
+    #[test]
+    fn overflow_threshold() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        assert_eq!(om.overflow_threshold(), 2.0f64.powf(1.0 / 62.0));
+        om.insert_only(0).unwrap();
+        om.insert_all_after(&0, 1..512).unwrap();
+        assert_eq!(om.overflow_threshold(), 1024.0f64.powf(1.0 / 62.0));
+        assert_eq!(om.set_overflow_threshold(1.0), Err(OrderMaintenanceError::InvalidThreshold));
+        assert_eq!(om.set_overflow_threshold(2.5), Err(OrderMaintenanceError::InvalidThreshold));
+        assert_eq!(om.set_overflow_threshold(f64::NAN), Err(OrderMaintenanceError::InvalidThreshold));
+
+        let relabeled = |threshold: Option<f64>| {
+            let mut om = OrderMaintenance::new();
+            if let Some(threshold) = threshold {
+                om.set_overflow_threshold(threshold).unwrap();
+            }
+            om.enable_stats();
+            om.insert_only(0u32).unwrap();
+            for value in 1..1000 {
+                om.insert_after(&0, value).unwrap();
+            }
+            assert_eq!(om.check_invariants(), Ok(()));
+            *om.stats().unwrap()
+        };
+        // inserting at the same spot over and over is where the default shines:
+        // near 1 nearly every rebalance is global, and at 2 the tags end up too
+        // tightly packed to leave room
+        let (sparse, default, dense) = (relabeled(Some(1.01)), relabeled(None), relabeled(Some(2.0)));
+        assert!(sparse.largest_cascade > 900);
+        assert!(default.relabeled < sparse.relabeled && default.relabeled < dense.relabeled);
+    }
+
+    fn same_workload<W>() -> Stats
+        where W: TagWidth {
+        let mut om: OrderMaintenance<u32, RandomState, W> = OrderMaintenance::default();
+        om.enable_stats();
+        om.insert_only(0).unwrap();
+        let first = om.handle(&0).unwrap();
+        let mut model = vec![0u32];
+        for value in 1..1500 {
+            // alternately piling up right after 0 and appending at the end
+            if value % 2 == 0 {
+                om.insert_after(&0, value).unwrap();
+                model.insert(1, value);
+            } else {
+                om.insert_after(model.last().unwrap(), value).unwrap();
+                model.push(value);
+            }
+        }
+        for &strategy in &[LabelingStrategy::DietzSleator, LabelingStrategy::Midpoint] {
+            om.set_strategy(strategy);
+            for value in 0..100 {
+                let value = 10000 * (strategy as u32 + 1) + value;
+                om.insert_after(&model[1], value).unwrap();
+                model.insert(2, value);
+            }
+        }
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), model);
+        assert_eq!(om.check_invariants(), Ok(()));
+        let last = om.handle(model.last().unwrap()).unwrap();
+        assert!(first < last);
+        om.reverse();
+        assert!(first > last);
+        *om.stats().unwrap()
+    }
+
+    #[test]
+    fn tag_widths() {
+        let (narrow, default, wide) = (same_workload::<u32>(), same_workload::<u64>(), same_workload::<u128>());
+        assert!(wide.relabeled <= default.relabeled && default.relabeled <= narrow.relabeled);
+        let mut om: OrderMaintenance<&str, RandomState, u128> = OrderMaintenance::default();
+        om.set_strategy(LabelingStrategy::Midpoint);
+        om.insert_only("a").unwrap();
+        om.insert_after(&"a", "b").unwrap();
+        assert_eq!(om.tag_of(&"b").unwrap().0, (1 << 127) + (u128::MAX - (1 << 127)) / 3);
+        assert_eq!(om.overflow_threshold(), 4.0f64.powf(1.0 / 126.0));
+    }
+
+    #[test]
+    fn wide_tags() {
+        // inserting after the same element over and over, so the same gap
+        // keeps getting split
+        fn hammer<W>() -> u64
+            where W: TagWidth {
+            let mut om: OrderMaintenance<u32, RandomState, W> = OrderMaintenance::default();
+            om.set_strategy(LabelingStrategy::Midpoint);
+            om.enable_stats();
+            om.insert_only(0).unwrap();
+            om.insert_after(&0, 1).unwrap();
+            for value in 2..3000 {
+                om.insert_after(&0, value).unwrap();
+            }
+            assert_eq!(om.check_invariants(), Ok(()));
+            om.stats().unwrap().relabeled
+        }
+        let (narrow, wide) = (hammer::<u64>(), hammer::<u128>());
+        assert!(wide * 2 < narrow, "{} vs {}", wide, narrow);
+        let mut om: WideOrderMaintenance<&str> = WideOrderMaintenance::default();
+        om.insert_only("a").unwrap();
+        assert_eq!(om.overflow_threshold(), 2.0f64.powf(1.0 / 126.0));
+    }
+
+    #[test]
+    fn hammering_the_ends() {
+        // u32 tags, so the ends of the tag space are within reach
+        for &strategy in &[LabelingStrategy::Bender, LabelingStrategy::DietzSleator, LabelingStrategy::Midpoint] {
+            let mut om: OrderMaintenance<u32, RandomState, u32> = OrderMaintenance::default();
+            om.set_strategy(strategy);
+            om.enable_stats();
+            om.insert_only(0).unwrap();
+            let mut model = vec![0];
+            for value in 1..2000 {
+                om.insert_after(model.last().unwrap(), value).unwrap();
+                model.push(value);
+            }
+            for value in 2000..2500 {
+                om.insert_after(&0, value).unwrap();
+                om.move_to_front(&value).unwrap();
+                model.insert(0, value);
+            }
+            assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), model, "{:?}", strategy);
+            assert_eq!(om.check_invariants(), Ok(()), "{:?}", strategy);
+            let relabeled = om.stats().unwrap().relabeled;
+            assert!(relabeled < 4 * model.len() as u64, "{:?}: {}", strategy, relabeled);
+        }
+    }
+
+    #[test]
+    fn starts_in_the_middle() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.enable_stats();
+        om.insert_only(0).unwrap();
+        assert_eq!(om.tag_of(&0).unwrap().0, 1 << 63);
+        // prepending, which used to start right up against tag 0
+        for value in 1..1000 {
+            om.insert_after(&0, value).unwrap();
+            om.move_to_front(&value).unwrap();
+        }
+        assert_eq!(om.stats().unwrap().relabeled, 0);
+        assert_eq!(om.iter_values_with_tags().next().map(|(v, _)| *v), Some(999));
+        // and again once it has been emptied
+        om.remove_range::<u32>(Bound::Unbounded, Bound::Unbounded).unwrap();
+        om.insert_only(0).unwrap();
+        assert_eq!(om.tag_of(&0).unwrap().0, 1 << 63);
+    }
+
+    #[test]
+    fn new_tags_split_the_gap() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.enable_stats();
+        om.insert_only(0).unwrap();
+        om.insert_after(&0, 1).unwrap();
+        // each of these halves the gap after 0, which has room for 60 halvings
+        for value in 2..60 {
+            om.insert_after(&0, value).unwrap();
+        }
+        assert_eq!(om.stats().unwrap().rebalances, 0);
+        let (a, b) = (om.tag_of(&0).unwrap().0, om.tag_of(&59).unwrap().0);
+        let next = om.tag_of(&58).unwrap().0;
+        assert_eq!(b, a + (next - a) / 2);
+        // and once it's used up, a rebalance makes room as before
+        for value in 60..200 {
+            om.insert_after(&0, value).unwrap();
+        }
+        assert!(om.stats().unwrap().rebalances > 0);
+        assert_eq!(om.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn rebalance_all() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.rebalance_all();
+        om.insert_only(0).unwrap();
+        for value in 1..300 {
+            om.insert_after(&0, value).unwrap();
+        }
+        om.remove_range(Bound::Excluded(&250), Bound::Unbounded).unwrap();
+        let before: Vec<u32> = om.iter_values_with_tags().map(|(v, _)| *v).collect();
+        let epoch = om.epoch();
+        let told = Arc::new(core::sync::atomic::AtomicU64::new(0));
+        let counter = told.clone();
+        om.set_on_relabel(move |_, _| { counter.fetch_add(1, Relaxed); });
+        om.enable_stats();
+        om.rebalance_all();
+        assert_eq!(om.iter_values_with_tags().map(|(v, _)| *v).collect::<Vec<_>>(), before);
+        assert_ne!(om.epoch(), epoch);
+        assert_eq!(told.load(Relaxed), om.len() as u64);
+        assert_eq!(om.stats().unwrap().rebalances, 1);
+        let spacing = u64::MAX / (om.len() as u64 + 1);
+        let tags: Vec<u64> = om.iter_values_with_tags().map(|(_, tag)| tag.0).collect();
+        assert_eq!(tags[0], spacing);
+        for pair in tags.windows(2) {
+            assert_eq!(pair[1] - pair[0], spacing);
+        }
+    }
+
+    #[test]
+    fn expected_len() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::with_expected_len(5000);
+        assert_eq!(om.expected_len(), 5000);
+        assert_eq!(om.overflow_threshold(), 10000.0f64.powf(1.0 / 62.0));
+        om.insert_only(0).unwrap();
+        // appends leave room for the other 4999
+        om.insert_after(&0, 1).unwrap();
+        let (a, b) = (om.tag_of(&0).unwrap().0, om.tag_of(&1).unwrap().0);
+        assert_eq!(b - a, (u64::MAX - a) / 5001);
+        for value in 2..3000 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        assert!(om.iter_values_with_tags().map(|(_, tag)| tag).collect::<Vec<_>>().windows(2).all(|pair| pair[0] < pair[1]));
+        // and there's still most of the room left behind the last one
+        assert!(u64::MAX - om.tag_of(&2999).unwrap().0 > (u64::MAX - a) / 2);
+        om.set_expected_len(0);
+        assert_eq!(om.overflow_threshold(), 6000.0f64.powf(1.0 / 62.0));
+    }
+
+    #[test]
+    fn memory_usage() {
+        let mut om: OrderMaintenance<u64> = OrderMaintenance::new();
+        assert_eq!(om.memory_usage().total_bytes(), 0);
+        assert_eq!(om.memory_usage().bytes_per_element(), 0.0);
+        om.set_expected_len(1000);
+        let reserved = om.memory_usage();
+        assert!(reserved.slot_capacity >= 1000 && reserved.index_capacity >= 1000);
+        om.insert_only(0).unwrap();
+        for value in 1..1000 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        let usage = om.memory_usage();
+        assert_eq!(usage.len, 1000);
+        assert_eq!(usage.slot_capacity, reserved.slot_capacity);
+        // a u64 key, two u32 links, a u64 tag and a pointer, and whether
+        // the slot's vacant
+        assert!(usage.bytes_per_slot >= 32);
+        assert_eq!(usage.handle_bytes, 0);
+        assert!(usage.index_bytes >= usage.index_capacity * 4);
+        let _handle = om.handle(&5).unwrap();
+        assert!(om.memory_usage().handle_bytes > 0);
+        assert!(om.memory_usage().bytes_per_element() > (usage.bytes_per_slot + 4) as f64);
+    }
+
+    #[test]
+    fn nth_after() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..10 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        assert_eq!(om.nth_after(&3, 0), Some(&3));
+        assert_eq!(om.nth_after(&3, 4), Some(&7));
+        assert_eq!(om.nth_after(&3, 6), Some(&9));
+        assert_eq!(om.nth_after(&3, 7), None);
+        assert_eq!(om.nth_before(&3, 3), Some(&0));
+        assert_eq!(om.nth_before(&3, 4), None);
+        assert_eq!(om.nth_before(&30, 0), None);
+        // the list is circular inside, but these stop at the ends
+        om.rotate_to_front(&5).unwrap();
+        assert_eq!(om.nth_after(&9, 1), Some(&0));
+        assert_eq!(om.nth_after(&4, 1), None);
+        assert_eq!(om.nth_before(&5, 1), None);
+        assert_eq!(om.nth_before(&0, 5), Some(&5));
+    }
+
+    #[test]
+    fn clone() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..200 {
+            om.insert_after(&0, value).unwrap();
+        }
+        om.remove(&50);
+        om.rotate_to_front(&100).unwrap();
+        let handle = om.handle(&7).unwrap();
+        let tag = om.tag_of(&7);
+        let mut copy = om.clone();
+        assert_eq!(copy.iter_values_with_tags().collect::<Vec<_>>(), om.iter_values_with_tags().collect::<Vec<_>>());
+        // reuses the slot 50 left
+        copy.insert_after(&7, 1000).unwrap();
+        for value in 2000..2100 {
+            copy.insert_after(&7, value).unwrap();
+        }
+        assert!(!om.contains(&1000));
+        assert_eq!(om.len(), 199);
+        assert_eq!(copy.len(), 300);
+        copy.check_invariants().unwrap();
+        // the original's handle never saw the copy relabel
+        assert_eq!(handle.cmp(&om.handle(&8).unwrap()), om.compare(&7, &8).unwrap());
+        assert_eq!(om.tag_of(&7), tag);
+        assert_ne!(copy.tag_of(&7), tag);
+        copy.remove(&7);
+        assert!(!handle.is_removed());
+    }
+
+    #[test]
+    fn equality() {
+        let mut a: OrderMaintenance<u32> = OrderMaintenance::new();
+        let mut b: OrderMaintenance<u32, RandomState, u32> = OrderMaintenance::default();
+        assert!(a == b);
+        a.insert_only(1).unwrap();
+        a.insert_after(&1, 3).unwrap();
+        a.insert_after(&1, 2).unwrap();
+        // the same order, built differently, so with other tags
+        b.insert_only(1).unwrap();
+        b.insert_after(&1, 2).unwrap();
+        b.insert_after(&2, 3).unwrap();
+        assert!(a == b);
+        let mut c = a.clone();
+        c.rebalance_all();
+        assert_ne!(c.tag_of(&2), a.tag_of(&2));
+        assert_eq!(a, c);
+        c.swap_positions(&1, &2).unwrap();
+        assert_ne!(a, c);
+        assert!(!a.same_order_as(&c) && a.same_order_as(&b) && b.same_order_as(&a));
+        c.remove(&1);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn debug_in_order() {
+        use std::format;
+        let mut om: OrderMaintenance<&str, RandomState, u32> = OrderMaintenance::default();
+        assert_eq!(format!("{:?}", om), "OrderMaintenance []");
+        om.insert_only("b").unwrap();
+        om.insert_after(&"b", "c").unwrap();
+        om.insert_after(&"b", "a").unwrap();
+        om.move_to_front(&"a").unwrap();
+        assert_eq!(format!("{:?}", om), r#"OrderMaintenance ["a", "b", "c"]"#);
+        let tag = |value| om.tag_of(&value).unwrap().0;
+        assert_eq!(format!("{:#?}", om), format!("OrderMaintenance {{\n    \"a\": Tag(\n        {},\n    ),\n    \"b\": Tag(\n        {},\n    ),\n    \"c\": Tag(\n        {},\n    ),\n}}", tag("a"), tag("b"), tag("c")));
+    }
+
+    #[test]
+    fn handles_compare_while_relabeling() {
+        use std::{panic, thread};
+        let mut om: WideOrderMaintenance<u32> = OrderMaintenance::default();
+        om.insert_only(0).unwrap();
+        om.insert_after(&0, 1).unwrap();
+        om.insert_after(&1, 2).unwrap();
+        let handles = [om.handle(&0).unwrap(), om.handle(&1).unwrap(), om.handle(&2).unwrap()];
+        // told once the relabel's over, so comparing doesn't wait on itself
+        let (first, second) = (handles[0].clone(), handles[1].clone());
+        om.set_on_relabel(move |_, _| assert!(first < second));
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    while !done.load(Relaxed) {
+                        assert!(handles[0] < handles[1] && handles[1] < handles[2]);
+                        let mut sorted = [handles[2].clone(), handles[0].clone(), handles[1].clone()];
+                        OrderedHandle::sort(&mut sorted);
+                        assert_eq!(sorted, handles);
+                    }
+                });
+            }
+            // crowding the gaps after 0 and 1 keeps all three in
+            // rebalances, which move their tags one at a time
+            let writes = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                for value in 3..3000 {
+                    om.insert_after(&(value % 2), value).unwrap();
+                    if value > 42 {
+                        om.remove(&(value - 40));
+                    }
+                    if value % 500 == 0 {
+                        om.rebalance_all();
+                    }
+                }
+            }));
+            done.store(true, Relaxed);
+            writes.unwrap();
+        });
+        assert_eq!(handles[1].cmp(&handles[1]), Ordering::Equal);
+    }
+
+    #[test]
+    fn iterates_by_reference_or_by_value() {
+        let mut om: OrderMaintenance<String> = OrderMaintenance::new();
+        om.insert_only("b".to_string()).unwrap();
+        om.insert_after("b", "c".to_string()).unwrap();
+        om.insert_after("b", "x".to_string()).unwrap();
+        om.remove("x");
+        om.insert_after("c", "d".to_string()).unwrap();
+        om.move_to_front("d").unwrap();
+        om.rotate_to_front("b").unwrap();
+        let mut borrowed = Vec::new();
+        for (key, tag) in &om {
+            // borrowed straight out of the order, not copies
+            assert!(core::ptr::eq(key, om.stored(key.as_str()).unwrap()));
+            borrowed.push((key.clone(), tag));
+        }
+        assert_eq!(borrowed.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), ["b", "c", "d"]);
+        let owned = om.into_iter();
+        assert_eq!(owned.len(), 3);
+        assert_eq!(owned.rev().collect::<Vec<_>>(), borrowed.into_iter().rev().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,117 @@
+// An order of values that needn't be Hash, Eq or anything else: floats,
+// trait objects, big structs. Each value gets a ListHandle when it goes in,
+// and that's what everything else takes. Handles are Copy and never reused,
+// so one for a value that's since been removed just finds nothing.
+
+use core::cmp::Ordering;
+use core::hash::BuildHasher;
+
+use super::{OrderMaintenanceError, OrderedMap, OrderedMapIter, RandomState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListHandle(u64);
+
+#[derive(Debug)]
+pub struct OrderedList<V, S = RandomState> {
+    map: OrderedMap<ListHandle, V, S>,
+    next_handle: u64,
+}
+
+#[derive(Debug)]
+pub struct OrderedListIter<'a, V, S = RandomState>
+    where V: 'a, S: 'a {
+    iter: OrderedMapIter<'a, ListHandle, V, S>,
+}
+impl<'a, V, S> Iterator for OrderedListIter<'a, V, S> {
+    type Item = (ListHandle, &'a V);
+    fn next(&mut self) -> Option<(ListHandle, &'a V)> {
+        self.iter.next().map(|(&handle, value)| (handle, value))
+    }
+}
+
+impl<V> OrderedList<V> {
+    pub fn new() -> OrderedList<V> {
+        OrderedList::with_hasher(RandomState::default())
+    }
+}
+
+impl<V, S> Default for OrderedList<V, S>
+    where S: BuildHasher + Default {
+    fn default() -> OrderedList<V, S> {
+        OrderedList::with_hasher(S::default())
+    }
+}
+
+impl<V, S> OrderedList<V, S>
+    where S: BuildHasher {
+    // handles are only ever made here, so an attacker can't pick what gets
+    // hashed: any hasher is safe
+    pub fn with_hasher(hasher: S) -> OrderedList<V, S> {
+        OrderedList { map: OrderedMap::with_hasher(hasher), next_handle: 0 }
+    }
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    // in order, with their handles
+    pub fn iter(&self) -> OrderedListIter<'_, V, S> {
+        OrderedListIter { iter: self.map.iter() }
+    }
+    pub fn contains(&self, handle: ListHandle) -> bool {
+        self.map.contains_key(&handle)
+    }
+    pub fn get(&self, handle: ListHandle) -> Option<&V> {
+        self.map.get(&handle)
+    }
+    pub fn get_mut(&mut self, handle: ListHandle) -> Option<&mut V> {
+        self.map.get_mut(&handle)
+    }
+    pub fn compare(&self, a: ListHandle, b: ListHandle) -> Option<Ordering> {
+        self.map.compare(&a, &b)
+    }
+    pub fn insert_only(&mut self, value: V) -> Result<ListHandle, OrderMaintenanceError> {
+        let handle = ListHandle(self.next_handle);
+        self.map.insert_only(handle, value)?;
+        self.next_handle += 1;
+        Ok(handle)
+    }
+    pub fn insert_after(&mut self, after: ListHandle, value: V) -> Result<ListHandle, OrderMaintenanceError> {
+        let handle = ListHandle(self.next_handle);
+        self.map.insert_after(&after, handle, value)?;
+        self.next_handle += 1;
+        Ok(handle)
+    }
+    pub fn remove(&mut self, handle: ListHandle) -> Option<V> {
+        self.map.remove(&handle).map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn floats() {
+        let mut list: OrderedList<f64> = OrderedList::new();
+        let one = list.insert_only(1.0).unwrap();
+        let three = list.insert_after(one, 3.0).unwrap();
+        let two = list.insert_after(one, f64::NAN).unwrap();
+        *list.get_mut(two).unwrap() = 2.0;
+        assert_eq!(list.iter().map(|(_, &value)| value).collect::<Vec<_>>(), [1.0, 2.0, 3.0]);
+        assert_eq!(list.iter().map(|(handle, _)| handle).collect::<Vec<_>>(), [one, two, three]);
+        assert_eq!(list.compare(three, two), Some(Ordering::Greater));
+        assert_eq!(list.insert_only(0.0), Err(OrderMaintenanceError::NotEmpty));
+        assert_eq!(list.remove(two), Some(2.0));
+        assert_eq!(list.get(two), None);
+        assert_eq!(list.compare(one, two), None);
+        assert_eq!(list.insert_after(two, 4.0), Err(OrderMaintenanceError::AnchorMissing));
+        // handles aren't reused
+        let four = list.insert_after(one, 4.0).unwrap();
+        assert_ne!(four, two);
+        assert!(!list.contains(two));
+        assert_eq!(list.len(), 3);
+    }
+}
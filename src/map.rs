@@ -0,0 +1,143 @@
+// An order of keys with a value attached to each, so that the data that goes
+// with an element comes and goes with it instead of living in a HashMap on
+// the side that has to be kept in step by hand. Each key/value pair is one
+// element of an OrderMaintenance, hashed and compared by its key alone, so
+// the key is still only stored once. Lookups take &K itself, not any other
+// form it borrows as.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash, Hasher};
+
+use super::{IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+// one element: Hash, Eq and Borrow only see the key, so the value can change
+// in place
+#[derive(Debug)]
+struct Keyed<K, V> {
+    key: K,
+    value: V,
+}
+impl<K, V> Hash for Keyed<K, V>
+    where K: Hash {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state)
+    }
+}
+impl<K, V> PartialEq for Keyed<K, V>
+    where K: Eq {
+    fn eq(&self, other: &Keyed<K, V>) -> bool {
+        self.key == other.key
+    }
+}
+impl<K, V> Eq for Keyed<K, V>
+    where K: Eq {}
+impl<K, V> Borrow<K> for Keyed<K, V> {
+    fn borrow(&self) -> &K {
+        &self.key
+    }
+}
+
+#[derive(Debug)]
+pub struct OrderedMap<K, V, S = RandomState>
+    where K: Hash + Eq {
+    om: OrderMaintenance<Keyed<K, V>, S>,
+}
+
+#[derive(Debug)]
+pub struct OrderedMapIter<'a, K, V, S = RandomState>
+    where K: Hash + Eq + 'a, V: 'a, S: 'a {
+    iter: IterWithTag<'a, Keyed<K, V>, S>,
+}
+impl<'a, K, V, S> Iterator for OrderedMapIter<'a, K, V, S>
+    where K: Hash + Eq {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.iter.next().map(|(keyed, _)| (&keyed.key, &keyed.value))
+    }
+}
+
+impl<K, V> OrderedMap<K, V>
+    where K: Hash + Eq {
+    pub fn new() -> OrderedMap<K, V> {
+        OrderedMap::with_hasher(RandomState::default())
+    }
+}
+
+impl<K, V, S> Default for OrderedMap<K, V, S>
+    where K: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> OrderedMap<K, V, S> {
+        OrderedMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> OrderedMap<K, V, S>
+    where K: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> OrderedMap<K, V, S> {
+        OrderedMap { om: OrderMaintenance::with_hasher(hasher) }
+    }
+    pub fn len(&self) -> usize {
+        self.om.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.om.is_empty()
+    }
+    // in order
+    pub fn iter(&self) -> OrderedMapIter<'_, K, V, S> {
+        OrderedMapIter { iter: self.om.iter_values_with_tags() }
+    }
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.om.contains(key)
+    }
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.om.stored(key).map(|keyed| &keyed.value)
+    }
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.om.stored_mut(key).map(|keyed| &mut keyed.value)
+    }
+    pub fn compare(&self, a: &K, b: &K) -> Option<Ordering> {
+        self.om.compare(a, b)
+    }
+    pub fn insert_only(&mut self, key: K, value: V) -> Result<(), OrderMaintenanceError> {
+        self.om.insert_only(Keyed { key, value })
+    }
+    pub fn insert_after(&mut self, after: &K, key: K, value: V) -> Result<(), OrderMaintenanceError> {
+        self.om.insert_after(after, Keyed { key, value })
+    }
+    pub fn insert_between(&mut self, a: &K, b: &K, key: K, value: V) -> Result<(), OrderMaintenanceError> {
+        self.om.insert_between(a, b, Keyed { key, value })
+    }
+    pub fn remove(&mut self, key: &K) -> Option<(K, V)> {
+        self.om.remove(key).map(|removed| (removed.value.key, removed.value.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    #[test]
+    fn values_follow_their_keys() {
+        let mut map: OrderedMap<u32, String> = OrderedMap::new();
+        map.insert_only(1, "one".to_string()).unwrap();
+        map.insert_after(&1, 3, "three".to_string()).unwrap();
+        map.insert_between(&1, &3, 2, "two".to_string()).unwrap();
+        assert_eq!(map.insert_after(&3, 2, "again".to_string()), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(map.get(&2).map(String::as_str), Some("two"));
+        map.get_mut(&2).unwrap().push_str(" (2)");
+        assert_eq!(map.iter().map(|(&k, v)| (k, v.as_str())).collect::<Vec<_>>(), [(1, "one"), (2, "two (2)"), (3, "three")]);
+        assert_eq!(map.compare(&3, &1), Some(Ordering::Greater));
+        assert_eq!(map.remove(&1), Some((1, "one".to_string())));
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.len(), 2);
+        // relabels carry the values along
+        for key in 4..600 {
+            map.insert_after(&2, key, key.to_string()).unwrap();
+        }
+        assert!(map.iter().skip(1).take(596).all(|(k, v)| *v == k.to_string()));
+        assert_eq!(map.get(&3).map(String::as_str), Some("three"));
+    }
+}
@@ -0,0 +1,222 @@
+// For the markers-in-a-document case (cursors, selections, diagnostics and
+// the like, kept in document order): putting a run of markers in at one
+// spot, splitting the order in two at a marker or joining one onto the end
+// of another, and walking the markers between two others. Each of them
+// gives the markers it moves tags once, spread over the room where they
+// land, and touches nothing outside that stretch unless there's no room
+// there at all; walking between two markers follows links, so it's two
+// lookups however many markers there are in between.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+
+use super::{Index, OrderMaintenance, OrderMaintenanceError, RandomState, Tag, TagWidth};
+
+// The markers strictly between two others, in order.
+#[derive(Debug)]
+pub struct Between<'a, T, S = RandomState, W = u64>
+    where T: Hash + Eq + 'a, S: 'a, W: TagWidth {
+    om: &'a OrderMaintenance<T, S, W>,
+    next: Option<Index>,
+    // the far anchor
+    end: Index,
+}
+impl<'a, T, S, W> Iterator for Between<'a, T, S, W>
+    where T: Hash + Eq, W: TagWidth {
+    type Item = (&'a T, Tag<W>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.filter(|&current| current != self.end)?;
+        let position = &self.om.slots[current];
+        self.next = Some(position.next);
+        Some((&position.key, Tag(position.tag.wrapping_sub(self.om.rotation))))
+    }
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // The run just before before, in the order given; as with
+    // insert_all_after, nothing goes in unless all of it can.
+    pub fn insert_all_before<Q, I>(&mut self, before: &Q, values: I) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized, I: IntoIterator<Item = T> {
+        let before = self.find(before).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        let values: Vec<T> = values.into_iter().collect();
+        self.check_run(before, &values)?;
+        if values.is_empty() {
+            return Ok(());
+        }
+        if self.front == Some(before) {
+            self.link_run_at_front(values);
+        } else {
+            let after = self.slots[before].prev;
+            self.link_run_after(after, values);
+        }
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // Moves everything from first's position on (first too) into the
+    // returned order, leaving the rest here. The moved markers keep their
+    // tags, and only their own handles go stale (as if they'd been removed
+    // from here); the new order has this one's labeling strategy, but none
+    // of its callbacks or stats.
+    pub fn split_off<Q>(&mut self, first: &Q) -> Result<OrderMaintenance<T, S, W>, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized, S: Clone {
+        let first = self.find(first).ok_or(OrderMaintenanceError::NotPresent)?;
+        let mut rest = OrderMaintenance::with_hasher(self.hasher.clone());
+        rest.strategy = self.strategy;
+        let front = self.front.expect("first is there");
+        let last = self.slots[front].prev;
+        let kept = self.slots[first].prev;
+        let mut index = first;
+        loop {
+            let tag = self.tag(index);
+            let position = self.deallocate(index);
+            let moved = rest.allocate(position.key, tag);
+            rest.link_at_back(moved);
+            if index == last {
+                break;
+            }
+            index = position.next;
+        }
+        if first == front {
+            self.front = None;
+        } else {
+            self.slots[kept].next = front;
+            self.slots[front].prev = kept;
+        }
+        self.verify_valid_structure();
+        rest.verify_valid_structure();
+        Ok(rest)
+    }
+    // other's markers, in their order, after all of these. They're given
+    // tags spread over the room after the last marker here, so other's own
+    // tags and handles don't come with them. Any marker that's in both
+    // fails the whole thing with AlreadyPresent.
+    pub fn append<S2>(&mut self, other: OrderMaintenance<T, S2, W>) -> Result<(), OrderMaintenanceError>
+        where S2: BuildHasher {
+        if other.iter_values_with_tags().any(|(value, _)| self.contains(value)) {
+            return Err(OrderMaintenanceError::AlreadyPresent);
+        }
+        let mut values: Vec<T> = other.into_iter().map(|(value, _)| value).collect();
+        let last = match self.last() {
+            Some(last) => last,
+            None if values.is_empty() => return Ok(()),
+            None => {
+                self.insert_only(values.remove(0))?;
+                self.front.expect("just inserted")
+            }
+        };
+        if !values.is_empty() {
+            self.link_run_after(last, values);
+        }
+        self.verify_valid_structure();
+        Ok(())
+    }
+    // None if either anchor isn't there; nothing if a isn't before b.
+    pub fn between<Q>(&self, a: &Q, b: &Q) -> Option<Between<'_, T, S, W>>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let start = self.find(a)?;
+        let end = self.find(b)?;
+        let next = if self.tag(start).cmp(&self.tag(end)) == Ordering::Less { Some(self.slots[start].next) } else { None };
+        Some(Between { om: self, next, end })
+    }
+
+    // values, checked and not empty, before the front, spread over the room
+    // below its tag
+    fn link_run_at_front(&mut self, values: Vec<T>) {
+        let front = self.front.expect("not empty");
+        let last = self.slots[front].prev;
+        let step = self.tag(front) / W::from_usize(values.len() + 1);
+        let mut tag = W::ZERO;
+        let mut prev = last;
+        let mut first = None;
+        for value in values {
+            tag = tag + step;
+            let rotation = self.rotation;
+            let index = self.allocate(value, tag.wrapping_add(rotation));
+            {
+                let position = &mut self.slots[index];
+                position.prev = prev;
+                position.next = front;
+            }
+            self.slots[prev].next = index;
+            self.slots[front].prev = index;
+            first = first.or(Some(index));
+            prev = index;
+        }
+        self.front = first;
+        if step == W::ZERO {
+            // they'd all be at 0 or on top of each other, with no room
+            // below them to spread into
+            self.relabel_everything();
+        }
+    }
+    // index, allocated with its tag but not linked in, after the last
+    // element; its tag has to be past the last one's
+    fn link_at_back(&mut self, index: Index) {
+        let front = match self.front {
+            None => {
+                self.front = Some(index);
+                return;
+            }
+            Some(front) => front,
+        };
+        let last = self.slots[front].prev;
+        {
+            let position = &mut self.slots[index];
+            position.prev = last;
+            position.next = front;
+        }
+        self.slots[last].next = index;
+        self.slots[front].prev = index;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn runs_splits_and_joins() {
+        let values = |om: &OrderMaintenance<u32>| om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>();
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(50).unwrap();
+        om.insert_all_before(&50, 10..20).unwrap();
+        om.insert_all_before(&50, 20..30).unwrap();
+        om.insert_all_after(&50, 60..70).unwrap();
+        assert_eq!(om.insert_all_before(&10, [5, 50]), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(om.insert_all_before(&10, [10]), Err(OrderMaintenanceError::SelfInsert));
+        assert_eq!(om.insert_all_before(&99, [5]), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(om.len(), 31);
+        let mut expected: Vec<u32> = (10..30).chain([50]).chain(60..70).collect();
+        assert_eq!(values(&om), expected);
+        // crowding the front until there's no room left below it
+        for value in (100..340).step_by(8) {
+            om.insert_all_before(&values(&om)[0], value..value + 8).unwrap();
+            expected.splice(0..0, value..value + 8);
+        }
+        assert_eq!(values(&om), expected);
+        let between: Vec<u32> = om.between(&20, &60).unwrap().map(|(&value, _)| value).collect();
+        assert_eq!(between, (21..30).chain([50]).collect::<Vec<_>>());
+        assert_eq!(om.between(&60, &20).unwrap().count(), 0);
+        assert_eq!(om.between(&20, &21).unwrap().count(), 0);
+        assert!(om.between(&20, &999).is_none());
+        let rest = om.split_off(&50).unwrap();
+        assert_eq!(values(&rest), [50, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69]);
+        assert_eq!(values(&om), expected[..expected.len() - 11]);
+        assert!(!om.contains(&50) && rest.contains(&50));
+        let mut front = om.split_off(&expected[0]).unwrap();
+        assert!(om.is_empty());
+        assert_eq!(front.append(om), Ok(()));
+        assert_eq!(front.append(rest.clone()), Ok(()));
+        assert_eq!(values(&front), expected);
+        assert_eq!(front.append(rest), Err(OrderMaintenanceError::AlreadyPresent));
+        let mut empty: OrderMaintenance<u32> = OrderMaintenance::new();
+        empty.append(front).unwrap();
+        assert_eq!(values(&empty), expected);
+        empty.check_invariants().unwrap();
+    }
+}
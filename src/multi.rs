@@ -0,0 +1,238 @@
+// The same keys kept in several orders at once (priority order and arrival
+// order, say), each compared within its own order. Keys are stored once, in
+// a table shared by every order; each order is an OrderMaintenance of
+// small ids into that table, with links and tags of its own. A key needn't
+// be in every order, and stays in the table for as long as it's in any.
+// Orders are numbered from 0, and an ordering past the last one panics, as
+// indexing past the end of a slice would.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::HashTable;
+
+use super::{IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+#[derive(Debug)]
+struct Shared<K> {
+    key: K,
+    // how many orders it's in
+    orderings: usize,
+}
+
+#[derive(Debug)]
+pub struct MultiOrder<K, S = RandomState>
+    where K: Hash + Eq {
+    keys: Vec<Option<Shared<K>>>,
+    free: Vec<u32>,
+    // ids, hashed by their keys
+    index: HashTable<u32>,
+    hasher: S,
+    // ids are ours, so these don't need the caller's hasher
+    orders: Vec<OrderMaintenance<u32>>,
+}
+
+#[derive(Debug)]
+pub struct MultiOrderIter<'a, K, S = RandomState>
+    where K: Hash + Eq + 'a, S: 'a {
+    multi: &'a MultiOrder<K, S>,
+    iter: IterWithTag<'a, u32>,
+}
+impl<'a, K, S> Iterator for MultiOrderIter<'a, K, S>
+    where K: Hash + Eq {
+    type Item = &'a K;
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(&id, _)| &self.multi.shared(id).key)
+    }
+}
+
+impl<K> MultiOrder<K>
+    where K: Hash + Eq {
+    pub fn new(orderings: usize) -> MultiOrder<K> {
+        MultiOrder::with_hasher(orderings, RandomState::default())
+    }
+}
+
+// key access, which the iterator needs too, whatever the hasher
+impl<K, S> MultiOrder<K, S>
+    where K: Hash + Eq {
+    fn shared(&self, id: u32) -> &Shared<K> {
+        self.keys[id as usize].as_ref().expect("dangling key id")
+    }
+}
+
+impl<K, S> MultiOrder<K, S>
+    where K: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(orderings: usize, hasher: S) -> MultiOrder<K, S> {
+        MultiOrder {
+            keys: Vec::new(),
+            free: Vec::new(),
+            index: HashTable::new(),
+            hasher,
+            orders: (0..orderings).map(|_| OrderMaintenance::new()).collect(),
+        }
+    }
+    pub fn orderings(&self) -> usize {
+        self.orders.len()
+    }
+    // keys in at least one order
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    // keys in that order
+    pub fn len_of(&self, ordering: usize) -> usize {
+        self.orders[ordering].len()
+    }
+    pub fn iter(&self, ordering: usize) -> MultiOrderIter<'_, K, S> {
+        MultiOrderIter { multi: self, iter: self.orders[ordering].iter_values_with_tags() }
+    }
+    pub fn contains<Q>(&self, ordering: usize, key: &Q) -> bool
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(key).is_some_and(|id| self.orders[ordering].contains(&id))
+    }
+    pub fn compare<Q>(&self, ordering: usize, a: &Q, b: &Q) -> Option<Ordering>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.orders[ordering].compare(&self.find(a)?, &self.find(b)?)
+    }
+    pub fn insert_only(&mut self, ordering: usize, key: K) -> Result<(), OrderMaintenanceError> {
+        if !self.orders[ordering].is_empty() {
+            return Err(OrderMaintenanceError::NotEmpty);
+        }
+        let id = self.intern(key);
+        self.orders[ordering].insert_only(id).expect("checked it was empty");
+        Ok(())
+    }
+    // key may already be in other orders, but not this one
+    pub fn insert_after<Q>(&mut self, ordering: usize, after: &Q, key: K) -> Result<(), OrderMaintenanceError>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if key.borrow() == after {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        let after = match self.find(after) {
+            Some(after) if self.orders[ordering].contains(&after) => after,
+            _ => return Err(OrderMaintenanceError::AnchorMissing),
+        };
+        if self.find::<K>(&key).is_some_and(|id| self.orders[ordering].contains(&id)) {
+            return Err(OrderMaintenanceError::AlreadyPresent);
+        }
+        let id = self.intern(key);
+        self.orders[ordering].insert_after(&after, id).expect("checked the anchor and the key");
+        Ok(())
+    }
+    // Takes key out of just this order; once it's in no order at all it's
+    // dropped. False if it wasn't in this one.
+    pub fn remove_from<Q>(&mut self, ordering: usize, key: &Q) -> bool
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let id = match self.find(key) {
+            Some(id) => id,
+            None => return false,
+        };
+        if self.orders[ordering].remove(&id).is_none() {
+            return false;
+        }
+        let shared = self.keys[id as usize].as_mut().expect("dangling key id");
+        shared.orderings -= 1;
+        if shared.orderings == 0 {
+            self.release(id);
+        }
+        true
+    }
+    // out of every order, handing the key back
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<K>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let id = self.find(key)?;
+        for order in &mut self.orders {
+            order.remove(&id);
+        }
+        Some(self.release(id))
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<u32>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let hash = self.hasher.hash_one(key);
+        let keys = &self.keys;
+        self.index.find(hash, |&id| keys[id as usize].as_ref().is_some_and(|shared| shared.key.borrow() == key)).copied()
+    }
+    // key's id, counting one more order it's in; a new id if it's new
+    fn intern(&mut self, key: K) -> u32 {
+        if let Some(id) = self.find(&key) {
+            self.keys[id as usize].as_mut().expect("dangling key id").orderings += 1;
+            return id;
+        }
+        let shared = Some(Shared { key, orderings: 1 });
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.keys[id as usize] = shared;
+                id
+            }
+            None => {
+                assert!(self.keys.len() < u32::MAX as usize, "too many keys");
+                self.keys.push(shared);
+                (self.keys.len() - 1) as u32
+            }
+        };
+        let keys = &self.keys;
+        let hasher = &self.hasher;
+        let hash = hasher.hash_one(&keys[id as usize].as_ref().unwrap().key);
+        self.index.insert_unique(hash, id, |&i| hasher.hash_one(&keys[i as usize].as_ref().unwrap().key));
+        id
+    }
+    // forgets id, which must be in no order by now
+    fn release(&mut self, id: u32) -> K {
+        let hash = self.hasher.hash_one(&self.shared(id).key);
+        if let Ok(entry) = self.index.find_entry(hash, |&i| i == id) {
+            entry.remove();
+        }
+        self.free.push(id);
+        self.keys[id as usize].take().expect("dangling key id").key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    const PRIORITY: usize = 0;
+    const ARRIVAL: usize = 1;
+
+    #[test]
+    fn two_orders() {
+        let mut multi: MultiOrder<String> = MultiOrder::new(2);
+        assert_eq!(multi.orderings(), 2);
+        multi.insert_only(ARRIVAL, "a".to_string()).unwrap();
+        multi.insert_after(ARRIVAL, "a", "b".to_string()).unwrap();
+        multi.insert_after(ARRIVAL, "b", "c".to_string()).unwrap();
+        multi.insert_only(PRIORITY, "c".to_string()).unwrap();
+        multi.insert_after(PRIORITY, "c", "a".to_string()).unwrap();
+        assert_eq!(multi.len(), 3);
+        assert_eq!(multi.len_of(PRIORITY), 2);
+        assert_eq!(multi.iter(ARRIVAL).map(String::as_str).collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(multi.iter(PRIORITY).map(String::as_str).collect::<Vec<_>>(), ["c", "a"]);
+        assert_eq!(multi.compare(ARRIVAL, "a", "c"), Some(Ordering::Less));
+        assert_eq!(multi.compare(PRIORITY, "a", "c"), Some(Ordering::Greater));
+        assert_eq!(multi.compare(PRIORITY, "a", "b"), None);
+        assert!(!multi.contains(PRIORITY, "b"));
+        assert_eq!(multi.insert_after(PRIORITY, "b", "d".to_string()), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(multi.insert_after(PRIORITY, "c", "a".to_string()), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(multi.insert_only(PRIORITY, "b".to_string()), Err(OrderMaintenanceError::NotEmpty));
+        // out of one order stays in the other
+        assert!(multi.remove_from(ARRIVAL, "c"));
+        assert!(!multi.remove_from(ARRIVAL, "c"));
+        assert!(multi.contains(PRIORITY, "c"));
+        assert_eq!(multi.len(), 3);
+        // and out of the last one it's gone
+        assert!(multi.remove_from(ARRIVAL, "b"));
+        assert_eq!(multi.len(), 2);
+        assert_eq!(multi.remove("a"), Some("a".to_string()));
+        assert_eq!(multi.iter(ARRIVAL).count(), 0);
+        assert_eq!(multi.iter(PRIORITY).map(String::as_str).collect::<Vec<_>>(), ["c"]);
+        assert_eq!(multi.remove("a"), None);
+    }
+}
@@ -0,0 +1,476 @@
+// Many separate orders in one arena: every element is in exactly one list,
+// elements can be compared with others in the same list, and elements (or
+// whole runs of them) can be moved between lists. That's the shape of order
+// maintenance over a forest or a partition, without an OrderMaintenance (and
+// a hash table) per group.
+//
+// Every list has a tag space of its own. New tags split the gap or take a
+// share of the room at the back, as OrderMaintenance's do; when a gap is
+// used up the whole list is spread out again, like LabelingStrategy::Midpoint.
+// That's cheap for the many small lists this is for, and much worse than
+// OrderMaintenance for one big list that keeps getting inserts in one place.
+//
+// Lists can't be empty: new_list starts one with its first element, and one
+// goes away when its last element is removed or moved out. A ListId is
+// only good for as long as its list is; after that it finds nothing, even
+// once the slot is reused.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::HashTable;
+
+use super::{OrderMaintenanceError, RandomState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListId {
+    index: u32,
+    // bumped every time the slot is reused
+    generation: u32,
+}
+
+#[derive(Debug)]
+struct Element<T> {
+    key: T,
+    list: u32,
+    prev: Option<u32>,
+    next: Option<u32>,
+    tag: u64,
+}
+
+#[derive(Debug)]
+struct List {
+    generation: u32,
+    // None once the list is gone and the slot is free
+    ends: Option<(u32, u32)>,
+    len: usize,
+}
+
+#[derive(Debug)]
+pub struct PartitionedOrder<T, S = RandomState>
+    where T: Hash + Eq {
+    elements: Vec<Option<Element<T>>>,
+    free: Vec<u32>,
+    // element indices, hashed by their keys
+    index: HashTable<u32>,
+    hasher: S,
+    lists: Vec<List>,
+    free_lists: Vec<u32>,
+    live_lists: usize,
+}
+
+#[derive(Debug)]
+pub struct PartitionIter<'a, T, S = RandomState>
+    where T: Hash + Eq + 'a, S: 'a {
+    om: &'a PartitionedOrder<T, S>,
+    current: Option<u32>,
+}
+impl<'a, T, S> Iterator for PartitionIter<'a, T, S>
+    where T: Hash + Eq {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let element = self.om.element(self.current?);
+        self.current = element.next;
+        Some(&element.key)
+    }
+}
+
+impl<T> PartitionedOrder<T>
+    where T: Hash + Eq {
+    pub fn new() -> PartitionedOrder<T> {
+        PartitionedOrder::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for PartitionedOrder<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> PartitionedOrder<T, S> {
+        PartitionedOrder::with_hasher(S::default())
+    }
+}
+
+// element access, which the iterator needs too, whatever the hasher
+impl<T, S> PartitionedOrder<T, S>
+    where T: Hash + Eq {
+    fn element(&self, index: u32) -> &Element<T> {
+        self.elements[index as usize].as_ref().expect("dangling element index")
+    }
+    fn element_mut(&mut self, index: u32) -> &mut Element<T> {
+        self.elements[index as usize].as_mut().expect("dangling element index")
+    }
+}
+
+impl<T, S> PartitionedOrder<T, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> PartitionedOrder<T, S> {
+        PartitionedOrder {
+            elements: Vec::new(),
+            free: Vec::new(),
+            index: HashTable::new(),
+            hasher,
+            lists: Vec::new(),
+            free_lists: Vec::new(),
+            live_lists: 0,
+        }
+    }
+    // elements, in all the lists together
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    pub fn list_count(&self) -> usize {
+        self.live_lists
+    }
+    pub fn list_len(&self, list: ListId) -> Option<usize> {
+        self.live(list).map(|list| self.lists[list as usize].len)
+    }
+    // in order; empty for a list that's gone
+    pub fn iter(&self, list: ListId) -> PartitionIter<'_, T, S> {
+        let front = self.live(list).and_then(|list| self.lists[list as usize].ends).map(|(front, _)| front);
+        PartitionIter { om: self, current: front }
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).is_some()
+    }
+    pub fn list_of<Q>(&self, value: &Q) -> Option<ListId>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let list = self.element(self.find(value)?).list;
+        Some(ListId { index: list, generation: self.lists[list as usize].generation })
+    }
+    // false if either is missing
+    pub fn same_list<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        match (self.find(a), self.find(b)) {
+            (Some(a), Some(b)) => self.element(a).list == self.element(b).list,
+            _ => false,
+        }
+    }
+    // None unless both are there, in the same list
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let (a, b) = (self.element(self.find(a)?), self.element(self.find(b)?));
+        if a.list != b.list {
+            return None;
+        }
+        Some(a.tag.cmp(&b.tag))
+    }
+    // a list of its own, with value as its only element
+    pub fn new_list(&mut self, value: T) -> Result<ListId, OrderMaintenanceError> {
+        self.check_absent(&value)?;
+        let list = self.allocate_list();
+        let index = self.allocate(value, list.index);
+        self.link(index, list.index, None);
+        Ok(list)
+    }
+    // into whichever list after is in
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.find_anchor(after, &value)?;
+        self.check_absent(&value)?;
+        let list = self.element(after).list;
+        let index = self.allocate(value, list);
+        self.link(index, list, Some(after));
+        Ok(())
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        self.unlink(index);
+        let hash = self.hasher.hash_one(&self.element(index).key);
+        if let Ok(entry) = self.index.find_entry(hash, |&i| i == index) {
+            entry.remove();
+        }
+        self.free.push(index);
+        Some(self.elements[index as usize].take().expect("dangling element index").key)
+    }
+    // Moves value to just after after, which may be in another list (value's
+    // old list goes away if that was all it had).
+    pub fn move_after<Q>(&mut self, value: &Q, after: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        if value == after {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        let after = self.find(after).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        self.unlink(index);
+        let list = self.element(after).list;
+        self.element_mut(index).list = list;
+        self.link(index, list, Some(after));
+        Ok(())
+    }
+    // Everything after value, moved to a new list of its own, in the same
+    // order; None if value was last.
+    pub fn split_after<Q>(&mut self, value: &Q) -> Result<Option<ListId>, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        let start = match self.element(index).next {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+        let old = self.element(index).list;
+        let (_, back) = self.lists[old as usize].ends.expect("element in a list that's gone");
+        let new = self.allocate_list();
+        // the tags are still in order, so they can stay
+        let mut moved = 0;
+        let mut current = Some(start);
+        while let Some(i) = current {
+            let element = self.element_mut(i);
+            element.list = new.index;
+            current = element.next;
+            moved += 1;
+        }
+        self.element_mut(index).next = None;
+        self.element_mut(start).prev = None;
+        self.lists[old as usize].ends = Some((self.lists[old as usize].ends.unwrap().0, index));
+        self.lists[old as usize].len -= moved;
+        self.lists[new.index as usize].ends = Some((start, back));
+        self.lists[new.index as usize].len = moved;
+        Ok(Some(new))
+    }
+    // Moves all of from onto the back of onto, keeping its order; from goes away.
+    pub fn append(&mut self, onto: ListId, from: ListId) -> Result<(), OrderMaintenanceError> {
+        let (onto, from) = match (self.live(onto), self.live(from)) {
+            (Some(onto), Some(from)) if onto == from => return Err(OrderMaintenanceError::SelfInsert),
+            (Some(onto), Some(from)) => (onto, from),
+            _ => return Err(OrderMaintenanceError::NotPresent),
+        };
+        let (front, back) = self.lists[from as usize].ends.expect("live list without elements");
+        let (onto_front, onto_back) = self.lists[onto as usize].ends.expect("live list without elements");
+        let len = self.lists[from as usize].len;
+        // spread evenly over the room behind onto's back, if there's enough
+        let last = self.element(onto_back).tag;
+        let step = (u64::MAX - last) / (len as u64 + 1);
+        let mut tag = last;
+        let mut current = Some(front);
+        while let Some(i) = current {
+            tag = tag.wrapping_add(step);
+            let element = self.element_mut(i);
+            element.list = onto;
+            element.tag = tag;
+            current = element.next;
+        }
+        self.element_mut(onto_back).next = Some(front);
+        self.element_mut(front).prev = Some(onto_back);
+        self.lists[onto as usize].ends = Some((onto_front, back));
+        self.lists[onto as usize].len += len;
+        self.release_list(from);
+        if step == 0 {
+            self.relabel(onto);
+        }
+        Ok(())
+    }
+
+    fn find<Q>(&self, value: &Q) -> Option<u32>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let hash = self.hasher.hash_one(value);
+        let elements = &self.elements;
+        self.index.find(hash, |&i| elements[i as usize].as_ref().is_some_and(|element| element.key.borrow() == value)).copied()
+    }
+    fn find_anchor<Q>(&self, anchor: &Q, value: &T) -> Result<u32, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if value.borrow() == anchor {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        self.find(anchor).ok_or(OrderMaintenanceError::AnchorMissing)
+    }
+    fn check_absent(&self, value: &T) -> Result<(), OrderMaintenanceError> {
+        if self.find(value).is_some() { Err(OrderMaintenanceError::AlreadyPresent) } else { Ok(()) }
+    }
+    // the list's slot, if it's still the same list
+    fn live(&self, list: ListId) -> Option<u32> {
+        match self.lists.get(list.index as usize) {
+            Some(slot) if slot.generation == list.generation && slot.ends.is_some() => Some(list.index),
+            _ => None,
+        }
+    }
+    // empty until something's linked into it
+    fn allocate_list(&mut self) -> ListId {
+        self.live_lists += 1;
+        match self.free_lists.pop() {
+            Some(index) => {
+                let slot = &mut self.lists[index as usize];
+                slot.generation = slot.generation.wrapping_add(1);
+                ListId { index, generation: slot.generation }
+            }
+            None => {
+                assert!(self.lists.len() < u32::MAX as usize, "too many lists");
+                self.lists.push(List { generation: 0, ends: None, len: 0 });
+                ListId { index: (self.lists.len() - 1) as u32, generation: 0 }
+            }
+        }
+    }
+    fn release_list(&mut self, list: u32) {
+        self.lists[list as usize].ends = None;
+        self.lists[list as usize].len = 0;
+        self.free_lists.push(list);
+        self.live_lists -= 1;
+    }
+    // a findable element, not yet linked into its list
+    fn allocate(&mut self, key: T, list: u32) -> u32 {
+        let element = Some(Element { key, list, prev: None, next: None, tag: 0 });
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.elements[index as usize] = element;
+                index
+            }
+            None => {
+                assert!(self.elements.len() < u32::MAX as usize, "too many elements");
+                self.elements.push(element);
+                (self.elements.len() - 1) as u32
+            }
+        };
+        let elements = &self.elements;
+        let hasher = &self.hasher;
+        let hash = hasher.hash_one(&elements[index as usize].as_ref().unwrap().key);
+        self.index.insert_unique(hash, index, |&i| hasher.hash_one(&elements[i as usize].as_ref().unwrap().key));
+        index
+    }
+    // Puts index into list after after (at the front for None, which is only
+    // for a list that's still empty), with a tag to match.
+    fn link(&mut self, index: u32, list: u32, after: Option<u32>) {
+        let after = match after {
+            Some(after) => after,
+            None => {
+                self.element_mut(index).tag = 1 << 63;
+                self.lists[list as usize].ends = Some((index, index));
+                self.lists[list as usize].len = 1;
+                return;
+            }
+        };
+        let next = self.element(after).next;
+        let len = self.lists[list as usize].len as u64;
+        let prev_tag = self.element(after).tag;
+        let tag = match next {
+            Some(next) => prev_tag + (self.element(next).tag - prev_tag) / 2,
+            None => {
+                let room = u64::MAX - prev_tag;
+                prev_tag + if room == 0 { 0 } else { (room / (len + 1)).max(1) }
+            }
+        };
+        {
+            let element = self.element_mut(index);
+            element.prev = Some(after);
+            element.next = next;
+            element.tag = tag;
+        }
+        self.element_mut(after).next = Some(index);
+        let ends = self.lists[list as usize].ends.expect("linking into a list that's gone");
+        match next {
+            Some(next) => self.element_mut(next).prev = Some(index),
+            None => self.lists[list as usize].ends = Some((ends.0, index)),
+        }
+        self.lists[list as usize].len += 1;
+        if tag == prev_tag {
+            self.relabel(list);
+        }
+    }
+    // takes index out of its list, which goes away if that leaves it empty
+    fn unlink(&mut self, index: u32) {
+        let (list, prev, next) = {
+            let element = self.element(index);
+            (element.list, element.prev, element.next)
+        };
+        let (mut front, mut back) = self.lists[list as usize].ends.expect("element in a list that's gone");
+        match prev {
+            Some(prev) => self.element_mut(prev).next = next,
+            None => front = next.unwrap_or(index),
+        }
+        match next {
+            Some(next) => self.element_mut(next).prev = prev,
+            None => back = prev.unwrap_or(index),
+        }
+        let element = self.element_mut(index);
+        element.prev = None;
+        element.next = None;
+        self.lists[list as usize].len -= 1;
+        if self.lists[list as usize].len == 0 {
+            self.release_list(list);
+        } else {
+            self.lists[list as usize].ends = Some((front, back));
+        }
+    }
+    // the whole list, evenly, leaving room at both ends
+    fn relabel(&mut self, list: u32) {
+        let increment = u64::MAX / (self.lists[list as usize].len as u64 + 1);
+        let mut tag = 0;
+        let mut current = self.lists[list as usize].ends.map(|(front, _)| front);
+        while let Some(i) = current {
+            tag += increment;
+            let element = self.element_mut(i);
+            element.tag = tag;
+            current = element.next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn lists_stay_apart() {
+        let mut om: PartitionedOrder<u32> = PartitionedOrder::new();
+        let evens = om.new_list(0).unwrap();
+        let odds = om.new_list(1).unwrap();
+        assert_eq!(om.new_list(0), Err(OrderMaintenanceError::AlreadyPresent));
+        for value in 2..400 {
+            // always right after the first, to use the gaps up
+            om.insert_after(&(value % 2), value).unwrap();
+        }
+        assert_eq!(om.list_count(), 2);
+        assert_eq!(om.list_len(evens), Some(200));
+        assert!(om.same_list(&4, &10));
+        assert!(!om.same_list(&4, &5));
+        assert!(!om.same_list(&4, &1000));
+        assert_eq!(om.compare(&4, &5), None);
+        assert_eq!(om.compare(&10, &4), Some(Ordering::Less));
+        let expected: Vec<u32> = [1].iter().copied().chain((3..400).rev().step_by(2)).collect();
+        assert_eq!(om.iter(odds).copied().collect::<Vec<_>>(), expected);
+        for pair in expected.windows(2) {
+            assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+        }
+        assert_eq!(om.list_of(&7), Some(odds));
+    }
+
+    #[test]
+    fn splicing() {
+        let mut om: PartitionedOrder<u32> = PartitionedOrder::new();
+        let first = om.new_list(0).unwrap();
+        for value in 1..10 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        let second = om.split_after(&4).unwrap().unwrap();
+        assert_eq!(om.split_after(&9), Ok(None));
+        assert_eq!(om.iter(first).copied().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(om.iter(second).copied().collect::<Vec<_>>(), [5, 6, 7, 8, 9]);
+        assert!(!om.same_list(&4, &5));
+        // moving out and back in
+        om.move_after(&7, &2).unwrap();
+        assert_eq!(om.iter(first).copied().collect::<Vec<_>>(), [0, 1, 2, 7, 3, 4]);
+        assert_eq!(om.compare(&7, &3), Some(Ordering::Less));
+        assert_eq!(om.list_len(second), Some(4));
+        om.append(second, first).unwrap();
+        assert_eq!(om.iter(second).copied().collect::<Vec<_>>(), [5, 6, 8, 9, 0, 1, 2, 7, 3, 4]);
+        assert_eq!(om.compare(&9, &0), Some(Ordering::Less));
+        // first is gone, and stays gone once its slot is reused
+        assert_eq!(om.list_len(first), None);
+        assert_eq!(om.iter(first).count(), 0);
+        assert_eq!(om.append(second, first), Err(OrderMaintenanceError::NotPresent));
+        let third = om.new_list(100).unwrap();
+        assert_ne!(third, first);
+        assert_eq!(om.list_len(first), None);
+        // moving the only element of a list ends it
+        om.move_after(&100, &5).unwrap();
+        assert_eq!(om.list_len(third), None);
+        assert_eq!(om.list_count(), 1);
+        assert_eq!(om.remove(&5), Some(5));
+        assert_eq!(om.iter(second).copied().collect::<Vec<_>>(), [100, 6, 8, 9, 0, 1, 2, 7, 3, 4]);
+        assert_eq!(om.len(), 10);
+    }
+}
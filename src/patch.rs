@@ -0,0 +1,134 @@
+// apply_patch(): apply(), but all or nothing. A patch (from diff(), say,
+// sent over from a replica, which the serde feature can do for Ops) is
+// checked against the order as it would be at each step before anything is
+// changed, so that one that doesn't fit — an anchor that's gone, an insert
+// of something that's already there — is refused whole, saying which op
+// was the first that couldn't be done and why.
+
+use core::error::Error;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+
+use super::{Op, OrderMaintenance, OrderMaintenanceError, RandomState, TagWidth};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchConflict<T> {
+    // how far into the patch
+    pub position: usize,
+    pub op: Op<T>,
+    pub error: OrderMaintenanceError,
+}
+impl<T> fmt::Display for PatchConflict<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "patch op {} conflicts: {}", self.position, self.error)
+    }
+}
+impl<T> Error for PatchConflict<T>
+    where T: fmt::Debug {}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    pub fn apply_patch<P>(&mut self, patch: P) -> Result<(), PatchConflict<T>>
+        where P: IntoIterator<Item = Op<T>> {
+        let patch: Vec<Op<T>> = patch.into_iter().collect();
+        if let Some((position, error)) = self.check_patch(&patch) {
+            let op = patch.into_iter().nth(position).expect("the conflict is in the patch");
+            return Err(PatchConflict { position, op, error });
+        }
+        for op in patch {
+            self.apply_op(op).expect("checked patch");
+        }
+        Ok(())
+    }
+    // where the patch would first fail, going only by what's present when:
+    // that's all any op checks
+    fn check_patch(&self, patch: &[Op<T>]) -> Option<(usize, OrderMaintenanceError)> {
+        // what the patch has put in (true) or taken out (false) so far
+        let mut changed: HashMap<&T, bool, RandomState> = HashMap::with_hasher(RandomState::default());
+        let mut len = self.len();
+        for (position, op) in patch.iter().enumerate() {
+            let present = |changed: &HashMap<&T, bool, RandomState>, value: &T| changed.get(value).copied().unwrap_or_else(|| self.contains(value));
+            let need = |changed: &HashMap<&T, bool, RandomState>, value: &T, error| if present(changed, value) { Ok(()) } else { Err(error) };
+            let absent = |changed: &HashMap<&T, bool, RandomState>, value: &T| if present(changed, value) { Err(OrderMaintenanceError::AlreadyPresent) } else { Ok(()) };
+            let checked = match *op {
+                Op::InsertOnly(_) => if len == 0 { Ok(()) } else { Err(OrderMaintenanceError::NotEmpty) },
+                Op::InsertAfter { ref after, ref value } => {
+                    if after == value {
+                        Err(OrderMaintenanceError::SelfInsert)
+                    } else {
+                        need(&changed, after, OrderMaintenanceError::AnchorMissing).and_then(|()| absent(&changed, value))
+                    }
+                }
+                Op::Remove(ref value) => need(&changed, value, OrderMaintenanceError::NotPresent),
+                Op::ReplaceKey { ref old, ref new } => need(&changed, old, OrderMaintenanceError::NotPresent).and_then(|()| if old == new { Ok(()) } else { absent(&changed, new) }),
+                Op::MoveAfter { ref value, after: ref anchor } | Op::MoveBefore { ref value, before: ref anchor } => {
+                    need(&changed, value, OrderMaintenanceError::NotPresent)
+                        .and_then(|()| need(&changed, anchor, OrderMaintenanceError::AnchorMissing))
+                        .and_then(|()| if value == anchor { Err(OrderMaintenanceError::SelfInsert) } else { Ok(()) })
+                }
+                Op::MoveToFront(ref value) | Op::MoveToBack(ref value) | Op::RotateToFront(ref value) => need(&changed, value, OrderMaintenanceError::NotPresent),
+                Op::SwapPositions(ref a, ref b) => need(&changed, a, OrderMaintenanceError::NotPresent).and_then(|()| need(&changed, b, OrderMaintenanceError::NotPresent)),
+                Op::Reverse | Op::RebalanceAll => Ok(()),
+            };
+            if let Err(error) = checked {
+                return Some((position, error));
+            }
+            match *op {
+                Op::InsertOnly(ref value) | Op::InsertAfter { ref value, .. } => {
+                    changed.insert(value, true);
+                    len += 1;
+                }
+                Op::Remove(ref value) => {
+                    changed.insert(value, false);
+                    len -= 1;
+                }
+                Op::ReplaceKey { ref old, ref new } if old != new => {
+                    changed.insert(old, false);
+                    changed.insert(new, true);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    #[test]
+    fn all_or_nothing() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(1).unwrap();
+        om.insert_after(&1, 2).unwrap();
+        om.insert_after(&2, 3).unwrap();
+        let values = |om: &OrderMaintenance<u32>| om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>();
+        // fine as a whole, though 4 and 5 only exist partway through
+        om.apply_patch([Op::InsertAfter { after: 3, value: 4 }, Op::Remove(1), Op::ReplaceKey { old: 4, new: 5 }, Op::MoveToFront(5), Op::InsertAfter { after: 5, value: 1 }]).unwrap();
+        assert_eq!(values(&om), [5, 1, 2, 3]);
+        let conflicts = [
+            (Vec::from([Op::Remove(2), Op::MoveAfter { value: 3, after: 2 }]), 1, OrderMaintenanceError::AnchorMissing),
+            (Vec::from([Op::Remove(2), Op::InsertAfter { after: 3, value: 2 }, Op::InsertAfter { after: 3, value: 2 }]), 2, OrderMaintenanceError::AlreadyPresent),
+            (Vec::from([Op::MoveBefore { value: 5, before: 5 }]), 0, OrderMaintenanceError::SelfInsert),
+            (Vec::from([Op::InsertAfter { after: 5, value: 5 }]), 0, OrderMaintenanceError::SelfInsert),
+            (Vec::from([Op::Remove(2), Op::InsertAfter { after: 2, value: 2 }]), 1, OrderMaintenanceError::SelfInsert),
+            (Vec::from([Op::Remove(5), Op::Remove(1), Op::Remove(2), Op::Remove(3), Op::InsertOnly(9), Op::InsertOnly(8)]), 5, OrderMaintenanceError::NotEmpty),
+            (Vec::from([Op::Reverse, Op::ReplaceKey { old: 5, new: 1 }]), 1, OrderMaintenanceError::AlreadyPresent),
+            (Vec::from([Op::SwapPositions(5, 6)]), 0, OrderMaintenanceError::NotPresent),
+        ];
+        for (patch, position, error) in conflicts {
+            let op = patch[position].clone();
+            assert_eq!(om.apply_patch(patch), Err(PatchConflict { position, op, error }));
+            // and nothing changed
+            assert_eq!(values(&om), [5, 1, 2, 3]);
+        }
+        let conflict = om.apply_patch([Op::Remove(7)]).unwrap_err();
+        assert_eq!(conflict.to_string(), "patch op 0 conflicts: element is not present");
+    }
+}
@@ -0,0 +1,145 @@
+// An order for many readers and the odd writer. Readers load() the latest
+// published version, a CowOrderMaintenance, and then compare, iterate and
+// the rest against it for as long as they like without any locking at all:
+// it's frozen, and nothing a writer does afterwards shows through. Writers
+// make their changes in update(), on a working copy, and the version the
+// closure leaves is published in one step once it returns, so a reader sees
+// all of an update or none of it.
+//
+// Loading is an Arc clone, taken under a read lock held for just that long,
+// and publishing is an Arc swap under the write lock, held just as briefly.
+// The published version is the only copy kept between updates, so each
+// update copies it (O(n)) to work on, which is what keeps it frozen for
+// whoever has it loaded; it pays to make several changes in one update
+// rather than one each. As with CowOrderMaintenance, the copy doesn't bring
+// the on_relabel callback, relabel listener or handles along.
+
+use core::hash::{BuildHasher, Hash};
+use core::mem;
+
+use std::sync::{Mutex, PoisonError, RwLock};
+
+use super::{CowOrderMaintenance, OrderMaintenance, RandomState};
+
+#[derive(Debug)]
+pub struct PublishedOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    // holding this lock is what makes one the writer
+    writer: Mutex<()>,
+    published: RwLock<CowOrderMaintenance<T, S>>,
+}
+
+impl<T> PublishedOrderMaintenance<T>
+    where T: Hash + Eq {
+    pub fn new() -> PublishedOrderMaintenance<T> {
+        PublishedOrderMaintenance::from(OrderMaintenance::new())
+    }
+}
+
+impl<T, S> Default for PublishedOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> PublishedOrderMaintenance<T, S> {
+        PublishedOrderMaintenance::from(OrderMaintenance::default())
+    }
+}
+
+// Published as it is.
+impl<T, S> From<OrderMaintenance<T, S>> for PublishedOrderMaintenance<T, S>
+    where T: Hash + Eq {
+    fn from(om: OrderMaintenance<T, S>) -> PublishedOrderMaintenance<T, S> {
+        PublishedOrderMaintenance { writer: Mutex::new(()), published: RwLock::new(CowOrderMaintenance::from(om)) }
+    }
+}
+
+impl<T, S> PublishedOrderMaintenance<T, S>
+    where T: Hash + Eq {
+    pub fn load(&self) -> CowOrderMaintenance<T, S> {
+        self.published.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+    // Updates wait for each other, but never for readers, nor readers for
+    // them while f runs. If f panics, nothing it did is published, and the
+    // next update starts over from what was.
+    pub fn update<R, F>(&self, f: F) -> R
+        where T: Clone, S: Clone, F: FnOnce(&mut OrderMaintenance<T, S>) -> R {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut working = self.load();
+        let result = f(working.make_mut());
+        // the old version, if nobody has it loaded, is freed after the lock
+        // is let go
+        let _old = mem::replace(&mut *self.published.write().unwrap_or_else(PoisonError::into_inner), working);
+        result
+    }
+    pub fn into_inner(self) -> OrderMaintenance<T, S>
+        where T: Clone, S: Clone {
+        self.published.into_inner().unwrap_or_else(PoisonError::into_inner).into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cmp::Ordering;
+    use std::panic;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn readers_see_whole_updates() {
+        let order: PublishedOrderMaintenance<u32> = PublishedOrderMaintenance::new();
+        order.update(|om| om.insert_only(0)).unwrap();
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..200 {
+                        // each update puts a pair in, in order, and the
+                        // pair always lands together
+                        let snapshot = order.load();
+                        assert_eq!(snapshot.len() % 2, 1);
+                        for (&value, _) in snapshot.iter_values_with_tags().filter(|&(&value, _)| value % 2 == 1) {
+                            assert_eq!(snapshot.compare(&value, &(value + 1)), Some(Ordering::Less));
+                        }
+                    }
+                });
+            }
+            for value in (1..100).step_by(2) {
+                order.update(|om| {
+                    om.insert_after(&0, value).unwrap();
+                    om.insert_after(&value, value + 1).unwrap();
+                });
+            }
+        });
+        let before = order.load();
+        let failed = panic::catch_unwind(panic::AssertUnwindSafe(|| order.update(|om| {
+            om.remove(&1);
+            panic!("halfway through");
+        })));
+        assert!(failed.is_err());
+        assert!(order.load().contains(&1));
+        order.update(|om| om.move_to_back(&0)).unwrap();
+        // started over from what was published, not from the half-done one
+        let after = order.load();
+        assert!(after.contains(&1) && after.is_after(&0, &100) && before.is_before(&0, &100));
+        assert_eq!(order.into_inner().len(), 101);
+    }
+    #[test]
+    fn loads_go_on_during_updates() {
+        let order: PublishedOrderMaintenance<u32> = PublishedOrderMaintenance::new();
+        order.update(|om| om.insert_only(0)).unwrap();
+        let (started, wait_for_start) = mpsc::channel();
+        let (finish, wait_to_finish) = mpsc::channel();
+        let shared = &order;
+        thread::scope(|scope| {
+            scope.spawn(move || shared.update(|om| {
+                om.insert_after(&0, 1).unwrap();
+                started.send(()).unwrap();
+                wait_to_finish.recv().unwrap();
+            }));
+            wait_for_start.recv().unwrap();
+            // the update is still going, and neither blocks this nor shows
+            let during = order.load();
+            assert_eq!(during.len(), 1);
+            finish.send(()).unwrap();
+        });
+        assert_eq!(order.load().len(), 2);
+    }
+}
@@ -0,0 +1,171 @@
+// The order for Python, through pyo3: a class that's `OrderMaintenance` on
+// the Python side, keyed by any hashable objects, equal as Python sees them
+// (so 1 and 1.0 are the same key, as they would be in a dict). An error the
+// order returns is raised: KeyError for a key or anchor that isn't there,
+// ValueError for the rest.
+//
+// compare(a, b) is -1, 0 or 1, or None if either isn't there; iterating
+// gives the keys front to back, from a list taken when iteration starts, so
+// changing the order meanwhile doesn't disturb it.
+//
+// register() adds the class to a module, for the #[pymodule] of a cdylib
+// crate that depends on this one with the feature on.
+//
+// #[pymethods] wraps every PyResult in a conversion clippy sees as useless.
+#![allow(clippy::useless_conversion)]
+
+use core::hash::{Hash, Hasher};
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use pyo3::exceptions::{PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyIterator, PyList};
+
+use super::{OrderMaintenance, OrderMaintenanceError};
+
+// A Python object with the hash it had going in, so that hashing it again
+// doesn't need the GIL; equality does, and an __eq__ that raises counts as
+// not equal.
+struct Key {
+    object: Py<PyAny>,
+    hash: isize,
+}
+impl Key {
+    fn new(object: &Bound<'_, PyAny>) -> PyResult<Key> {
+        Ok(Key { hash: object.hash()?, object: object.clone().unbind() })
+    }
+}
+impl Hash for Key {
+    fn hash<H>(&self, state: &mut H)
+        where H: Hasher {
+        self.hash.hash(state);
+    }
+}
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.hash == other.hash
+            && (self.object.is(&other.object) || Python::with_gil(|py| self.object.bind(py).eq(other.object.bind(py)).unwrap_or(false)))
+    }
+}
+impl Eq for Key {}
+
+fn raise(error: OrderMaintenanceError) -> PyErr {
+    match error {
+        OrderMaintenanceError::NotPresent | OrderMaintenanceError::AnchorMissing => PyKeyError::new_err(error.to_string()),
+        _ => PyValueError::new_err(error.to_string()),
+    }
+}
+
+#[pyclass(name = "OrderMaintenance")]
+pub struct PyOrderMaintenance {
+    om: OrderMaintenance<Key>,
+}
+
+impl Default for PyOrderMaintenance {
+    fn default() -> PyOrderMaintenance {
+        PyOrderMaintenance::new()
+    }
+}
+
+#[pymethods]
+impl PyOrderMaintenance {
+    #[new]
+    pub fn new() -> PyOrderMaintenance {
+        PyOrderMaintenance { om: OrderMaintenance::new() }
+    }
+    fn __len__(&self) -> usize {
+        self.om.len()
+    }
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.om.contains(&Key::new(key)?))
+    }
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        let keys: Vec<&Py<PyAny>> = self.om.iter_values_with_tags().map(|(key, _)| &key.object).collect();
+        PyList::new_bound(py, keys).as_any().iter()
+    }
+    pub fn compare(&self, a: &Bound<'_, PyAny>, b: &Bound<'_, PyAny>) -> PyResult<Option<i32>> {
+        Ok(self.om.compare(&Key::new(a)?, &Key::new(b)?).map(|ordering| ordering as i32))
+    }
+    pub fn is_before(&self, a: &Bound<'_, PyAny>, b: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.om.is_before(&Key::new(a)?, &Key::new(b)?))
+    }
+    pub fn insert_only(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.insert_only(Key::new(key)?).map_err(raise)
+    }
+    pub fn insert_after(&mut self, after: &Bound<'_, PyAny>, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.insert_after(&Key::new(after)?, Key::new(key)?).map_err(raise)
+    }
+    pub fn insert_before(&mut self, before: &Bound<'_, PyAny>, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.insert_all_before(&Key::new(before)?, Some(Key::new(key)?)).map_err(raise)
+    }
+    // false if it wasn't there
+    pub fn remove(&mut self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.om.remove(&Key::new(key)?).is_some())
+    }
+    pub fn move_after(&mut self, key: &Bound<'_, PyAny>, after: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.move_after(&Key::new(key)?, &Key::new(after)?).map_err(raise)
+    }
+    pub fn move_before(&mut self, key: &Bound<'_, PyAny>, before: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.move_before(&Key::new(key)?, &Key::new(before)?).map_err(raise)
+    }
+    pub fn move_to_front(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.move_to_front(&Key::new(key)?).map_err(raise)
+    }
+    pub fn move_to_back(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.om.move_to_back(&Key::new(key)?).map_err(raise)
+    }
+}
+
+pub fn register(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyOrderMaintenance>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn round_trips_through_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "order_maintenance").unwrap();
+            register(&module).unwrap();
+            let globals = PyDict::new_bound(py);
+            globals.set_item("OrderMaintenance", module.getattr("OrderMaintenance").unwrap()).unwrap();
+            py.run_bound(r#"
+om = OrderMaintenance()
+om.insert_only(1)
+om.insert_after(1, "one")
+om.insert_before(1, (0, "zero"))
+assert len(om) == 3 and list(om) == [(0, "zero"), 1, "one"]
+# equal as Python sees it
+assert 1.0 in om and "1" not in om
+assert om.compare("one", 1) == 1 and om.compare(1, 1.0) == 0 and om.compare(1, 2) is None
+om.move_before("one", (0, "zero"))
+assert list(om) == ["one", (0, "zero"), 1] and om.is_before("one", 1)
+for key in om:
+    om.remove(key)
+assert len(om) == 0 and not om.remove(1)
+try:
+    om.insert_after(5, 6)
+    raise AssertionError("no KeyError")
+except KeyError:
+    pass
+try:
+    om.insert_only([])
+    raise AssertionError("no TypeError")
+except TypeError:
+    pass
+om.insert_only("a")
+try:
+    om.insert_only("b")
+    raise AssertionError("no ValueError")
+except ValueError:
+    pass
+"#, Some(&globals), None).unwrap();
+        });
+    }
+}
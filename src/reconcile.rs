@@ -0,0 +1,95 @@
+// Keyed-list reconciliation, as a virtual-DOM renderer does it: given the
+// keys a list should now have, in order, make the order that, and say what
+// it took, so the same changes can be made to the real list. It's diff()
+// against the desired order, applied: keys that aren't wanted any more are
+// removed, the longest run of the rest that's already in the right order
+// stays where it is, and everything else is moved or inserted. Nothing
+// that stays is touched, tags included.
+//
+// To replay it on the real list: remove what was removed, then walk the
+// desired keys front to back, putting each one that was inserted or moved
+// just after the key before it (or at the front, for the first).
+
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+
+use super::{Op, OrderMaintenance, OrderMaintenanceError, TagWidth};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation<T> {
+    // in the order they were in
+    pub removed: Vec<T>,
+    // these two in the desired order
+    pub inserted: Vec<T>,
+    pub moved: Vec<T>,
+}
+impl<T> Reconciliation<T> {
+    // nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.inserted.is_empty() && self.moved.is_empty()
+    }
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone, W: TagWidth {
+    // O((n + m) log(n + m)). A key that's in desired twice is AlreadyPresent,
+    // and then nothing changes.
+    pub fn reconcile<I>(&mut self, desired: I) -> Result<Reconciliation<T>, OrderMaintenanceError>
+        where I: IntoIterator<Item = T> {
+        let mut target: OrderMaintenance<T, S, W> = OrderMaintenance::with_hasher(self.hasher.clone());
+        let mut desired = desired.into_iter();
+        if let Some(first) = desired.next() {
+            let front = first.clone();
+            target.insert_only(first)?;
+            // the first key again is just as much a repeat as any other
+            target.insert_all_after(&front, desired).map_err(|error| match error {
+                OrderMaintenanceError::SelfInsert => OrderMaintenanceError::AlreadyPresent,
+                error => error,
+            })?;
+        }
+        let ops = self.diff(&target);
+        let mut reconciliation = Reconciliation { removed: Vec::new(), inserted: Vec::new(), moved: Vec::new() };
+        for op in &ops {
+            match *op {
+                Op::Remove(ref value) => reconciliation.removed.push(value.clone()),
+                Op::InsertOnly(ref value) | Op::InsertAfter { ref value, .. } => reconciliation.inserted.push(value.clone()),
+                // inserting at the front is inserting anywhere and then this
+                Op::MoveToFront(ref value) if reconciliation.inserted.last() == Some(value) => {}
+                Op::MoveAfter { ref value, .. } | Op::MoveToFront(ref value) => reconciliation.moved.push(value.clone()),
+                _ => unreachable!("diff only removes, inserts and moves"),
+            }
+        }
+        self.apply(ops).expect("diff's ops apply");
+        Ok(reconciliation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn reports_the_fewest_moves() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(1).unwrap();
+        om.insert_all_after(&1, 2..=6).unwrap();
+        let tag_of_2 = om.tag_of(&2);
+        let desired = [3, 1, 2, 7, 5, 4];
+        let reconciliation = om.reconcile(desired.iter().copied()).unwrap();
+        assert_eq!(om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>(), desired);
+        assert_eq!(reconciliation.removed, [6]);
+        assert_eq!(reconciliation.inserted, [7]);
+        // 1 and 2 stay as they are; 3 has to come to the front, and 4 and 5
+        // can't both stay
+        assert_eq!(reconciliation.moved, [3, 4]);
+        assert_eq!(om.tag_of(&2), tag_of_2);
+        assert_eq!(om.reconcile([0, 3]).unwrap(), Reconciliation { removed: Vec::from([1, 2, 7, 5, 4]), inserted: Vec::from([0]), moved: Vec::new() });
+        assert!(om.reconcile([0, 3]).unwrap().is_empty());
+        assert_eq!(om.reconcile([3, 9, 3]), Err(OrderMaintenanceError::AlreadyPresent));
+        assert_eq!(om.len(), 2);
+        assert_eq!(om.reconcile([]).unwrap().removed, [0, 3]);
+        assert!(om.is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+// An order that remembers every change made to it, so that when one leaves
+// the structure broken (check_invariants() fails afterwards, or the op panics
+// partway through, as the debug-build checks do) the panic that follows
+// carries a repro: the shortest run of ops found that, applied to a new
+// order, breaks it the same way, written out ready to paste into a test.
+//
+// Changes go in as Ops, so the log is exactly what happened and replaying it
+// is deterministic: nothing an order does depends on its hasher's seed, only
+// on the ops. Shrinking the log is a delta-debugging pass (drop halves, then
+// quarters and so on down to single ops, keeping every drop that still
+// fails), which replays it O(n log n) times, but only once something has
+// already gone wrong. The replays' own panics are kept off stderr meanwhile
+// by swapping the panic hook out, which goes for every thread.
+
+use core::fmt::{self, Debug};
+use core::hash::{BuildHasher, Hash};
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use std::panic::{self, AssertUnwindSafe};
+
+use super::{IntegrityError, Op, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+#[derive(Debug)]
+pub struct RecordingOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    ops: Vec<Op<T>>,
+    // replays start from a new order with a copy of this
+    hasher: S,
+}
+
+// How an order broke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReproFailure {
+    Integrity(IntegrityError),
+    // with the panic's message
+    Panic(String),
+}
+impl fmt::Display for ReproFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReproFailure::Integrity(ref error) => write!(f, "check_invariants failed: {}", error),
+            ReproFailure::Panic(ref message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+// Applying ops to a new order, one after another, fails with failure at the
+// last of them. Ops that return an error are left in (they don't change
+// anything, but the shrinking keeps whatever it finds still failing).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repro<T> {
+    pub ops: Vec<Op<T>>,
+    pub failure: ReproFailure,
+}
+// As a test to paste in.
+impl<T> fmt::Display for Repro<T>
+    where T: Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} ({} ops); to reproduce:", self.failure, self.ops.len())?;
+        writeln!(f, "let mut om = OrderMaintenance::new();")?;
+        for op in &self.ops {
+            writeln!(f, "let _ = om.apply_op(Op::{:?});", op)?;
+        }
+        write!(f, "om.check_invariants().unwrap();")
+    }
+}
+
+impl<T> RecordingOrderMaintenance<T>
+    where T: Hash + Eq + Clone + Debug {
+    pub fn new() -> RecordingOrderMaintenance<T> {
+        RecordingOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for RecordingOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone + Debug, S: BuildHasher + Clone + Default {
+    fn default() -> RecordingOrderMaintenance<T, S> {
+        RecordingOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+impl<T, S> RecordingOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone + Debug, S: BuildHasher + Clone {
+    // Recording has to start from empty, for the log to say how the order
+    // got the way it is.
+    pub fn with_hasher(hasher: S) -> RecordingOrderMaintenance<T, S> {
+        RecordingOrderMaintenance { om: OrderMaintenance::with_hasher(hasher.clone()), ops: Vec::new(), hasher }
+    }
+    pub fn order(&self) -> &OrderMaintenance<T, S> {
+        &self.om
+    }
+    // everything applied so far, failed ops included
+    pub fn ops(&self) -> &[Op<T>] {
+        &self.ops
+    }
+    pub fn into_inner(self) -> OrderMaintenance<T, S> {
+        self.om
+    }
+    pub fn apply<I>(&mut self, ops: I) -> Result<(), OrderMaintenanceError>
+        where I: IntoIterator<Item = Op<T>> {
+        for op in ops {
+            self.apply_op(op)?;
+        }
+        Ok(())
+    }
+    // Panics, with the repro as its message, if op breaks the order.
+    pub fn apply_op(&mut self, op: Op<T>) -> Result<(), OrderMaintenanceError> {
+        self.ops.push(op.clone());
+        match apply_checked(&mut self.om, op) {
+            Ok(result) => result,
+            Err(failure) => panic!("{}", self.repro(failure)),
+        }
+    }
+    // Shrinks the log down to a repro of failure.
+    fn repro(&self, failure: ReproFailure) -> Repro<T> {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let ops = minimize(self.ops.clone(), |ops| self.replay(ops).filter(|(_, failed)| *failed == failure).map(|(at, _)| at));
+        panic::set_hook(hook);
+        Repro { ops, failure }
+    }
+    // where (and how) replaying ops onto a new order first breaks it
+    fn replay(&self, ops: &[Op<T>]) -> Option<(usize, ReproFailure)> {
+        let mut om = OrderMaintenance::with_hasher(self.hasher.clone());
+        ops.iter().enumerate().find_map(|(at, op)| apply_checked(&mut om, op.clone()).err().map(|failure| (at, failure)))
+    }
+}
+
+fn apply_checked<T, S>(om: &mut OrderMaintenance<T, S>, op: Op<T>) -> Result<Result<(), OrderMaintenanceError>, ReproFailure>
+    where T: Hash + Eq, S: BuildHasher {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| om.apply_op(op))).map_err(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|message| message.to_string());
+        ReproFailure::Panic(message.or_else(|| payload.downcast_ref::<String>().cloned()).unwrap_or_default())
+    })?;
+    om.check_invariants().map_err(ReproFailure::Integrity)?;
+    Ok(result)
+}
+
+// Drops as many ops as it can while fails still finds the failure in what's
+// left (as the index of the op it happens at, so everything after that can
+// go too). ops has to fail to begin with.
+fn minimize<T, F>(mut ops: Vec<Op<T>>, mut fails: F) -> Vec<Op<T>>
+    where T: Clone, F: FnMut(&[Op<T>]) -> Option<usize> {
+    let at = fails(&ops).expect("ops reproduce the failure");
+    ops.truncate(at + 1);
+    let mut chunk = ops.len().div_ceil(2);
+    while chunk > 0 {
+        let mut start = 0;
+        let mut dropped = false;
+        while start < ops.len() {
+            let end = (start + chunk).min(ops.len());
+            let mut fewer = ops[..start].to_vec();
+            fewer.extend_from_slice(&ops[end..]);
+            match fails(&fewer) {
+                Some(at) => {
+                    fewer.truncate(at + 1);
+                    ops = fewer;
+                    dropped = true;
+                }
+                None => start = end,
+            }
+        }
+        // once nothing at this size can go, try smaller; single ops get
+        // another pass while that still drops something
+        if !dropped || chunk > 1 {
+            chunk /= 2;
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+    use std::string::ToString;
+    use std::vec;
+
+    #[test]
+    fn shrinks_to_what_matters() {
+        let mut ops = vec![Op::InsertOnly(0)];
+        ops.extend((1..40).map(|value| Op::InsertAfter { after: (value * 7 + 3) % value, value }));
+        ops.push(Op::MoveToFront(20));
+        let mut om: RecordingOrderMaintenance<u32> = RecordingOrderMaintenance::new();
+        om.apply(ops.clone()).unwrap();
+        assert_eq!(om.apply_op(Op::Remove(1000)), Err(OrderMaintenanceError::NotPresent));
+        assert_eq!(om.ops().len(), 42);
+        assert_eq!(om.replay(om.ops()), None);
+        // nothing breaks a real order, so stand in a "bug": 20 coming
+        // before 10
+        let broken = |ops: &[Op<u32>]| {
+            let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+            ops.iter().position(|op| {
+                let _ = om.apply_op(op.clone());
+                om.is_before(&20, &10)
+            })
+        };
+        let shrunk = minimize(ops, broken);
+        assert!(shrunk.len() < 10, "{:?}", shrunk);
+        assert_eq!(broken(&shrunk), Some(shrunk.len() - 1));
+        // not one op left that it could do without
+        for skip in 0..shrunk.len() {
+            let mut fewer = shrunk.clone();
+            fewer.remove(skip);
+            assert_eq!(broken(&fewer), None, "{:?} without op {}", shrunk, skip);
+        }
+        let repro = Repro { ops: shrunk, failure: ReproFailure::Integrity(IntegrityError::TagInversion) };
+        let shown = repro.to_string();
+        assert!(shown.starts_with(&format!("check_invariants failed: {} (", IntegrityError::TagInversion)));
+        assert_eq!(shown.lines().nth(1), Some("let mut om = OrderMaintenance::new();"));
+        assert!(shown.contains("let _ = om.apply_op(Op::InsertOnly(0));"));
+    }
+}
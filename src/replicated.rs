@@ -0,0 +1,503 @@
+// A sequence that several sites edit at once, each its own copy, and that
+// comes out the same everywhere once everyone has seen every op, whatever
+// order they arrived in: a replicated growable array (RGA). Every element
+// gets an ElementId, a Lamport clock reading plus the site that made it,
+// and goes in after an anchor (or at the front). Where two sites put
+// something after the same anchor at once, the one with the greater id goes
+// first; so to place an insert, skip past everything right after its anchor
+// with a greater id (and everything after those, which is all greater
+// still), and it goes in before the first smaller one. Removed elements stay
+// as tombstones, since ops from elsewhere may still be anchored to them.
+//
+// Under it is an ordinary order of ids, tombstones and all, so comparing
+// two elements is still the O(1) tag comparison.
+//
+// Sites can also catch up with each other in bulk. Each keeps every op it's
+// seen, by the site that made it, numbered 1, 2, 3.. per site; a
+// VersionVector says how many of each site's it has. export_delta(since)
+// is everything past since (the other site's version), and merge_delta
+// takes in whatever's new, so two sites exchanging versions then deltas
+// end up with the same ops. Delta::encode is a compact byte form of one.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SiteId(pub u64);
+
+// ordered by clock, then site; every site's are distinct, and greater than
+// every id it had seen when it made them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ElementId {
+    pub clock: u64,
+    pub site: SiteId,
+}
+
+// What one site sends the others. after: None is the front.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SequenceOp<V> {
+    Insert { id: ElementId, after: Option<ElementId>, value: V },
+    Remove(ElementId),
+}
+
+// how many ops of each site's have been seen
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VersionVector(BTreeMap<SiteId, u64>);
+impl VersionVector {
+    pub fn get(&self, site: SiteId) -> u64 {
+        self.0.get(&site).copied().unwrap_or(0)
+    }
+}
+
+// Ops to catch another site up: for each site, a run of its ops in order,
+// starting from the given number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Delta<V> {
+    runs: Vec<(SiteId, u64, Vec<SequenceOp<V>>)>,
+}
+
+// Delta::decode's error: cut short, or not a delta at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MalformedDelta;
+impl fmt::Display for MalformedDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("malformed delta")
+    }
+}
+impl ::core::error::Error for MalformedDelta {}
+
+#[derive(Debug)]
+pub struct ReplicatedSequence<V, S = RandomState> {
+    site: SiteId,
+    // the greatest clock seen
+    clock: u64,
+    order: OrderMaintenance<ElementId, S>,
+    // None for a tombstone
+    values: HashMap<ElementId, Option<V>, S>,
+    len: usize,
+    // ops that arrived before what they refer to
+    pending: Vec<SequenceOp<V>>,
+    // every op seen through merge_delta or made here, by site
+    log: BTreeMap<SiteId, Vec<SequenceOp<V>>>,
+}
+
+#[derive(Debug)]
+pub struct ReplicatedIter<'a, V, S = RandomState>
+    where V: 'a, S: 'a {
+    iter: IterWithTag<'a, ElementId, S>,
+    values: &'a HashMap<ElementId, Option<V>, S>,
+}
+impl<'a, V, S> Iterator for ReplicatedIter<'a, V, S>
+    where S: BuildHasher {
+    type Item = (ElementId, &'a V);
+    fn next(&mut self) -> Option<(ElementId, &'a V)> {
+        for (&id, _) in self.iter.by_ref() {
+            if let Some(value) = self.values[&id].as_ref() {
+                return Some((id, value));
+            }
+        }
+        None
+    }
+}
+
+impl<V> ReplicatedSequence<V> {
+    pub fn new(site: SiteId) -> ReplicatedSequence<V> {
+        ReplicatedSequence::with_hasher(site, RandomState::default())
+    }
+}
+
+impl<V, S> ReplicatedSequence<V, S>
+    where S: BuildHasher + Clone {
+    // ids are made here or by other sites running this, so any hasher is
+    // as safe as the other sites are trusted
+    pub fn with_hasher(site: SiteId, hasher: S) -> ReplicatedSequence<V, S> {
+        ReplicatedSequence {
+            site,
+            clock: 0,
+            order: OrderMaintenance::with_hasher(hasher.clone()),
+            values: HashMap::with_hasher(hasher),
+            len: 0,
+            pending: Vec::new(),
+            log: BTreeMap::new(),
+        }
+    }
+    pub fn site(&self) -> SiteId {
+        self.site
+    }
+    // what's there, not counting tombstones
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn iter(&self) -> ReplicatedIter<'_, V, S> {
+        ReplicatedIter { iter: self.order.iter_values_with_tags(), values: &self.values }
+    }
+    pub fn contains(&self, id: ElementId) -> bool {
+        self.get(id).is_some()
+    }
+    pub fn get(&self, id: ElementId) -> Option<&V> {
+        self.values.get(&id)?.as_ref()
+    }
+    pub fn compare(&self, a: ElementId, b: ElementId) -> Option<Ordering> {
+        if self.contains(a) && self.contains(b) { self.order.compare(&a, &b) } else { None }
+    }
+    // ops still waiting for what they refer to
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+    // Inserts here, handing back the op for the other sites. after can be a
+    // tombstone (it's still somewhere), but has to be known here.
+    pub fn insert_after(&mut self, after: Option<ElementId>, value: V) -> Result<SequenceOp<V>, OrderMaintenanceError>
+        where V: Clone {
+        if after.is_some_and(|after| !self.values.contains_key(&after)) {
+            return Err(OrderMaintenanceError::AnchorMissing);
+        }
+        self.clock += 1;
+        let id = ElementId { clock: self.clock, site: self.site };
+        self.integrate(id, after, value.clone());
+        let op = SequenceOp::Insert { id, after, value };
+        self.log.entry(self.site).or_default().push(op.clone());
+        Ok(op)
+    }
+    // None if it isn't there (or is already a tombstone)
+    pub fn remove(&mut self, id: ElementId) -> Option<SequenceOp<V>> {
+        self.values.get_mut(&id)?.take()?;
+        self.len -= 1;
+        self.log.entry(self.site).or_default().push(SequenceOp::Remove(id));
+        Some(SequenceOp::Remove(id))
+    }
+    pub fn version(&self) -> VersionVector {
+        VersionVector(self.log.iter().map(|(&site, ops)| (site, ops.len() as u64)).collect())
+    }
+    // everything seen here that since hasn't
+    pub fn export_delta(&self, since: &VersionVector) -> Delta<V>
+        where V: Clone {
+        let runs = self.log.iter()
+            .filter_map(|(&site, ops)| {
+                let seen = since.get(site) as usize;
+                ops.get(seen..).filter(|new| !new.is_empty()).map(|new| (site, seen as u64 + 1, new.to_vec()))
+            })
+            .collect();
+        Delta { runs }
+    }
+    // Takes in the ops in delta not seen before, returning how many. A run
+    // that starts past what's been seen from its site (it skips some) is
+    // left out; the version says where to send from instead.
+    pub fn merge_delta(&mut self, delta: Delta<V>) -> usize
+        where V: Clone {
+        let mut merged = 0;
+        for (site, first, ops) in delta.runs {
+            let seen = self.log.get(&site).map_or(0, Vec::len) as u64;
+            if first == 0 || first > seen + 1 {
+                continue;
+            }
+            for op in ops.into_iter().skip((seen + 1 - first) as usize) {
+                self.log.entry(site).or_default().push(op.clone());
+                self.apply(op);
+                merged += 1;
+            }
+        }
+        merged
+    }
+    // An op from another site (or this one: seeing one twice is harmless).
+    // One that refers to something not seen yet waits until it is.
+    pub fn apply(&mut self, op: SequenceOp<V>) {
+        if !self.try_apply(op) {
+            return;
+        }
+        // which may be what some of the pending ones were waiting for
+        while let Some(ready) = self.pending.iter().position(|op| self.is_ready(op)) {
+            let op = self.pending.swap_remove(ready);
+            self.try_apply(op);
+        }
+    }
+    // false if op has to wait
+    fn try_apply(&mut self, op: SequenceOp<V>) -> bool {
+        if !self.is_ready(&op) {
+            self.pending.push(op);
+            return false;
+        }
+        match op {
+            SequenceOp::Insert { id, after, value } => {
+                self.clock = self.clock.max(id.clock);
+                if !self.values.contains_key(&id) {
+                    self.integrate(id, after, value);
+                }
+            }
+            SequenceOp::Remove(id) => {
+                if self.values.get_mut(&id).is_some_and(|value| value.take().is_some()) {
+                    self.len -= 1;
+                }
+            }
+        }
+        true
+    }
+    fn is_ready(&self, op: &SequenceOp<V>) -> bool {
+        match *op {
+            SequenceOp::Insert { after, .. } => after.is_none_or(|after| self.values.contains_key(&after)),
+            SequenceOp::Remove(id) => self.values.contains_key(&id),
+        }
+    }
+    fn integrate(&mut self, id: ElementId, after: Option<ElementId>, value: V) {
+        let mut after = after;
+        loop {
+            let next = match after {
+                Some(after) => self.order.successor(&after).copied(),
+                None => self.order.iter_values_with_tags().next().map(|(&front, _)| front),
+            };
+            match next {
+                Some(next) if next > id => after = Some(next),
+                _ => break,
+            }
+        }
+        match (after, self.order.iter_values_with_tags().next().map(|(&front, _)| front)) {
+            (Some(after), _) => self.order.insert_after(&after, id),
+            (None, None) => self.order.insert_only(id),
+            (None, Some(front)) => self.order.insert_after(&front, id).and_then(|()| self.order.move_to_front(&id)),
+        }.expect("ids are new and anchors are known");
+        self.values.insert(id, Some(value));
+        self.len += 1;
+    }
+}
+
+const DELTA_MAGIC: &[u8; 4] = b"OMDL";
+const DELTA_VERSION: u8 = 1;
+
+// LEB128
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+fn get_varint(input: &mut &[u8]) -> Result<u64, MalformedDelta> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or(MalformedDelta)?;
+        *input = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(MalformedDelta)
+}
+fn put_id(out: &mut Vec<u8>, id: ElementId) {
+    put_varint(out, id.clock);
+    put_varint(out, id.site.0);
+}
+fn get_id(input: &mut &[u8]) -> Result<ElementId, MalformedDelta> {
+    Ok(ElementId { clock: get_varint(input)?, site: SiteId(get_varint(input)?) })
+}
+
+impl<V> Delta<V> {
+    // how many ops
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|(_, _, ops)| ops.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // b"OMDL", version (1), then varints: the number of runs, and each run's
+    // site, first number and op count, then its ops: 0 and the id, the
+    // anchor (0, or 1 and its id) and the value as write_value writes it,
+    // for an insert; 1 and the id for a remove.
+    pub fn encode<F>(&self, mut write_value: F) -> Vec<u8>
+        where F: FnMut(&V, &mut Vec<u8>) {
+        let mut out = Vec::from(&DELTA_MAGIC[..]);
+        out.push(DELTA_VERSION);
+        put_varint(&mut out, self.runs.len() as u64);
+        for &(site, first, ref ops) in &self.runs {
+            put_varint(&mut out, site.0);
+            put_varint(&mut out, first);
+            put_varint(&mut out, ops.len() as u64);
+            for op in ops {
+                match *op {
+                    SequenceOp::Insert { id, after, ref value } => {
+                        out.push(0);
+                        put_id(&mut out, id);
+                        match after {
+                            None => out.push(0),
+                            Some(after) => {
+                                out.push(1);
+                                put_id(&mut out, after);
+                            }
+                        }
+                        write_value(value, &mut out);
+                    }
+                    SequenceOp::Remove(id) => {
+                        out.push(1);
+                        put_id(&mut out, id);
+                    }
+                }
+            }
+        }
+        out
+    }
+    // read_value takes one value off the front of what it's given, or None
+    // if it can't
+    pub fn decode<F>(bytes: &[u8], mut read_value: F) -> Result<Delta<V>, MalformedDelta>
+        where F: FnMut(&mut &[u8]) -> Option<V> {
+        let (header, mut input) = bytes.split_at_checked(5).ok_or(MalformedDelta)?;
+        if header[..4] != DELTA_MAGIC[..] || header[4] != DELTA_VERSION {
+            return Err(MalformedDelta);
+        }
+        let count = get_varint(&mut input)?;
+        let mut runs = Vec::new();
+        for _ in 0..count {
+            let site = SiteId(get_varint(&mut input)?);
+            let first = get_varint(&mut input)?;
+            let len = get_varint(&mut input)?;
+            let mut ops = Vec::new();
+            for _ in 0..len {
+                let (&kind, rest) = input.split_first().ok_or(MalformedDelta)?;
+                input = rest;
+                ops.push(match kind {
+                    0 => {
+                        let id = get_id(&mut input)?;
+                        let (&anchored, rest) = input.split_first().ok_or(MalformedDelta)?;
+                        input = rest;
+                        let after = match anchored {
+                            0 => None,
+                            1 => Some(get_id(&mut input)?),
+                            _ => return Err(MalformedDelta),
+                        };
+                        let value = read_value(&mut input).ok_or(MalformedDelta)?;
+                        SequenceOp::Insert { id, after, value }
+                    }
+                    1 => SequenceOp::Remove(get_id(&mut input)?),
+                    _ => return Err(MalformedDelta),
+                });
+            }
+            runs.push((site, first, ops));
+        }
+        if !input.is_empty() {
+            return Err(MalformedDelta);
+        }
+        Ok(Delta { runs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn text(sequence: &ReplicatedSequence<char>) -> Vec<char> {
+        sequence.iter().map(|(_, &c)| c).collect()
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let mut sites: Vec<ReplicatedSequence<char>> = (0..3).map(|site| ReplicatedSequence::new(SiteId(site))).collect();
+        let mut ops = Vec::new();
+        let mut previous = None;
+        for c in "abc".chars() {
+            let op = sites[0].insert_after(previous, c).unwrap();
+            previous = match op { SequenceOp::Insert { id, .. } => Some(id), _ => unreachable!() };
+            ops.push(op);
+        }
+        for op in &ops {
+            sites[1].apply(op.clone());
+            sites[2].apply(op.clone());
+        }
+        let ids: Vec<ElementId> = sites[0].iter().map(|(id, _)| id).collect();
+        // all at once: two inserts after a, one at the front, one after c,
+        // and b removed where someone is inserting after it
+        let concurrent = [
+            sites[0].insert_after(Some(ids[0]), 'x').unwrap(),
+            sites[1].insert_after(Some(ids[0]), 'y').unwrap(),
+            sites[1].insert_after(None, 'z').unwrap(),
+            sites[2].insert_after(Some(ids[1]), 'w').unwrap(),
+            sites[2].remove(ids[1]).unwrap(),
+            sites[0].insert_after(Some(ids[2]), 'v').unwrap(),
+        ];
+        // each site gets the others' ops in a different order, some twice
+        for (site, order) in [(0, [1, 2, 3, 4, 2, 0, 5]), (1, [4, 3, 0, 5, 1, 2, 0]), (2, [5, 0, 1, 2, 3, 4, 5])] {
+            for op in order {
+                sites[site].apply(concurrent[op].clone());
+            }
+        }
+        // y (from site 1) went in after a before x (from site 0), being
+        // the greater id
+        for site in &sites {
+            assert_eq!(text(site), ['z', 'a', 'y', 'x', 'w', 'c', 'v']);
+            assert_eq!(site.len(), 7);
+            assert_eq!(site.pending(), 0);
+            site.order.check_invariants().unwrap();
+        }
+        assert_eq!(sites[0].compare(ids[0], ids[2]), Some(Ordering::Less));
+        assert_eq!(sites[0].compare(ids[0], ids[1]), None);
+
+        // an op that arrives before its anchor waits for it
+        let mut late = ReplicatedSequence::new(SiteId(9));
+        for op in ops.iter().rev().chain(concurrent.iter().rev()) {
+            late.apply(op.clone());
+        }
+        assert_eq!(text(&late), text(&sites[0]));
+        assert_eq!(late.insert_after(Some(ElementId { clock: 99, site: SiteId(5) }), 'q'), Err(OrderMaintenanceError::AnchorMissing));
+        // and its own ids come after everything it's seen
+        let SequenceOp::Insert { id, .. } = late.insert_after(None, 'q').unwrap() else { unreachable!() };
+        assert!(sites[0].iter().all(|(seen, _)| seen < id));
+    }
+    #[test]
+    fn catch_up_by_delta() {
+        let mut a: ReplicatedSequence<char> = ReplicatedSequence::new(SiteId(1));
+        let mut b: ReplicatedSequence<char> = ReplicatedSequence::new(SiteId(2));
+        let mut c: ReplicatedSequence<char> = ReplicatedSequence::new(SiteId(3));
+        let mut previous = None;
+        for ch in "hello".chars() {
+            let SequenceOp::Insert { id, .. } = a.insert_after(previous, ch).unwrap() else { unreachable!() };
+            previous = Some(id);
+        }
+        let write = |&ch: &char, out: &mut Vec<u8>| out.push(ch as u8);
+        let read = |input: &mut &[u8]| {
+            let (&byte, rest) = input.split_first()?;
+            *input = rest;
+            Some(byte as char)
+        };
+        let bytes = a.export_delta(&b.version()).encode(write);
+        // every varint here fits in a byte; only the first insert has no anchor
+        assert_eq!(bytes.len(), 5 + 1 + 3 + 5 + 4 * 7);
+        assert_eq!(b.merge_delta(Delta::decode(&bytes, read).unwrap()), 5);
+        assert_eq!(b.merge_delta(a.export_delta(&VersionVector::default())), 0);
+        // then both edit, and c hears from them only through b
+        let ids: Vec<ElementId> = b.iter().map(|(id, _)| id).collect();
+        a.insert_after(Some(ids[4]), '!').unwrap();
+        b.remove(ids[0]).unwrap();
+        b.insert_after(None, 'j').unwrap();
+        assert_eq!(b.merge_delta(a.export_delta(&b.version())), 1);
+        assert_eq!(c.merge_delta(b.export_delta(&c.version())), 8);
+        assert_eq!(a.merge_delta(c.export_delta(&a.version())), 2);
+        for site in [&a, &b, &c] {
+            assert_eq!(text(site), ['j', 'e', 'l', 'l', 'o', '!']);
+            assert_eq!(site.version(), a.version());
+        }
+        assert_eq!(a.version().get(SiteId(1)), 6);
+        assert!(a.export_delta(&c.version()).is_empty());
+        // a run with a hole before it waits for the rest
+        let mut d: ReplicatedSequence<char> = ReplicatedSequence::new(SiteId(4));
+        let version = VersionVector(BTreeMap::from([(SiteId(1), 2)]));
+        assert_eq!(d.merge_delta(a.export_delta(&version)), 2);
+        assert_eq!(d.version().get(SiteId(1)), 0);
+        assert_eq!(Delta::<char>::decode(&bytes[..bytes.len() - 1], read), Err(MalformedDelta));
+        assert_eq!(Delta::<char>::decode(b"OMDL\x02\x00", read), Err(MalformedDelta));
+    }
+}
@@ -0,0 +1,81 @@
+// With the serde feature, an order serializes as nothing but its elements,
+// front to back. Tags are an implementation detail, so they're left out and
+// deserializing spreads out fresh ones; a repeated element is an error.
+
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{OrderMaintenance, OrderMaintenanceError, TagWidth};
+
+impl<T, S, W> Serialize for OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Serialize, S: BuildHasher, W: TagWidth {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+        where Z: Serializer {
+        serializer.collect_seq(self.iter_values_with_tags().map(|(value, _)| value))
+    }
+}
+
+struct OrderVisitor<T, S, W>(PhantomData<OrderMaintenance<T, S, W>>)
+    where T: Hash + Eq, W: TagWidth;
+impl<'de, T, S, W> Visitor<'de> for OrderVisitor<T, S, W>
+    where T: Hash + Eq + Deserialize<'de>, S: BuildHasher + Default, W: TagWidth {
+    type Value = OrderMaintenance<T, S, W>;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of distinct elements")
+    }
+    fn visit_seq<A>(self, mut seq: A) -> Result<OrderMaintenance<T, S, W>, A::Error>
+        where A: SeqAccess<'de> {
+        let mut om = OrderMaintenance::default();
+        while let Some(value) = seq.next_element()? {
+            let inserted = match om.last() {
+                None => om.insert_only(value),
+                Some(last) => om.insert_after_index(last, value).map(|_| ()),
+            };
+            inserted.map_err(|error| match error {
+                OrderMaintenanceError::AlreadyPresent => A::Error::custom("duplicate element in order"),
+                other => A::Error::custom(other),
+            })?;
+        }
+        // everyone went in at the back, which leaves them bunched up there
+        om.rebalance_all();
+        Ok(om)
+    }
+}
+
+impl<'de, T, S, W> Deserialize<'de> for OrderMaintenance<T, S, W>
+    where T: Hash + Eq + Deserialize<'de>, S: BuildHasher + Default, W: TagWidth {
+    fn deserialize<D>(deserializer: D) -> Result<OrderMaintenance<T, S, W>, D::Error>
+        where D: Deserializer<'de> {
+        deserializer.deserialize_seq(OrderVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error as ValueError, SeqDeserializer};
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    fn from_values(values: Vec<u32>) -> Result<OrderMaintenance<u32>, ValueError> {
+        OrderMaintenance::deserialize(SeqDeserializer::<_, ValueError>::new(values.into_iter()))
+    }
+
+    #[test]
+    fn rebuilds_the_order() {
+        let om = from_values((0..500).rev().collect()).unwrap();
+        assert_eq!(om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>(), (0..500).rev().collect::<Vec<_>>());
+        // evenly spread, as if by rebalance_all
+        let tags: Vec<u64> = om.iter_values_with_tags().map(|(_, tag)| tag.0).collect();
+        let step = u64::MAX / 501;
+        assert!(tags.iter().enumerate().all(|(i, &tag)| tag == step * (i as u64 + 1)));
+        om.check_invariants().unwrap();
+        assert_eq!(from_values(Vec::new()).unwrap().len(), 0);
+        let error = from_values(Vec::from([1, 2, 1])).unwrap_err();
+        assert_eq!(error.to_string(), "duplicate element in order");
+    }
+}
@@ -0,0 +1,170 @@
+// save_to/load_from: a small binary format of our own, for snapshots that
+// have to stay readable across versions of this crate (which a serde or rkyv
+// form can't promise). It's versioned, and anything newer than this code
+// understands is refused rather than misread:
+//
+//     b"OMSN", version (1), flags, tag width in bytes, element count (u64)
+//     then per element, in order: its tag (if flag 1 is set), then the
+//     element itself, however write_element wrote it
+//
+// All little-endian. Elements are written and read by the caller's
+// functions, since only the caller knows what they are.
+
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use std::io::{self, Read, Write};
+
+use super::{OrderMaintenance, OrderMaintenanceError, TagWidth};
+
+const MAGIC: &[u8; 4] = b"OMSN";
+const VERSION: u8 = 1;
+// flags
+const WITH_TAGS: u8 = 1;
+
+fn invalid<E>(error: E) -> io::Error
+    where E: Into<std::boxed::Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // With with_tags the tags are saved too, so that tags kept elsewhere
+    // (see tag_of) still line up after loading; without, it's a bit smaller
+    // and load_from spreads out fresh ones.
+    pub fn save_to<O, F>(&self, out: &mut O, with_tags: bool, mut write_element: F) -> io::Result<()>
+        where O: Write, F: FnMut(&mut O, &T) -> io::Result<()> {
+        let width = (W::BITS / 8) as usize;
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION, if with_tags { WITH_TAGS } else { 0 }, width as u8])?;
+        out.write_all(&(self.len() as u64).to_le_bytes())?;
+        for (value, tag) in self.iter_values_with_tags() {
+            if with_tags {
+                out.write_all(&tag.0.to_u128().to_le_bytes()[..width])?;
+            }
+            write_element(out, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher + Default, W: TagWidth {
+    // Saved tags are kept if they're the same width as W; otherwise (or
+    // without them) the elements are spread out evenly.
+    pub fn load_from<I, F>(input: &mut I, mut read_element: F) -> io::Result<OrderMaintenance<T, S, W>>
+        where I: Read, F: FnMut(&mut I) -> io::Result<T> {
+        let mut header = [0; 15];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an order snapshot"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unknown order snapshot version"));
+        }
+        let flags = header[5];
+        if flags & !WITH_TAGS != 0 {
+            return Err(invalid("unknown order snapshot flags"));
+        }
+        let width = header[6] as usize;
+        if ![4, 8, 16].contains(&width) {
+            return Err(invalid("unknown tag width in order snapshot"));
+        }
+        let mut len = [0; 8];
+        len.copy_from_slice(&header[7..]);
+        let len = u64::from_le_bytes(len);
+        let mut om = OrderMaintenance::default();
+        let mut tags = Vec::new();
+        for _ in 0..len {
+            if flags & WITH_TAGS != 0 {
+                let mut tag = [0; 16];
+                input.read_exact(&mut tag[..width])?;
+                tags.push(u128::from_le_bytes(tag));
+            }
+            let value = read_element(input)?;
+            match om.last() {
+                None => om.insert_only(value),
+                Some(last) => om.insert_after_index(last, value).map(|_| ()),
+            }.map_err(|error| match error {
+                OrderMaintenanceError::AlreadyPresent => invalid("repeated element in order snapshot"),
+                other => invalid(other),
+            })?;
+        }
+        if !tags.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(invalid("order snapshot tags out of order"));
+        }
+        if !tags.is_empty() && width as u32 * 8 == W::BITS {
+            let mut index = om.front;
+            for tag in tags {
+                let i = index.expect("as many tags as elements");
+                om.slots[i].set_tag(W::from_u128(tag));
+                index = Some(om.slots[i].next).filter(|&next| Some(next) != om.front);
+            }
+        } else {
+            om.rebalance_all();
+        }
+        om.verify_valid_structure();
+        Ok(om)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    fn write_u32<O: Write>(out: &mut O, value: &u32) -> io::Result<()> {
+        out.write_all(&value.to_le_bytes())
+    }
+    fn read_u32<I: Read>(input: &mut I) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        input.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..100 {
+            om.insert_after(&(value / 2), value).unwrap();
+        }
+        let mut with_tags = Vec::new();
+        om.save_to(&mut with_tags, true, write_u32).unwrap();
+        assert_eq!(&with_tags[..7], b"OMSN\x01\x01\x08");
+        assert_eq!(with_tags.len(), 15 + 100 * (8 + 4));
+        let loaded: OrderMaintenance<u32> = OrderMaintenance::load_from(&mut &with_tags[..], read_u32).unwrap();
+        assert_eq!(loaded.iter_values_with_tags().collect::<Vec<_>>(), om.iter_values_with_tags().collect::<Vec<_>>());
+        // other widths get the order, but not the tags
+        let wide: OrderMaintenance<u32, std::collections::hash_map::RandomState, u128> = OrderMaintenance::load_from(&mut &with_tags[..], read_u32).unwrap();
+        assert_eq!(wide, om);
+        let mut without = Vec::new();
+        om.save_to(&mut without, false, write_u32).unwrap();
+        assert_eq!(without.len(), 15 + 100 * 4);
+        let respread: OrderMaintenance<u32> = OrderMaintenance::load_from(&mut &without[..], read_u32).unwrap();
+        assert_eq!(respread, om);
+    }
+
+    #[test]
+    fn refuses_what_it_cant_read() {
+        let load = |bytes: &[u8]| OrderMaintenance::<u32>::load_from(&mut &bytes[..], read_u32).map(|_| ()).map_err(|error| error.to_string());
+        let mut bytes = Vec::new();
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        om.insert_only(7).unwrap();
+        om.insert_after(&7, 8).unwrap();
+        om.save_to(&mut bytes, true, write_u32).unwrap();
+        assert_eq!(load(&bytes), Ok(()));
+        assert_eq!(load(&bytes[..bytes.len() - 1]).unwrap_err(), "failed to fill whole buffer");
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(load(&newer).unwrap_err(), "unknown order snapshot version");
+        let mut repeated = bytes.clone();
+        repeated[35..39].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(load(&repeated).unwrap_err(), "repeated element in order snapshot");
+        let mut backwards = bytes.clone();
+        backwards[27..35].copy_from_slice(&0u64.to_le_bytes());
+        assert_eq!(load(&backwards).unwrap_err(), "order snapshot tags out of order");
+        assert_eq!(load(b"JUNKJUNKJUNKJUNK").unwrap_err(), "not an order snapshot");
+    }
+}
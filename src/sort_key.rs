@@ -0,0 +1,376 @@
+// An order for ORDER BY: each row's label is a plain i64 (a tag shifted down
+// by 2^63, so it sorts the same as a BIGINT), and every change says exactly
+// which rows need their label written back. dirty() lists them, with their
+// new labels, until the next change: the row inserted or moved, and any
+// neighbours that had to be relabeled to make room for it.
+//
+// What makes it fit for a table is that no change relabels more than
+// relabel_budget() existing rows. Where the gap at the destination has run
+// out, the rows either side of it are spread out evenly over the span around
+// them, the window growing one row on each side at a time until it's roomy
+// enough (its spacing more than its size, as Dietz and Sleator have it) or
+// the budget is spent. If even the whole budget can't make room, the change
+// is refused with OrderMaintenanceError::Full and nothing is touched;
+// spread_out() then relabels every row at once, which is for a migration or
+// a quiet moment, not for the middle of a request.
+
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::slice;
+
+use alloc::vec::Vec;
+
+use super::{Index, IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState, Tag};
+
+// enough to absorb dozens of inserts at the same spot between relabels
+const DEFAULT_RELABEL_BUDGET: usize = 64;
+
+fn sort_key(tag: Tag) -> i64 {
+    (tag.0 ^ (1 << 63)) as i64
+}
+
+#[derive(Debug)]
+pub struct SortKeyOrder<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    // the rows the last change gave new labels to
+    dirty: Vec<Index>,
+    relabel_budget: usize,
+}
+
+impl<T> SortKeyOrder<T>
+    where T: Hash + Eq {
+    pub fn new() -> SortKeyOrder<T> {
+        SortKeyOrder::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for SortKeyOrder<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> SortKeyOrder<T, S> {
+        SortKeyOrder::with_hasher(S::default())
+    }
+}
+
+impl<T, S> SortKeyOrder<T, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> SortKeyOrder<T, S> {
+        SortKeyOrder { om: OrderMaintenance::with_hasher(hasher), dirty: Vec::new(), relabel_budget: DEFAULT_RELABEL_BUDGET }
+    }
+    pub fn order(&self) -> &OrderMaintenance<T, S> {
+        &self.om
+    }
+    pub fn into_inner(self) -> OrderMaintenance<T, S> {
+        self.om
+    }
+    // A bigger budget means fewer refusals but bigger writes when one's
+    // needed; at least 1.
+    pub fn set_relabel_budget(&mut self, relabel_budget: usize) {
+        self.relabel_budget = relabel_budget.max(1);
+    }
+    pub fn relabel_budget(&self) -> usize {
+        self.relabel_budget
+    }
+    pub fn len(&self) -> usize {
+        self.om.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.om.is_empty()
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.contains(value)
+    }
+    pub fn sort_key<Q>(&self, value: &Q) -> Option<i64>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.tag_of(value).map(sort_key)
+    }
+    pub fn iter(&self) -> SortKeyIter<'_, T, S> {
+        SortKeyIter(self.om.iter_values_with_tags())
+    }
+    // The rows whose labels the last change set, in no particular order.
+    pub fn dirty(&self) -> DirtyRows<'_, T, S> {
+        DirtyRows { om: &self.om, dirty: self.dirty.iter() }
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        self.dirty.clear();
+        self.om.insert_only(value)?;
+        self.dirty.push(self.om.front.expect("just inserted"));
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.dirty.clear();
+        let after = self.om.find_anchor(after, &value)?;
+        self.om.check_absent(&value)?;
+        let next = self.next(after);
+        self.make_room(Some(after), next)?;
+        let index = self.om.allocate(value, 0);
+        self.om.link_after(index, after);
+        self.placed(index);
+        Ok(())
+    }
+    pub fn insert_before<Q>(&mut self, before: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.dirty.clear();
+        let before = self.om.find_anchor(before, &value)?;
+        self.om.check_absent(&value)?;
+        let prev = self.prev(before);
+        self.make_room(prev, Some(before))?;
+        let index = self.om.allocate(value, 0);
+        self.om.link_before(index, before);
+        self.placed(index);
+        Ok(())
+    }
+    // Deleting a row never relabels the others.
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.dirty.clear();
+        self.om.remove(value).map(|removed| removed.value)
+    }
+    pub fn move_after<Q>(&mut self, value: &Q, after: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let (index, after) = self.find_pair(value, after)?;
+        self.move_to(index, |order| (Some(after), order.next(after)))
+    }
+    pub fn move_before<Q>(&mut self, value: &Q, before: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let (index, before) = self.find_pair(value, before)?;
+        self.move_to(index, |order| (order.prev(before), Some(before)))
+    }
+    pub fn move_to_front<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.dirty.clear();
+        let index = self.om.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        self.move_to(index, |order| (None, order.om.front))
+    }
+    pub fn move_to_back<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.dirty.clear();
+        let index = self.om.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        self.move_to(index, |order| (order.om.last(), None))
+    }
+    // Relabels every row, evenly over the whole range, however far past the
+    // budget that is.
+    pub fn spread_out(&mut self) {
+        self.dirty.clear();
+        self.om.relabel_everything();
+        let mut index = self.om.front;
+        for _ in 0..self.om.len() {
+            let current = index.expect("len says there's more");
+            self.dirty.push(current);
+            index = Some(self.om.slots[current].next);
+        }
+    }
+
+    fn next(&self, index: Index) -> Option<Index> {
+        let next = self.om.slots[index].next;
+        if Some(next) == self.om.front { None } else { Some(next) }
+    }
+    fn prev(&self, index: Index) -> Option<Index> {
+        if Some(index) == self.om.front { None } else { Some(self.om.slots[index].prev) }
+    }
+    fn placed(&mut self, index: Index) {
+        self.dirty.push(index);
+        self.om.verify_valid_structure();
+    }
+    fn find_pair<Q>(&mut self, value: &Q, anchor: &Q) -> Result<(Index, Index), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.dirty.clear();
+        let index = self.om.find(value).ok_or(OrderMaintenanceError::NotPresent)?;
+        if value == anchor {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        let anchor = self.om.find(anchor).ok_or(OrderMaintenanceError::AnchorMissing)?;
+        Ok((index, anchor))
+    }
+    // Takes index out, and puts it back between the neighbours destination
+    // picks from what's left, or where it was if there's no room there.
+    fn move_to<F>(&mut self, index: Index, destination: F) -> Result<(), OrderMaintenanceError>
+        where F: FnOnce(&SortKeyOrder<T, S>) -> (Option<Index>, Option<Index>) {
+        if self.om.len() == 1 {
+            return Ok(());
+        }
+        let was_front = self.om.front == Some(index);
+        let (prev, tag) = (self.om.slots[index].prev, self.om.tag(index));
+        self.om.unlink(index);
+        let (lo, hi) = destination(self);
+        if let Err(error) = self.make_room(lo, hi) {
+            // its old tag still fits between its old neighbours
+            self.om.link_after_with_tag(index, prev, tag);
+            if was_front {
+                self.om.front = Some(index);
+            }
+            self.om.verify_valid_structure();
+            return Err(error);
+        }
+        match (lo, hi) {
+            (Some(lo), _) => self.om.link_after(index, lo),
+            (None, Some(hi)) => self.om.link_before(index, hi),
+            (None, None) => unreachable!("there's something else to move next to"),
+        }
+        self.placed(index);
+        Ok(())
+    }
+    // Makes sure a new element between lo and hi (neighbours, or None for
+    // either end) won't collide with either, within the budget.
+    fn make_room(&mut self, lo: Option<Index>, hi: Option<Index>) -> Result<(), OrderMaintenanceError> {
+        // as if there were elements at -1 and 2^64, so that the ends keep
+        // some room of their own
+        let bound = |index: Option<Index>, outside: i128| index.map_or(outside, |index| self.om.tag(index) as i128);
+        let room = bound(hi, 1 << 64) - bound(lo, -1);
+        // two apart is all OrderMaintenance needs not to collide
+        if lo.is_none() && hi.is_none() || room >= 2 {
+            return Ok(());
+        }
+        // the window is first..=lo and hi..=last, with the new one's spot
+        // left between them
+        let (mut first, mut last) = (lo, hi);
+        let (mut left, mut right) = (lo.is_some() as usize, hi.is_some() as usize);
+        loop {
+            let lower = bound(first.and_then(|first| self.prev(first)), -1);
+            let upper = bound(last.and_then(|last| self.next(last)), 1 << 64);
+            let slots = (left + right + 1) as i128;
+            let increment = (upper - lower) / (slots + 1);
+            let budget_left = left + right < self.relabel_budget;
+            let grow_first = first.and_then(|first| self.prev(first)).filter(|_| budget_left);
+            let grow_last = last.and_then(|last| self.next(last)).filter(|_| left + right + (grow_first.is_some() as usize) < self.relabel_budget);
+            let can_grow = grow_first.is_some() || grow_last.is_some();
+            if increment >= 1 && (increment > slots || !can_grow) {
+                if let Some(first) = first {
+                    self.relabel(first, left, (lower + increment) as u64, increment as u64);
+                }
+                if let Some(hi) = hi {
+                    self.relabel(hi, right, (lower + (left as i128 + 2) * increment) as u64, increment as u64);
+                }
+                return Ok(());
+            }
+            if !can_grow {
+                return Err(OrderMaintenanceError::Full);
+            }
+            if grow_first.is_some() {
+                first = grow_first;
+                left += 1;
+            }
+            if grow_last.is_some() {
+                last = grow_last;
+                right += 1;
+            }
+        }
+    }
+    fn relabel(&mut self, first: Index, count: usize, base_tag: u64, increment: u64) {
+        self.om.relabel_run(first, count, base_tag, increment);
+        let mut index = first;
+        for _ in 0..count {
+            self.dirty.push(index);
+            index = self.om.slots[index].next;
+        }
+    }
+}
+
+pub struct SortKeyIter<'a, T, S = RandomState>(IterWithTag<'a, T, S>)
+    where T: Hash + Eq;
+impl<'a, T, S> Iterator for SortKeyIter<'a, T, S>
+    where T: Hash + Eq {
+    type Item = (&'a T, i64);
+    fn next(&mut self) -> Option<(&'a T, i64)> {
+        self.0.next().map(|(value, tag)| (value, sort_key(tag)))
+    }
+}
+
+pub struct DirtyRows<'a, T, S = RandomState>
+    where T: Hash + Eq {
+    om: &'a OrderMaintenance<T, S>,
+    dirty: slice::Iter<'a, Index>,
+}
+impl<'a, T, S> Iterator for DirtyRows<'a, T, S>
+    where T: Hash + Eq {
+    type Item = (&'a T, i64);
+    fn next(&mut self) -> Option<(&'a T, i64)> {
+        let om = self.om;
+        self.dirty.next().map(|&index| {
+            let position = &om.slots[index];
+            (&position.key, sort_key(Tag(position.tag.wrapping_sub(om.rotation))))
+        })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.dirty.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashbrown::HashMap;
+    use std::vec::Vec;
+
+    // what a table kept up to date from dirty() alone would hold
+    fn write_back(order: &SortKeyOrder<u32>, table: &mut HashMap<u32, i64>) {
+        for (&row, key) in order.dirty() {
+            table.insert(row, key);
+        }
+        let keys: Vec<i64> = order.iter().map(|(row, key)| {
+            assert_eq!(table.get(row), Some(&key), "{} wasn't reported", row);
+            key
+        }).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn reports_every_relabel_within_budget() {
+        let mut order: SortKeyOrder<u32> = SortKeyOrder::new();
+        let mut table = HashMap::new();
+        order.set_relabel_budget(4);
+        order.insert_only(0).unwrap();
+        write_back(&order, &mut table);
+        order.insert_after(&0, 1).unwrap();
+        write_back(&order, &mut table);
+        // always between 0 and whatever went in last uses up the gaps there fast
+        let (mut relabeled, mut refused) = (false, false);
+        for row in 2..400 {
+            match order.insert_after(&0, row) {
+                Ok(()) => {}
+                Err(error) => {
+                    assert_eq!(error, OrderMaintenanceError::Full);
+                    refused = true;
+                    assert_eq!(order.dirty().count(), 0);
+                    order.spread_out();
+                    assert_eq!(order.dirty().count(), order.len());
+                    write_back(&order, &mut table);
+                    order.insert_after(&0, row).unwrap();
+                }
+            }
+            let dirty = order.dirty().count();
+            assert!(dirty <= order.relabel_budget() + 1);
+            relabeled |= dirty > 1;
+            write_back(&order, &mut table);
+            if row % 7 == 0 {
+                order.move_to_front(&(row / 2)).unwrap();
+                assert!(order.dirty().count() <= order.relabel_budget() + 1);
+                write_back(&order, &mut table);
+            }
+        }
+        assert!(relabeled);
+        // with room for hardly any relabeling, the same spot fills up for good
+        order.set_relabel_budget(2);
+        for row in 1000..3000 {
+            if order.insert_after(&0, row).is_err() {
+                refused = true;
+                break;
+            }
+            write_back(&order, &mut table);
+        }
+        assert!(refused);
+        assert_eq!(order.insert_before(&0, 400), Ok(()));
+        assert!(order.dirty().any(|(&row, _)| row == 400));
+        write_back(&order, &mut table);
+        assert_eq!(order.move_after(&5, &5), Err(OrderMaintenanceError::SelfInsert));
+        assert_eq!(order.move_before(&5, &5000), Err(OrderMaintenanceError::AnchorMissing));
+        assert_eq!(order.remove(&5), Some(5));
+        assert_eq!(order.dirty().count(), 0);
+        order.move_to_back(&0).unwrap();
+        write_back(&order, &mut table);
+        assert_eq!(order.iter().last().map(|(&row, _)| row), Some(0));
+        assert_eq!(order.sort_key(&0), table.get(&0).copied());
+    }
+}
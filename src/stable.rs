@@ -0,0 +1,323 @@
+// An order whose labels never change. Each label is a fraction between 0 and
+// 1, written as base-2^64 digits (most significant first, never ending in a
+// zero), and a new element always gets one strictly between its neighbours':
+// there's always room one digit further down, so nothing is ever relabeled.
+// That's for when the labels are stored somewhere that can't be told about
+// relabels, at the cost of labels that get longer wherever the order is
+// crowded: inserting into the same gap over and over adds a digit every 64
+// inserts or so. Appending and prepending only step the last digit, so they
+// stay one digit long for 2^64 inserts.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::HashTable;
+
+use super::{OrderMaintenanceError, RandomState};
+
+// Compares (as the derived lexicographic order does) like the elements it
+// belongs to, forever: good as a persistent sort key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StableLabel(Vec<u64>);
+impl StableLabel {
+    pub fn digits(&self) -> &[u64] {
+        &self.0
+    }
+}
+
+// A label strictly between lo and hi, where None means the bottom (0) or the
+// top (1) of the label space.
+fn label_between(lo: Option<&[u64]>, hi: Option<&[u64]>) -> StableLabel {
+    let (at_front, at_back) = (lo.is_none(), hi.is_none());
+    let lo = lo.unwrap_or(&[]);
+    // stays Some while the digits so far are hi's: the next one must be below hi's
+    let mut hi = hi;
+    let mut digits = Vec::new();
+    for i in 0.. {
+        // lo's digits run out into zeros; past the end of hi (or without it)
+        // anything up to 2^64 - 1 will do
+        let low = lo.get(i).copied().unwrap_or(0) as u128;
+        let high = match hi {
+            Some(hi) => hi[i] as u128,
+            None => 1 << 64,
+        };
+        if high - low >= 2 {
+            let digit = if at_back {
+                low + 1
+            } else if at_front {
+                high - 1
+            } else {
+                low + (high - low) / 2
+            };
+            digits.push(digit as u64);
+            break;
+        }
+        // no room between them: take lo's digit and look further down
+        digits.push(low as u64);
+        if high > low {
+            hi = None;
+        }
+    }
+    StableLabel(digits)
+}
+
+#[derive(Debug)]
+struct Element<T> {
+    key: T,
+    label: StableLabel,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct StableOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    elements: Vec<Option<Element<T>>>,
+    free: Vec<usize>,
+    // element indices, hashed by their keys
+    index: HashTable<usize>,
+    hasher: S,
+    front: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct StableIter<'a, T, S = RandomState>
+    where T: Hash + Eq + 'a, S: 'a {
+    om: &'a StableOrderMaintenance<T, S>,
+    current: Option<usize>,
+}
+impl<'a, T, S> Iterator for StableIter<'a, T, S>
+    where T: Hash + Eq {
+    type Item = (&'a T, &'a StableLabel);
+    fn next(&mut self) -> Option<Self::Item> {
+        let element = self.om.element(self.current?);
+        self.current = element.next;
+        Some((&element.key, &element.label))
+    }
+}
+
+impl<T> StableOrderMaintenance<T>
+    where T: Hash + Eq {
+    pub fn new() -> StableOrderMaintenance<T> {
+        StableOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for StableOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher + Default {
+    fn default() -> StableOrderMaintenance<T, S> {
+        StableOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+// element access, which the iterator needs too, whatever the hasher
+impl<T, S> StableOrderMaintenance<T, S>
+    where T: Hash + Eq {
+    fn element(&self, index: usize) -> &Element<T> {
+        self.elements[index].as_ref().expect("dangling element index")
+    }
+    fn element_mut(&mut self, index: usize) -> &mut Element<T> {
+        self.elements[index].as_mut().expect("dangling element index")
+    }
+}
+
+impl<T, S> StableOrderMaintenance<T, S>
+    where T: Hash + Eq, S: BuildHasher {
+    pub fn with_hasher(hasher: S) -> StableOrderMaintenance<T, S> {
+        StableOrderMaintenance {
+            elements: Vec::new(),
+            free: Vec::new(),
+            index: HashTable::new(),
+            hasher,
+            front: None,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    pub fn iter_values_with_labels(&self) -> StableIter<'_, T, S> {
+        StableIter { om: self, current: self.front }
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).is_some()
+    }
+    // the label value had when it was inserted, and will have until it's removed
+    pub fn label_of<Q>(&self, value: &Q) -> Option<&StableLabel>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).map(|index| &self.element(index).label)
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        Some(self.label_of(a)?.cmp(self.label_of(b)?))
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        if !self.is_empty() {
+            return Err(OrderMaintenanceError::NotEmpty);
+        }
+        // the middle, leaving as much room on either side
+        let index = self.allocate(value, StableLabel(Vec::from([1 << 63])), None, None);
+        self.front = Some(index);
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.find_anchor(after, &value)?;
+        self.check_absent(&value)?;
+        let next = self.element(after).next;
+        self.insert_between(Some(after), next, value);
+        Ok(())
+    }
+    pub fn insert_before<Q>(&mut self, before: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let before = self.find_anchor(before, &value)?;
+        self.check_absent(&value)?;
+        let prev = self.element(before).prev;
+        self.insert_between(prev, Some(before), value);
+        Ok(())
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        let hash = self.hasher.hash_one(&self.element(index).key);
+        if let Ok(entry) = self.index.find_entry(hash, |&i| i == index) {
+            entry.remove();
+        }
+        let element = self.elements[index].take().expect("dangling element index");
+        self.free.push(index);
+        match element.prev {
+            Some(prev) => self.element_mut(prev).next = element.next,
+            None => self.front = element.next,
+        }
+        if let Some(next) = element.next {
+            self.element_mut(next).prev = element.prev;
+        }
+        Some(element.key)
+    }
+
+    fn find<Q>(&self, value: &Q) -> Option<usize>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let hash = self.hasher.hash_one(value);
+        let elements = &self.elements;
+        self.index.find(hash, |&i| elements[i].as_ref().is_some_and(|element| element.key.borrow() == value)).copied()
+    }
+    fn find_anchor<Q>(&self, anchor: &Q, value: &T) -> Result<usize, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if value.borrow() == anchor {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        self.find(anchor).ok_or(OrderMaintenanceError::AnchorMissing)
+    }
+    fn check_absent(&self, value: &T) -> Result<(), OrderMaintenanceError> {
+        if self.find(value).is_some() { Err(OrderMaintenanceError::AlreadyPresent) } else { Ok(()) }
+    }
+    // prev and next are neighbours (or the ends); value goes between them
+    fn insert_between(&mut self, prev: Option<usize>, next: Option<usize>, value: T) {
+        let label = {
+            let digits = |neighbour: Option<usize>| neighbour.map(|i| self.element(i).label.digits());
+            label_between(digits(prev), digits(next))
+        };
+        let index = self.allocate(value, label, prev, next);
+        match prev {
+            Some(prev) => self.element_mut(prev).next = Some(index),
+            None => self.front = Some(index),
+        }
+        if let Some(next) = next {
+            self.element_mut(next).prev = Some(index);
+        }
+    }
+    // a findable element, not yet linked in from its neighbours
+    fn allocate(&mut self, key: T, label: StableLabel, prev: Option<usize>, next: Option<usize>) -> usize {
+        let element = Element { key, label, prev, next };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.elements[index] = Some(element);
+                index
+            }
+            None => {
+                self.elements.push(Some(element));
+                self.elements.len() - 1
+            }
+        };
+        let elements = &self.elements;
+        let hasher = &self.hasher;
+        let hash = hasher.hash_one(&elements[index].as_ref().unwrap().key);
+        self.index.insert_unique(hash, index, |&i| hasher.hash_one(&elements[i].as_ref().unwrap().key));
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::vec::Vec;
+    use test_random;
+
+    #[test]
+    fn labels_never_change() {
+        let mut om: StableOrderMaintenance<u32> = StableOrderMaintenance::new();
+        let mut model: Vec<u32> = Vec::new();
+        let mut labels: HashMap<u32, StableLabel> = HashMap::new();
+        om.insert_only(0).unwrap();
+        model.push(0);
+        labels.insert(0, om.label_of(&0).unwrap().clone());
+        let mut random = test_random(4242);
+        for value in 1..3000 {
+            let at = match value % 3 {
+                // the same gap over and over, to make the labels grow
+                0 => 0,
+                _ => random(model.len()),
+            };
+            if value % 2 == 0 {
+                om.insert_after(&model[at], value).unwrap();
+                model.insert(at + 1, value);
+            } else {
+                om.insert_before(&model[at], value).unwrap();
+                model.insert(at, value);
+            }
+            labels.insert(value, om.label_of(&value).unwrap().clone());
+            if value % 5 == 0 {
+                let at = random(model.len());
+                assert_eq!(om.remove(&model[at]), Some(model[at]));
+                labels.remove(&model[at]);
+                model.remove(at);
+            }
+        }
+        assert_eq!(om.iter_values_with_labels().map(|(v, _)| *v).collect::<Vec<_>>(), model);
+        for (value, label) in om.iter_values_with_labels() {
+            assert_eq!(&labels[value], label);
+            assert_ne!(label.digits().last(), Some(&0));
+        }
+        for pair in model.windows(2) {
+            assert!(labels[&pair[0]] < labels[&pair[1]]);
+            assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+        }
+        assert_eq!(om.insert_after(&model[0], model[1]), Err(OrderMaintenanceError::AlreadyPresent));
+    }
+
+    #[test]
+    fn label_lengths() {
+        let mut om: StableOrderMaintenance<u32> = StableOrderMaintenance::new();
+        om.insert_only(0).unwrap();
+        for value in 1..1000 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        for value in 1000..2000 {
+            let before = if value == 1000 { 0 } else { value - 1 };
+            om.insert_before(&before, value).unwrap();
+        }
+        assert!(om.iter_values_with_labels().all(|(_, label)| label.digits().len() == 1));
+        // splitting the gap right after 0 every time
+        for value in 2000..2640 {
+            om.insert_after(&0, value).unwrap();
+        }
+        let longest = om.iter_values_with_labels().map(|(_, label)| label.digits().len()).max();
+        assert!(longest <= Some(12), "{:?}", longest);
+    }
+}
@@ -0,0 +1,280 @@
+// Random orders and random ops on them, for property tests in crates that
+// use this one: OrderGenerator turns a seed into an order of u32s built by
+// a random history of changes, or into ops that are all valid to apply, in
+// sequence, to a given order; OpSequence is an order and ops for it
+// together. Under any other property-testing crate, any random u64 will do
+// for the seed.
+//
+// With the proptest feature, order_strategy and op_sequence_strategy make
+// them from a seed, a length and an op count, and shrink by shrinking
+// those, so a failure comes down to the shortest order or history that
+// still shows it. With the quickcheck feature, OrderMaintenance<u32> and
+// OpSequence are Arbitrary, and shrink by taking elements out of the order
+// (half of them, then one at a time) or ops out of the sequence (all but
+// the first half, then any one whose removal leaves the rest valid).
+//
+// Elements are u32s, and new ones are ones not already in the order, so
+// the ops can be mapped onto a crate's own element type one-to-one.
+
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+#[cfg(feature = "quickcheck")]
+use alloc::boxed::Box;
+
+#[cfg(feature = "proptest")]
+use proptest::arbitrary::any;
+#[cfg(feature = "proptest")]
+use proptest::strategy::Strategy;
+#[cfg(feature = "quickcheck")]
+use quickcheck::{Arbitrary, Gen};
+
+use super::{Op, OrderMaintenance, TagWidth};
+
+#[derive(Debug, Clone)]
+pub struct OrderGenerator {
+    state: u64,
+    next_value: u32,
+}
+
+impl OrderGenerator {
+    pub fn new(seed: u64) -> OrderGenerator {
+        OrderGenerator { state: seed, next_value: 0 }
+    }
+    // An order of len elements, left by inserts, removes, moves and the rest
+    // at random, so the tags are as uneven as real use leaves them.
+    pub fn order(&mut self, len: usize) -> OrderMaintenance<u32> {
+        let mut om = OrderMaintenance::new();
+        while om.len() != len {
+            let op = self.op(&om);
+            om.apply_op(op).expect("generated ops are valid");
+        }
+        om
+    }
+    // count ops that can be applied to om one after another without any of
+    // them failing
+    pub fn ops<S, W>(&mut self, om: &OrderMaintenance<u32, S, W>, count: usize) -> Vec<Op<u32>>
+        where S: BuildHasher + Clone, W: TagWidth {
+        let mut om = om.clone();
+        (0..count).map(|_| {
+            let op = self.op(&om);
+            om.apply_op(op.clone()).expect("generated ops are valid");
+            op
+        }).collect()
+    }
+    // an order of len elements and count ops valid for it
+    pub fn op_sequence(&mut self, len: usize, count: usize) -> OpSequence {
+        let order = self.order(len);
+        let ops = self.ops(&order, count);
+        OpSequence { order, ops }
+    }
+    // one op that's valid for om as it is
+    pub fn op<S, W>(&mut self, om: &OrderMaintenance<u32, S, W>) -> Op<u32>
+        where S: BuildHasher, W: TagWidth {
+        if om.is_empty() {
+            return Op::InsertOnly(self.fresh(om));
+        }
+        let len = om.len();
+        let value = self.pick(om);
+        // inserts outnumber removes, so orders tend to grow
+        match self.below(16) {
+            0..=4 => Op::InsertAfter { after: value, value: self.fresh(om) },
+            5 | 6 => Op::Remove(value),
+            7 => Op::ReplaceKey { old: value, new: self.fresh(om) },
+            8 | 9 if len > 1 => {
+                let mut anchor = self.pick(om);
+                while anchor == value {
+                    anchor = self.pick(om);
+                }
+                if self.below(2) == 0 { Op::MoveAfter { value, after: anchor } } else { Op::MoveBefore { value, before: anchor } }
+            }
+            8 | 9 => Op::InsertAfter { after: value, value: self.fresh(om) },
+            10 => Op::MoveToFront(value),
+            11 => Op::MoveToBack(value),
+            12 => Op::SwapPositions(value, self.pick(om)),
+            13 => Op::Reverse,
+            14 => Op::RotateToFront(value),
+            _ => Op::RebalanceAll,
+        }
+    }
+
+    // splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+    fn pick<T, S, W>(&mut self, om: &OrderMaintenance<T, S, W>) -> T
+        where T: Hash + Eq + Copy, S: BuildHasher, W: TagWidth {
+        let nth = self.below(om.len());
+        om.iter_values_with_tags().nth(nth).map(|(&value, _)| value).expect("nth is within len")
+    }
+    fn fresh<S, W>(&mut self, om: &OrderMaintenance<u32, S, W>) -> u32
+        where S: BuildHasher, W: TagWidth {
+        while om.contains(&self.next_value) {
+            self.next_value = self.next_value.wrapping_add(1);
+        }
+        let value = self.next_value;
+        self.next_value = self.next_value.wrapping_add(1);
+        value
+    }
+}
+
+// ops that all apply, in turn, starting from order
+#[derive(Debug, Clone)]
+pub struct OpSequence {
+    pub order: OrderMaintenance<u32>,
+    pub ops: Vec<Op<u32>>,
+}
+impl OpSequence {
+    // where the ops leave the order
+    pub fn result(&self) -> OrderMaintenance<u32> {
+        let mut om = self.order.clone();
+        om.apply(self.ops.iter().cloned()).expect("generated ops are valid");
+        om
+    }
+    #[cfg(feature = "quickcheck")]
+    fn applies(&self) -> bool {
+        self.order.clone().apply(self.ops.iter().cloned()).is_ok()
+    }
+}
+
+// Orders of up to max_len elements.
+#[cfg(feature = "proptest")]
+pub fn order_strategy(max_len: usize) -> impl Strategy<Value = OrderMaintenance<u32>> {
+    (any::<u64>(), 0..=max_len).prop_map(|(seed, len)| OrderGenerator::new(seed).order(len))
+}
+// Orders of up to max_len elements, with up to max_ops ops each.
+#[cfg(feature = "proptest")]
+pub fn op_sequence_strategy(max_len: usize, max_ops: usize) -> impl Strategy<Value = OpSequence> {
+    (any::<u64>(), 0..=max_len, 0..=max_ops).prop_map(|(seed, len, count)| OrderGenerator::new(seed).op_sequence(len, count))
+}
+
+// Up to g.size() elements.
+#[cfg(feature = "quickcheck")]
+impl Arbitrary for OrderMaintenance<u32> {
+    fn arbitrary(g: &mut Gen) -> OrderMaintenance<u32> {
+        let len = usize::arbitrary(g) % (g.size() + 1);
+        OrderGenerator::new(u64::arbitrary(g)).order(len)
+    }
+    fn shrink(&self) -> Box<dyn Iterator<Item = OrderMaintenance<u32>>> {
+        let values: Vec<u32> = self.iter_values_with_tags().map(|(&value, _)| value).collect();
+        let om = self.clone();
+        let half = if values.len() > 1 {
+            let mut front = om.clone();
+            front.split_off(&values[values.len() / 2]).expect("it's there");
+            Some(front)
+        } else {
+            None
+        };
+        Box::new(half.into_iter().chain((0..values.len()).map(move |i| {
+            let mut smaller = om.clone();
+            smaller.remove(&values[i]);
+            smaller
+        })))
+    }
+}
+// Up to g.size() elements and g.size() ops; shrinks the ops first, then the
+// order once they're gone.
+#[cfg(feature = "quickcheck")]
+impl Arbitrary for OpSequence {
+    fn arbitrary(g: &mut Gen) -> OpSequence {
+        let len = usize::arbitrary(g) % (g.size() + 1);
+        let count = usize::arbitrary(g) % (g.size() + 1);
+        OrderGenerator::new(u64::arbitrary(g)).op_sequence(len, count)
+    }
+    fn shrink(&self) -> Box<dyn Iterator<Item = OpSequence>> {
+        if self.ops.is_empty() {
+            return Box::new(self.order.shrink().map(|order| OpSequence { order, ops: Vec::new() }));
+        }
+        let sequence = self.clone();
+        let half = if self.ops.len() > 1 {
+            Some(OpSequence { order: self.order.clone(), ops: self.ops[..self.ops.len() / 2].to_vec() })
+        } else {
+            None
+        };
+        let without_each = (0..self.ops.len()).map(move |i| {
+            let mut ops = sequence.ops.clone();
+            ops.remove(i);
+            OpSequence { order: sequence.order.clone(), ops }
+        }).filter(OpSequence::applies);
+        Box::new(half.into_iter().chain(without_each))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_generated_applies() {
+        for seed in 0..20 {
+            let mut generator = OrderGenerator::new(seed);
+            let mut om = generator.order(seed as usize * 3);
+            assert_eq!(om.len(), seed as usize * 3);
+            om.check_invariants().unwrap();
+            let ops = generator.ops(&om, 50);
+            assert_eq!(ops.len(), 50);
+            om.apply(ops).unwrap();
+            om.check_invariants().unwrap();
+        }
+        // the same seed, the same history
+        let (mut a, mut b) = (OrderGenerator::new(7), OrderGenerator::new(7));
+        assert_eq!(a.order(40), b.order(40));
+        let om = OrderGenerator::new(1).order(10);
+        assert_eq!(a.ops(&om, 20), b.ops(&om, 20));
+    }
+    #[cfg(feature = "proptest")]
+    #[test]
+    fn proptest_shrinks_to_the_shortest() {
+        use proptest::test_runner::{TestCaseError, TestError, TestRunner};
+        let mut runner = TestRunner::default();
+        let failed = runner.run(&order_strategy(200), |om| {
+            om.check_invariants().unwrap();
+            if om.len() < 5 { Ok(()) } else { Err(TestCaseError::fail("too long")) }
+        });
+        match failed {
+            Err(TestError::Fail(_, om)) => assert_eq!(om.len(), 5),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+        let failed = runner.run(&op_sequence_strategy(20, 100), |sequence| {
+            sequence.result().check_invariants().unwrap();
+            if sequence.ops.contains(&Op::Reverse) { Err(TestCaseError::fail("reversed")) } else { Ok(()) }
+        });
+        match failed {
+            // shrunk to stop at the first reverse
+            Err(TestError::Fail(_, sequence)) => assert_eq!(sequence.ops.last(), Some(&Op::Reverse)),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+    #[cfg(feature = "quickcheck")]
+    #[test]
+    fn quickcheck_shrinks_to_what_matters() {
+        use quickcheck::QuickCheck;
+        fn applies(sequence: OpSequence) -> bool {
+            sequence.result().check_invariants().is_ok()
+        }
+        QuickCheck::new().tests(50).quickcheck(applies as fn(OpSequence) -> bool);
+        // shrinking the way quickcheck does, until nothing smaller still has
+        // a reverse in it
+        let reverses = |sequence: &OpSequence| sequence.ops.contains(&Op::Reverse);
+        let mut sequence = (0..).map(|seed| OrderGenerator::new(seed).op_sequence(10, 60)).find(|sequence| reverses(sequence)).unwrap();
+        while let Some(smaller) = sequence.shrink().find(|smaller| reverses(smaller)) {
+            assert!(smaller.applies());
+            sequence = smaller;
+        }
+        assert_eq!(sequence.ops, [Op::Reverse]);
+        assert_eq!(sequence.order.len(), 10);
+        let mut om = OrderGenerator::new(3).order(12);
+        while let Some(smaller) = om.shrink().find(|smaller| smaller.len() >= 3) {
+            smaller.check_invariants().unwrap();
+            om = smaller;
+        }
+        assert_eq!(om.len(), 3);
+    }
+}
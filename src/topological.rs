@@ -0,0 +1,239 @@
+// A topological order of a DAG that's kept up to date as edges go in, the
+// textbook use for order maintenance: "does a come before b" is one compare
+// of tags however the graph got that way. An edge that already agrees with
+// the order costs nothing more than recording it; one that doesn't gets the
+// Pearce–Kelly treatment. Only nodes between its two ends can be out of place:
+// those reachable from the edge's head (without going past its tail) and
+// those reaching its tail (without going back past its head). Reaching the
+// tail from the head means the edge would close a cycle, and it's refused;
+// otherwise whichever of the two sets is smaller moves, keeping its own
+// order, to just past the other end of the edge (the ones reaching the tail
+// to just before the head, or the ones reachable from the head to just after
+// the tail), and nothing outside them moves at all.
+//
+// New nodes go at the back, where no edge out of them can be in the way yet.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::{HashMap, HashSet};
+
+use super::{OrderMaintenance, OrderMaintenanceError, RandomState, Tag};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopologicalError {
+    // one of the edge's ends isn't a node
+    NotPresent,
+    // the edge would make a cycle (a self-loop included)
+    Cycle,
+}
+impl fmt::Display for TopologicalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TopologicalError::NotPresent => "edge end is not a node",
+            TopologicalError::Cycle => "edge would make a cycle",
+        })
+    }
+}
+impl Error for TopologicalError {}
+
+#[derive(Debug, Clone)]
+struct Edges<T> {
+    successors: Vec<T>,
+    predecessors: Vec<T>,
+}
+
+#[derive(Debug)]
+pub struct TopologicalOrder<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    edges: HashMap<T, Edges<T>, S>,
+}
+
+impl<T> TopologicalOrder<T>
+    where T: Hash + Eq + Clone {
+    pub fn new() -> TopologicalOrder<T> {
+        TopologicalOrder::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for TopologicalOrder<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone + Default {
+    fn default() -> TopologicalOrder<T, S> {
+        TopologicalOrder::with_hasher(S::default())
+    }
+}
+
+// Each node is kept twice, in the order and with its edges, hence T: Clone.
+impl<T, S> TopologicalOrder<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone {
+    pub fn with_hasher(hasher: S) -> TopologicalOrder<T, S> {
+        TopologicalOrder { om: OrderMaintenance::with_hasher(hasher.clone()), edges: HashMap::with_hasher(hasher) }
+    }
+    // for iterating, tags and the rest
+    pub fn order(&self) -> &OrderMaintenance<T, S> {
+        &self.om
+    }
+    pub fn len(&self) -> usize {
+        self.om.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.om.is_empty()
+    }
+    pub fn contains<Q>(&self, node: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.contains(node)
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.compare(a, b)
+    }
+    pub fn is_before<Q>(&self, a: &Q, b: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.is_before(a, b)
+    }
+    // in the order the edges went in
+    pub fn successors<Q>(&self, node: &Q) -> &[T]
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.edges.get(node).map_or(&[], |edges| &edges.successors)
+    }
+    pub fn predecessors<Q>(&self, node: &Q) -> &[T]
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.edges.get(node).map_or(&[], |edges| &edges.predecessors)
+    }
+    pub fn add_node(&mut self, node: T) -> Result<(), OrderMaintenanceError> {
+        match self.om.last() {
+            None => self.om.insert_only(node.clone())?,
+            Some(last) => {
+                self.om.insert_after_index(last, node.clone())?;
+            }
+        }
+        self.edges.insert(node, Edges { successors: Vec::new(), predecessors: Vec::new() });
+        Ok(())
+    }
+    // Takes its edges with it.
+    pub fn remove_node<Q>(&mut self, node: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let edges = match self.edges.remove(node) {
+            None => return false,
+            Some(edges) => edges,
+        };
+        for successor in &edges.successors {
+            self.edges.get_mut(successor).expect("edges end at nodes").predecessors.retain(|other| other.borrow() != node);
+        }
+        for predecessor in &edges.predecessors {
+            self.edges.get_mut(predecessor).expect("edges end at nodes").successors.retain(|other| other.borrow() != node);
+        }
+        self.om.remove(node);
+        true
+    }
+    // An edge that's already there is left as it is. On an error, nothing
+    // has changed.
+    pub fn add_edge(&mut self, from: &T, to: &T) -> Result<(), TopologicalError> {
+        let (upper, lower) = match (self.om.tag_of(from), self.om.tag_of(to)) {
+            (Some(upper), Some(lower)) => (upper, lower),
+            _ => return Err(TopologicalError::NotPresent),
+        };
+        if from == to {
+            return Err(TopologicalError::Cycle);
+        }
+        if self.edges[from].successors.contains(to) {
+            return Ok(());
+        }
+        if upper > lower {
+            let forward = self.reach(to, |edges| &edges.successors, |tag| tag <= upper);
+            if forward.contains(from) {
+                return Err(TopologicalError::Cycle);
+            }
+            let backward = self.reach(from, |edges| &edges.predecessors, |tag| tag > lower);
+            // each keeps its own order as it goes
+            if backward.len() <= forward.len() {
+                for node in backward {
+                    self.om.move_before(&node, to).expect("nodes are present");
+                }
+            } else {
+                for node in forward.into_iter().rev() {
+                    self.om.move_after(&node, from).expect("nodes are present");
+                }
+            }
+        }
+        self.edges.get_mut(from).expect("from is a node").successors.push(to.clone());
+        self.edges.get_mut(to).expect("to is a node").predecessors.push(from.clone());
+        Ok(())
+    }
+    pub fn remove_edge(&mut self, from: &T, to: &T) -> bool {
+        let removed = match self.edges.get_mut(from) {
+            Some(edges) => {
+                let before = edges.successors.len();
+                edges.successors.retain(|other| other != to);
+                edges.successors.len() != before
+            }
+            None => false,
+        };
+        if removed {
+            self.edges.get_mut(to).expect("edges end at nodes").predecessors.retain(|other| other != from);
+        }
+        removed
+    }
+
+    // Every node reachable from start along next's edges, start included,
+    // without passing through any whose tag isn't within; sorted into the
+    // current order.
+    fn reach<N, F>(&self, start: &T, next: N, within: F) -> Vec<T>
+        where N: Fn(&Edges<T>) -> &Vec<T>, F: Fn(Tag<u64>) -> bool {
+        let mut seen = HashSet::with_hasher(self.edges.hasher().clone());
+        let mut stack = Vec::from([start]);
+        seen.insert(start);
+        let mut found = Vec::new();
+        while let Some(node) = stack.pop() {
+            found.push((self.om.tag_of(node).expect("edges end at nodes"), node.clone()));
+            for other in next(&self.edges[node]) {
+                if within(self.om.tag_of(other).expect("edges end at nodes")) && seen.insert(other) {
+                    stack.push(other);
+                }
+            }
+        }
+        found.sort_unstable_by_key(|&(tag, _)| tag);
+        found.into_iter().map(|(_, node)| node).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn keeps_every_edge_forwards() {
+        let mut graph: TopologicalOrder<u32> = TopologicalOrder::new();
+        for node in 0..20 {
+            graph.add_node(node).unwrap();
+        }
+        // backwards edges all, so every one has to reorder something
+        let edges: Vec<(u32, u32)> = (0..20).flat_map(|a| (0..a).filter(move |b| (a * 7 + b * 3) % 5 == 0).map(move |b| (a, b))).collect();
+        for &(a, b) in &edges {
+            graph.add_edge(&a, &b).unwrap();
+            graph.order().check_invariants().unwrap();
+        }
+        for &(a, b) in &edges {
+            assert!(graph.is_before(&a, &b), "{} -> {}", a, b);
+            // and the other way round would close a cycle
+            assert_eq!(graph.add_edge(&b, &a), Err(TopologicalError::Cycle));
+        }
+        assert_eq!(graph.add_edge(&3, &3), Err(TopologicalError::Cycle));
+        assert_eq!(graph.add_edge(&3, &99), Err(TopologicalError::NotPresent));
+        let (a, b) = edges[edges.len() / 2];
+        assert!(graph.remove_edge(&a, &b));
+        assert!(!graph.remove_edge(&a, &b));
+        assert!(!graph.predecessors(&b).contains(&a));
+        graph.add_edge(&a, &b).unwrap();
+        assert_eq!(graph.successors(&a).iter().filter(|&&other| other == b).count(), 1);
+        assert!(graph.remove_node(&b));
+        assert!(graph.successors(&a).iter().chain(graph.predecessors(&a)).all(|&other| other != b));
+        assert_eq!(graph.len(), 19);
+    }
+}
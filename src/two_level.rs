@@ -0,0 +1,614 @@
+// The full two-level scheme from Bender et al: elements are grouped into
+// buckets of Θ(log n) consecutive elements, each with a local label that only
+// orders it within its bucket, and the buckets themselves are kept in an
+// OrderMaintenance. An insert only ever relabels its own bucket (O(log n)),
+// and only a bucket filling up (once per capacity / 4 inserts into it, at
+// worst) touches the top level, where it costs O(log n) amortized: O(1)
+// amortized per update, all told. Comparing is a label comparison within a
+// bucket and a top-level comparison between buckets.
+//
+// A bucket that removals leave under a quarter full merges with a neighbour,
+// or takes some of its elements if together they'd be too many, so there are
+// never many more buckets than n / log n. The capacity is 2 log n (but at
+// least MIN_BUCKET_CAPACITY), chosen again each time n doubles or halves;
+// when that changes it, the whole order is cut into new half-full buckets,
+// which is O(n) but only once per n / 2 updates.
+//
+// How the buckets are kept in order is up to L, a TopLevel: AmortizedTop
+// (an OrderMaintenance, the default) or IncrementalTop (see
+// DeamortizedOrderMaintenance), which keeps the capacity at BUCKET_CAPACITY
+// instead.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::HashTable;
+
+use super::{OrderMaintenance, OrderMaintenanceError, RandomState};
+
+// the most elements a bucket holds under a TopLevel that doesn't rescale
+pub const BUCKET_CAPACITY: usize = 64;
+// and the least the capacity goes down to when it does
+const MIN_BUCKET_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BucketId(usize);
+
+pub(crate) mod sealed {
+    pub trait Sealed {}
+}
+// The order over a TwoLevelOrderMaintenance's buckets, which are named by
+// small dense ids. Sealed: the implementations are AmortizedTop and
+// IncrementalTop.
+pub trait TopLevel: sealed::Sealed + Default {
+    fn insert_only(&mut self, bucket: usize);
+    fn insert_after(&mut self, after: usize, bucket: usize);
+    fn remove(&mut self, bucket: usize);
+    fn compare(&self, a: usize, b: usize) -> Ordering;
+    fn is_empty(&self) -> bool;
+    // called once per update, for implementations that spread their work out
+    fn tick(&mut self) {}
+    // whether the bucket capacity follows log n, re-bucketing everything when
+    // it changes, or stays at BUCKET_CAPACITY
+    const RESCALES: bool = true;
+}
+
+// buckets in an ordinary OrderMaintenance, relabeled in whole windows
+#[derive(Debug, Default)]
+pub struct AmortizedTop(OrderMaintenance<usize>);
+impl sealed::Sealed for AmortizedTop {}
+impl TopLevel for AmortizedTop {
+    fn insert_only(&mut self, bucket: usize) {
+        self.0.insert_only(bucket).expect("top level is not empty");
+    }
+    fn insert_after(&mut self, after: usize, bucket: usize) {
+        self.0.insert_after(&after, bucket).expect("bucket missing from the top level");
+    }
+    fn remove(&mut self, bucket: usize) {
+        self.0.remove(&bucket);
+    }
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        self.0.compare(&a, &b).expect("bucket missing from the top level")
+    }
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    first: usize,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Element<T> {
+    key: T,
+    bucket: BucketId,
+    label: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct TwoLevelOrderMaintenance<T, S = RandomState, L = AmortizedTop>
+    where T: Hash + Eq {
+    elements: Vec<Option<Element<T>>>,
+    free_elements: Vec<usize>,
+    buckets: Vec<Option<Bucket>>,
+    free_buckets: Vec<usize>,
+    // element indices, hashed by their keys
+    index: HashTable<usize>,
+    hasher: S,
+    front: Option<usize>,
+    top: L,
+    // the most elements a bucket may hold, chosen for a length of scale
+    capacity: usize,
+    scale: usize,
+}
+
+#[derive(Debug)]
+pub struct TwoLevelIter<'a, T, S = RandomState, L = AmortizedTop>
+    where T: Hash + Eq + 'a, S: 'a, L: 'a {
+    om: &'a TwoLevelOrderMaintenance<T, S, L>,
+    current: Option<usize>,
+}
+impl<'a, T, S, L> Iterator for TwoLevelIter<'a, T, S, L>
+    where T: Hash + Eq {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let element = self.om.element(self.current?);
+        self.current = element.next;
+        Some(&element.key)
+    }
+}
+
+// A label strictly between lo and hi, where None means "no neighbour on that
+// side" (so anything down to 0, or up to u64::MAX, will do): halfway, or None
+// if they're adjacent.
+fn label_between(lo: Option<u64>, hi: Option<u64>) -> Option<u64> {
+    let lo = lo.map_or(-1, |lo| lo as i128);
+    let hi = hi.map_or(u64::MAX as i128 + 1, |hi| hi as i128);
+    if hi - lo >= 2 { Some((lo + (hi - lo) / 2) as u64) } else { None }
+}
+
+// 2 log len, within bounds
+fn capacity_for(len: usize) -> usize {
+    let log = (usize::BITS - len.leading_zeros()) as usize;
+    (2 * log).max(MIN_BUCKET_CAPACITY)
+}
+
+impl<T, L> TwoLevelOrderMaintenance<T, RandomState, L>
+    where T: Hash + Eq, L: TopLevel {
+    pub fn new() -> TwoLevelOrderMaintenance<T, RandomState, L> {
+        TwoLevelOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S, L> Default for TwoLevelOrderMaintenance<T, S, L>
+    where T: Hash + Eq, S: BuildHasher + Default, L: TopLevel {
+    fn default() -> TwoLevelOrderMaintenance<T, S, L> {
+        TwoLevelOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+// element access, which the iterator needs too, whatever the hasher
+impl<T, S, L> TwoLevelOrderMaintenance<T, S, L>
+    where T: Hash + Eq {
+    fn element(&self, index: usize) -> &Element<T> {
+        self.elements[index].as_ref().expect("dangling element index")
+    }
+    fn element_mut(&mut self, index: usize) -> &mut Element<T> {
+        self.elements[index].as_mut().expect("dangling element index")
+    }
+    fn bucket(&self, bucket: BucketId) -> &Bucket {
+        self.buckets[bucket.0].as_ref().expect("dangling bucket id")
+    }
+    fn bucket_mut(&mut self, bucket: BucketId) -> &mut Bucket {
+        self.buckets[bucket.0].as_mut().expect("dangling bucket id")
+    }
+}
+
+impl<T, S, L> TwoLevelOrderMaintenance<T, S, L>
+    where T: Hash + Eq, S: BuildHasher, L: TopLevel {
+    pub fn with_hasher(hasher: S) -> TwoLevelOrderMaintenance<T, S, L> {
+        TwoLevelOrderMaintenance {
+            elements: Vec::new(),
+            free_elements: Vec::new(),
+            buckets: Vec::new(),
+            free_buckets: Vec::new(),
+            index: HashTable::new(),
+            hasher,
+            front: None,
+            top: L::default(),
+            capacity: if L::RESCALES { capacity_for(0) } else { BUCKET_CAPACITY },
+            scale: 0,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+    pub fn iter(&self) -> TwoLevelIter<'_, T, S, L> {
+        TwoLevelIter { om: self, current: self.front }
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.find(value).is_some()
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.element(self.find(a)?);
+        let b = self.element(self.find(b)?);
+        if a.bucket == b.bucket {
+            Some(a.label.cmp(&b.label))
+        } else {
+            Some(self.top.compare(a.bucket.0, b.bucket.0))
+        }
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        if !self.is_empty() {
+            return Err(OrderMaintenanceError::NotEmpty);
+        }
+        let index = self.allocate(value, BucketId(0), 0);
+        let bucket = self.allocate_bucket(index, 1);
+        self.element_mut(index).bucket = bucket;
+        self.top.insert_only(bucket.0);
+        self.front = Some(index);
+        self.rescale();
+        self.top.tick();
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let after = self.find_anchor(after, &value)?;
+        self.check_absent(&value)?;
+        let (bucket, next) = {
+            let after = self.element(after);
+            (after.bucket, after.next)
+        };
+        let index = self.allocate(value, bucket, 0);
+        {
+            let element = self.element_mut(index);
+            element.prev = Some(after);
+            element.next = next;
+        }
+        self.element_mut(after).next = Some(index);
+        if let Some(next) = next {
+            self.element_mut(next).prev = Some(index);
+        }
+        self.placed(index);
+        self.rescale();
+        self.top.tick();
+        Ok(())
+    }
+    pub fn insert_before<Q>(&mut self, before: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let before = self.find_anchor(before, &value)?;
+        self.check_absent(&value)?;
+        let (bucket, prev) = {
+            let before = self.element(before);
+            (before.bucket, before.prev)
+        };
+        let index = self.allocate(value, bucket, 0);
+        {
+            let element = self.element_mut(index);
+            element.prev = prev;
+            element.next = Some(before);
+        }
+        self.element_mut(before).prev = Some(index);
+        match prev {
+            Some(prev) => self.element_mut(prev).next = Some(index),
+            None => self.front = Some(index),
+        }
+        if self.bucket_mut(bucket).first == before {
+            self.bucket_mut(bucket).first = index;
+        }
+        self.placed(index);
+        self.rescale();
+        self.top.tick();
+        Ok(())
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index = self.find(value)?;
+        let hash = self.hasher.hash_one(&self.element(index).key);
+        if let Ok(entry) = self.index.find_entry(hash, |&i| i == index) {
+            entry.remove();
+        }
+        let element = self.elements[index].take().expect("dangling element index");
+        self.free_elements.push(index);
+        match element.prev {
+            Some(prev) => self.element_mut(prev).next = element.next,
+            None => self.front = element.next,
+        }
+        if let Some(next) = element.next {
+            self.element_mut(next).prev = element.prev;
+        }
+        let len = {
+            let bucket = self.bucket_mut(element.bucket);
+            bucket.len -= 1;
+            if bucket.first == index {
+                // the rest of the bucket, if any, starts right after
+                if let Some(next) = element.next {
+                    bucket.first = next;
+                }
+            }
+            bucket.len
+        };
+        if len == 0 {
+            self.free_bucket(element.bucket);
+        } else if len < self.capacity / 4 {
+            self.underfull(element.bucket);
+        }
+        self.rescale();
+        self.top.tick();
+        Some(element.key)
+    }
+
+    fn find<Q>(&self, value: &Q) -> Option<usize>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let hash = self.hasher.hash_one(value);
+        let elements = &self.elements;
+        self.index.find(hash, |&i| elements[i].as_ref().is_some_and(|element| element.key.borrow() == value)).copied()
+    }
+    fn find_anchor<Q>(&self, anchor: &Q, value: &T) -> Result<usize, OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        if value.borrow() == anchor {
+            return Err(OrderMaintenanceError::SelfInsert);
+        }
+        self.find(anchor).ok_or(OrderMaintenanceError::AnchorMissing)
+    }
+    fn check_absent(&self, value: &T) -> Result<(), OrderMaintenanceError> {
+        if self.find(value).is_some() { Err(OrderMaintenanceError::AlreadyPresent) } else { Ok(()) }
+    }
+    // an unlinked, findable element
+    fn allocate(&mut self, key: T, bucket: BucketId, label: u64) -> usize {
+        let element = Element { key, bucket, label, prev: None, next: None };
+        let index = match self.free_elements.pop() {
+            Some(index) => {
+                self.elements[index] = Some(element);
+                index
+            }
+            None => {
+                self.elements.push(Some(element));
+                self.elements.len() - 1
+            }
+        };
+        let elements = &self.elements;
+        let hasher = &self.hasher;
+        let hash = hasher.hash_one(&elements[index].as_ref().unwrap().key);
+        self.index.insert_unique(hash, index, |&i| hasher.hash_one(&elements[i].as_ref().unwrap().key));
+        index
+    }
+    fn allocate_bucket(&mut self, first: usize, len: usize) -> BucketId {
+        let bucket = Bucket { first, len };
+        match self.free_buckets.pop() {
+            Some(id) => {
+                self.buckets[id] = Some(bucket);
+                BucketId(id)
+            }
+            None => {
+                self.buckets.push(Some(bucket));
+                BucketId(self.buckets.len() - 1)
+            }
+        }
+    }
+    fn free_bucket(&mut self, bucket: BucketId) {
+        self.buckets[bucket.0] = None;
+        self.free_buckets.push(bucket.0);
+        self.top.remove(bucket.0);
+    }
+    fn last_of(&self, bucket: BucketId) -> usize {
+        let bucket = self.bucket(bucket);
+        (1..bucket.len).fold(bucket.first, |current, _| self.element(current).next.expect("bucket runs off the end"))
+    }
+    // A just-linked element joins its neighbours' bucket: it gets a label
+    // between theirs if there's room, and otherwise the bucket is relabeled,
+    // or split if that would make it too big.
+    fn placed(&mut self, index: usize) {
+        let (bucket, prev, next) = {
+            let element = self.element(index);
+            (element.bucket, element.prev, element.next)
+        };
+        let neighbour_label = |neighbour: Option<usize>| {
+            neighbour.map(|i| self.element(i)).filter(|element| element.bucket == bucket).map(|element| element.label)
+        };
+        let label = label_between(neighbour_label(prev), neighbour_label(next));
+        let len = {
+            let bucket = self.bucket_mut(bucket);
+            bucket.len += 1;
+            bucket.len
+        };
+        if len > self.capacity {
+            self.split(bucket);
+        } else {
+            match label {
+                Some(label) => self.element_mut(index).label = label,
+                None => self.relabel(bucket),
+            }
+        }
+    }
+    // spreads the bucket's labels evenly over the whole label space
+    fn relabel(&mut self, bucket: BucketId) {
+        let (first, len) = {
+            let bucket = self.bucket_mut(bucket);
+            (bucket.first, bucket.len)
+        };
+        let step = u64::MAX / (len as u64 + 1);
+        let mut current = first;
+        for i in 0..len {
+            let element = self.element_mut(current);
+            element.label = (i as u64 + 1) * step;
+            if let Some(next) = element.next {
+                current = next;
+            }
+        }
+    }
+    // moves the second half of the bucket into a new bucket right after it
+    fn split(&mut self, bucket: BucketId) {
+        let (first, len) = {
+            let bucket = self.bucket_mut(bucket);
+            (bucket.first, bucket.len)
+        };
+        let keep = len / 2;
+        let mut second = first;
+        for _ in 0..keep {
+            second = self.element(second).next.expect("bucket runs off the end");
+        }
+        let new_bucket = self.allocate_bucket(second, len - keep);
+        let mut current = second;
+        for _ in keep..len {
+            let element = self.element_mut(current);
+            element.bucket = new_bucket;
+            if let Some(next) = element.next {
+                current = next;
+            }
+        }
+        self.bucket_mut(bucket).len = keep;
+        self.top.insert_after(bucket.0, new_bucket.0);
+        self.relabel(bucket);
+        self.relabel(new_bucket);
+    }
+    // A bucket under a quarter full joins the bucket after it (or before it,
+    // at the back), or if that would leave the two of them over three
+    // quarters full, they share their elements evenly instead.
+    fn underfull(&mut self, bucket: BucketId) {
+        let next = self.element(self.last_of(bucket)).next;
+        let prev = self.element(self.bucket(bucket).first).prev;
+        let (left, right) = match (next, prev) {
+            (Some(next), _) => (bucket, self.element(next).bucket),
+            (None, Some(prev)) => (self.element(prev).bucket, bucket),
+            // the only bucket
+            (None, None) => return,
+        };
+        let total = self.bucket(left).len + self.bucket(right).len;
+        if total <= self.capacity * 3 / 4 {
+            self.free_bucket(right);
+            self.rebucket_run(left, None, total);
+        } else {
+            self.rebucket_run(left, Some(right), total);
+        }
+    }
+    // Gives the total elements starting at left's first to left, or half
+    // each to left and right, relabeling them.
+    fn rebucket_run(&mut self, left: BucketId, right: Option<BucketId>, total: usize) {
+        let keep = if right.is_some() { total / 2 } else { total };
+        let mut current = self.bucket_mut(left).first;
+        for i in 0..total {
+            let target = if i < keep { left } else { right.expect("elements left over for no bucket") };
+            if i == keep {
+                self.bucket_mut(target).first = current;
+            }
+            let element = self.element_mut(current);
+            element.bucket = target;
+            if let Some(next) = element.next {
+                current = next;
+            }
+        }
+        self.bucket_mut(left).len = keep;
+        self.relabel(left);
+        if let Some(right) = right {
+            self.bucket_mut(right).len = total - keep;
+            self.relabel(right);
+        }
+    }
+    // once the length has doubled or halved since the capacity was chosen,
+    // chooses it again, and re-buckets everything if that changes it
+    fn rescale(&mut self) {
+        let len = self.len();
+        if !L::RESCALES || (len < 2 * self.scale && 2 * len >= self.scale) {
+            return;
+        }
+        self.scale = len;
+        let capacity = capacity_for(len);
+        if capacity != self.capacity {
+            self.capacity = capacity;
+            self.rebucket_all();
+        }
+    }
+    // cuts the whole order into new half-full buckets, kept in a new top level
+    fn rebucket_all(&mut self) {
+        self.buckets.clear();
+        self.free_buckets.clear();
+        self.top = L::default();
+        let chunk = self.capacity / 2;
+        let mut current = self.front;
+        let mut prev_bucket: Option<BucketId> = None;
+        while let Some(first) = current {
+            let bucket = self.allocate_bucket(first, 0);
+            let mut len = 0;
+            while let Some(index) = current.filter(|_| len < chunk) {
+                let element = self.element_mut(index);
+                element.bucket = bucket;
+                current = element.next;
+                len += 1;
+            }
+            self.bucket_mut(bucket).len = len;
+            self.relabel(bucket);
+            match prev_bucket {
+                Some(prev) => self.top.insert_after(prev.0, bucket.0),
+                None => self.top.insert_only(bucket.0),
+            }
+            prev_bucket = Some(bucket);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use test_random;
+
+    // every bucket one run of elements in label order, none too full, and
+    // not many more of them than a quarter full would need
+    fn check_buckets<L>(om: &TwoLevelOrderMaintenance<u32, RandomState, L>)
+        where L: TopLevel {
+        let mut runs: Vec<(BucketId, usize)> = Vec::new();
+        let mut label = 0;
+        let mut current = om.front;
+        while let Some(index) = current {
+            let element = om.element(index);
+            match runs.last_mut() {
+                Some(&mut (bucket, ref mut len)) if bucket == element.bucket => {
+                    assert!(element.label > label);
+                    *len += 1;
+                }
+                _ => {
+                    assert_eq!(om.bucket(element.bucket).first, index);
+                    runs.push((element.bucket, 1));
+                }
+            }
+            label = element.label;
+            current = element.next;
+        }
+        for &(bucket, len) in &runs {
+            assert_eq!(om.bucket(bucket).len, len);
+            assert!(len <= om.capacity);
+        }
+        assert_eq!(runs.len(), om.buckets.iter().flatten().count());
+        assert!(runs.len() <= 4 * om.len() / om.capacity + 2, "{} buckets for {}", runs.len(), om.len());
+    }
+
+    #[test]
+    fn matches_a_vec() {
+        let mut om: TwoLevelOrderMaintenance<u32> = TwoLevelOrderMaintenance::new();
+        let mut model: Vec<u32> = Vec::new();
+        om.insert_only(0).unwrap();
+        model.push(0);
+        let mut random = test_random(12345);
+        for value in 1..5000 {
+            if value % 500 == 0 {
+                check_buckets(&om);
+            }
+            let at = random(model.len());
+            if random(2) == 0 {
+                om.insert_after(&model[at], value).unwrap();
+                model.insert(at + 1, value);
+            } else {
+                om.insert_before(&model[at], value).unwrap();
+                model.insert(at, value);
+            }
+            if value % 7 == 0 {
+                let at = random(model.len());
+                assert_eq!(om.remove(&model[at]), Some(model[at]));
+                model.remove(at);
+            }
+        }
+        // and a run of appends, which keeps splitting the last bucket
+        for value in 5000..6000 {
+            om.insert_after(model.last().unwrap(), value).unwrap();
+            model.push(value);
+        }
+        assert_eq!(om.iter().copied().collect::<Vec<_>>(), model);
+        assert_eq!(om.len(), model.len());
+        for pair in model.windows(2) {
+            assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+        }
+        assert_eq!(om.compare(&model[0], model.last().unwrap()), Some(Ordering::Less));
+        assert_eq!(om.insert_after(&model[0], model[1]), Err(OrderMaintenanceError::AlreadyPresent));
+        let grown = om.capacity;
+        check_buckets(&om);
+        // removing all over the place leaves buckets to merge, and the
+        // capacity comes down with the length
+        while !model.is_empty() {
+            if model.len().is_multiple_of(250) {
+                check_buckets(&om);
+                for pair in model.windows(2) {
+                    assert_eq!(om.compare(&pair[0], &pair[1]), Some(Ordering::Less));
+                }
+            }
+            let at = random(model.len());
+            assert_eq!(om.remove(&model[at]), Some(model[at]));
+            model.remove(at);
+        }
+        assert!(om.capacity < grown);
+        assert!(om.is_empty());
+        assert!(om.top.is_empty());
+    }
+}
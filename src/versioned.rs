@@ -0,0 +1,286 @@
+// An order that remembers how it used to be: checkpoint() names the order
+// as it is now, and compare_at/contains_at answer as of any checkpoint since.
+// Nothing is copied to do it. Every element has a run of lives, one for
+// each stretch of versions it spent in one place (a move ends one and starts
+// another), and every life ever started is a node in a single timeline
+// order that's only ever inserted into. A new life goes in the timeline
+// right next to its anchor's current one, which leaves every older pair of
+// nodes as they were; so at any version, the lives that were current then
+// are in the timeline in the order the elements were in. compare_at is then
+// a binary search for each element's life plus one tag comparison, and
+// each change costs one more insert, into the timeline: partial persistence,
+// in the usual sense, in space proportional to the number of changes.
+
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::hash::{BuildHasher, Hash};
+
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+
+use super::{IterWithTag, OrderMaintenance, OrderMaintenanceError, RandomState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VersionId(u64);
+
+// from born up to (not including) died
+#[derive(Debug, Clone, Copy)]
+struct Life {
+    born: u64,
+    died: u64,
+    node: u64,
+}
+
+// where a new life goes in the timeline
+enum Place {
+    After(u64),
+    Before(u64),
+    Front,
+    Back,
+}
+
+#[derive(Debug)]
+pub struct VersionedOrderMaintenance<T, S = RandomState>
+    where T: Hash + Eq {
+    om: OrderMaintenance<T, S>,
+    timeline: OrderMaintenance<u64, S>,
+    lives: HashMap<T, Vec<Life>, S>,
+    // what changes are stamped with: one past the last checkpoint
+    version: u64,
+    next_node: u64,
+}
+
+impl<T> VersionedOrderMaintenance<T>
+    where T: Hash + Eq + Clone {
+    pub fn new() -> VersionedOrderMaintenance<T> {
+        VersionedOrderMaintenance::with_hasher(RandomState::default())
+    }
+}
+
+impl<T, S> Default for VersionedOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone + Default {
+    fn default() -> VersionedOrderMaintenance<T, S> {
+        VersionedOrderMaintenance::with_hasher(S::default())
+    }
+}
+
+impl<T, S> VersionedOrderMaintenance<T, S>
+    where T: Hash + Eq + Clone, S: BuildHasher + Clone {
+    pub fn with_hasher(hasher: S) -> VersionedOrderMaintenance<T, S> {
+        VersionedOrderMaintenance {
+            om: OrderMaintenance::with_hasher(hasher.clone()),
+            timeline: OrderMaintenance::with_hasher(hasher.clone()),
+            lives: HashMap::with_hasher(hasher),
+            version: 0,
+            next_node: 0,
+        }
+    }
+    // The order as it is now, to ask about later. Checkpoints are cheap: no
+    // more than a counter until something changes.
+    pub fn checkpoint(&mut self) -> VersionId {
+        self.version += 1;
+        VersionId(self.version - 1)
+    }
+    pub fn len(&self) -> usize {
+        self.om.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.om.is_empty()
+    }
+    pub fn iter_values_with_tags(&self) -> IterWithTag<'_, T, S> {
+        self.om.iter_values_with_tags()
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.contains(value)
+    }
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.compare(a, b)
+    }
+    pub fn contains_at<Q>(&self, version: VersionId, value: &Q) -> bool
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.life_at(version, value).is_some()
+    }
+    // None unless both were in the order then
+    pub fn compare_at<Q>(&self, version: VersionId, a: &Q, b: &Q) -> Option<Ordering>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let a = self.life_at(version, a)?;
+        let b = self.life_at(version, b)?;
+        self.timeline.compare(&a.node, &b.node)
+    }
+    pub fn insert_only(&mut self, value: T) -> Result<(), OrderMaintenanceError> {
+        self.om.insert_only(value.clone())?;
+        self.start_life(value, Place::Back);
+        Ok(())
+    }
+    pub fn insert_after<Q>(&mut self, after: &Q, value: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.insert_after(after, value.clone())?;
+        let after = self.node(after);
+        self.start_life(value, Place::After(after));
+        Ok(())
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<T>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.om.remove(value)?.value;
+        self.end_life::<T>(&value);
+        Some(value)
+    }
+    // new takes over old's place, from now on
+    pub fn replace_key<Q>(&mut self, old: &Q, new: T) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let old = self.om.stored(old).ok_or(OrderMaintenanceError::NotPresent)?.clone();
+        if old == new {
+            return Ok(());
+        }
+        self.om.replace_key::<T>(&old, new.clone())?;
+        let place = Place::After(self.node::<T>(&old));
+        self.end_life::<T>(&old);
+        self.start_life(new, place);
+        Ok(())
+    }
+    pub fn move_after<Q>(&mut self, value: &Q, after: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.move_after(value, after)?;
+        let place = Place::After(self.node(after));
+        self.restart_life(value, place);
+        Ok(())
+    }
+    pub fn move_before<Q>(&mut self, value: &Q, before: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.move_before(value, before)?;
+        let place = Place::Before(self.node(before));
+        self.restart_life(value, place);
+        Ok(())
+    }
+    pub fn move_to_front<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.move_to_front(value)?;
+        self.restart_life(value, Place::Front);
+        Ok(())
+    }
+    pub fn move_to_back<Q>(&mut self, value: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.move_to_back(value)?;
+        self.restart_life(value, Place::Back);
+        Ok(())
+    }
+    // each takes the other's place, from now on
+    pub fn swap_positions<Q>(&mut self, a: &Q, b: &Q) -> Result<(), OrderMaintenanceError>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.om.swap_positions(a, b)?;
+        let (a_node, b_node) = (self.node(a), self.node(b));
+        if a_node != b_node {
+            self.restart_life(a, Place::After(b_node));
+            self.restart_life(b, Place::After(a_node));
+        }
+        Ok(())
+    }
+    fn life_at<Q>(&self, version: VersionId, value: &Q) -> Option<Life>
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let lives = self.lives.get(value)?;
+        // lives are in order and don't overlap
+        let after = lives.partition_point(|life| life.born <= version.0);
+        lives[..after].last().filter(|life| version.0 < life.died).copied()
+    }
+    // the current life's node, for an element that's in the order
+    fn node<Q>(&self, value: &Q) -> u64
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.lives[value].last().expect("present elements have lives").node
+    }
+    fn start_life(&mut self, value: T, place: Place) {
+        let node = self.next_node;
+        self.next_node += 1;
+        match (place, self.timeline.last()) {
+            (_, None) => self.timeline.insert_only(node),
+            (Place::After(after), _) => self.timeline.insert_after(&after, node),
+            (Place::Before(before), _) => self.timeline.insert_after(&before, node).and_then(|()| self.timeline.move_before(&node, &before)),
+            (Place::Front, Some(last)) => {
+                let last = self.timeline.slots[last].key;
+                self.timeline.insert_after(&last, node).and_then(|()| self.timeline.move_to_front(&node))
+            }
+            (Place::Back, Some(last)) => {
+                let last = self.timeline.slots[last].key;
+                self.timeline.insert_after(&last, node)
+            }
+        }.expect("timeline anchors are never removed");
+        self.lives.entry(value).or_default().push(Life { born: self.version, died: u64::MAX, node });
+    }
+    fn end_life<Q>(&mut self, value: &Q)
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let version = self.version;
+        let lives = self.lives.get_mut(value).expect("present elements have lives");
+        let life = lives.last_mut().expect("present elements have lives");
+        if life.born == version {
+            // nobody can ask about a version it was alive in
+            lives.pop();
+        } else {
+            life.died = version;
+        }
+    }
+    fn restart_life<Q>(&mut self, value: &Q, place: Place)
+        where T: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let value = self.om.stored(value).expect("moved elements are present").clone();
+        self.end_life::<T>(&value);
+        self.start_life(value, place);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn compares_as_of_a_checkpoint() {
+        let mut om: VersionedOrderMaintenance<u32> = VersionedOrderMaintenance::new();
+        let empty = om.checkpoint();
+        om.insert_only(0).unwrap();
+        for value in 1..50 {
+            om.insert_after(&(value - 1), value).unwrap();
+        }
+        let mut checkpoints = Vec::new();
+        let mut orders = Vec::new();
+        let mut remember = |om: &mut VersionedOrderMaintenance<u32>| {
+            checkpoints.push(om.checkpoint());
+            orders.push(om.iter_values_with_tags().map(|(&value, _)| value).collect::<Vec<_>>());
+        };
+        remember(&mut om);
+        om.move_to_front(&30).unwrap();
+        om.move_after(&2, &40).unwrap();
+        om.move_after(&2, &41).unwrap();
+        remember(&mut om);
+        om.remove(&10);
+        om.swap_positions(&0, &49).unwrap();
+        om.move_before(&5, &30).unwrap();
+        om.move_to_back(&20).unwrap();
+        remember(&mut om);
+        om.replace_key(&25, 100).unwrap();
+        om.insert_after(&100, 10).unwrap();
+        om.move_to_back(&100).unwrap();
+        om.remove(&3);
+        remember(&mut om);
+        // a change and its undoing between checkpoints leave no trace
+        om.insert_after(&4, 200).unwrap();
+        om.remove(&200);
+        om.insert_only(300).unwrap_err();
+
+        assert!(!om.contains_at(empty, &0));
+        assert_eq!(om.compare_at(empty, &0, &1), None);
+        for (&version, order) in checkpoints.iter().zip(&orders) {
+            for value in 0..301 {
+                assert_eq!(om.contains_at(version, &value), order.contains(&value));
+            }
+            for (i, a) in order.iter().enumerate() {
+                for (j, b) in order.iter().enumerate() {
+                    assert_eq!(om.compare_at(version, a, b), Some(i.cmp(&j)));
+                }
+            }
+        }
+        assert_eq!(om.compare_at(checkpoints[1], &10, &3), Some(Ordering::Greater));
+        assert_eq!(om.compare_at(checkpoints[2], &10, &3), None);
+        assert_eq!(om.compare_at(checkpoints[3], &10, &100), Some(Ordering::Less));
+        assert_eq!(om.lives[&200].len(), 0);
+    }
+}
@@ -0,0 +1,224 @@
+// The order for JavaScript, through wasm-bindgen: a class that's
+// `OrderMaintenance` on the JS side, keyed by strings or integers (any
+// number that's a safe integer; 1 and 1.0 are the same key, as they are in
+// JS, and "1" is a different one). Anything else as a key, and any error
+// the order returns, is thrown as an Error.
+//
+// tagOf(key) is an element's tag, as a BigInt, for keeping a copy of the
+// tags on the JS side (compared as numbers, they order like the elements).
+// The function given to onRelabel keeps that copy current: it's called with
+// (key, tag) for every element whose tag a change set, rebalances and all,
+// and last of all the element that was inserted or moved. They're
+// collected while a change is being made and delivered once it's done, so
+// the callback sees the order as that change left it; if the callback
+// throws, the change has still happened and the rest of its notifications
+// are dropped. Removals aren't reported: remove() says what went.
+//
+// Built into a cdylib crate that depends on this one with the feature on,
+// the class comes along with whatever that crate exports itself.
+
+use core::fmt::Display;
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use js_sys::{Array, BigInt, Function};
+use wasm_bindgen::prelude::*;
+
+use super::OrderMaintenance;
+
+// Number.MAX_SAFE_INTEGER, 2^53 - 1
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Str(String),
+}
+impl Key {
+    fn from_js(value: &JsValue) -> Result<Key, JsValue> {
+        Key::from_parts(value.as_string(), value.as_f64()).map_err(error)
+    }
+    // what from_js makes of a value that's this string, or else this number
+    fn from_parts(string: Option<String>, number: Option<f64>) -> Result<Key, &'static str> {
+        if let Some(string) = string {
+            return Ok(Key::Str(string));
+        }
+        match number {
+            Some(number) if number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER => Ok(Key::Int(number as i64)),
+            _ => Err("keys have to be strings or safe integers"),
+        }
+    }
+    fn to_js(&self) -> JsValue {
+        match *self {
+            Key::Int(int) => JsValue::from_f64(int as f64),
+            Key::Str(ref string) => JsValue::from_str(string),
+        }
+    }
+}
+
+fn error<E>(error: E) -> JsValue
+    where E: Display {
+    JsError::new(&error.to_string()).into()
+}
+
+// Tag changes waiting to go to onRelabel: the order's on_relabel fills it
+// in during a change, and the change empties it once it's done.
+#[derive(Debug, Clone, Default)]
+struct Pending(Arc<Mutex<Vec<(Key, u64)>>>);
+impl Pending {
+    fn listen(&self, om: &mut OrderMaintenance<Key>) {
+        let pending = self.clone();
+        om.set_on_relabel(move |key, tag| pending.lock().push((key.clone(), tag.0)));
+    }
+    // everything recorded since last time, then touched with its tag now
+    fn take(&self, om: &OrderMaintenance<Key>, touched: &Key) -> Vec<(Key, u64)> {
+        let mut relabeled: Vec<(Key, u64)> = self.lock().drain(..).collect();
+        if let Some(tag) = om.tag_of(touched) {
+            relabeled.push((touched.clone(), tag.0));
+        }
+        relabeled
+    }
+    fn lock(&self) -> MutexGuard<'_, Vec<(Key, u64)>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[wasm_bindgen(js_name = OrderMaintenance)]
+#[derive(Debug)]
+pub struct JsOrderMaintenance {
+    om: OrderMaintenance<Key>,
+    pending: Pending,
+    on_relabel: Option<Function>,
+}
+
+impl Default for JsOrderMaintenance {
+    fn default() -> JsOrderMaintenance {
+        JsOrderMaintenance::new()
+    }
+}
+
+#[wasm_bindgen(js_class = OrderMaintenance)]
+impl JsOrderMaintenance {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> JsOrderMaintenance {
+        JsOrderMaintenance { om: OrderMaintenance::new(), pending: Pending::default(), on_relabel: None }
+    }
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.om.len()
+    }
+    pub fn contains(&self, key: &JsValue) -> Result<bool, JsValue> {
+        Ok(self.om.contains(&Key::from_js(key)?))
+    }
+    // -1, 0 or 1, or undefined if either isn't there
+    pub fn compare(&self, a: &JsValue, b: &JsValue) -> Result<Option<i32>, JsValue> {
+        Ok(self.om.compare(&Key::from_js(a)?, &Key::from_js(b)?).map(|ordering| ordering as i32))
+    }
+    #[wasm_bindgen(js_name = isBefore)]
+    pub fn is_before(&self, a: &JsValue, b: &JsValue) -> Result<bool, JsValue> {
+        Ok(self.om.is_before(&Key::from_js(a)?, &Key::from_js(b)?))
+    }
+    // a BigInt, or undefined if it isn't there
+    #[wasm_bindgen(js_name = tagOf)]
+    pub fn tag_of(&self, key: &JsValue) -> Result<JsValue, JsValue> {
+        Ok(match self.om.tag_of(&Key::from_js(key)?) {
+            Some(tag) => BigInt::from(tag.0).into(),
+            None => JsValue::UNDEFINED,
+        })
+    }
+    // every key, front to back
+    pub fn keys(&self) -> Array {
+        self.om.iter_values_with_tags().map(|(key, _)| key.to_js()).collect()
+    }
+    #[wasm_bindgen(js_name = insertOnly)]
+    pub fn insert_only(&mut self, key: &JsValue) -> Result<(), JsValue> {
+        let key = Key::from_js(key)?;
+        self.om.insert_only(key.clone()).map_err(error)?;
+        self.notify(&key)
+    }
+    #[wasm_bindgen(js_name = insertAfter)]
+    pub fn insert_after(&mut self, after: &JsValue, key: &JsValue) -> Result<(), JsValue> {
+        let (after, key) = (Key::from_js(after)?, Key::from_js(key)?);
+        self.om.insert_after(&after, key.clone()).map_err(error)?;
+        self.notify(&key)
+    }
+    #[wasm_bindgen(js_name = insertBefore)]
+    pub fn insert_before(&mut self, before: &JsValue, key: &JsValue) -> Result<(), JsValue> {
+        let (before, key) = (Key::from_js(before)?, Key::from_js(key)?);
+        self.om.insert_all_before(&before, Some(key.clone())).map_err(error)?;
+        self.notify(&key)
+    }
+    // false if it wasn't there
+    pub fn remove(&mut self, key: &JsValue) -> Result<bool, JsValue> {
+        Ok(self.om.remove(&Key::from_js(key)?).is_some())
+    }
+    #[wasm_bindgen(js_name = moveAfter)]
+    pub fn move_after(&mut self, key: &JsValue, after: &JsValue) -> Result<(), JsValue> {
+        let key = Key::from_js(key)?;
+        self.om.move_after(&key, &Key::from_js(after)?).map_err(error)?;
+        self.notify(&key)
+    }
+    #[wasm_bindgen(js_name = moveBefore)]
+    pub fn move_before(&mut self, key: &JsValue, before: &JsValue) -> Result<(), JsValue> {
+        let key = Key::from_js(key)?;
+        self.om.move_before(&key, &Key::from_js(before)?).map_err(error)?;
+        self.notify(&key)
+    }
+    // null or undefined stops the notifications
+    #[wasm_bindgen(js_name = onRelabel)]
+    pub fn on_relabel(&mut self, callback: Option<Function>) {
+        match callback {
+            None => self.om.clear_on_relabel(),
+            Some(_) => self.pending.listen(&mut self.om),
+        }
+        self.on_relabel = callback;
+    }
+
+    fn notify(&mut self, touched: &Key) -> Result<(), JsValue> {
+        if let Some(ref callback) = self.on_relabel {
+            for (key, tag) in self.pending.take(&self.om, touched) {
+                callback.call2(&JsValue::NULL, &key.to_js(), &BigInt::from(tag).into())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// JsValues only work on wasm, so these are the parts that don't need them.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn keys_and_pending_tags() {
+        assert_eq!(Key::from_parts(Some("1".to_string()), None), Ok(Key::Str("1".to_string())));
+        assert_eq!(Key::from_parts(None, Some(1.0)), Ok(Key::Int(1)));
+        assert_eq!(Key::from_parts(None, Some(-MAX_SAFE_INTEGER)), Ok(Key::Int(-9_007_199_254_740_991)));
+        assert!(Key::from_parts(None, Some(1.5)).is_err());
+        assert!(Key::from_parts(None, Some(MAX_SAFE_INTEGER + 2.0)).is_err());
+        assert!(Key::from_parts(None, Some(f64::NAN)).is_err());
+        assert!(Key::from_parts(None, None).is_err());
+        // a mirror kept only from what notify would deliver
+        let mut om: OrderMaintenance<Key> = OrderMaintenance::new();
+        let pending = Pending::default();
+        pending.listen(&mut om);
+        let mut mirror: HashMap<Key, u64> = HashMap::new();
+        om.insert_only(Key::Int(0)).unwrap();
+        mirror.extend(pending.take(&om, &Key::Int(0)));
+        for int in 1..300 {
+            om.insert_after(&Key::Int(0), Key::Int(int)).unwrap();
+            mirror.extend(pending.take(&om, &Key::Int(int)));
+        }
+        om.move_after(&Key::Int(0), &Key::Int(150)).unwrap();
+        let moved = pending.take(&om, &Key::Int(0));
+        assert_eq!(moved.last(), Some(&(Key::Int(0), om.tag_of(&Key::Int(0)).unwrap().0)));
+        mirror.extend(moved);
+        for (key, tag) in om.iter_values_with_tags() {
+            assert_eq!(mirror[key], tag.0);
+        }
+    }
+}
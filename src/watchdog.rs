@@ -0,0 +1,143 @@
+// An early warning that the tags are working harder than they should: a
+// single rebalance relabeling a big share of the order, or rebalances
+// adding up to much more than the O(log n) per insert the labeling is meant
+// to cost. Either one usually means the tags are too narrow for how many
+// elements there are (WideOrderMaintenance), the overflow threshold is off
+// for the workload, or the inserts are all piling into one spot
+// (gap_report() will say which). The watchdog only reports it, through its
+// callback and cascade_alarms(); the relabeling still happens.
+
+use alloc::boxed::Box;
+use core::fmt::{self, Debug};
+use core::hash::{BuildHasher, Hash};
+
+use super::{OrderMaintenance, TagWidth};
+
+// When to raise an alarm; the default never does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CascadeLimits {
+    // the most elements a single rebalance may relabel
+    pub max_cascade: usize,
+    // the most elements all the rebalances may relabel together over any
+    // window inserts in a row (counted from the first insert after the
+    // watchdog was set, window at a time; relabeling set off by moves
+    // counts too)
+    pub max_relabeled: u64,
+    pub window: u64,
+}
+impl Default for CascadeLimits {
+    fn default() -> CascadeLimits {
+        CascadeLimits { max_cascade: usize::MAX, max_relabeled: u64::MAX, window: u64::MAX }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CascadeAlarm {
+    // one rebalance relabeled this many elements, more than max_cascade,
+    // out of len
+    Large { relabeled: usize, len: usize },
+    // this many elements were relabeled, more than max_relabeled, in the
+    // first inserts of the current window; raised once per window
+    Frequent { relabeled: u64, inserts: u64 },
+}
+
+pub(crate) struct Watchdog {
+    limits: CascadeLimits,
+    on_alarm: Box<dyn FnMut(CascadeAlarm) + Send + Sync>,
+    alarms: u64,
+    // so far in the current window
+    inserts: u64,
+    relabeled: u64,
+    raised: bool,
+}
+impl Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchdog").field("limits", &self.limits).field("alarms", &self.alarms).finish_non_exhaustive()
+    }
+}
+impl Watchdog {
+    pub(crate) fn inserted(&mut self) {
+        self.inserts += 1;
+        if self.inserts >= self.limits.window.max(1) {
+            self.inserts = 0;
+            self.relabeled = 0;
+            self.raised = false;
+        }
+    }
+    // after a rebalance of num_items out of len
+    pub(crate) fn relabeled(&mut self, num_items: usize, len: usize) {
+        if num_items > self.limits.max_cascade {
+            self.alarm(CascadeAlarm::Large { relabeled: num_items, len });
+        }
+        self.relabeled = self.relabeled.saturating_add(num_items as u64);
+        if self.relabeled > self.limits.max_relabeled && !self.raised {
+            self.raised = true;
+            let alarm = CascadeAlarm::Frequent { relabeled: self.relabeled, inserts: self.inserts };
+            self.alarm(alarm);
+        }
+    }
+    fn alarm(&mut self, alarm: CascadeAlarm) {
+        self.alarms += 1;
+        (self.on_alarm)(alarm);
+    }
+}
+
+impl<T, S, W> OrderMaintenance<T, S, W>
+    where T: Hash + Eq, S: BuildHasher, W: TagWidth {
+    // Calls on_alarm whenever relabeling goes past limits, from inside the
+    // insert or move that set it off (so it can't touch the order, but it
+    // can log, count or flag it for later). Replaces any watchdog already
+    // set, alarm count and all. Like on_relabel it stays behind when the
+    // order is cloned.
+    pub fn set_cascade_watchdog<F>(&mut self, limits: CascadeLimits, on_alarm: F)
+        where F: FnMut(CascadeAlarm) + Send + Sync + 'static {
+        self.watchdog = Some(Watchdog { limits, on_alarm: Box::new(on_alarm), alarms: 0, inserts: 0, relabeled: 0, raised: false });
+    }
+    pub fn clear_cascade_watchdog(&mut self) {
+        self.watchdog = None;
+    }
+    // how many alarms the watchdog has raised; None without one
+    pub fn cascade_alarms(&self) -> Option<u64> {
+        self.watchdog.as_ref().map(|watchdog| watchdog.alarms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::vec::Vec;
+
+    #[test]
+    fn raises_alarms() {
+        let mut om: OrderMaintenance<u32> = OrderMaintenance::new();
+        assert_eq!(om.cascade_alarms(), None);
+        let alarms = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&alarms);
+        let limits = CascadeLimits { max_cascade: 32, max_relabeled: 1000, window: 100 };
+        om.set_cascade_watchdog(limits, move |alarm| seen.lock().unwrap().push(alarm));
+        // always inserting at the same spot keeps splitting the same gap
+        om.insert_only(0).unwrap();
+        for value in 1..2000 {
+            om.insert_after(&0, value).unwrap();
+        }
+        let alarms = alarms.lock().unwrap();
+        assert_eq!(om.cascade_alarms(), Some(alarms.len() as u64));
+        assert!(alarms.iter().any(|alarm| match *alarm {
+            CascadeAlarm::Large { relabeled, len } => relabeled > 32 && relabeled <= len,
+            CascadeAlarm::Frequent { .. } => false,
+        }));
+        // no more than one Frequent per window
+        let frequent = alarms.iter().filter(|alarm| matches!(**alarm, CascadeAlarm::Frequent { .. })).count();
+        assert!((1..=20).contains(&frequent));
+        om.clear_cascade_watchdog();
+        assert_eq!(om.cascade_alarms(), None);
+        let mut quiet: OrderMaintenance<u32> = OrderMaintenance::new();
+        quiet.set_cascade_watchdog(CascadeLimits::default(), |_| panic!("no limits"));
+        quiet.insert_only(0).unwrap();
+        for value in 1..2000 {
+            quiet.insert_after(&0, value).unwrap();
+        }
+        assert_eq!(quiet.cascade_alarms(), Some(0));
+    }
+}
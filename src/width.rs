@@ -0,0 +1,150 @@
+// The integer an OrderMaintenance's tags are made of: u32, u64 (the default)
+// or u128. Wider tags cost more memory per element but leave more room
+// between neighbours, so relabels are rarer. Sealed; only those three.
+
+use core::fmt::Debug;
+use core::hash::Hash;
+use core::ops::{Add, BitAnd, Div, Not, Shl, Sub};
+use core::sync::atomic::Ordering::Relaxed;
+use core::sync::atomic::{AtomicU32, AtomicU64};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+pub trait TagWidth: sealed::Sealed + Copy + Ord + Hash + Debug + Default + Send + Sync + 'static
+    + Add<Output = Self> + Sub<Output = Self> + Div<Output = Self>
+    + BitAnd<Output = Self> + Not<Output = Self> + Shl<u32, Output = Self> {
+    const ZERO: Self;
+    const ONE: Self;
+    const MAX: Self;
+    const BITS: u32;
+    // what OrderedHandles keep their copy of the tag in
+    #[doc(hidden)]
+    type Atomic: Debug + Send + Sync;
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_sub(self, other: Self) -> Self;
+    // saturating, for element counts
+    fn from_usize(n: usize) -> Self;
+    fn to_f64(self) -> f64;
+    fn count_ones(self) -> u32;
+    fn leading_zeros(self) -> u32;
+    // rounding down
+    fn half(self) -> Self;
+    // for snapshots; from_u128 keeps the low bits
+    #[doc(hidden)]
+    fn to_u128(self) -> u128;
+    #[doc(hidden)]
+    fn from_u128(n: u128) -> Self;
+    #[doc(hidden)]
+    fn new_atomic(self) -> Self::Atomic;
+    #[doc(hidden)]
+    fn load(atomic: &Self::Atomic) -> Self;
+    #[doc(hidden)]
+    fn store(atomic: &Self::Atomic, value: Self);
+}
+
+macro_rules! native_width {
+    ($int:ident, $atomic:ident) => {
+        impl sealed::Sealed for $int {}
+        impl TagWidth for $int {
+            const ZERO: $int = 0;
+            const ONE: $int = 1;
+            const MAX: $int = $int::MAX;
+            const BITS: u32 = $int::BITS;
+            type Atomic = $atomic;
+            fn wrapping_add(self, other: $int) -> $int {
+                $int::wrapping_add(self, other)
+            }
+            fn wrapping_sub(self, other: $int) -> $int {
+                $int::wrapping_sub(self, other)
+            }
+            fn from_usize(n: usize) -> $int {
+                if n as u128 > $int::MAX as u128 { $int::MAX } else { n as $int }
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn count_ones(self) -> u32 {
+                $int::count_ones(self)
+            }
+            fn leading_zeros(self) -> u32 {
+                $int::leading_zeros(self)
+            }
+            fn half(self) -> $int {
+                self >> 1
+            }
+            fn to_u128(self) -> u128 {
+                self as u128
+            }
+            fn from_u128(n: u128) -> $int {
+                n as $int
+            }
+            fn new_atomic(self) -> $atomic {
+                $atomic::new(self)
+            }
+            fn load(atomic: &$atomic) -> $int {
+                atomic.load(Relaxed)
+            }
+            fn store(atomic: &$atomic, value: $int) {
+                atomic.store(value, Relaxed)
+            }
+        }
+    };
+}
+native_width!(u32, AtomicU32);
+native_width!(u64, AtomicU64);
+
+// There's no stable AtomicU128, so u128 handles keep the two halves
+// separately. A handle read while the order is writing its tag can see one
+// half old and the other new, but the read happens under HandleShared's
+// sequence check, which sees the write and retries, so a torn tag is never
+// compared and handles can be compared while the order changes.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct AtomicU128Halves(AtomicU64, AtomicU64);
+impl sealed::Sealed for u128 {}
+impl TagWidth for u128 {
+    const ZERO: u128 = 0;
+    const ONE: u128 = 1;
+    const MAX: u128 = u128::MAX;
+    const BITS: u32 = u128::BITS;
+    type Atomic = AtomicU128Halves;
+    fn wrapping_add(self, other: u128) -> u128 {
+        u128::wrapping_add(self, other)
+    }
+    fn wrapping_sub(self, other: u128) -> u128 {
+        u128::wrapping_sub(self, other)
+    }
+    fn from_usize(n: usize) -> u128 {
+        n as u128
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+    fn count_ones(self) -> u32 {
+        u128::count_ones(self)
+    }
+    fn leading_zeros(self) -> u32 {
+        u128::leading_zeros(self)
+    }
+    fn half(self) -> u128 {
+        self >> 1
+    }
+    fn to_u128(self) -> u128 {
+        self
+    }
+    fn from_u128(n: u128) -> u128 {
+        n
+    }
+    fn new_atomic(self) -> AtomicU128Halves {
+        AtomicU128Halves(AtomicU64::new((self >> 64) as u64), AtomicU64::new(self as u64))
+    }
+    fn load(atomic: &AtomicU128Halves) -> u128 {
+        ((atomic.0.load(Relaxed) as u128) << 64) | atomic.1.load(Relaxed) as u128
+    }
+    fn store(atomic: &AtomicU128Halves, value: u128) {
+        atomic.0.store((value >> 64) as u64, Relaxed);
+        atomic.1.store(value as u64, Relaxed);
+    }
+}